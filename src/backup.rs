@@ -0,0 +1,242 @@
+use std::{
+    fs::File,
+    io::{BufRead, BufReader, BufWriter, Read, Write},
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use crate::{
+    context::Context,
+    error::Error,
+    jsonl::{self, Value, Writers},
+    snapshot::{Snapshot, SnapshotId},
+    utils::Crc32,
+};
+
+/// Distinguishes the copies of backups restored concurrently.
+static SPOOL_ID: AtomicU64 = AtomicU64::new(0);
+
+/// Version of the backup format, in the header line.
+pub const VERSION: u64 = 1;
+
+/// What a backup holds. Backups are JSON Lines: a header naming the format
+/// version, the snapshot and, for incremental backups, the snapshot they
+/// build on,
+///
+/// ```text
+/// {"backup":1,"snapshot":"3.1700000000000000000","base":"3.1699900000000000000"}
+/// ```
+///
+/// then one line per entry as [`crate::jsonl::export`] writes them, then a
+/// trailer with the number of entries and the CRC-32 of their lines:
+///
+/// ```text
+/// {"entries":2,"checksum":"1c291ca3"}
+/// ```
+///
+/// Entries written while the backup is taken are in it as they were when
+/// read, and in the next incremental backup again. Incremental backups only
+/// hold the entries written since their base, not the ones removed: restoring a full backup and the incremental ones after
+/// it brings back the entries removed meanwhile, until they expire.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Backup {
+    pub snapshot: SnapshotId,
+    /// The snapshot an incremental backup holds the changes since.
+    pub base: Option<SnapshotId>,
+    pub entries: u64,
+}
+
+pub(crate) fn write(
+    snapshot: &Snapshot,
+    since: Option<SnapshotId>,
+    writer: impl Write,
+) -> Result<Backup, Error> {
+    let id = snapshot.id();
+    if let Some(since) = since
+        && since.generation != id.generation
+    {
+        return Err(Error::Cleared);
+    }
+
+    let mut writer = BufWriter::new(writer);
+    let mut header = format!("{{\"backup\":{VERSION},\"snapshot\":\"{id}\"");
+    if let Some(since) = since {
+        header.push_str(&format!(",\"base\":\"{since}\""));
+    }
+    header.push_str("}\n");
+    writer.write_all(header.as_bytes())?;
+
+    let mut crc = Crc32::default();
+    let mut entries = 0;
+    for (hash, info) in snapshot.changes(since.map(|since| since.pinned)) {
+        let Some(payload) = snapshot.read_changed(&hash, &info)? else {
+            continue;
+        };
+
+        let line = jsonl::record(None, &hash, &info, &payload);
+        crc.update(line.as_bytes());
+        writer.write_all(line.as_bytes())?;
+        entries += 1;
+    }
+
+    if !snapshot.is_valid() {
+        return Err(Error::Cleared);
+    }
+    let trailer = format!(
+        "{{\"entries\":{entries},\"checksum\":\"{:08x}\"}}\n",
+        crc.finish()
+    );
+    writer.write_all(trailer.as_bytes())?;
+    writer.flush()?;
+
+    Ok(Backup {
+        snapshot: id,
+        base: since,
+        entries,
+    })
+}
+
+/// Stores the entries of a backup like [`crate::jsonl::import`] does. A
+/// backup of another format version, one cut short or one whose checksum
+/// doesn't match fails with [`Error::InvalidData`] before anything is
+/// written: the backup is copied next to the store while being checked, then
+/// read again from there.
+pub(crate) fn restore(ctx: &Context, root: &Path, reader: impl Read) -> Result<Backup, Error> {
+    let id = SPOOL_ID.fetch_add(1, Ordering::Relaxed);
+    let spool = Spool(root.join(format!("RESTORE.{}.{id}.tmp", std::process::id())));
+    let backup = verify(
+        reader,
+        BufWriter::new(ctx.permissions.create_file(&spool.0)?),
+    )?;
+
+    let file = File::open(&spool.0)?;
+    let now = ctx.now();
+    let mut writer = Writers::new(ctx, root);
+    for line in BufReader::new(file)
+        .lines()
+        .skip(1)
+        .take(backup.entries as usize)
+    {
+        jsonl::import_line(ctx, &mut writer, &line?, now)?;
+    }
+    writer.finish()?;
+    Ok(backup)
+}
+
+/// Copies the header and entry lines of a backup to `copy`, checking the
+/// trailer against them.
+fn verify(reader: impl Read, mut copy: impl Write) -> Result<Backup, Error> {
+    let mut lines = BufReader::new(reader).lines();
+    let header = lines.next().transpose()?.ok_or(Error::InvalidData)?;
+    let (snapshot, base) = parse_header(&header).ok_or(Error::InvalidData)?;
+    copy.write_all(header.as_bytes())?;
+    copy.write_all(b"\n")?;
+
+    let mut crc = Crc32::default();
+    for (entries, line) in (0..).zip(lines) {
+        let line = line?;
+        if let Some((count, checksum)) = parse_trailer(&line) {
+            if count != entries || checksum != crc.finish() {
+                return Err(Error::InvalidData);
+            }
+            copy.flush()?;
+            return Ok(Backup {
+                snapshot,
+                base,
+                entries,
+            });
+        }
+
+        crc.update(line.as_bytes());
+        crc.update(b"\n");
+        copy.write_all(line.as_bytes())?;
+        copy.write_all(b"\n")?;
+    }
+
+    Err(Error::InvalidData)
+}
+
+/// Removes the copy of a backup being restored.
+struct Spool(PathBuf);
+
+impl Drop for Spool {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.0);
+    }
+}
+
+fn parse_header(line: &str) -> Option<(SnapshotId, Option<SnapshotId>)> {
+    let (mut version, mut snapshot, mut base) = (None, None, None);
+    for (name, value) in jsonl::object(line)? {
+        match (name.as_str(), value) {
+            ("backup", Value::Number(n)) => version = Some(n),
+            ("snapshot", Value::String(id)) => snapshot = Some(id.parse().ok()?),
+            ("base", Value::String(id)) => base = Some(id.parse().ok()?),
+            _ => {}
+        }
+    }
+    (version? == VERSION).then_some((snapshot?, base))
+}
+
+fn parse_trailer(line: &str) -> Option<(u64, u32)> {
+    let (mut entries, mut checksum) = (None, None);
+    for (name, value) in jsonl::object(line)? {
+        match (name.as_str(), value) {
+            ("entries", Value::Number(n)) => entries = Some(n),
+            ("checksum", Value::String(crc)) => checksum = u32::from_str_radix(&crc, 16).ok(),
+            _ => {}
+        }
+    }
+    Some((entries?, checksum?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HEADER: &str = "{\"backup\":1,\"snapshot\":\"3.1700000000000000000\"}\n";
+    const ENTRY: &str = "{\"hash\":\"0123456789abcdef0123456789abcdef\",\"key\":\"k\",\
+                         \"value\":\"dg==\",\"expires_at\":0,\"version\":2}\n";
+
+    fn backup(trailer: &str) -> String {
+        format!("{HEADER}{ENTRY}{trailer}\n")
+    }
+
+    #[test]
+    fn verify_checksum() {
+        let mut copy = Vec::new();
+        let backup = verify(
+            backup("{\"entries\":1,\"checksum\":\"89e98249\"}").as_bytes(),
+            &mut copy,
+        )
+        .unwrap();
+
+        assert_eq!(backup.snapshot, "3.1700000000000000000".parse().unwrap());
+        assert_eq!((backup.base, backup.entries), (None, 1));
+        assert_eq!(copy, format!("{HEADER}{ENTRY}").into_bytes());
+    }
+
+    #[test]
+    fn verify_rejects_mismatches() {
+        for trailer in [
+            "{\"entries\":1,\"checksum\":\"89e98248\"}",
+            "{\"entries\":2,\"checksum\":\"89e98249\"}",
+            "",
+        ] {
+            assert!(matches!(
+                verify(backup(trailer).as_bytes(), Vec::new()),
+                Err(Error::InvalidData)
+            ));
+        }
+
+        let other_version = backup("{\"entries\":1,\"checksum\":\"89e98249\"}").replacen(
+            "\"backup\":1",
+            "\"backup\":2",
+            1,
+        );
+        assert!(matches!(
+            verify(other_version.as_bytes(), Vec::new()),
+            Err(Error::InvalidData)
+        ));
+    }
+}
@@ -0,0 +1,98 @@
+use std::{path::PathBuf, process::ExitCode};
+
+use keeper::{entry, keeper::Keeper};
+
+const USAGE: &str = "usage: keeper-cli migrate <path> [--to <version>]
+       keeper-cli rebuild-index <path>
+       keeper-cli admin <socket> cleanup-now|pause-janitor|resume-janitor|set-interval <seconds>";
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+
+    let result = match args.first().map(String::as_str) {
+        Some("migrate") => migrate(&args[1..]),
+        Some("rebuild-index") => rebuild_index(&args[1..]),
+        Some("admin") => admin(&args[1..]),
+        _ => Err(USAGE.to_string()),
+    };
+
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("{e}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn migrate(args: &[String]) -> Result<(), String> {
+    let mut path = None;
+    let mut target = entry::CURRENT;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--to" => {
+                target = args
+                    .next()
+                    .and_then(|v| v.parse().ok())
+                    .ok_or_else(|| USAGE.to_string())?;
+            }
+            _ if path.is_none() => path = Some(PathBuf::from(arg)),
+            _ => return Err(USAGE.to_string()),
+        }
+    }
+
+    let path = path.ok_or_else(|| USAGE.to_string())?;
+    let report = Keeper::migrate(&path, target).map_err(|e| e.to_string())?;
+
+    println!(
+        "migrated {} from format {} to {}: {} rewritten, {} removed",
+        path.display(),
+        report.from_version,
+        report.to_version,
+        report.rewritten,
+        report.removed
+    );
+    Ok(())
+}
+
+fn rebuild_index(args: &[String]) -> Result<(), String> {
+    let [path] = args else {
+        return Err(USAGE.to_string());
+    };
+
+    let keeper = Keeper::new(PathBuf::from(path)).map_err(|e| e.to_string())?;
+    let report = keeper.rebuild_index().map_err(|e| e.to_string())?;
+
+    println!(
+        "rebuilt the index of {path}: {} entries, {} in namespaces, {} with unknown keys",
+        report.entries, report.namespaced, report.unknown_keys
+    );
+    Ok(())
+}
+
+/// Sends a command to the control socket of a running keeper, see
+/// `KeeperBuilder::with_control_socket`.
+#[cfg(unix)]
+fn admin(args: &[String]) -> Result<(), String> {
+    let [socket, command @ ..] = args else {
+        return Err(USAGE.to_string());
+    };
+    if command.is_empty() {
+        return Err(USAGE.to_string());
+    }
+
+    let reply = keeper::control::request(socket.as_ref(), &command.join(" "))
+        .map_err(|e| format!("{socket}: {e}"))?;
+    match reply.strip_prefix("ERR ") {
+        Some(e) => Err(e.to_string()),
+        None if reply == "OK" => Ok(()),
+        None => Err(format!("{socket}: unexpected reply `{reply}`")),
+    }
+}
+
+#[cfg(not(unix))]
+fn admin(_args: &[String]) -> Result<(), String> {
+    Err("admin commands need Unix sockets".to_string())
+}
@@ -0,0 +1,183 @@
+use std::{
+    collections::{HashMap, HashSet},
+    io::ErrorKind,
+    path::{Path, PathBuf},
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicBool, AtomicU32, Ordering},
+    },
+    time::{Duration, Instant},
+};
+
+use crate::{error::Error, permissions::Permissions};
+
+const PROBE_FILE: &str = "PROBE";
+const PROBE_DATA: &[u8] = b"keeper health probe";
+
+/// Writes dropped while degraded that are remembered one by one. Past this,
+/// the whole store is cleared on recovery instead.
+const MAX_DROPPED: usize = 65536;
+
+/// Bypasses the disk after `failures` IO errors in a row, probing it again
+/// every `probe_interval`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Degradation {
+    pub failures: u32,
+    pub probe_interval: Duration,
+}
+
+#[derive(Debug)]
+struct State {
+    next_probe: Instant,
+    /// Keys whose writes were dropped, by store or tenant root. Their entries
+    /// on disk are stale and are removed before the disk is used again.
+    dropped: HashMap<Arc<PathBuf>, HashSet<String>>,
+    /// Too many writes were dropped to remember, or a clear was.
+    overflowed: bool,
+}
+
+/// Tracks the IO failures of the store workers and decides when the store
+/// bypasses the disk, see
+/// [`crate::keeper::KeeperBuilder::with_degradation`].
+#[derive(Debug)]
+pub struct Bypass {
+    policy: Degradation,
+    root: PathBuf,
+    failures: AtomicU32,
+    degraded: AtomicBool,
+    state: Mutex<State>,
+}
+
+impl Bypass {
+    pub fn new(policy: Degradation, root: PathBuf) -> Self {
+        Self {
+            policy,
+            root,
+            failures: AtomicU32::new(0),
+            degraded: AtomicBool::new(false),
+            state: Mutex::new(State {
+                next_probe: Instant::now(),
+                dropped: HashMap::new(),
+                overflowed: false,
+            }),
+        }
+    }
+
+    pub fn is_bypassing(&self) -> bool {
+        self.degraded.load(Ordering::Acquire)
+    }
+
+    /// Counts the outcome of a request. Returns whether it tripped the store
+    /// into bypassing the disk.
+    pub fn observe<T>(&self, res: &Result<T, Error>) -> bool {
+        match res {
+            Err(Error::Io(e)) if e.kind() != ErrorKind::NotFound => {
+                let failures = self.failures.fetch_add(1, Ordering::AcqRel) + 1;
+                failures >= self.policy.failures.max(1) && self.degrade()
+            }
+            Ok(_) => {
+                self.failures.store(0, Ordering::Release);
+                false
+            }
+            Err(_) => false,
+        }
+    }
+
+    fn degrade(&self) -> bool {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        state.next_probe = Instant::now() + self.policy.probe_interval;
+        !self.degraded.swap(true, Ordering::AcqRel)
+    }
+
+    /// The store root, where probes are written.
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Remembers that writes of `keys` under `root`, or a clear when `clear`
+    /// is set, are dropped. Returns `false`, remembering nothing, when the
+    /// store recovered meanwhile and the writes must go to the disk.
+    pub fn drop_writes(&self, root: &Arc<PathBuf>, keys: &[&str], clear: bool) -> bool {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        if !self.is_bypassing() {
+            return false;
+        }
+
+        let remembered: usize = state.dropped.values().map(HashSet::len).sum();
+        if clear || remembered + keys.len() > MAX_DROPPED {
+            state.overflowed = true;
+        }
+        match state.overflowed {
+            true => state.dropped.clear(),
+            false => state
+                .dropped
+                .entry(root.clone())
+                .or_default()
+                .extend(keys.iter().map(|&key| key.to_string())),
+        }
+        true
+    }
+
+    /// Checks the disk when a probe is due, by writing, reading back and
+    /// removing a small file in the store root. On success, hands over the
+    /// dropped writes, which the caller must invalidate before calling
+    /// [`Bypass::recover`]. Only one caller probes at a time.
+    pub fn probe(&self, permissions: &Permissions) -> Option<Dropped> {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        let now = Instant::now();
+        if now < state.next_probe {
+            return None;
+        }
+        state.next_probe = now + self.policy.probe_interval;
+        drop(state);
+
+        let path = self.root.join(PROBE_FILE);
+        let healthy = permissions
+            .write(&path, PROBE_DATA)
+            .and_then(|()| std::fs::read(&path))
+            .is_ok_and(|data| data == PROBE_DATA);
+        let _ = std::fs::remove_file(&path);
+        if !healthy {
+            return None;
+        }
+
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        Some(Dropped {
+            keys: std::mem::take(&mut state.dropped),
+            overflowed: std::mem::take(&mut state.overflowed),
+        })
+    }
+
+    /// Stops bypassing the disk once the dropped writes were invalidated,
+    /// unless more were dropped meanwhile, which are returned instead.
+    pub fn recover(&self) -> Option<Dropped> {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        if !state.dropped.is_empty() || state.overflowed {
+            return Some(Dropped {
+                keys: std::mem::take(&mut state.dropped),
+                overflowed: std::mem::take(&mut state.overflowed),
+            });
+        }
+
+        self.failures.store(0, Ordering::Release);
+        self.degraded.store(false, Ordering::Release);
+        None
+    }
+
+    /// Puts back the dropped writes that couldn't be invalidated.
+    pub fn restore(&self, dropped: Dropped) {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        state.overflowed |= dropped.overflowed;
+        for (root, keys) in dropped.keys {
+            state.dropped.entry(root).or_default().extend(keys);
+        }
+    }
+}
+
+/// The writes dropped while the disk was bypassed.
+#[derive(Debug, Default)]
+pub struct Dropped {
+    pub keys: HashMap<Arc<PathBuf>, HashSet<String>>,
+    /// The whole store must be cleared instead.
+    pub overflowed: bool,
+}
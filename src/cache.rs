@@ -0,0 +1,136 @@
+use std::time::Duration;
+
+use crate::{error::Error, keeper::Keeper};
+
+/// The get/set/remove interface cache middleware and frameworks take as a
+/// pluggable backend, implemented by [`Keeper`] so it can be handed to them
+/// through a thin adapter of their own trait instead of a per-project one.
+/// Misses are `Ok(None)` rather than errors.
+///
+/// The methods block the calling thread whatever the API mode; in async code
+/// use [`AsyncCache`].
+pub trait Cache {
+    type Error;
+
+    fn get(&self, key: &str) -> Result<Option<Vec<u8>>, Self::Error>;
+
+    /// Stores `value` under `key`, expiring after `ttl` if set.
+    fn set(&self, key: &str, value: &[u8], ttl: Option<Duration>) -> Result<(), Self::Error>;
+
+    /// Removes `key`, succeeding whether or not it was stored.
+    fn remove(&self, key: &str) -> Result<(), Self::Error>;
+
+    fn clear(&self) -> Result<(), Self::Error>;
+}
+
+impl Cache for Keeper {
+    type Error = Error;
+
+    fn get(&self, key: &str) -> Result<Option<Vec<u8>>, Error> {
+        let (tx, rx) = std::sync::mpsc::sync_channel(1);
+        self.dispatch_get(key, move |res| {
+            let _ = tx.send(res);
+        });
+        match rx.recv().map_err(|_| self.closed())? {
+            Ok(value) => Ok(Some(value)),
+            Err(Error::NotFound) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn set(&self, key: &str, value: &[u8], ttl: Option<Duration>) -> Result<(), Error> {
+        let (tx, rx) = std::sync::mpsc::sync_channel(1);
+        self.dispatch_set(key, value, ttl, move |res| {
+            let _ = tx.send(res);
+        });
+        rx.recv().map_err(|_| self.closed())?
+    }
+
+    fn remove(&self, key: &str) -> Result<(), Error> {
+        let (tx, rx) = std::sync::mpsc::sync_channel(1);
+        self.dispatch_remove(key, move |res| {
+            let _ = tx.send(res);
+        });
+        rx.recv().map_err(|_| self.closed())?
+    }
+
+    fn clear(&self) -> Result<(), Error> {
+        let (tx, rx) = std::sync::mpsc::sync_channel(1);
+        self.dispatch_clear(move |res| {
+            let _ = tx.send(res);
+        });
+        rx.recv().map_err(|_| self.closed())?
+    }
+}
+
+/// [`Cache`] for async frameworks, answering from the store workers without
+/// blocking the runtime.
+#[cfg(feature = "async")]
+pub trait AsyncCache {
+    type Error;
+
+    fn get(&self, key: &str) -> impl Future<Output = Result<Option<Vec<u8>>, Self::Error>> + Send;
+
+    fn set(
+        &self,
+        key: &str,
+        value: &[u8],
+        ttl: Option<Duration>,
+    ) -> impl Future<Output = Result<(), Self::Error>> + Send;
+
+    fn remove(&self, key: &str) -> impl Future<Output = Result<(), Self::Error>> + Send;
+
+    fn clear(&self) -> impl Future<Output = Result<(), Self::Error>> + Send;
+}
+
+#[cfg(feature = "async")]
+impl AsyncCache for Keeper {
+    type Error = Error;
+
+    fn get(&self, key: &str) -> impl Future<Output = Result<Option<Vec<u8>>, Error>> + Send {
+        let (tx, rx) = tokio::sync::oneshot::channel();
+        self.dispatch_get(key, move |res| {
+            let _ = tx.send(res);
+        });
+        let keeper = self.clone();
+        async move {
+            match rx.await.map_err(|_| keeper.closed())? {
+                Ok(value) => Ok(Some(value)),
+                Err(Error::NotFound) => Ok(None),
+                Err(e) => Err(e),
+            }
+        }
+    }
+
+    fn set(
+        &self,
+        key: &str,
+        value: &[u8],
+        ttl: Option<Duration>,
+    ) -> impl Future<Output = Result<(), Error>> + Send {
+        let (tx, rx) = tokio::sync::oneshot::channel();
+        self.dispatch_set(key, value, ttl, move |res| {
+            let _ = tx.send(res);
+        });
+        let keeper = self.clone();
+        async move { rx.await.map_err(|_| keeper.closed())? }
+    }
+
+    fn remove(&self, key: &str) -> impl Future<Output = Result<(), Error>> + Send {
+        let (tx, rx) = tokio::sync::oneshot::channel();
+        self.dispatch_remove(key, move |res| {
+            let _ = tx.send(res);
+        });
+        let keeper = self.clone();
+        async move { rx.await.map_err(|_| keeper.closed())? }
+    }
+
+    fn clear(&self) -> impl Future<Output = Result<(), Error>> + Send {
+        let (tx, rx) = tokio::sync::oneshot::channel();
+        self.dispatch_clear(move |res| {
+            let _ = tx.send(res);
+        });
+        let keeper = self.clone();
+        async move { rx.await.map_err(|_| keeper.closed())? }
+    }
+}
@@ -0,0 +1,47 @@
+use std::{
+    fmt,
+    time::{Instant, SystemTime, UNIX_EPOCH},
+};
+
+pub trait Clock: fmt::Debug + Send + Sync + 'static {
+    /// Seconds since the Unix epoch.
+    fn now(&self) -> u64;
+}
+
+/// Wall clock. Reports `0` instead of panicking when the system time is set
+/// before the epoch.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0)
+    }
+}
+
+/// Wall clock sampled once at creation and advanced with [`Instant`], so jumps
+/// of the system clock while the process runs neither expire entries early nor
+/// bring expired ones back.
+#[derive(Debug, Clone, Copy)]
+pub struct MonotonicClock {
+    base: u64,
+    start: Instant,
+}
+
+impl Default for MonotonicClock {
+    fn default() -> Self {
+        Self {
+            base: SystemClock.now(),
+            start: Instant::now(),
+        }
+    }
+}
+
+impl Clock for MonotonicClock {
+    fn now(&self) -> u64 {
+        self.base.saturating_add(self.start.elapsed().as_secs())
+    }
+}
@@ -0,0 +1,91 @@
+use std::{fmt, sync::Arc};
+
+use crate::error::Error;
+
+/// A reversible transform applied to values, like compression or
+/// encryption. Codecs are chained with
+/// [`crate::keeper::KeeperBuilder::with_codec`]; each entry records the ids of
+/// the codecs its value went through, so entries written with a different
+/// chain, or none, still read correctly as long as their codecs are
+/// registered.
+pub trait Codec: Send + Sync {
+    /// Identifies the codec in the entries it encoded. It must be unique
+    /// among the codecs of a store and never change.
+    fn id(&self) -> u8;
+
+    fn encode(&self, data: &[u8]) -> Vec<u8>;
+
+    fn decode(&self, data: &[u8]) -> Result<Vec<u8>, Error>;
+}
+
+/// The codecs of a store: the chain applied on writes, in order, and every
+/// codec entries may have been written with.
+#[derive(Clone, Default)]
+pub struct Codecs {
+    chain: Vec<Arc<dyn Codec>>,
+    known: Vec<Arc<dyn Codec>>,
+}
+
+impl fmt::Debug for Codecs {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let ids = |codecs: &[Arc<dyn Codec>]| codecs.iter().map(|c| c.id()).collect::<Vec<_>>();
+        f.debug_struct("Codecs")
+            .field("chain", &ids(&self.chain))
+            .field("known", &ids(&self.known))
+            .finish()
+    }
+}
+
+impl Codecs {
+    /// Appends `codec` to the chain applied on writes.
+    pub fn push(&mut self, codec: Arc<dyn Codec>) {
+        self.register(codec.clone());
+        self.chain.push(codec);
+    }
+
+    /// Makes `codec` available for reading entries, replacing any codec with
+    /// the same id.
+    pub fn register(&mut self, codec: Arc<dyn Codec>) {
+        self.known.retain(|c| c.id() != codec.id());
+        self.known.push(codec);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.chain.is_empty()
+    }
+
+    /// Runs `value` through the chain, prefixed with `[u8 count][ids]` in the
+    /// order the codecs were applied.
+    pub fn encode(&self, value: &[u8]) -> Vec<u8> {
+        let mut data = value.to_vec();
+        for codec in &self.chain {
+            data = codec.encode(&data);
+        }
+
+        let mut encoded = Vec::with_capacity(1 + self.chain.len() + data.len());
+        encoded.push(self.chain.len() as u8);
+        encoded.extend(self.chain.iter().map(|c| c.id()));
+        encoded.extend_from_slice(&data);
+        encoded
+    }
+
+    /// Reverses [`Codecs::encode`], undoing the codecs in reverse order.
+    pub fn decode(&self, payload: &[u8]) -> Result<Vec<u8>, Error> {
+        let (&count, rest) = payload.split_first().ok_or(Error::InvalidData)?;
+        if rest.len() < count as usize {
+            return Err(Error::InvalidData);
+        }
+
+        let (ids, data) = rest.split_at(count as usize);
+        let mut data = data.to_vec();
+        for &id in ids.iter().rev() {
+            let codec = self
+                .known
+                .iter()
+                .find(|c| c.id() == id)
+                .ok_or(Error::UnknownCodec(id))?;
+            data = codec.decode(&data)?;
+        }
+        Ok(data)
+    }
+}
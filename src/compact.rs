@@ -0,0 +1,115 @@
+use std::{
+    io::ErrorKind,
+    path::{Path, PathBuf},
+};
+
+use crate::context::Context;
+
+/// Suffix of the directory a shard folder is repacked into.
+pub const SUFFIX: &str = "compact";
+
+/// Folders whose directory is smaller than this are never repacked.
+const MIN_DIR_BYTES: u64 = 32 * 1024;
+
+/// Roughly what an entry takes in an ext4 directory block: the record header
+/// and a 29 character name, rounded up.
+const BYTES_PER_ENTRY: u64 = 40;
+
+/// How much larger than its entries need a directory may grow before it's
+/// repacked.
+const SLACK: u64 = 4;
+
+/// Attempts at swapping the repacked directory in, each moving the files
+/// created meanwhile.
+const SWAP_ATTEMPTS: usize = 3;
+
+/// Repacks the shard folder at `folder` when its directory is much larger
+/// than its entries need, which happens on ext4 after heavy churn: directories
+/// never shrink there, and lookups keep scanning the empty blocks.
+///
+/// The files are moved into a fresh `<folder>.compact` directory, which then
+/// replaces the emptied folder in a single rename, so the folder path never
+/// goes missing. Entries keep their inodes, so open handles and cached values
+/// stay valid. Folders linked from another path, see [`crate::mounts`], are
+/// repacked where they live. The caller must hold every shard lock of the
+/// folder. Returns whether the folder was repacked.
+pub fn compact(ctx: &Context, folder: &Path) -> std::io::Result<bool> {
+    let folder = &std::fs::canonicalize(folder)?;
+    restore(ctx, folder)?;
+
+    let dir_bytes = std::fs::metadata(folder)?.len();
+    if dir_bytes < MIN_DIR_BYTES {
+        return Ok(false);
+    }
+
+    let files = std::fs::read_dir(folder)?.count() as u64;
+    if dir_bytes <= files.max(1) * BYTES_PER_ENTRY * SLACK {
+        return Ok(false);
+    }
+
+    let target = folder.with_extension(SUFFIX);
+    ctx.permissions.create_dir_all(&target)?;
+
+    let mut attempts = 0;
+    loop {
+        if let Err(e) = move_all(folder, &target) {
+            restore(ctx, folder)?;
+            return Err(e);
+        }
+
+        match std::fs::rename(&target, folder) {
+            Ok(()) => break,
+            // Streamed writes create their temporary files without a lock.
+            Err(e) if e.kind() == ErrorKind::DirectoryNotEmpty && attempts < SWAP_ATTEMPTS => {
+                attempts += 1;
+            }
+            Err(e) => {
+                restore(ctx, folder)?;
+                return Err(e);
+            }
+        }
+    }
+
+    if let Some(root) = folder.parent() {
+        ctx.durable(&[folder, root])?;
+    }
+    Ok(true)
+}
+
+/// Moves back the files of a repack that didn't finish into `folder`, and
+/// removes the leftover directory. Files already in `folder` are newer and
+/// win. The caller must hold every shard lock of the folder.
+pub fn restore(ctx: &Context, folder: &Path) -> std::io::Result<()> {
+    let target = folder.with_extension(SUFFIX);
+    let files = match std::fs::read_dir(&target) {
+        Ok(files) => files,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e),
+    };
+
+    ctx.permissions.create_dir_all(folder)?;
+    for file in files {
+        let file = file?;
+        let path = folder.join(file.file_name());
+        match path.exists() {
+            true => std::fs::remove_file(file.path())?,
+            false => std::fs::rename(file.path(), path)?,
+        }
+    }
+    std::fs::remove_dir(&target)
+}
+
+/// The shard folder a leftover repack directory named `name` belongs to.
+pub fn leftover(root: &Path, name: &str) -> Option<(u16, PathBuf)> {
+    let folder = name.strip_suffix(SUFFIX)?.strip_suffix('.')?;
+    let id = u16::from_str_radix(folder, 16).ok()?;
+    Some((id, root.join(folder)))
+}
+
+fn move_all(from: &Path, to: &Path) -> std::io::Result<()> {
+    for file in std::fs::read_dir(from)? {
+        let file = file?;
+        std::fs::rename(file.path(), to.join(file.file_name()))?;
+    }
+    Ok(())
+}
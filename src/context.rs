@@ -0,0 +1,580 @@
+use std::{
+    borrow::Cow,
+    fs::{File, Metadata},
+    io::Read,
+    path::{Path, PathBuf},
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::{Duration, SystemTime},
+};
+
+use crate::{
+    bypass::Bypass,
+    clock::Clock,
+    codec::Codecs,
+    direct,
+    durability::GroupCommit,
+    entry,
+    error::{Error, Worker},
+    eviction::{Eviction, EvictionCause},
+    handles::HandleCache,
+    headers::HeaderCache,
+    hooks::Hooks,
+    horizon::Horizons,
+    janitor::JanitorStatus,
+    keystats::KeyTracker,
+    latency::{Latencies, Op, SlowOp},
+    loader::Loader,
+    mac::SigningKey,
+    manifest::Manifest,
+    memory::MemoryCache,
+    namespace::{self, Namespaces},
+    origin::Attached,
+    permissions::Permissions,
+    pressure::Pressure,
+    queue::Watermarks,
+    semaphore::{Permit, Semaphore},
+    shards::Shards,
+    stats::Counters,
+    store,
+    utils::{self, guard},
+    weigher::Weigher,
+};
+
+#[derive(Debug)]
+pub struct Context {
+    pub shards: Shards,
+    pub watermarks: Watermarks,
+    pub hooks: Hooks,
+    pub clock: Box<dyn Clock>,
+    pub skew_tolerance: u64,
+    pub evict_on_full: bool,
+    pub manifest: Mutex<Manifest>,
+    pub namespaces: Namespaces,
+    pub counters: Counters,
+    pub latencies: Latencies,
+    pub slow_op_threshold: Option<Duration>,
+    pub janitor_status: Mutex<JanitorStatus>,
+    pub pressure: Pressure,
+    pub temp_grace: Duration,
+    pub loader: Option<Loader>,
+    pub memory: Option<MemoryCache>,
+    pub io_limit: Option<Semaphore>,
+    pub signing_key: Option<SigningKey>,
+    pub codecs: Codecs,
+    pub hash_key: Option<SigningKey>,
+    /// Seed of the layout, see [`crate::keeper::KeeperBuilder::with_seeded_layout`].
+    pub hash_seed: Option<u64>,
+    pub secure_delete: bool,
+    /// Whether the janitor repacks bloated shard folders, see
+    /// [`crate::compact`].
+    pub compact_dirs: bool,
+    /// Weighs entries for the store size limit, see
+    /// [`crate::keeper::KeeperBuilder::with_weigher`].
+    pub weigher: Option<Weigher>,
+    /// Bypasses the disk when it keeps failing, see
+    /// [`crate::keeper::KeeperBuilder::with_degradation`].
+    pub bypass: Option<Bypass>,
+    #[cfg(feature = "testing")]
+    pub faults: Option<Arc<crate::faults::FaultInjector>>,
+    pub permissions: Permissions,
+    pub origin: Option<Attached>,
+    pub key_stats: Option<KeyTracker>,
+    pub durability: Option<GroupCommit>,
+    /// The store root and the other paths its folders live on, see
+    /// [`crate::mounts::roots`].
+    pub mounts: Vec<PathBuf>,
+    pub handles: Option<HandleCache>,
+    pub headers: Option<HeaderCache>,
+    /// Entries at least this large bypass the page cache.
+    pub direct_io: Option<u64>,
+    /// How often the modification time of read entries is bumped, when
+    /// access tracking is enabled.
+    pub access_resolution: Option<Duration>,
+    /// See [`Manifest::cleared_before`].
+    pub cleared_before: AtomicU64,
+    pub horizons: Horizons,
+    /// Bumped whenever the store is cleared, so reads that overlapped a clear
+    /// can tell.
+    pub generation: AtomicU64,
+}
+
+impl Context {
+    pub fn now(&self) -> u64 {
+        self.clock.now()
+    }
+
+    pub fn expires_at(&self, duration: Option<Duration>) -> u64 {
+        duration
+            .map(|d| self.now().saturating_add(d.as_secs()).max(1))
+            .unwrap_or(0)
+    }
+
+    /// Like [`Context::expires_at`], falling back to the default TTL of the
+    /// namespace of `key`, if any.
+    pub fn expires_for(&self, key: &str, duration: Option<Duration>) -> u64 {
+        let duration = duration.or_else(|| {
+            namespace::namespace_of(key).and_then(|ns| self.namespaces.policy(ns).default_ttl)
+        });
+        self.expires_at(duration)
+    }
+
+    /// Fails if `key` is too long to be stored with its entry.
+    pub fn check_key(&self, key: &str) -> Result<(), Error> {
+        match key.len() > entry::MAX_KEY_LEN {
+            true => Err(Error::KeyTooLong(key.len())),
+            false => Ok(()),
+        }
+    }
+
+    /// Fails if a value of `len` bytes is too large for the namespace of
+    /// `key`.
+    pub fn check_size(&self, key: &str, len: u64) -> Result<(), Error> {
+        match namespace::namespace_of(key) {
+            Some(ns)
+                if self
+                    .namespaces
+                    .policy(ns)
+                    .max_value_size
+                    .is_some_and(|max| len > max) =>
+            {
+                Err(Error::ValueTooLarge(ns.into()))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// `0` never expires. Otherwise an entry is only considered expired once the
+    /// clock has moved past its deadline by more than the skew tolerance.
+    pub fn is_expired(&self, expires_at: u64, now: u64) -> bool {
+        expires_at != 0 && expires_at.saturating_add(self.skew_tolerance) < now
+    }
+
+    /// Runs a read that must see the store as it was when it started. An I/O
+    /// or decoding error from a read that overlapped a clear is reported as
+    /// [`Error::Cleared`], since it most likely comes from files removed under
+    /// it.
+    pub fn snapshot<T>(&self, read: impl FnOnce() -> Result<T, Error>) -> Result<T, Error> {
+        let generation = self.generation.load(Ordering::Acquire);
+        match read() {
+            Err(Error::Io(_) | Error::InvalidData)
+                if self.generation.load(Ordering::Acquire) != generation =>
+            {
+                Err(Error::Cleared)
+            }
+            res => res,
+        }
+    }
+
+    /// Whether the entry with `metadata` was written before the last lazy
+    /// clear, and so counts as missing.
+    pub fn is_cleared(&self, metadata: &Metadata) -> bool {
+        let cleared_before = self.cleared_before.load(Ordering::Acquire);
+        cleared_before != 0
+            && metadata
+                .modified()
+                .is_ok_and(|modified| utils::nanos(modified) <= cleared_before)
+    }
+
+    /// The size of the live entry at `path`, if there is one.
+    pub fn live_size(&self, path: &Path) -> Option<u64> {
+        std::fs::metadata(path)
+            .ok()
+            .filter(|metadata| !self.is_cleared(metadata))
+            .map(|metadata| metadata.len())
+    }
+
+    /// The weight of an entry of `size` bytes holding `value`, counted against
+    /// the store size limit: its size unless there is a weigher.
+    pub fn weigh(&self, key: &str, value: &[u8], size: u64) -> u64 {
+        match &self.weigher {
+            Some(weigher) => weigher.weigh(key, value),
+            None => size,
+        }
+    }
+
+    /// Like [`Context::weigh`], for the encoded entry in `buffer`. Entries
+    /// that don't decode weigh their size.
+    pub fn weigh_encoded(&self, buffer: &[u8]) -> u64 {
+        let size = buffer.len() as u64;
+        if self.weigher.is_none() {
+            return size;
+        }
+
+        let key = entry::read_header_from(buffer)
+            .ok()
+            .and_then(|header| header.key)
+            .unwrap_or_default();
+        match self.decode(buffer, &key) {
+            Ok((_, value)) => self.weigh(&key, &value, size),
+            Err(_) => size,
+        }
+    }
+
+    /// Like [`Context::weigh`], for the entry of `size` bytes at `path`, which
+    /// is only read with a weigher.
+    pub fn weigh_file(&self, path: &Path, size: u64) -> u64 {
+        if self.weigher.is_none() {
+            return size;
+        }
+        match std::fs::read(path) {
+            Ok(buffer) => self.weigh_encoded(&buffer),
+            Err(_) => size,
+        }
+    }
+
+    /// Hashes `key` into the 32 hex characters naming its shard and file.
+    pub fn hash(&self, key: &str) -> Vec<u8> {
+        match (&self.hash_key, self.hash_seed) {
+            (Some(hash_key), _) => store::keyed_hash(hash_key, key),
+            (None, Some(seed)) => store::seeded_hash(seed, key),
+            (None, None) => store::hash(key),
+        }
+    }
+
+    /// Encodes an entry in the current format, with the value run through the
+    /// codecs and the entry signed if the store has a signing key. Fails for
+    /// keys longer than [`entry::MAX_KEY_LEN`].
+    pub fn encode(&self, expires_at: u64, key: &str, value: &[u8]) -> Result<Vec<u8>, Error> {
+        let (flags, payload) = match self.codecs.is_empty() {
+            true => (0, Cow::Borrowed(value)),
+            false => (entry::CODECS, Cow::Owned(self.codecs.encode(value))),
+        };
+        match &self.signing_key {
+            Some(signing_key) => {
+                entry::encode_signed(flags, expires_at, key, &payload, signing_key)
+            }
+            None => entry::encode(entry::CURRENT, flags, expires_at, Some(key), &payload),
+        }
+    }
+
+    /// Starts encoding an entry whose payload is streamed, signed if the
+    /// store has a signing key.
+    pub fn stream_encoder(
+        &self,
+        expires_at: u64,
+        key: &str,
+    ) -> Result<entry::StreamEncoder, Error> {
+        entry::StreamEncoder::new(expires_at, key, self.signing_key.as_ref())
+    }
+
+    /// Decodes the entry stored for `key`. With a signing key, the entry must
+    /// carry a valid tag and have been written for `key`, so a file copied
+    /// over another is detected too.
+    /// Values that went through codecs are decoded back.
+    pub fn decode<'a>(
+        &self,
+        buffer: &'a [u8],
+        key: &str,
+    ) -> Result<(entry::Header, Cow<'a, [u8]>), Error> {
+        let (header, payload) = match &self.signing_key {
+            Some(signing_key) => {
+                let (header, payload) = entry::decode_signed(buffer, signing_key)?;
+                if header.key.as_deref() != Some(key) {
+                    return Err(Error::TamperDetected);
+                }
+                (header, payload)
+            }
+            None => entry::decode(buffer)?,
+        };
+
+        match header.flags & entry::CODECS {
+            0 => Ok((header, Cow::Borrowed(payload))),
+            _ => {
+                let value = self.codecs.decode(payload)?;
+                Ok((header, Cow::Owned(value)))
+            }
+        }
+    }
+
+    /// Removes the entry stored at `path`, releasing its usage from the
+    /// namespace it belongs to. Returns its key, if recorded, and its size.
+    /// With secure delete, the contents are overwritten first and the entry is
+    /// kept if that fails.
+    pub fn remove_entry(&self, path: &Path) -> std::io::Result<(Option<String>, u64)> {
+        self.remove_entry_with(path, None)
+    }
+
+    /// Like [`Context::remove_entry`], taking the size and key from `file`,
+    /// a handle already open on the entry, instead of opening it again.
+    pub fn remove_entry_with(
+        &self,
+        path: &Path,
+        file: Option<&File>,
+    ) -> std::io::Result<(Option<String>, u64)> {
+        let (metadata, header) = match file {
+            Some(file) => (
+                file.metadata()?,
+                entry::read_header_from(utils::ReadAt::new(file)),
+            ),
+            None => (std::fs::metadata(path)?, entry::read_header(path)),
+        };
+        let (size, key) = (metadata.len(), header.ok().and_then(|h| h.key));
+        let weight = self.weigh_file(path, size);
+
+        self.forget(path);
+        if self.secure_delete {
+            utils::scrub(path, size)?;
+        }
+        utils::retry_io(|| std::fs::remove_file(path))?;
+        // Usage was reset when a lazy clear made the entry stale.
+        if !self.is_cleared(&metadata) {
+            self.released(key.as_deref(), size);
+            self.pressure.resize(weight, 0);
+        }
+        if let (Some(tracker), Some(key)) = (&self.key_stats, &key) {
+            tracker.forget(key);
+        }
+        Ok((key, size))
+    }
+
+    /// Waits for a turn to touch the disk when concurrent I/O is capped. Must
+    /// be taken before any shard lock.
+    pub fn io_permit(&self) -> Option<Permit<'_>> {
+        self.io_limit.as_ref().map(Semaphore::acquire)
+    }
+
+    /// Like [`Context::io_permit`], failing with [`Error::WouldBlock`]
+    /// instead of waiting.
+    pub fn try_io_permit(&self) -> Result<Option<Permit<'_>>, Error> {
+        match &self.io_limit {
+            Some(limit) => limit.try_acquire().map(Some).ok_or(Error::WouldBlock),
+            None => Ok(None),
+        }
+    }
+
+    /// Drops the in-memory copy and the open handle of the entry stored at
+    /// `path`. Called before the file changes, with the shard write lock held,
+    /// so the old value can't be read back.
+    pub fn forget(&self, path: &Path) {
+        if let Some(memory) = &self.memory {
+            memory.invalidate(&hash_of(path));
+        }
+        if let Some(handles) = &self.handles {
+            handles.invalidate(&hash_of(path));
+        }
+        if let Some(headers) = &self.headers {
+            headers.invalidate(&hash_of(path));
+        }
+    }
+
+    /// Opens the entry file at `path`, reusing an open handle when handles are
+    /// cached. The caller must hold the shard lock.
+    pub fn open_file(&self, path: &Path) -> std::io::Result<Arc<File>> {
+        self.inject_read()?;
+        match &self.handles {
+            Some(handles) => handles.open(&hash_of(path), path),
+            None => utils::retry_io(|| File::open(path)).map(Arc::new),
+        }
+    }
+
+    /// Reads the entry file at `path`, failing with `NotFound` if a lazy clear
+    /// made it stale. See [`Context::read_opened`].
+    pub fn read_file(&self, path: &Path) -> std::io::Result<Vec<u8>> {
+        let file = self.open_file(path)?;
+        if self.is_cleared(&file.metadata()?) {
+            return Err(std::io::ErrorKind::NotFound.into());
+        }
+        self.read_opened(path, &file)
+    }
+
+    /// Reads the whole entry through `file`, opened on `path` by
+    /// [`Context::open_file`], or bypasses the page cache if the entry is
+    /// large enough for direct I/O.
+    pub fn read_opened(&self, path: &Path, file: &File) -> std::io::Result<Vec<u8>> {
+        let len = file.metadata()?.len();
+        if let Some(threshold) = self.direct_io
+            && len >= threshold
+        {
+            // Direct I/O changes the descriptor's flags, so a cached handle
+            // can't be used.
+            return direct::read(&File::open(path)?, len);
+        }
+
+        let mut buffer = vec![0; len as usize];
+        utils::ReadAt::new(file).read_exact(&mut buffer)?;
+        Ok(buffer)
+    }
+
+    /// Like [`Context::remove_entry`], also notifying the eviction hook.
+    pub fn evict_entry(&self, path: &Path, cause: EvictionCause) -> std::io::Result<()> {
+        self.evict_entry_with(path, None, cause)
+    }
+
+    /// Like [`Context::remove_entry_with`], also notifying the eviction hook.
+    pub fn evict_entry_with(
+        &self,
+        path: &Path,
+        file: Option<&File>,
+        cause: EvictionCause,
+    ) -> std::io::Result<()> {
+        let (key, size) = self.remove_entry_with(path, file)?;
+
+        if self.hooks.has_eviction() {
+            self.hooks.report_eviction(&Eviction {
+                hash: String::from_utf8_lossy(&hash_of(path)).into_owned(),
+                key,
+                size,
+                cause,
+            });
+        }
+        Ok(())
+    }
+
+    /// Lets the fault injector, if any, fail or stall an entry read.
+    pub fn inject_read(&self) -> std::io::Result<()> {
+        #[cfg(feature = "testing")]
+        if let Some(faults) = &self.faults {
+            return faults.read();
+        }
+        Ok(())
+    }
+
+    /// Lets the fault injector, if any, fail or stall an entry write.
+    pub fn inject_write(&self) -> std::io::Result<()> {
+        #[cfg(feature = "testing")]
+        if let Some(faults) = &self.faults {
+            return faults.write();
+        }
+        Ok(())
+    }
+
+    /// How much of an `len` bytes entry to write: all of it, unless the fault
+    /// injector tears the write.
+    pub fn written_len(&self, len: usize) -> usize {
+        #[cfg(feature = "testing")]
+        if let Some(faults) = &self.faults {
+            return faults.tear(len);
+        }
+        len
+    }
+
+    /// With durable writes, waits until `paths`, and everything written
+    /// before, reached the disk.
+    pub fn durable(&self, paths: &[&Path]) -> std::io::Result<()> {
+        match &self.durability {
+            Some(group) => group.sync(paths),
+            None => Ok(()),
+        }
+    }
+
+    /// Counts a hit on `key`, stored at `path`, and with access tracking
+    /// marks the entry as recently used.
+    pub fn hit(&self, key: &str, path: &Path) {
+        Counters::add(&self.counters.hits, 1);
+        if let Some(tracker) = &self.key_stats {
+            tracker.hit(key, self.now());
+        }
+        if let Some(resolution) = self.access_resolution {
+            self.touch(path, resolution);
+        }
+    }
+
+    /// Sets the modification time of the entry at `path` to now, unless it
+    /// was set less than `resolution` ago, so eviction sees it as recently
+    /// used. Races with writers are harmless, a replaced file just gets a
+    /// fresh timestamp, but a lazy clear must not be missed, which the shard
+    /// read lock prevents. Skipped if a writer holds or waits for it.
+    fn touch(&self, path: &Path, resolution: Duration) {
+        let (_, _, shard_id) = utils::parse_hash(&hash_of(path));
+        let Some(_lock) = self.shards.read_now(shard_id) else {
+            return;
+        };
+
+        let now = SystemTime::now();
+        let Ok(metadata) = std::fs::metadata(path) else {
+            return;
+        };
+        let fresh = metadata
+            .modified()
+            .is_ok_and(|modified| now.duration_since(modified).unwrap_or_default() < resolution);
+        if fresh || self.is_cleared(&metadata) {
+            return;
+        }
+
+        let res = File::options()
+            .write(true)
+            .open(path)
+            .and_then(|file| file.set_modified(now));
+        match res {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => self.hooks.report(&e.into()),
+            _ => {}
+        }
+    }
+
+    /// Where the entry for `key` is stored under `root`.
+    pub fn entry_path(&self, root: &Path, key: &str) -> PathBuf {
+        let h = self.hash(key);
+        let (p_folder, filename, _) = utils::parse_hash(&h);
+        root.join(p_folder).join(filename)
+    }
+
+    pub fn released(&self, key: Option<&str>, size: u64) {
+        if let Some(ns) = key.and_then(namespace::namespace_of) {
+            self.namespaces.release(ns, size);
+        }
+    }
+
+    pub fn record(&self, op: Op, key: Option<&str>, size: usize, elapsed: Duration) {
+        self.latencies.histogram(op).record(elapsed);
+
+        if self.slow_op_threshold.is_some_and(|t| elapsed >= t) {
+            let hash = key.map(|key| self.hash(key)).unwrap_or_default();
+            self.hooks.report_slow_op(&SlowOp {
+                op,
+                hash: String::from_utf8(hash).unwrap_or_default(),
+                size,
+                elapsed,
+            });
+        }
+    }
+
+    pub fn manifest(&self) -> Manifest {
+        let mut manifest = self.manifest.lock().unwrap_or_else(|e| e.into_inner());
+        manifest.namespaces = self.namespaces.records();
+        manifest.stats = self.counters.snapshot();
+        manifest.clone()
+    }
+
+    pub fn persist(&self, root: &Path) -> Result<(), Error> {
+        self.manifest().save_with(root, &self.permissions)
+    }
+
+    pub fn guard<T>(
+        &self,
+        worker: Worker,
+        f: impl FnOnce() -> Result<T, Error>,
+    ) -> Result<T, Error> {
+        let res = guard(worker, f);
+        if let Err(e @ Error::WorkerPanicked(_)) = &res {
+            self.hooks.report(e);
+        }
+        if worker == Worker::Store
+            && let Some(bypass) = &self.bypass
+            && bypass.observe(&res)
+        {
+            self.hooks.report(&Error::DiskBypassed);
+        }
+        res
+    }
+
+    pub fn respawn(&self, worker: Worker, f: impl FnMut()) {
+        utils::respawn(f, || self.hooks.report(&Error::WorkerPanicked(worker)));
+    }
+}
+
+/// Rebuilds the hex hash of the entry stored at `path` from its folder and
+/// file names.
+fn hash_of(path: &Path) -> Vec<u8> {
+    let name = |p: Option<&Path>| {
+        p.and_then(Path::file_name)
+            .map(|n| n.as_encoded_bytes().to_vec())
+            .unwrap_or_default()
+    };
+
+    let mut hash = name(path.parent());
+    hash.extend(name(Some(path)));
+    hash
+}
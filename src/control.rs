@@ -0,0 +1,167 @@
+use std::{
+    io::{self, BufRead, BufReader, ErrorKind, Write},
+    os::unix::{
+        fs::{DirBuilderExt, FileTypeExt, PermissionsExt},
+        net::{UnixListener, UnixStream},
+    },
+    path::{Path, PathBuf},
+    thread::JoinHandle,
+    time::Duration,
+};
+
+use crossbeam::channel::{Receiver, RecvTimeoutError, Sender, unbounded};
+
+use crate::{keeper::Keeper, schedule::Schedule};
+
+/// How often the accept loop checks whether it should stop.
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// How long a client may take to send its command.
+const READ_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Binds the control socket at `path`, only accessible to the owner. A
+/// socket left there by a process that's gone is replaced; anything else
+/// there is left alone and fails the call.
+pub(crate) fn bind(path: &Path) -> io::Result<UnixListener> {
+    match std::fs::symlink_metadata(path) {
+        Ok(metadata) if !metadata.file_type().is_socket() => {
+            let message = format!("{} exists and isn't a socket", path.display());
+            return Err(io::Error::new(ErrorKind::AlreadyExists, message));
+        }
+        Ok(_) if UnixStream::connect(path).is_ok() => return Err(ErrorKind::AddrInUse.into()),
+        Ok(_) => {}
+        Err(e) if e.kind() == ErrorKind::NotFound => {}
+        Err(e) => return Err(e),
+    }
+
+    // The socket is bound in a directory only the owner can enter and moved
+    // into place once its own permissions are set, so no one else can ever
+    // connect to it.
+    let private = path.with_file_name(format!(".keeper-control.{}", std::process::id()));
+    std::fs::DirBuilder::new().mode(0o700).create(&private)?;
+    let bound = private.join("socket");
+    let res = UnixListener::bind(&bound).and_then(|listener| {
+        std::fs::set_permissions(&bound, std::fs::Permissions::from_mode(0o600))?;
+        std::fs::rename(&bound, path)?;
+        Ok(listener)
+    });
+    let _ = std::fs::remove_file(&bound);
+    let _ = std::fs::remove_dir(&private);
+
+    let listener = res?;
+    listener.set_nonblocking(true)?;
+    Ok(listener)
+}
+
+/// The thread serving a control socket.
+#[derive(Debug)]
+pub(crate) struct Server {
+    /// Dropping it stops the thread.
+    stop: Sender<()>,
+    thread: JoinHandle<()>,
+    socket: PathBuf,
+}
+
+impl Server {
+    /// Serves commands on `listener`, bound at `socket`, one connection at
+    /// a time. `keeper` returns `None` once the keeper is gone.
+    pub fn start<K>(listener: UnixListener, socket: PathBuf, keeper: K) -> Self
+    where
+        K: Fn() -> Option<Keeper> + Send + 'static,
+    {
+        let (stop_is, stop_ir) = unbounded::<()>();
+        Self {
+            stop: stop_is,
+            thread: std::thread::spawn(move || accept(listener, stop_ir, keeper)),
+            socket,
+        }
+    }
+
+    /// Stops the thread and removes the socket.
+    pub fn stop(self) {
+        drop(self.stop);
+        // The last handle can be dropped by a command the thread runs.
+        if self.thread.thread().id() != std::thread::current().id() {
+            self.thread.join().ok();
+        }
+        let _ = std::fs::remove_file(self.socket);
+    }
+}
+
+fn accept(listener: UnixListener, stop: Receiver<()>, keeper: impl Fn() -> Option<Keeper>) {
+    loop {
+        match listener.accept() {
+            Ok((stream, _)) => {
+                let _ = serve(stream, &keeper);
+            }
+            Err(e) if e.kind() == ErrorKind::WouldBlock => {
+                if let Err(RecvTimeoutError::Disconnected) = stop.recv_timeout(POLL_INTERVAL) {
+                    return;
+                }
+            }
+            Err(_) => std::thread::sleep(POLL_INTERVAL),
+        }
+    }
+}
+
+fn serve(stream: UnixStream, keeper: &impl Fn() -> Option<Keeper>) -> io::Result<()> {
+    stream.set_nonblocking(false)?;
+    stream.set_read_timeout(Some(READ_TIMEOUT))?;
+    let mut writer = stream.try_clone()?;
+
+    for line in BufReader::new(stream).lines() {
+        let reply = match keeper() {
+            Some(keeper) => execute(&keeper, line?.trim()),
+            None => Err("keeper is shutting down".to_string()),
+        };
+        match reply {
+            Ok(()) => writer.write_all(b"OK\n")?,
+            Err(e) => writer.write_all(format!("ERR {e}\n").as_bytes())?,
+        }
+    }
+    Ok(())
+}
+
+fn execute(keeper: &Keeper, command: &str) -> Result<(), String> {
+    let args: Vec<&str> = command.split_ascii_whitespace().collect();
+    match args[..] {
+        ["cleanup-now"] => {
+            let (tx, rx) = std::sync::mpsc::sync_channel(1);
+            keeper.dispatch_cleanup(move |res| {
+                let _ = tx.send(res);
+            });
+            rx.recv()
+                .map_err(|_| keeper.closed())
+                .and_then(|res| res)
+                .map_err(|e| e.to_string())
+        }
+        ["pause-janitor"] => {
+            keeper.pause_janitor();
+            Ok(())
+        }
+        ["resume-janitor"] => {
+            keeper.resume_janitor();
+            Ok(())
+        }
+        ["set-interval", seconds] => {
+            let seconds: u64 = seconds
+                .parse()
+                .map_err(|_| format!("invalid interval `{seconds}`"))?;
+            keeper.set_cleanup_schedule(Schedule::Interval(Duration::from_secs(seconds.max(1))));
+            Ok(())
+        }
+        _ => Err(format!("unknown command `{command}`")),
+    }
+}
+
+/// Sends `command` to the control socket at `path` and returns the reply,
+/// `OK` or `ERR <reason>`.
+pub fn request(path: &Path, command: &str) -> io::Result<String> {
+    let mut stream = UnixStream::connect(path)?;
+    stream.write_all(format!("{command}\n").as_bytes())?;
+    stream.shutdown(std::net::Shutdown::Write)?;
+
+    let mut reply = String::new();
+    BufReader::new(stream).read_line(&mut reply)?;
+    Ok(reply.trim_end().to_string())
+}
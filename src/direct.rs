@@ -0,0 +1,119 @@
+use std::{fs::File, io};
+
+use crate::utils::read_at;
+
+/// Covers the logical block size of virtually every device.
+const ALIGN: usize = 4096;
+
+/// Writes `data` at the start of `file` and truncates it to `data.len()`,
+/// bypassing the page cache where the platform and filesystem allow it. Direct
+/// I/O needs aligned buffers, so `data` is copied into a padded one first.
+pub fn write(file: &File, data: &[u8]) -> io::Result<()> {
+    let len = data.len();
+    if set_direct(file, true).is_ok() {
+        let mut buffer = AlignedBuffer::new(len);
+        buffer.as_mut()[..len].copy_from_slice(data);
+
+        match write_all_at(file, buffer.as_mut(), 0) {
+            Ok(()) => return file.set_len(len as u64),
+            Err(e) if e.kind() != io::ErrorKind::InvalidInput => return Err(e),
+            Err(_) => set_direct(file, false)?,
+        }
+    }
+
+    write_all_at(file, data, 0)?;
+    file.set_len(len as u64)
+}
+
+/// Reads the `len` bytes of `file`, bypassing the page cache when possible.
+pub fn read(file: &File, len: u64) -> io::Result<Vec<u8>> {
+    let len = len as usize;
+    if set_direct(file, true).is_ok() {
+        let mut buffer = AlignedBuffer::new(len);
+        match read_at_least(file, buffer.as_mut(), len) {
+            Ok(n) => return Ok(buffer.as_mut()[..n.min(len)].to_vec()),
+            Err(e) if e.kind() != io::ErrorKind::InvalidInput => return Err(e),
+            Err(_) => set_direct(file, false)?,
+        }
+    }
+
+    let mut buffer = vec![0; len];
+    let n = read_at_least(file, &mut buffer, len)?;
+    buffer.truncate(n);
+    Ok(buffer)
+}
+
+/// A zeroed buffer whose start is aligned and whose length is rounded up to
+/// [`ALIGN`].
+struct AlignedBuffer {
+    raw: Vec<u8>,
+    offset: usize,
+    len: usize,
+}
+
+impl AlignedBuffer {
+    fn new(len: usize) -> Self {
+        let len = len.div_ceil(ALIGN).max(1) * ALIGN;
+        let raw = vec![0u8; len + ALIGN];
+        let offset = raw.as_ptr().align_offset(ALIGN);
+        Self { raw, offset, len }
+    }
+
+    fn as_mut(&mut self) -> &mut [u8] {
+        &mut self.raw[self.offset..self.offset + self.len]
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn set_direct(file: &File, enabled: bool) -> io::Result<()> {
+    use std::os::fd::AsRawFd;
+
+    let fd = file.as_raw_fd();
+    let flags = unsafe { libc::fcntl(fd, libc::F_GETFL) };
+    if flags < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    let flags = match enabled {
+        true => flags | libc::O_DIRECT,
+        false => flags & !libc::O_DIRECT,
+    };
+    match unsafe { libc::fcntl(fd, libc::F_SETFL, flags) } {
+        0 => Ok(()),
+        _ => Err(io::Error::last_os_error()),
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn set_direct(_file: &File, _enabled: bool) -> io::Result<()> {
+    Err(io::ErrorKind::Unsupported.into())
+}
+
+#[cfg(unix)]
+fn write_all_at(file: &File, data: &[u8], offset: u64) -> io::Result<()> {
+    use std::os::unix::fs::FileExt;
+    file.write_all_at(data, offset)
+}
+
+#[cfg(not(unix))]
+fn write_all_at(mut file: &File, data: &[u8], offset: u64) -> io::Result<()> {
+    use std::io::{Seek, SeekFrom, Write};
+    file.seek(SeekFrom::Start(offset))?;
+    file.write_all(data)
+}
+
+/// Reads from the start of `file` into `buffer` until `want` bytes arrived or
+/// the file ended. Stopping there matters for direct I/O, where a read from
+/// the unaligned offset after the last block would fail.
+fn read_at_least(file: &File, buffer: &mut [u8], want: usize) -> io::Result<usize> {
+    let mut filled = 0;
+    while filled < want {
+        match read_at(file, &mut buffer[filled..], filled as u64) {
+            Ok(0) => break,
+            Ok(n) => filled += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(filled)
+}
@@ -0,0 +1,111 @@
+use std::{
+    io,
+    path::{Path, PathBuf},
+    sync::{Condvar, Mutex},
+};
+
+#[derive(Debug, Default)]
+struct State {
+    /// Tickets handed out to writers waiting for a sync.
+    requested: u64,
+    /// Every ticket up to this one is covered by a finished sync.
+    completed: u64,
+    syncing: bool,
+    /// Tickets in `(from, to]` were covered by a sync that failed.
+    failed: Option<(u64, u64, io::ErrorKind)>,
+}
+
+/// Makes writes durable before they are acknowledged, letting concurrent
+/// writers share a sync (group commit). The first writer to arrive syncs on
+/// behalf of everyone who wrote before it started; writers arriving meanwhile
+/// wait for the next round, which covers all of them at once.
+#[derive(Debug)]
+pub struct GroupCommit {
+    /// Every filesystem the store's folders live on.
+    roots: Vec<PathBuf>,
+    state: Mutex<State>,
+    synced: Condvar,
+}
+
+impl GroupCommit {
+    pub fn new(roots: Vec<PathBuf>) -> Self {
+        Self {
+            roots,
+            state: Mutex::new(State::default()),
+            synced: Condvar::new(),
+        }
+    }
+
+    /// Returns once everything written before the call, including `paths`,
+    /// reached the disk. On Linux one `syncfs` of each of the store's
+    /// filesystems covers every waiting writer; elsewhere `paths`, files and the directories
+    /// holding them, are synced one by one.
+    pub fn sync(&self, paths: &[&Path]) -> io::Result<()> {
+        if !cfg!(target_os = "linux") {
+            return sync_paths(paths);
+        }
+
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        state.requested += 1;
+        let ticket = state.requested;
+
+        loop {
+            if let Some((from, to, kind)) = state.failed
+                && from < ticket
+                && ticket <= to
+            {
+                return Err(io::Error::new(kind, "group sync failed"));
+            }
+            if state.completed >= ticket {
+                return Ok(());
+            }
+
+            if !state.syncing {
+                state.syncing = true;
+                let (from, to) = (state.completed, state.requested);
+                drop(state);
+
+                let res = self.roots.iter().try_for_each(|root| syncfs(root));
+
+                state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+                state.syncing = false;
+                state.completed = to;
+                if let Err(e) = &res {
+                    state.failed = Some((from, to, e.kind()));
+                }
+                self.synced.notify_all();
+                return res;
+            }
+
+            state = self.synced.wait(state).unwrap_or_else(|e| e.into_inner());
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn syncfs(root: &Path) -> io::Result<()> {
+    use std::os::fd::AsRawFd;
+
+    let dir = std::fs::File::open(root)?;
+    match unsafe { libc::syncfs(dir.as_raw_fd()) } {
+        0 => Ok(()),
+        _ => Err(io::Error::last_os_error()),
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn syncfs(_root: &Path) -> io::Result<()> {
+    Ok(())
+}
+
+fn sync_paths(paths: &[&Path]) -> io::Result<()> {
+    for path in paths {
+        match std::fs::File::open(path) {
+            Ok(file) => file.sync_all()?,
+            // Directories can't be opened on every platform.
+            Err(_) if path.is_dir() => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,102 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::SystemTime,
+};
+
+use crate::{context::Context, entry};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EntryInfo {
+    /// Only known for entries written with a format that records keys.
+    pub key: Option<String>,
+    pub version: u16,
+    /// Seconds since the Unix epoch, `0` when the entry never expires.
+    pub expires_at: u64,
+    pub size: u64,
+    pub modified: Option<SystemTime>,
+}
+
+/// Iterates over the entries of the store as `(hash, info)`, reading one shard
+/// at a time under its read lock. The file system is accessed on the calling
+/// thread as the iterator advances.
+#[derive(Debug)]
+pub struct Entries {
+    ctx: Arc<Context>,
+    root: Arc<PathBuf>,
+    next_shard: u32,
+    shard: std::vec::IntoIter<(String, EntryInfo)>,
+}
+
+impl Entries {
+    pub(crate) fn new(ctx: Arc<Context>, root: Arc<PathBuf>) -> Self {
+        Self {
+            ctx,
+            root,
+            next_shard: 0,
+            shard: Vec::new().into_iter(),
+        }
+    }
+}
+
+/// Reads the entries of one shard folder under its read locks.
+pub(crate) fn read_shard(ctx: &Context, root: &Path, folder: u16) -> Vec<(String, EntryInfo)> {
+    let _locks = ctx.shards.read_folder(folder);
+    read_folder(ctx, root, folder)
+}
+
+/// Like [`read_shard`], for callers already holding the folder's locks.
+pub(crate) fn read_folder(ctx: &Context, root: &Path, folder: u16) -> Vec<(String, EntryInfo)> {
+    let folder_name = format!("{folder:03x}");
+    let Ok(files) = std::fs::read_dir(root.join(&folder_name)) else {
+        return Vec::new();
+    };
+
+    let mut entries = Vec::new();
+    for file in files.flatten() {
+        let name = file.file_name();
+        let name = name.to_string_lossy();
+        if name.len() != 29 || !name.bytes().all(|b| b.is_ascii_hexdigit()) {
+            continue;
+        }
+
+        let metadata = file.metadata().ok();
+        if metadata.as_ref().is_some_and(|m| ctx.is_cleared(m)) {
+            continue;
+        }
+        let Ok(header) = entry::read_header(&file.path()) else {
+            continue;
+        };
+
+        entries.push((
+            format!("{folder_name}{name}"),
+            EntryInfo {
+                key: header.key,
+                version: header.version,
+                expires_at: header.expires_at,
+                size: metadata.as_ref().map(|m| m.len()).unwrap_or(0),
+                modified: metadata.and_then(|m| m.modified().ok()),
+            },
+        ));
+    }
+    entries
+}
+
+impl Iterator for Entries {
+    type Item = (String, EntryInfo);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(item) = self.shard.next() {
+                return Some(item);
+            }
+            if self.next_shard >= 4096 {
+                return None;
+            }
+
+            let shard_id = self.next_shard as u16;
+            self.next_shard += 1;
+            self.shard = read_shard(&self.ctx, &self.root, shard_id).into_iter();
+        }
+    }
+}
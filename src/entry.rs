@@ -0,0 +1,350 @@
+use std::{io::Read, path::Path};
+
+use crate::{
+    error::Error,
+    mac::{Mac, SigningKey, TAG_LEN},
+    utils::{Crc32, crc32},
+};
+
+/// Original layout: `[u16 0][u64 expires_at][payload]`.
+pub const V1: u16 = 1;
+/// `[u16 2][u64 expires_at][u32 crc32][u16 key_len][key][payload]`, where the
+/// checksum covers the key and the payload. A `key_len` of 0 means the key is
+/// unknown (entries migrated from v1).
+pub const V2: u16 = 2;
+/// `[u16 3][u64 expires_at][32 byte tag][u16 key_len][key][payload]`, written
+/// when the store has a signing key. The tag is an HMAC-SHA256 of everything
+/// else in the file.
+pub const V3: u16 = 3;
+pub const CURRENT: u16 = V2;
+
+/// Set in the version tag of [`V2`] and [`V3`] entries whose payload went
+/// through codecs and starts with their ids, see [`crate::codec::Codecs`].
+pub const CODECS: u16 = 1 << 8;
+/// The high byte of the version tag is reserved for flags.
+const FLAGS: u16 = 0xff00;
+
+/// Bytes every version shares: version tag followed by the expiration time.
+pub const PREFIX_LEN: usize = 10;
+
+/// Longest key, in bytes, that fits `key_len`.
+pub const MAX_KEY_LEN: usize = u16::MAX as usize;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Header {
+    pub version: u16,
+    pub expires_at: u64,
+    pub key: Option<String>,
+    pub flags: u16,
+}
+
+/// Splits a version tag into the version and its flags. [`V1`] entries can't
+/// have flags.
+fn split_tag(tag: u16) -> Option<(u16, u16)> {
+    match (tag & !FLAGS, tag & FLAGS) {
+        (0, 0) => Some((V1, 0)),
+        (version @ (V2 | V3), flags) if flags & !CODECS == 0 => Some((version, flags)),
+        _ => None,
+    }
+}
+
+pub fn is_known_tag(prefix: &[u8; PREFIX_LEN]) -> bool {
+    split_tag(u16::from_be_bytes([prefix[0], prefix[1]])).is_some()
+}
+
+pub fn expires_at(prefix: &[u8; PREFIX_LEN]) -> u64 {
+    u64::from_be_bytes(prefix[2..10].try_into().unwrap())
+}
+
+pub fn decode(buffer: &[u8]) -> Result<(Header, &[u8]), Error> {
+    if buffer.len() < PREFIX_LEN {
+        return Err(Error::InvalidData);
+    }
+
+    let tag = u16::from_be_bytes([buffer[0], buffer[1]]);
+    let (version, flags) = split_tag(tag).ok_or(Error::InvalidData)?;
+    let expires_at = expires_at(buffer[..PREFIX_LEN].try_into().unwrap());
+
+    match version {
+        V1 => Ok((
+            Header {
+                version: V1,
+                expires_at,
+                key: None,
+                flags,
+            },
+            &buffer[PREFIX_LEN..],
+        )),
+        V2 => {
+            let rest = &buffer[PREFIX_LEN..];
+            if rest.len() < 6 {
+                return Err(Error::InvalidData);
+            }
+
+            let checksum = u32::from_be_bytes(rest[0..4].try_into().unwrap());
+            let key_len = u16::from_be_bytes([rest[4], rest[5]]) as usize;
+            let rest = &rest[6..];
+            if rest.len() < key_len {
+                return Err(Error::InvalidData);
+            }
+
+            let (key, payload) = rest.split_at(key_len);
+            if crc32(&[key, payload]) != checksum {
+                return Err(Error::InvalidData);
+            }
+
+            let key = match key_len {
+                0 => None,
+                _ => Some(String::from_utf8(key.to_vec()).map_err(|_| Error::InvalidData)?),
+            };
+
+            Ok((
+                Header {
+                    version: V2,
+                    expires_at,
+                    key,
+                    flags,
+                },
+                payload,
+            ))
+        }
+        V3 => {
+            let (header, payload, _) = split_signed(buffer, expires_at, flags)?;
+            Ok((header, payload))
+        }
+        _ => Err(Error::InvalidData),
+    }
+}
+
+/// Like [`decode`], but only accepts signed entries whose tag matches `key`.
+pub fn decode_signed<'a>(buffer: &'a [u8], key: &SigningKey) -> Result<(Header, &'a [u8]), Error> {
+    let tag = (buffer.len() >= PREFIX_LEN)
+        .then(|| split_tag(u16::from_be_bytes([buffer[0], buffer[1]])))
+        .flatten();
+    let Some((V3, flags)) = tag else {
+        return Err(Error::TamperDetected);
+    };
+
+    let expires_at = expires_at(buffer[..PREFIX_LEN].try_into().unwrap());
+    let (header, payload, tag) = split_signed(buffer, expires_at, flags)?;
+    match key.verify(
+        &[&buffer[..PREFIX_LEN], &buffer[PREFIX_LEN + TAG_LEN..]],
+        tag,
+    ) {
+        true => Ok((header, payload)),
+        false => Err(Error::TamperDetected),
+    }
+}
+
+fn split_signed(
+    buffer: &[u8],
+    expires_at: u64,
+    flags: u16,
+) -> Result<(Header, &[u8], &[u8]), Error> {
+    let rest = &buffer[PREFIX_LEN..];
+    if rest.len() < TAG_LEN + 2 {
+        return Err(Error::InvalidData);
+    }
+
+    let (tag, rest) = rest.split_at(TAG_LEN);
+    let key_len = u16::from_be_bytes([rest[0], rest[1]]) as usize;
+    let rest = &rest[2..];
+    if rest.len() < key_len {
+        return Err(Error::InvalidData);
+    }
+
+    let (key, payload) = rest.split_at(key_len);
+    let key = String::from_utf8(key.to_vec()).map_err(|_| Error::InvalidData)?;
+    Ok((
+        Header {
+            version: V3,
+            expires_at,
+            key: Some(key),
+            flags,
+        },
+        payload,
+        tag,
+    ))
+}
+
+/// Encodes a [`V3`] entry signed with `signing_key`. Fails with
+/// [`Error::KeyTooLong`] for keys longer than [`MAX_KEY_LEN`].
+pub fn encode_signed(
+    flags: u16,
+    expires_at: u64,
+    key: &str,
+    payload: &[u8],
+    signing_key: &SigningKey,
+) -> Result<Vec<u8>, Error> {
+    let key = key_bytes(key)?;
+
+    let mut buf = Vec::with_capacity(PREFIX_LEN + TAG_LEN + 2 + key.len() + payload.len());
+    buf.extend_from_slice(&(V3 | flags).to_be_bytes());
+    buf.extend_from_slice(&expires_at.to_be_bytes());
+    buf.extend_from_slice(&[0; TAG_LEN]);
+    buf.extend_from_slice(&(key.len() as u16).to_be_bytes());
+    buf.extend_from_slice(key);
+    buf.extend_from_slice(payload);
+
+    let tag = signing_key.sign(&[&buf[..PREFIX_LEN], &buf[PREFIX_LEN + TAG_LEN..]]);
+    buf[PREFIX_LEN..PREFIX_LEN + TAG_LEN].copy_from_slice(&tag);
+    Ok(buf)
+}
+
+/// `flags` are dropped for [`V1`], which has no room for them, and so is the
+/// key. Fails with [`Error::KeyTooLong`] for keys longer than
+/// [`MAX_KEY_LEN`] otherwise.
+pub fn encode(
+    version: u16,
+    flags: u16,
+    expires_at: u64,
+    key: Option<&str>,
+    payload: &[u8],
+) -> Result<Vec<u8>, Error> {
+    match version {
+        V1 => {
+            let mut buf = Vec::with_capacity(PREFIX_LEN + payload.len());
+            buf.extend_from_slice(&0u16.to_be_bytes());
+            buf.extend_from_slice(&expires_at.to_be_bytes());
+            buf.extend_from_slice(payload);
+            Ok(buf)
+        }
+        _ => {
+            let key = key.map(key_bytes).transpose()?.unwrap_or_default();
+
+            let mut buf = Vec::with_capacity(PREFIX_LEN + 6 + key.len() + payload.len());
+            buf.extend_from_slice(&(V2 | flags).to_be_bytes());
+            buf.extend_from_slice(&expires_at.to_be_bytes());
+            buf.extend_from_slice(&crc32(&[key, payload]).to_be_bytes());
+            buf.extend_from_slice(&(key.len() as u16).to_be_bytes());
+            buf.extend_from_slice(key);
+            buf.extend_from_slice(payload);
+            Ok(buf)
+        }
+    }
+}
+
+fn key_bytes(key: &str) -> Result<&[u8], Error> {
+    match key.len() > MAX_KEY_LEN {
+        true => Err(Error::KeyTooLong(key.len())),
+        false => Ok(key.as_bytes()),
+    }
+}
+
+/// Encodes an entry whose payload is streamed rather than held in memory. The
+/// header is written first with a blank checksum, or tag when signing; the
+/// payload is passed through [`StreamEncoder::update`] as it is written, and
+/// [`StreamEncoder::finish`] returns the checksum to write at [`PREFIX_LEN`].
+pub struct StreamEncoder {
+    header: Vec<u8>,
+    checksum: Checksum,
+}
+
+enum Checksum {
+    Crc(Crc32),
+    Mac(Box<Mac>),
+}
+
+impl StreamEncoder {
+    /// Fails with [`Error::KeyTooLong`] for keys longer than [`MAX_KEY_LEN`].
+    pub fn new(
+        expires_at: u64,
+        key: &str,
+        signing_key: Option<&SigningKey>,
+    ) -> Result<Self, Error> {
+        let key = key_bytes(key)?;
+
+        let (version, blank) = match signing_key {
+            Some(_) => (V3, TAG_LEN),
+            None => (V2, 4),
+        };
+        let mut header = Vec::with_capacity(PREFIX_LEN + blank + 2 + key.len());
+        header.extend_from_slice(&version.to_be_bytes());
+        header.extend_from_slice(&expires_at.to_be_bytes());
+        header.resize(PREFIX_LEN + blank, 0);
+        header.extend_from_slice(&(key.len() as u16).to_be_bytes());
+        header.extend_from_slice(key);
+
+        let checksum = match signing_key {
+            Some(signing_key) => {
+                let mut mac = signing_key.begin();
+                mac.update(&header[..PREFIX_LEN]);
+                mac.update(&header[PREFIX_LEN + TAG_LEN..]);
+                Checksum::Mac(Box::new(mac))
+            }
+            None => {
+                let mut crc = Crc32::default();
+                crc.update(key);
+                Checksum::Crc(crc)
+            }
+        };
+
+        Ok(Self { header, checksum })
+    }
+
+    pub fn header(&self) -> &[u8] {
+        &self.header
+    }
+
+    pub fn update(&mut self, payload: &[u8]) {
+        match &mut self.checksum {
+            Checksum::Crc(crc) => crc.update(payload),
+            Checksum::Mac(mac) => mac.update(payload),
+        }
+    }
+
+    pub fn finish(self) -> Vec<u8> {
+        match self.checksum {
+            Checksum::Crc(crc) => crc.finish().to_be_bytes().to_vec(),
+            Checksum::Mac(mac) => mac.finish().to_vec(),
+        }
+    }
+}
+
+/// Reads only the header of the entry at `path`, without verifying the
+/// checksum or signature.
+pub fn read_header(path: &Path) -> Result<Header, Error> {
+    read_header_from(std::fs::File::open(path)?)
+}
+
+/// Like [`read_header`], reading from the start of `file` without touching the
+/// rest of the entry.
+pub fn read_header_from(mut file: impl Read) -> Result<Header, Error> {
+    let mut prefix = [0u8; PREFIX_LEN];
+    file.read_exact(&mut prefix)
+        .map_err(|_| Error::InvalidData)?;
+
+    let expires_at = expires_at(&prefix);
+    let (version, flags) =
+        split_tag(u16::from_be_bytes([prefix[0], prefix[1]])).ok_or(Error::InvalidData)?;
+    match version {
+        V1 => Ok(Header {
+            version: V1,
+            expires_at,
+            key: None,
+            flags,
+        }),
+        _ => {
+            let mut meta = [0u8; TAG_LEN + 2];
+            let meta = match version {
+                V2 => &mut meta[..6],
+                _ => &mut meta[..],
+            };
+            file.read_exact(meta).map_err(|_| Error::InvalidData)?;
+
+            let key_len = u16::from_be_bytes([meta[meta.len() - 2], meta[meta.len() - 1]]);
+            let mut key = vec![0u8; key_len as usize];
+            file.read_exact(&mut key).map_err(|_| Error::InvalidData)?;
+
+            Ok(Header {
+                version,
+                expires_at,
+                key: match key.is_empty() {
+                    true => None,
+                    false => Some(String::from_utf8(key).map_err(|_| Error::InvalidData)?),
+                },
+                flags,
+            })
+        }
+    }
+}
@@ -8,6 +8,73 @@ pub enum Error {
     NotFound,
     #[error("cache data is invalid or corrupted")]
     InvalidData,
-    #[error("worker response channel closed")]
-    WorkerClosed,
+    #[error("incompatible store: {0}")]
+    IncompatibleStore(String),
+    #[error("invalid namespace name `{0}`")]
+    InvalidNamespace(String),
+    #[error("quota of namespace `{0}` exceeded")]
+    QuotaExceeded(String),
+    #[error("value exceeds the size limit of namespace `{0}`")]
+    ValueTooLarge(String),
+    /// The key is longer than [`crate::entry::MAX_KEY_LEN`] bytes.
+    #[error("key is {0} bytes long, more than entries can hold")]
+    KeyTooLong(usize),
+    #[error("invalid schedule `{0}`")]
+    InvalidSchedule(String),
+    #[error("unrecognized file moved to {0}")]
+    Quarantined(std::path::PathBuf),
+    #[error("store was cleared while the operation was in flight")]
+    Cleared,
+    #[error("entry failed signature verification")]
+    TamperDetected,
+    #[error("entry was encoded with unknown codec {0}")]
+    UnknownCodec(u8),
+    #[error("loader circuit open for key `{0}`")]
+    CircuitOpen(String),
+    /// The loader didn't produce the value in time, see
+    /// [`crate::keeper::KeeperBuilder::with_load_timeout`].
+    #[error("loader timed out for key `{0}`")]
+    LoadTimeout(String),
+    #[error("operation would block")]
+    WouldBlock,
+    /// The keeper was shut down, or is shutting down, before the request was
+    /// handled. Retrying on the same keeper won't help.
+    #[error("keeper is shutting down")]
+    ShuttingDown,
+    /// A worker panicked while handling the request, or died before it could
+    /// answer. The store is still usable; the request may be retried.
+    #[error("{0} panicked while handling the request")]
+    WorkerPanicked(Worker),
+    /// The store queue already held as many requests as
+    /// [`crate::keeper::KeeperBuilder::with_max_queue_len`] allows, so the
+    /// request was rejected without being queued.
+    #[error("store queue is full ({0} pending requests)")]
+    QueueFull(usize),
+    /// The store bypasses the disk after repeated IO failures, see
+    /// [`crate::keeper::KeeperBuilder::with_degradation`], and the request
+    /// needs what is stored.
+    #[error("store is bypassing the disk after repeated IO failures")]
+    DiskBypassed,
+    /// The request's deadline passed before a store worker picked it up, see
+    /// [`crate::keeper::Keeper::with_deadline`].
+    #[error("request deadline passed before it was handled")]
+    Timeout,
+}
+
+/// The background thread an error comes from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Worker {
+    Store,
+    Janitor,
+    Loader,
+}
+
+impl std::fmt::Display for Worker {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Store => "store worker",
+            Self::Janitor => "janitor",
+            Self::Loader => "loader",
+        })
+    }
 }
@@ -0,0 +1,184 @@
+use std::{
+    path::Path,
+    time::{Duration, SystemTime},
+};
+
+use crate::{
+    context::Context,
+    entries::{EntryInfo, read_shard},
+    manifest::FANOUT,
+};
+
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct Estimate {
+    pub entries: u64,
+    pub bytes: u64,
+    /// Fraction of the entries that already expired, from `0.0` to `1.0`.
+    pub expired_ratio: f64,
+    pub sampled_shards: usize,
+    pub sampled_entries: u64,
+}
+
+/// Reads `sample_size` shards picked at random and extrapolates their totals
+/// to the whole store. Sampling every shard gives exact figures.
+pub fn estimate(ctx: &Context, root: &Path, sample_size: usize) -> Estimate {
+    let sample_size = sample_size.clamp(1, FANOUT);
+    let now = ctx.now();
+
+    let mut rng = Rng::new();
+    let mut shards: Vec<u16> = (0..FANOUT as u16).collect();
+    let (mut entries, mut bytes, mut expired) = (0u64, 0u64, 0u64);
+    for i in 0..sample_size {
+        rng.pick(&mut shards, i);
+
+        for (_, info) in read_shard(ctx, root, shards[i]) {
+            entries += 1;
+            bytes += info.size;
+            if ctx.is_expired(info.expires_at, now) {
+                expired += 1;
+            }
+        }
+    }
+
+    let scale = FANOUT as f64 / sample_size as f64;
+    Estimate {
+        entries: (entries as f64 * scale).round() as u64,
+        bytes: (bytes as f64 * scale).round() as u64,
+        expired_ratio: match entries {
+            0 => 0.0,
+            n => expired as f64 / n as f64,
+        },
+        sampled_shards: sample_size,
+        sampled_entries: entries,
+    }
+}
+
+/// Picks up to `n` entries at random, as `(hash, info)`, by reading shard
+/// folders in random order. Entries are spread over as many folders as
+/// possible; only when the folders read don't hold enough are more taken
+/// from each.
+pub fn sample(ctx: &Context, root: &Path, n: usize) -> Vec<(String, EntryInfo)> {
+    let per_folder = n.div_ceil(64).max(1);
+    let mut rng = Rng::new();
+    let mut shards: Vec<u16> = (0..FANOUT as u16).collect();
+
+    let mut sampled = Vec::with_capacity(n);
+    let mut spare = Vec::new();
+    for i in 0..FANOUT {
+        if sampled.len() >= n {
+            break;
+        }
+        rng.pick(&mut shards, i);
+
+        let mut entries = read_shard(ctx, root, shards[i]);
+        for k in 0..entries.len() {
+            rng.pick(&mut entries, k);
+        }
+        let taken = per_folder.min(entries.len()).min(n - sampled.len());
+        spare.extend(entries.drain(taken..));
+        sampled.append(&mut entries);
+    }
+
+    while sampled.len() < n && !spare.is_empty() {
+        rng.pick(&mut spare, 0);
+        sampled.push(spare.swap_remove(0));
+    }
+    sampled
+}
+
+/// Xorshift seeded from the clock, plenty for spreading samples.
+struct Rng(u64);
+
+impl Rng {
+    fn new() -> Self {
+        let seed = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map(|d| d.as_nanos() as u64)
+            .unwrap_or(0);
+        Self(seed | 1)
+    }
+
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    /// One step of a partial Fisher-Yates shuffle: moves an item picked at
+    /// random from `items[i..]` to `i`.
+    fn pick<T>(&mut self, items: &mut [T], i: usize) {
+        let j = i + (self.next() % (items.len() - i) as u64) as usize;
+        items.swap(i, j);
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct ExpiryBucket {
+    /// Entries counted expire within this long from now.
+    pub within: Duration,
+    pub entries: u64,
+    pub bytes: u64,
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct ExpiryForecast {
+    /// Entries that already expired but weren't removed yet, with `within`
+    /// zero.
+    pub overdue: ExpiryBucket,
+    /// One bucket per requested window, in the order given. Windows overlap:
+    /// an entry expiring within a minute also counts towards an hour.
+    pub buckets: Vec<ExpiryBucket>,
+}
+
+/// Counts the entries and bytes that expire within each of `windows` from
+/// now, reading every shard. Folders whose janitor horizon lies past the
+/// longest window are skipped without being read, so forecasts over short
+/// windows are cheap on stores the janitor swept.
+pub fn forecast(ctx: &Context, root: &Path, windows: &[Duration]) -> ExpiryForecast {
+    let now = ctx.now();
+    let mut forecast = ExpiryForecast {
+        buckets: windows
+            .iter()
+            .map(|&within| ExpiryBucket {
+                within,
+                ..Default::default()
+            })
+            .collect(),
+        ..Default::default()
+    };
+    let Some(longest) = windows.iter().max() else {
+        return forecast;
+    };
+    let horizon = now.saturating_add(longest.as_secs());
+
+    for folder in 0..FANOUT as u16 {
+        if ctx
+            .horizons
+            .earliest(folder)
+            .is_some_and(|earliest| earliest > horizon)
+        {
+            continue;
+        }
+
+        for (_, info) in read_shard(ctx, root, folder) {
+            if info.expires_at == 0 {
+                continue;
+            }
+            if ctx.is_expired(info.expires_at, now) {
+                forecast.overdue.entries += 1;
+                forecast.overdue.bytes += info.size;
+                continue;
+            }
+
+            let left = info.expires_at.saturating_sub(now);
+            for bucket in &mut forecast.buckets {
+                if left <= bucket.within.as_secs() {
+                    bucket.entries += 1;
+                    bucket.bytes += info.size;
+                }
+            }
+        }
+    }
+    forecast
+}
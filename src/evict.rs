@@ -0,0 +1,212 @@
+use std::{io::ErrorKind, path::Path, time::SystemTime};
+
+use crate::{
+    context::Context,
+    entry,
+    eviction::EvictionCause,
+    janitor,
+    manifest::FANOUT,
+    namespace::{Usage, namespace_of},
+    stats::Counters,
+    tenant,
+};
+
+pub fn is_storage_full(e: &std::io::Error) -> bool {
+    matches!(e.kind(), ErrorKind::StorageFull | ErrorKind::QuotaExceeded)
+}
+
+/// Frees space after a write failed because the disk is full: expired entries
+/// go first, then the least recently written ones until `needed` bytes were
+/// released. Shards that are currently locked, including the caller's own, are
+/// left untouched. Returns the number of bytes freed by the LRU pass.
+pub fn emergency(root: &Path, ctx: &Context, needed: u64) -> u64 {
+    janitor::cleanup(root, ctx);
+    lru(root, ctx, needed, false)
+}
+
+/// Brings the store back under its size and free space limits after the
+/// janitor was woken up by [`Pressure`](crate::pressure::Pressure).
+pub fn relieve(root: &Path, ctx: &Context) {
+    janitor::cleanup(root, ctx);
+
+    let excess = ctx.pressure.excess(root);
+    if excess > 0 {
+        lru(root, ctx, excess, true);
+    }
+    ctx.pressure.relieved();
+}
+
+/// Removes the least recently written entries until `needed` bytes were freed,
+/// tenants included, or `needed` in weight when `weighed`, see
+/// [`Context::weigh`]. With access tracking, reads count as writes.
+pub fn lru(root: &Path, ctx: &Context, needed: u64, weighed: bool) -> u64 {
+    let mut candidates = Vec::new();
+    let folders = tenant::roots(root)
+        .into_iter()
+        .filter_map(|root| std::fs::read_dir(root).ok())
+        .flatten();
+
+    for folder in folders.flatten() {
+        let Ok(folder_id) = u16::from_str_radix(&folder.file_name().to_string_lossy(), 16) else {
+            continue;
+        };
+
+        let Ok(files) = std::fs::read_dir(folder.path()) else {
+            continue;
+        };
+
+        for file in files.flatten() {
+            // Only live entries: temporary files of streamed writes are still
+            // to be renamed into place, and removing anything else wouldn't
+            // make room for the entries counted against the limit.
+            let name = file.file_name();
+            let name = name.to_string_lossy();
+            if name.ends_with(".tmp")
+                || name.len() != 29
+                || !name.bytes().all(|b| b.is_ascii_hexdigit())
+            {
+                continue;
+            }
+
+            let Ok(meta) = file.metadata() else {
+                continue;
+            };
+            if meta.is_file() && !ctx.is_cleared(&meta) {
+                let modified = meta.modified().unwrap_or(SystemTime::UNIX_EPOCH);
+                candidates.push((modified, folder_id, meta.len(), file.path()));
+            }
+        }
+    }
+
+    candidates.sort_unstable_by_key(|(modified, ..)| *modified);
+
+    let mut freed = 0;
+    for (_, folder_id, size, file_path) in candidates {
+        if freed >= needed {
+            break;
+        }
+
+        let Some(_locks) = ctx.shards.try_write_folder(folder_id) else {
+            continue;
+        };
+
+        let size = match weighed {
+            true => ctx.weigh_file(&file_path, size),
+            false => size,
+        };
+        match ctx.evict_entry(&file_path, EvictionCause::Capacity) {
+            Ok(()) => {
+                freed += size;
+                Counters::evicted(&ctx.counters.evicted_for_space, &ctx.counters.evictions);
+            }
+            Err(e) => ctx.hooks.report(&e.into()),
+        }
+    }
+
+    freed
+}
+
+/// Removes the least recently written entries of `namespace` until `excess`
+/// was freed, for namespaces that evict rather than reject writes over their
+/// quota. Every entry header under `root` and its tenants is read, and shards
+/// that are currently locked, including the caller's own, are left untouched.
+/// Returns whether enough was freed.
+pub fn namespace(root: &Path, ctx: &Context, namespace: &str, excess: Usage) -> bool {
+    let mut candidates = Vec::new();
+    for root in tenant::roots(root) {
+        for folder_id in 0..FANOUT as u16 {
+            let Ok(files) = std::fs::read_dir(root.join(format!("{folder_id:03x}"))) else {
+                continue;
+            };
+
+            for file in files.flatten() {
+                let Ok(meta) = file.metadata() else {
+                    continue;
+                };
+                if !meta.is_file() || ctx.is_cleared(&meta) {
+                    continue;
+                }
+
+                let path = file.path();
+                let in_namespace = entry::read_header(&path).is_ok_and(|h| {
+                    h.key
+                        .as_deref()
+                        .and_then(namespace_of)
+                        .is_some_and(|ns| ns == namespace)
+                });
+                if in_namespace {
+                    let modified = meta.modified().unwrap_or(SystemTime::UNIX_EPOCH);
+                    candidates.push((modified, folder_id, meta.len(), path));
+                }
+            }
+        }
+    }
+
+    candidates.sort_unstable_by_key(|(modified, ..)| *modified);
+
+    let mut freed = Usage::default();
+    for (_, folder_id, size, file_path) in candidates {
+        if freed.bytes >= excess.bytes && freed.entries >= excess.entries {
+            break;
+        }
+
+        let Some(_locks) = ctx.shards.try_write_folder(folder_id) else {
+            continue;
+        };
+
+        match ctx.evict_entry(&file_path, EvictionCause::Capacity) {
+            Ok(()) => {
+                freed.bytes += size;
+                freed.entries += 1;
+                Counters::evicted(&ctx.counters.evicted_for_space, &ctx.counters.evictions);
+            }
+            Err(e) => ctx.hooks.report(&e.into()),
+        }
+    }
+
+    freed.bytes >= excess.bytes && freed.entries >= excess.entries
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::mpsc::sync_channel,
+        time::{Duration, Instant},
+    };
+
+    use crate::keeper::KeeperBuilder;
+
+    #[test]
+    fn pressure_spares_temp_files() {
+        let path = std::env::temp_dir().join(format!("keeper-evict-tmp-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&path);
+
+        let keeper = KeeperBuilder::new(path.clone())
+            .with_max_store_size(1024)
+            .build()
+            .unwrap();
+
+        // Older than every entry, as a stream still being written would be.
+        std::fs::create_dir_all(path.join("000")).unwrap();
+        let tmp = path.join("000").join(format!("{}.0.tmp", "0".repeat(29)));
+        std::fs::write(&tmp, vec![0; 4096]).unwrap();
+        std::thread::sleep(Duration::from_millis(20));
+        for i in 0..64 {
+            let (tx, rx) = sync_channel(1);
+            keeper.dispatch_set(&format!("k{i}"), vec![0; 128], None, move |res| {
+                let _ = tx.send(res);
+            });
+            rx.recv_timeout(Duration::from_secs(10)).unwrap().unwrap();
+        }
+
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while keeper.stats().evicted_for_space == 0 && Instant::now() < deadline {
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        assert!(keeper.stats().evicted_for_space > 0);
+        assert!(tmp.exists());
+
+        drop(keeper);
+        let _ = std::fs::remove_dir_all(&path);
+    }
+}
@@ -0,0 +1,20 @@
+/// Why an entry was removed without an explicit `remove`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum EvictionCause {
+    /// Its expiration time passed.
+    Expired,
+    /// Space had to be freed, see [`crate::evict`].
+    Capacity,
+    /// It could not be decoded.
+    Corrupted,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Eviction {
+    /// Hex XXH3-128 hash of the key.
+    pub hash: String,
+    /// The key, when it is recorded in the entry.
+    pub key: Option<String>,
+    pub size: u64,
+    pub cause: EvictionCause,
+}
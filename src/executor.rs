@@ -0,0 +1,35 @@
+use std::{fmt, sync::Arc};
+
+/// A callback, bound to its result, ready to run.
+pub type Task = Box<dyn FnOnce() + Send + 'static>;
+
+/// Runs tasks somewhere other than the store worker that produced them, like
+/// a thread pool, a runtime or a channel drained by the application.
+pub type ExecuteFn = Box<dyn Fn(Task) + Send + Sync + 'static>;
+
+/// Where the callbacks of the callback API run, see
+/// [`crate::keeper::KeeperBuilder::with_callback_executor`].
+pub struct Executor(ExecuteFn);
+
+impl fmt::Debug for Executor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Executor").finish_non_exhaustive()
+    }
+}
+
+impl Executor {
+    pub fn new(execute: ExecuteFn) -> Self {
+        Self(execute)
+    }
+
+    /// Wraps `cb` so that calling it hands it, with its result, to the
+    /// executor instead of running it.
+    pub fn wrap<T, F>(self: &Arc<Self>, cb: F) -> Box<dyn FnOnce(T) + Send + Sync + 'static>
+    where
+        T: Send + 'static,
+        F: FnOnce(T) + Send + Sync + 'static,
+    {
+        let executor = self.clone();
+        Box::new(move |res| (executor.0)(Box::new(move || cb(res))))
+    }
+}
@@ -0,0 +1,119 @@
+use std::{
+    io,
+    sync::{
+        Mutex,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::Duration,
+};
+
+/// Makes entry reads and writes fail, stall or tear at random, so
+/// applications can exercise their fallback paths against a misbehaving
+/// store. Draws come from a generator seeded with `seed`, so a run with a
+/// single store worker sees the same faults every time.
+///
+/// Faults hit the store's entry files only: reads fail before the file is
+/// opened, writes before anything is written, and torn writes store a prefix
+/// of the entry, which then reads as damaged.
+#[derive(Debug)]
+pub struct FaultInjector {
+    read_errors: f64,
+    write_errors: f64,
+    torn_writes: f64,
+    delay: Option<(f64, Duration)>,
+    rng: Mutex<u64>,
+    injected: AtomicU64,
+}
+
+impl FaultInjector {
+    pub fn new(seed: u64) -> Self {
+        Self {
+            read_errors: 0.0,
+            write_errors: 0.0,
+            torn_writes: 0.0,
+            delay: None,
+            rng: Mutex::new(seed | 1),
+            injected: AtomicU64::new(0),
+        }
+    }
+
+    /// Fails reads with probability `p`.
+    pub fn with_read_errors(mut self, p: f64) -> Self {
+        self.read_errors = p;
+        self
+    }
+
+    /// Fails writes with probability `p`.
+    pub fn with_write_errors(mut self, p: f64) -> Self {
+        self.write_errors = p;
+        self
+    }
+
+    /// Stores only a prefix of the entry with probability `p`, as a crash
+    /// on a file system that reorders writes might.
+    pub fn with_torn_writes(mut self, p: f64) -> Self {
+        self.torn_writes = p;
+        self
+    }
+
+    /// Sleeps for `delay` before reads and writes with probability `p`.
+    pub fn with_delay(mut self, p: f64, delay: Duration) -> Self {
+        self.delay = Some((p, delay));
+        self
+    }
+
+    /// Faults injected so far, delays included.
+    pub fn injected(&self) -> u64 {
+        self.injected.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn read(&self) -> io::Result<()> {
+        self.stall();
+        match self.hit(self.read_errors) {
+            true => Err(io::Error::other("injected read fault")),
+            false => Ok(()),
+        }
+    }
+
+    pub(crate) fn write(&self) -> io::Result<()> {
+        self.stall();
+        match self.hit(self.write_errors) {
+            true => Err(io::Error::other("injected write fault")),
+            false => Ok(()),
+        }
+    }
+
+    /// How much of an entry of `len` bytes to store.
+    pub(crate) fn tear(&self, len: usize) -> usize {
+        match len > 0 && self.hit(self.torn_writes) {
+            true => (self.next() % len as u64) as usize,
+            false => len,
+        }
+    }
+
+    fn stall(&self) {
+        if let Some((p, delay)) = self.delay
+            && self.hit(p)
+        {
+            std::thread::sleep(delay);
+        }
+    }
+
+    fn hit(&self, p: f64) -> bool {
+        let draw = (self.next() >> 11) as f64 / (1u64 << 53) as f64;
+        let hit = p > 0.0 && draw < p;
+        if hit {
+            self.injected.fetch_add(1, Ordering::Relaxed);
+        }
+        hit
+    }
+
+    /// Xorshift, shared by every worker.
+    fn next(&self) -> u64 {
+        let mut state = self.rng.lock().unwrap_or_else(|e| e.into_inner());
+        *state ^= *state << 13;
+        *state ^= *state >> 7;
+        *state ^= *state << 17;
+        *state
+    }
+}
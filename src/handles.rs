@@ -0,0 +1,81 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs::File,
+    path::Path,
+    sync::{Arc, Mutex},
+};
+
+#[cfg(not(unix))]
+use crate::utils::retry_io;
+
+#[derive(Debug, Default)]
+struct Slots {
+    handles: HashMap<Vec<u8>, (Arc<File>, u64)>,
+    /// Handles by last use, oldest first.
+    order: BTreeMap<u64, Vec<u8>>,
+    tick: u64,
+}
+
+/// Keeps up to `capacity` entry files open so reading a hot key skips the
+/// `open`/`close` pair. Writers replace files through a rename, so a handle
+/// must be invalidated before its entry changes, like the memory tier. Only
+/// used on Unix, where a shared handle can be read at an offset.
+#[derive(Debug)]
+pub struct HandleCache {
+    capacity: usize,
+    slots: Mutex<Slots>,
+}
+
+impl HandleCache {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity: capacity.max(1),
+            slots: Mutex::new(Slots::default()),
+        }
+    }
+
+    /// Opens the entry at `path`, reusing the cached handle for `hash` if
+    /// there is one. Handles are shared, so reads must not move their cursor.
+    #[cfg(unix)]
+    pub fn open(&self, hash: &[u8], path: &Path) -> std::io::Result<Arc<File>> {
+        let mut slots = self.slots.lock().unwrap_or_else(|e| e.into_inner());
+        let slots = &mut *slots;
+        slots.tick += 1;
+        let tick = slots.tick;
+
+        if let Some((file, last)) = slots.handles.get_mut(hash) {
+            slots.order.remove(last);
+            slots.order.insert(tick, hash.to_vec());
+            *last = tick;
+            return Ok(file.clone());
+        }
+
+        let file = Arc::new(File::open(path)?);
+        if slots.handles.len() >= self.capacity
+            && let Some((_, oldest)) = slots.order.pop_first()
+        {
+            slots.handles.remove(&oldest);
+        }
+        slots.handles.insert(hash.to_vec(), (file.clone(), tick));
+        slots.order.insert(tick, hash.to_vec());
+        Ok(file)
+    }
+
+    #[cfg(not(unix))]
+    pub fn open(&self, _hash: &[u8], path: &Path) -> std::io::Result<Arc<File>> {
+        retry_io(|| File::open(path)).map(Arc::new)
+    }
+
+    pub fn invalidate(&self, hash: &[u8]) {
+        let mut slots = self.slots.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some((_, last)) = slots.handles.remove(hash) {
+            slots.order.remove(&last);
+        }
+    }
+
+    pub fn clear(&self) {
+        let mut slots = self.slots.lock().unwrap_or_else(|e| e.into_inner());
+        slots.handles.clear();
+        slots.order.clear();
+    }
+}
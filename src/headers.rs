@@ -0,0 +1,85 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::Mutex,
+};
+
+/// What [`crate::keeper::Keeper::head`] knows about an entry without reading
+/// its value.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Head {
+    /// Seconds since the Unix epoch, `0` when the entry never expires.
+    pub expires_at: u64,
+    /// Size of the entry file, header included.
+    pub size: u64,
+}
+
+#[derive(Debug, Default)]
+struct Slots {
+    heads: HashMap<Vec<u8>, (Head, u64)>,
+    /// Heads by last use, oldest first.
+    order: BTreeMap<u64, Vec<u8>>,
+    tick: u64,
+}
+
+/// Remembers the heads of up to `capacity` entries so checking hot keys
+/// doesn't touch the file system. Heads are inserted under the shard read
+/// lock and invalidated before their entry changes, like open handles.
+#[derive(Debug)]
+pub struct HeaderCache {
+    capacity: usize,
+    slots: Mutex<Slots>,
+}
+
+impl HeaderCache {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity: capacity.max(1),
+            slots: Mutex::new(Slots::default()),
+        }
+    }
+
+    pub fn get(&self, hash: &[u8]) -> Option<Head> {
+        let mut slots = self.slots.lock().unwrap_or_else(|e| e.into_inner());
+        let slots = &mut *slots;
+        slots.tick += 1;
+        let tick = slots.tick;
+
+        let (head, last) = slots.heads.get_mut(hash)?;
+        slots.order.remove(last);
+        slots.order.insert(tick, hash.to_vec());
+        *last = tick;
+        Some(*head)
+    }
+
+    /// Caches the head of the entry for `hash`. The caller must hold the
+    /// shard lock.
+    pub fn insert(&self, hash: &[u8], head: Head) {
+        let mut slots = self.slots.lock().unwrap_or_else(|e| e.into_inner());
+        let slots = &mut *slots;
+        slots.tick += 1;
+        let tick = slots.tick;
+
+        if let Some((_, last)) = slots.heads.remove(hash) {
+            slots.order.remove(&last);
+        } else if slots.heads.len() >= self.capacity
+            && let Some((_, oldest)) = slots.order.pop_first()
+        {
+            slots.heads.remove(&oldest);
+        }
+        slots.heads.insert(hash.to_vec(), (head, tick));
+        slots.order.insert(tick, hash.to_vec());
+    }
+
+    pub fn invalidate(&self, hash: &[u8]) {
+        let mut slots = self.slots.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some((_, last)) = slots.heads.remove(hash) {
+            slots.order.remove(&last);
+        }
+    }
+
+    pub fn clear(&self) {
+        let mut slots = self.slots.lock().unwrap_or_else(|e| e.into_inner());
+        slots.heads.clear();
+        slots.order.clear();
+    }
+}
@@ -0,0 +1,73 @@
+use std::fmt;
+
+use crate::{error::Error, eviction::Eviction, janitor::JanitorStatus, latency::SlowOp};
+
+pub type ErrorHook = Box<dyn Fn(&Error) + Send + Sync + 'static>;
+pub type SlowOpHook = Box<dyn Fn(&SlowOp) + Send + Sync + 'static>;
+pub type SweepHook = Box<dyn Fn(&JanitorStatus) + Send + Sync + 'static>;
+pub type EvictionHook = Box<dyn Fn(&Eviction) + Send + Sync + 'static>;
+
+#[derive(Default)]
+pub struct Hooks {
+    error: Option<ErrorHook>,
+    slow_op: Option<SlowOpHook>,
+    sweep: Option<SweepHook>,
+    eviction: Option<EvictionHook>,
+}
+
+impl fmt::Debug for Hooks {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Hooks")
+            .field("error", &self.error.is_some())
+            .field("slow_op", &self.slow_op.is_some())
+            .field("sweep", &self.sweep.is_some())
+            .field("eviction", &self.eviction.is_some())
+            .finish()
+    }
+}
+
+impl Hooks {
+    pub fn set_error(&mut self, hook: ErrorHook) {
+        self.error = Some(hook);
+    }
+
+    pub fn set_slow_op(&mut self, hook: SlowOpHook) {
+        self.slow_op = Some(hook);
+    }
+
+    pub fn report_slow_op(&self, op: &SlowOp) {
+        if let Some(hook) = &self.slow_op {
+            hook(op);
+        }
+    }
+
+    pub fn set_sweep(&mut self, hook: SweepHook) {
+        self.sweep = Some(hook);
+    }
+
+    pub fn report_sweep(&self, status: &JanitorStatus) {
+        if let Some(hook) = &self.sweep {
+            hook(status);
+        }
+    }
+
+    pub fn set_eviction(&mut self, hook: EvictionHook) {
+        self.eviction = Some(hook);
+    }
+
+    pub fn has_eviction(&self) -> bool {
+        self.eviction.is_some()
+    }
+
+    pub fn report_eviction(&self, eviction: &Eviction) {
+        if let Some(hook) = &self.eviction {
+            hook(eviction);
+        }
+    }
+
+    pub fn report(&self, err: &Error) {
+        if let Some(hook) = &self.error {
+            hook(err);
+        }
+    }
+}
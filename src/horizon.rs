@@ -0,0 +1,97 @@
+use std::{
+    io::ErrorKind,
+    path::Path,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use crate::permissions::Permissions;
+
+const FILE_NAME: &str = "HORIZONS";
+const FOLDERS: usize = 4096;
+
+/// Marks a folder whose entries weren't all seen, which must be swept.
+const UNKNOWN: u64 = 0;
+/// Marks a folder without any entry that expires.
+const NEVER: u64 = u64::MAX;
+
+/// The earliest expiration time of the entries of each shard folder, so the
+/// janitor can skip folders where nothing expired yet. Writers lower the
+/// horizon of a folder under its shard lock, and the janitor sets it after
+/// sweeping the folder with every lock of the folder held.
+///
+/// Horizons are only saved on shutdown, and the file is removed when the
+/// store opens, so after a crash every folder is swept again.
+#[derive(Debug)]
+pub struct Horizons {
+    folders: Box<[AtomicU64]>,
+}
+
+impl Horizons {
+    /// Loads the horizons saved by the last clean shutdown of the store at
+    /// `root`, every folder being unknown without them.
+    pub fn open(root: &Path) -> std::io::Result<Self> {
+        let path = root.join(FILE_NAME);
+        let saved = match std::fs::read(&path) {
+            Ok(saved) => saved,
+            Err(e) if e.kind() == ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(e),
+        };
+        if !saved.is_empty() {
+            std::fs::remove_file(&path)?;
+        }
+
+        let horizons = Self {
+            folders: (0..FOLDERS).map(|_| AtomicU64::new(UNKNOWN)).collect(),
+        };
+        if saved.len() == FOLDERS * 8 {
+            for (folder, bytes) in horizons.folders.iter().zip(saved.chunks_exact(8)) {
+                let bytes = bytes.try_into().unwrap_or_default();
+                folder.store(u64::from_le_bytes(bytes), Ordering::Relaxed);
+            }
+        }
+        Ok(horizons)
+    }
+
+    pub fn save(&self, root: &Path, permissions: &Permissions) -> std::io::Result<()> {
+        let bytes: Vec<u8> = self
+            .folders
+            .iter()
+            .flat_map(|folder| folder.load(Ordering::Acquire).to_le_bytes())
+            .collect();
+
+        let tmp = root.join(FILE_NAME).with_extension("tmp");
+        permissions.write(&tmp, &bytes)?;
+        std::fs::rename(tmp, root.join(FILE_NAME))
+    }
+
+    /// Records an entry expiring at `expires_at` in the folder of `shard_id`.
+    /// The caller must hold the shard write lock.
+    pub fn lower(&self, shard_id: u16, expires_at: u64) {
+        if expires_at != 0 {
+            self.folders[(shard_id >> 4) as usize].fetch_min(expires_at, Ordering::AcqRel);
+        }
+    }
+
+    /// The earliest expiration time in `folder`, [`u64::MAX`] when none of
+    /// its entries expire, or `None` when unknown.
+    pub fn earliest(&self, folder: u16) -> Option<u64> {
+        match self.folders[folder as usize].load(Ordering::Acquire) {
+            UNKNOWN => None,
+            earliest => Some(earliest),
+        }
+    }
+
+    /// Sets the horizon of `folder` after a sweep saw all of its entries,
+    /// `None` when none of them expire. The caller must hold every shard lock
+    /// of the folder.
+    pub fn set(&self, folder: u16, earliest: Option<u64>) {
+        self.folders[folder as usize].store(earliest.unwrap_or(NEVER), Ordering::Release);
+    }
+
+    /// Makes every folder unknown.
+    pub fn reset(&self) {
+        for folder in &self.folders {
+            folder.store(UNKNOWN, Ordering::Release);
+        }
+    }
+}
@@ -0,0 +1,485 @@
+use std::time::Duration;
+
+use crate::error::Error;
+
+/// A response as stored in the cache: the body plus what's needed to decide
+/// whether it can be served and how to revalidate it. Keys are built with
+/// [`primary_key`] and [`variant_key`], values with [`CachedResponse::encode`],
+/// and [`CachedResponse::ttl`] gives the `duration` to store them with.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CachedResponse {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+    /// When the response was received, in seconds since the Unix epoch.
+    pub stored_at: u64,
+}
+
+/// Only responses to these methods are cached.
+pub fn is_cacheable_method(method: &str) -> bool {
+    method.eq_ignore_ascii_case("GET") || method.eq_ignore_ascii_case("HEAD")
+}
+
+/// Whether a shared cache may store the response, per RFC 9111 section 3:
+/// neither side said `no-store`, the response isn't `private`, and requests
+/// carrying credentials are only stored when the response explicitly allows
+/// it.
+pub fn is_storable(
+    method: &str,
+    request_headers: &[(String, String)],
+    status: u16,
+    response_headers: &[(String, String)],
+) -> bool {
+    let request = CacheControl::parse(header(request_headers, "cache-control"));
+    let response = CacheControl::parse(header(response_headers, "cache-control"));
+
+    if !is_cacheable_method(method) || request.no_store || response.no_store || response.private {
+        return false;
+    }
+    if header(request_headers, "authorization").is_some()
+        && !(response.public || response.s_maxage.is_some() || response.must_revalidate)
+    {
+        return false;
+    }
+    if header(response_headers, "vary").is_some_and(|vary| vary.trim() == "*") {
+        return false;
+    }
+
+    header(response_headers, "expires").is_some()
+        || response.max_age.is_some()
+        || response.s_maxage.is_some()
+        || response.public
+        || is_heuristically_cacheable(status)
+}
+
+/// The key a response to `method url` is looked up under. When the response
+/// varies, the entry under it holds the names from its `Vary` header (see
+/// [`vary_names`]) and the response itself lives under [`variant_key`].
+pub fn primary_key(method: &str, url: &str) -> String {
+    format!("{} {url}", method.to_ascii_uppercase())
+}
+
+/// The key of the variant selected by `request_headers` among the responses
+/// stored for `primary`, given the `Vary` header names of the response. The
+/// selecting header values are hashed, so whatever they hold can't make the
+/// key look like it belongs to a namespace.
+pub fn variant_key(primary: &str, vary: &[String], request_headers: &[(String, String)]) -> String {
+    let mut selected = Vec::new();
+    for name in vary {
+        let value = header(request_headers, name)
+            .map(normalize)
+            .unwrap_or_default();
+        selected.extend_from_slice(name.to_ascii_lowercase().as_bytes());
+        selected.push(b'=');
+        selected.extend_from_slice(value.as_bytes());
+        selected.push(b'\n');
+    }
+    let hash = xxhash_rust::xxh3::xxh3_128(&selected);
+    format!("{primary} {hash:032x}")
+}
+
+/// The header names a `Vary` value lists, lowercased and sorted so that
+/// equivalent headers select the same variant.
+pub fn vary_names(vary: &str) -> Vec<String> {
+    let mut names: Vec<String> = vary
+        .split(',')
+        .map(|name| name.trim().to_ascii_lowercase())
+        .filter(|name| !name.is_empty())
+        .collect();
+    names.sort();
+    names.dedup();
+    names
+}
+
+impl CachedResponse {
+    pub fn new(status: u16, headers: Vec<(String, String)>, body: Vec<u8>, now: u64) -> Self {
+        Self {
+            status,
+            headers,
+            body,
+            stored_at: now,
+        }
+    }
+
+    /// The value of the first header called `name`, ignoring case.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        header(&self.headers, name)
+    }
+
+    /// How long the response stays fresh after it was generated: `s-maxage`,
+    /// then `max-age`, then `Expires` relative to `Date`, then 10% of the time
+    /// since `Last-Modified` for statuses that allow heuristics.
+    pub fn freshness_lifetime(&self) -> Duration {
+        let cache_control = CacheControl::parse(self.header("cache-control"));
+        if let Some(secs) = cache_control.s_maxage.or(cache_control.max_age) {
+            return Duration::from_secs(secs);
+        }
+
+        let date = self
+            .header("date")
+            .and_then(parse_http_date)
+            .unwrap_or(self.stored_at);
+        if let Some(expires) = self.header("expires") {
+            // An invalid Expires means already expired.
+            let expires = parse_http_date(expires).unwrap_or(0);
+            return Duration::from_secs(expires.saturating_sub(date));
+        }
+
+        match self.header("last-modified").and_then(parse_http_date) {
+            Some(modified) if is_heuristically_cacheable(self.status) => {
+                Duration::from_secs(date.saturating_sub(modified) / 10)
+            }
+            _ => Duration::ZERO,
+        }
+    }
+
+    /// The response's age at `now`, counting the `Age` it arrived with.
+    pub fn age(&self, now: u64) -> Duration {
+        let initial = self
+            .header("age")
+            .and_then(|age| age.trim().parse().ok())
+            .unwrap_or(0u64);
+        Duration::from_secs(initial + now.saturating_sub(self.stored_at))
+    }
+
+    /// Whether the response can be served at `now` without revalidation.
+    pub fn is_fresh(&self, now: u64) -> bool {
+        let cache_control = CacheControl::parse(self.header("cache-control"));
+        !cache_control.no_cache && self.age(now) < self.freshness_lifetime()
+    }
+
+    /// Whether a stale response may still be served while the origin is
+    /// unreachable.
+    pub fn allows_stale(&self) -> bool {
+        let cache_control = CacheControl::parse(self.header("cache-control"));
+        !cache_control.must_revalidate && !cache_control.proxy_revalidate
+    }
+
+    /// How long to keep the entry: its remaining freshness, plus `keep_stale`
+    /// when it carries a validator and can be revalidated instead of fetched
+    /// again.
+    pub fn ttl(&self, now: u64, keep_stale: Duration) -> Duration {
+        let remaining = self.freshness_lifetime().saturating_sub(self.age(now));
+        match self.header("etag").is_some() || self.header("last-modified").is_some() {
+            true => remaining + keep_stale,
+            false => remaining,
+        }
+    }
+
+    /// The conditional headers to send the origin when revalidating.
+    pub fn revalidation_headers(&self) -> Vec<(String, String)> {
+        let mut headers = Vec::new();
+        if let Some(etag) = self.header("etag") {
+            headers.push(("If-None-Match".into(), etag.into()));
+        }
+        if let Some(modified) = self.header("last-modified") {
+            headers.push(("If-Modified-Since".into(), modified.into()));
+        }
+        headers
+    }
+
+    /// Applies a `304 Not Modified` received at `now`: the stored body is kept
+    /// and the headers it came with replace the stored ones.
+    pub fn refresh(&mut self, not_modified_headers: &[(String, String)], now: u64) {
+        for (name, value) in not_modified_headers {
+            if name.eq_ignore_ascii_case("content-length") {
+                continue;
+            }
+            match self
+                .headers
+                .iter_mut()
+                .find(|(stored, _)| stored.eq_ignore_ascii_case(name))
+            {
+                Some((_, stored)) => *stored = value.clone(),
+                None => self.headers.push((name.clone(), value.clone())),
+            }
+        }
+        self.headers
+            .retain(|(name, _)| !name.eq_ignore_ascii_case("age"));
+        self.stored_at = now;
+    }
+
+    /// `[u16 status][u64 stored_at][u32 header count]`, then every header as
+    /// `[u32 len][name][u32 len][value]`, then the body.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(14 + self.body.len());
+        buf.extend_from_slice(&self.status.to_be_bytes());
+        buf.extend_from_slice(&self.stored_at.to_be_bytes());
+        buf.extend_from_slice(&(self.headers.len() as u32).to_be_bytes());
+        for (name, value) in &self.headers {
+            for field in [name, value] {
+                buf.extend_from_slice(&(field.len() as u32).to_be_bytes());
+                buf.extend_from_slice(field.as_bytes());
+            }
+        }
+        buf.extend_from_slice(&self.body);
+        buf
+    }
+
+    pub fn decode(buf: &[u8]) -> Result<Self, Error> {
+        let mut rest = buf;
+        let status = u16::from_be_bytes(take(&mut rest)?);
+        let stored_at = u64::from_be_bytes(take(&mut rest)?);
+        let count = u32::from_be_bytes(take(&mut rest)?);
+
+        let mut headers = Vec::new();
+        for _ in 0..count {
+            let mut field = || {
+                let len = u32::from_be_bytes(take(&mut rest)?) as usize;
+                if rest.len() < len {
+                    return Err(Error::InvalidData);
+                }
+                let (field, tail) = rest.split_at(len);
+                rest = tail;
+                String::from_utf8(field.to_vec()).map_err(|_| Error::InvalidData)
+            };
+            headers.push((field()?, field()?));
+        }
+
+        Ok(Self {
+            status,
+            headers,
+            body: rest.to_vec(),
+            stored_at,
+        })
+    }
+}
+
+fn take<const N: usize>(rest: &mut &[u8]) -> Result<[u8; N], Error> {
+    let Some((head, tail)) = rest.split_first_chunk::<N>() else {
+        return Err(Error::InvalidData);
+    };
+    *rest = tail;
+    Ok(*head)
+}
+
+/// The directives of a `Cache-Control` header this module acts on.
+#[derive(Debug, Default)]
+struct CacheControl {
+    no_store: bool,
+    no_cache: bool,
+    private: bool,
+    public: bool,
+    must_revalidate: bool,
+    proxy_revalidate: bool,
+    max_age: Option<u64>,
+    s_maxage: Option<u64>,
+}
+
+impl CacheControl {
+    fn parse(value: Option<&str>) -> Self {
+        let mut directives = Self::default();
+        for directive in value.unwrap_or_default().split(',') {
+            let (name, argument) = match directive.split_once('=') {
+                Some((name, argument)) => (name, Some(argument.trim().trim_matches('"'))),
+                None => (directive, None),
+            };
+            let seconds = argument.and_then(|a| a.parse().ok());
+
+            match name.trim().to_ascii_lowercase().as_str() {
+                "no-store" => directives.no_store = true,
+                "no-cache" => directives.no_cache = true,
+                "private" => directives.private = true,
+                "public" => directives.public = true,
+                "must-revalidate" => directives.must_revalidate = true,
+                "proxy-revalidate" => directives.proxy_revalidate = true,
+                // A malformed age makes the response stale.
+                "max-age" => directives.max_age = Some(seconds.unwrap_or(0)),
+                "s-maxage" => directives.s_maxage = Some(seconds.unwrap_or(0)),
+                _ => {}
+            }
+        }
+        directives
+    }
+}
+
+fn header<'a>(headers: &'a [(String, String)], name: &str) -> Option<&'a str> {
+    headers
+        .iter()
+        .find(|(header, _)| header.eq_ignore_ascii_case(name))
+        .map(|(_, value)| value.as_str())
+}
+
+/// Collapses whitespace so formatting differences don't split variants.
+fn normalize(value: &str) -> String {
+    value.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// Statuses cacheable without explicit freshness, RFC 9110 section 15.1.
+fn is_heuristically_cacheable(status: u16) -> bool {
+    matches!(
+        status,
+        200 | 203 | 204 | 206 | 300 | 301 | 308 | 404 | 405 | 410 | 414 | 501
+    )
+}
+
+/// Parses an IMF-fixdate like `Sun, 06 Nov 1994 08:49:37 GMT` into seconds
+/// since the Unix epoch. The obsolete formats aren't supported, nor are dates
+/// that don't exist or fall outside 1970 to 9999.
+pub fn parse_http_date(value: &str) -> Option<u64> {
+    let mut parts = value.split_whitespace();
+    let _weekday = parts.next()?;
+    let day: u64 = parts.next()?.parse().ok()?;
+    let month = parts.next()?;
+    let month = MONTHS.iter().position(|m| m.eq_ignore_ascii_case(month))? as u64 + 1;
+    let year: u64 = parts.next()?.parse().ok()?;
+    let mut time = parts.next()?.split(':').map(|t| t.parse::<u64>().ok());
+    let (hour, minute, second) = (time.next()??, time.next()??, time.next()??);
+    if time.next().is_some()
+        || parts.next() != Some("GMT")
+        || parts.next().is_some()
+        || !(1..=31).contains(&day)
+        || !(1970..=9999).contains(&year)
+        || hour > 23
+        || minute > 59
+        || second > 60
+    {
+        return None;
+    }
+
+    let days = days_from_civil(year, month, day);
+    (civil_from_days(days) == (year, month, day))
+        .then_some(days * 86400 + hour * 3600 + minute * 60 + second)
+}
+
+/// Formats seconds since the Unix epoch as an IMF-fixdate.
+pub fn format_http_date(secs: u64) -> String {
+    let days = secs / 86400;
+    let (year, month, day) = civil_from_days(days);
+    let weekday = WEEKDAYS[((days + 4) % 7) as usize];
+    let secs = secs % 86400;
+    format!(
+        "{weekday}, {day:02} {} {year} {:02}:{:02}:{:02} GMT",
+        MONTHS[month as usize - 1],
+        secs / 3600,
+        secs / 60 % 60,
+        secs % 60
+    )
+}
+
+const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+const WEEKDAYS: [&str; 7] = ["Sun", "Mon", "Tue", "Wed", "Thu", "Fri", "Sat"];
+
+/// Days since 1970-01-01 of a proleptic Gregorian date, after Howard
+/// Hinnant's algorithm.
+fn days_from_civil(year: u64, month: u64, day: u64) -> u64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year / 400;
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+fn civil_from_days(days: u64) -> (u64, u64, u64) {
+    let days = days + 719468;
+    let era = days / 146097;
+    let day_of_era = days - era * 146097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + u64::from(month <= 2);
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DATES: [(&str, u64); 7] = [
+        ("Thu, 01 Jan 1970 00:00:00 GMT", 0),
+        ("Sun, 06 Nov 1994 08:49:37 GMT", 784_111_777),
+        ("Wed, 01 Mar 2000 00:00:00 GMT", 951_868_800),
+        ("Thu, 29 Feb 2024 12:00:00 GMT", 1_709_208_000),
+        ("Sun, 28 Feb 2100 23:59:59 GMT", 4_107_542_399),
+        ("Mon, 01 Mar 2100 00:00:00 GMT", 4_107_542_400),
+        ("Fri, 31 Dec 9999 23:59:59 GMT", 253_402_300_799),
+    ];
+
+    #[test]
+    fn http_date_known_answers() {
+        for (date, secs) in DATES {
+            assert_eq!(parse_http_date(date), Some(secs), "{date}");
+            assert_eq!(format_http_date(secs), date);
+        }
+    }
+
+    #[test]
+    fn http_date_round_trip() {
+        for days in (0..2_932_897).step_by(997) {
+            let secs = days * 86400 + days % 86400;
+            assert_eq!(parse_http_date(&format_http_date(secs)), Some(secs));
+        }
+    }
+
+    #[test]
+    fn http_date_rejects_invalid() {
+        for date in [
+            "",
+            "Sun, 06 Nov 1994 08:49:37",
+            "Sun, 06 Nov 1994 08:49:37 UTC",
+            "Sun, 06 Nov 1994 08:49:37 GMT extra",
+            "Sun, 06 Nov 1994 08:49 GMT",
+            "Sun, 06 Nov 1994 08:49:37:00 GMT",
+            "Sun, 06 Nov 1994 24:00:00 GMT",
+            "Sun, 06 Nov 1994 08:60:00 GMT",
+            "Sun, 06 Nov 1994 08:49:61 GMT",
+            "Sun, 00 Nov 1994 08:49:37 GMT",
+            "Sun, 31 Nov 1994 08:49:37 GMT",
+            "Thu, 29 Feb 2100 00:00:00 GMT",
+            "Sun, 06 Foo 1994 08:49:37 GMT",
+            "Wed, 31 Dec 1969 23:59:59 GMT",
+            "Sat, 01 Jan 10000 00:00:00 GMT",
+            "Sun, 06 Nov 99999999999999999 08:49:37 GMT",
+            // The obsolete RFC 850 and asctime formats.
+            "Sunday, 06-Nov-94 08:49:37 GMT",
+            "Sun Nov  6 08:49:37 1994",
+        ] {
+            assert_eq!(parse_http_date(date), None, "{date}");
+        }
+        assert_eq!(
+            parse_http_date("sun, 06 nov 1994 08:49:37 GMT"),
+            Some(784_111_777)
+        );
+    }
+
+    fn response(headers: &[(&str, &str)]) -> CachedResponse {
+        let headers = headers
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect();
+        CachedResponse::new(200, headers, Vec::new(), 784_111_777)
+    }
+
+    #[test]
+    fn freshness_from_dates() {
+        let expires = response(&[
+            ("Date", "Sun, 06 Nov 1994 08:49:37 GMT"),
+            ("Expires", "Sun, 06 Nov 1994 09:49:37 GMT"),
+        ]);
+        assert_eq!(expires.freshness_lifetime(), Duration::from_secs(3600));
+
+        let past = response(&[
+            ("Date", "Sun, 06 Nov 1994 08:49:37 GMT"),
+            ("Expires", "Sat, 05 Nov 1994 08:49:37 GMT"),
+        ]);
+        assert_eq!(past.freshness_lifetime(), Duration::ZERO);
+
+        let invalid = response(&[("Expires", "0")]);
+        assert_eq!(invalid.freshness_lifetime(), Duration::ZERO);
+
+        // A tenth of the ten days since the last modification.
+        let heuristic = response(&[
+            ("Date", "Sun, 06 Nov 1994 08:49:37 GMT"),
+            ("Last-Modified", "Thu, 27 Oct 1994 08:49:37 GMT"),
+        ]);
+        assert_eq!(heuristic.freshness_lifetime(), Duration::from_secs(86400));
+        assert!(heuristic.is_fresh(784_111_777 + 86399));
+        assert!(!heuristic.is_fresh(784_111_777 + 86400));
+    }
+}
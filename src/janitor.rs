@@ -2,47 +2,198 @@ use std::{
     io::Read,
     path::{Path, PathBuf},
     sync::Arc,
-    time::Duration,
+    time::{Duration, Instant},
 };
 
 use crossbeam::channel::{Receiver, RecvTimeoutError};
 
-use crate::{error::Error, shards::Shards, utils::now};
+use crate::{
+    compact,
+    context::Context,
+    entry,
+    error::{Error, Worker},
+    evict,
+    eviction::EvictionCause,
+    schedule::Schedule,
+    stats::Counters,
+    tenant, utils,
+};
+
+pub const QUARANTINE_DIR: &str = "quarantine";
+
+/// Every this many sweeps, folders are swept even when their horizon says
+/// nothing expired yet, to catch stray temporary and foreign files.
+const FULL_SWEEP_EVERY: u64 = 16;
 
 type Callback = Box<dyn FnOnce(Result<(), Error>) + Send + Sync + 'static>;
 
 pub enum InputMessage {
     Cleanup(Callback),
+    /// Sent when the store grew past its limits; see [`crate::pressure`].
+    Pressure,
+    /// Stops scheduled sweeps until `Resume`. Requested sweeps and pressure
+    /// relief still run.
+    Pause,
+    Resume,
+    Reschedule(Schedule),
     Quit,
 }
 
+/// Janitor settings changed at run time, kept by the caller so a respawned
+/// worker picks them up.
+pub struct Control {
+    pub schedule: Schedule,
+    pub paused: bool,
+}
+
 pub fn worker(
-    interval: Duration,
+    control: &mut Control,
     path: Arc<PathBuf>,
-    shards: Shards,
+    ctx: Arc<Context>,
     input_receiver: Receiver<InputMessage>,
+    on_start: bool,
 ) {
+    if on_start {
+        run(&path, &ctx, Duration::ZERO);
+    } else if ctx.pressure.is_enabled() {
+        cleanup(&path, &ctx);
+    }
+
     loop {
-        match input_receiver.recv_timeout(interval) {
-            Ok(InputMessage::Cleanup(callback)) => {
-                cleanup(&path, &shards);
-                callback(Ok(()));
+        let received = match control.paused {
+            true => input_receiver
+                .recv()
+                .map_err(|_| RecvTimeoutError::Disconnected),
+            false => input_receiver.recv_timeout(control.schedule.next_delay(ctx.now())),
+        };
+        match received {
+            Ok(InputMessage::Cleanup(callback)) => callback(ctx.guard(Worker::Janitor, || {
+                cleanup(&path, &ctx);
+                ctx.persist(&path)
+            })),
+            Ok(InputMessage::Pressure) => {
+                if let Err(e) = ctx.guard(Worker::Janitor, || {
+                    evict::relieve(&path, &ctx);
+                    Ok(())
+                }) {
+                    ctx.hooks.report(&e);
+                }
             }
+            Ok(InputMessage::Pause) => control.paused = true,
+            Ok(InputMessage::Resume) => control.paused = false,
+            Ok(InputMessage::Reschedule(schedule)) => control.schedule = schedule,
             Ok(InputMessage::Quit) => break,
             Err(RecvTimeoutError::Disconnected) => break,
-            Err(RecvTimeoutError::Timeout) => cleanup(&path, &shards),
+            Err(RecvTimeoutError::Timeout) => {
+                if let Err(e) = ctx.guard(Worker::Janitor, || {
+                    cleanup(&path, &ctx);
+                    ctx.persist(&path)
+                }) {
+                    ctx.hooks.report(&e);
+                }
+            }
         }
     }
 }
 
-fn cleanup(root: &Path, shards: &Shards) {
-    let now_ts = now();
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct JanitorStatus {
+    pub runs: u64,
+    /// Start of the last sweep, in seconds since the Unix epoch.
+    pub last_run_at: Option<u64>,
+    pub last_duration: Duration,
+    pub last_removed: u64,
+    /// Shards skipped because they were locked by store operations.
+    pub last_skipped: u64,
+    /// Folders skipped because none of their entries expired yet, see
+    /// [`crate::horizon`].
+    pub last_pruned: u64,
+    /// Folders repacked because their directory grew much larger than
+    /// their entries need, see [`crate::compact`].
+    pub last_compacted: u64,
+    pub last_errors: u64,
+}
+
+/// Removes expired entries and temporary files older than the configured
+/// grace period, and quarantines files that aren't keeper entries.
+pub fn cleanup(root: &Path, ctx: &Context) {
+    run(root, ctx, ctx.temp_grace);
+}
+
+fn run(root: &Path, ctx: &Context, temp_grace: Duration) {
+    let start = Instant::now();
+    let now_ts = ctx.now();
+    Counters::add(&ctx.counters.janitor_runs, 1);
+
+    let mut status = JanitorStatus {
+        last_run_at: Some(now_ts),
+        ..Default::default()
+    };
+    // Pruned folders aren't counted, so sweeps that size the store for
+    // pressure go through every folder.
+    let runs = ctx
+        .janitor_status
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .runs;
+    let prune = !ctx.pressure.is_enabled() && !(runs + 1).is_multiple_of(FULL_SWEEP_EVERY);
+
+    let mut store_bytes = 0;
+    for (i, root) in tenant::roots(root).iter().enumerate() {
+        // Tenants share the folder ids of the store root, and horizons only
+        // track the latter.
+        let main = i == 0;
+        store_bytes += sweep(
+            root,
+            ctx,
+            now_ts,
+            temp_grace,
+            main,
+            prune && main,
+            &mut status,
+        );
+    }
+    if status.last_skipped == 0 {
+        ctx.pressure.reset(store_bytes);
+    }
+    status.last_duration = start.elapsed();
+
+    let status = {
+        let mut current = ctx.janitor_status.lock().unwrap_or_else(|e| e.into_inner());
+        status.runs = current.runs + 1;
+        *current = status;
+        status
+    };
+    ctx.hooks.report_sweep(&status);
+}
+
+fn sweep(
+    root: &Path,
+    ctx: &Context,
+    now_ts: u64,
+    temp_grace: Duration,
+    main: bool,
+    prune: bool,
+    status: &mut JanitorStatus,
+) -> u64 {
+    let mut report = |e: std::io::Error| {
+        status.last_errors += 1;
+        ctx.hooks.report(&e.into());
+    };
 
     let entries = match std::fs::read_dir(root) {
         Ok(d) => d,
-        Err(_) => return,
+        Err(e) => {
+            report(e);
+            return 0;
+        }
     };
 
+    let mut removed = 0;
+    let mut skipped = 0;
+    let mut pruned = 0;
+    let mut compacted = 0;
+    let mut store_bytes = 0;
     for entry in entries.flatten() {
         let folder_path = entry.path();
         if !folder_path.is_dir() {
@@ -52,47 +203,183 @@ fn cleanup(root: &Path, shards: &Shards) {
         let folder_name = entry.file_name();
         let name_str = folder_name.to_string_lossy();
 
-        let shard_id = match u16::from_str_radix(&name_str, 16) {
+        let folder_id = match u16::from_str_radix(&name_str, 16) {
             Ok(id) => id,
-            Err(_) => continue,
+            Err(_) => {
+                if let Some((id, folder)) = compact::leftover(root, &name_str)
+                    && let Some(_locks) = ctx.shards.try_write_folder(id)
+                    && let Err(e) = compact::restore(ctx, &folder)
+                {
+                    report(e);
+                }
+                continue;
+            }
         };
 
-        let Ok(_lock) = shards.try_write(shard_id) else {
+        if prune
+            && let Some(earliest) = ctx.horizons.earliest(folder_id)
+            && !ctx.is_expired(earliest, now_ts)
+        {
+            pruned += 1;
             continue;
-        };
+        }
 
-        let Ok(files) = std::fs::read_dir(&folder_path) else {
+        let Some(_locks) = ctx.shards.try_write_folder(folder_id) else {
+            skipped += 1;
             continue;
         };
 
+        let files = match std::fs::read_dir(&folder_path) {
+            Ok(files) => files,
+            Err(e) => {
+                report(e);
+                continue;
+            }
+        };
+
+        // The horizon is only known when every file left is an entry whose
+        // expiration time was read.
+        let mut earliest: Option<u64> = None;
+        let mut complete = true;
         for file_entry in files.flatten() {
             let file_path = file_entry.path();
             if !file_path.is_file() {
                 continue;
             }
 
-            match is_file_expired(&file_path, now_ts) {
-                Ok(true) | Err(_) => {
-                    let _ = std::fs::remove_file(file_path);
+            match inspect(ctx, &file_entry, now_ts, temp_grace) {
+                Verdict::Keep(expires_at) => {
+                    match expires_at {
+                        Some(0) => {}
+                        Some(t) => earliest = Some(earliest.map_or(t, |e| e.min(t))),
+                        None => complete = false,
+                    }
+                    if ctx.pressure.is_enabled() {
+                        let size = file_entry.metadata().map(|m| m.len()).unwrap_or(0);
+                        store_bytes += ctx.weigh_file(&file_path, size);
+                    }
+                }
+                Verdict::Expired => match ctx.evict_entry(&file_path, EvictionCause::Expired) {
+                    Ok(()) => {
+                        removed += 1;
+                        Counters::evicted(
+                            &ctx.counters.expired_by_janitor,
+                            &ctx.counters.evictions,
+                        );
+                    }
+                    Err(e) => {
+                        complete = false;
+                        report(e);
+                    }
+                },
+                Verdict::Cleared => match ctx.remove_entry(&file_path) {
+                    Ok(_) => removed += 1,
+                    Err(e) => {
+                        complete = false;
+                        report(e);
+                    }
+                },
+                Verdict::Temp => {
+                    if let Err(e) = utils::retry_io(|| std::fs::remove_file(&file_path)) {
+                        report(e);
+                    }
                 }
-                Ok(false) => continue,
+                Verdict::Foreign => match quarantine(ctx, root, &name_str, &file_entry) {
+                    Ok(target) => ctx.hooks.report(&Error::Quarantined(target)),
+                    Err(e) => report(e),
+                },
+            }
+        }
+
+        if main && complete {
+            ctx.horizons.set(folder_id, earliest);
+        }
+
+        if ctx.compact_dirs {
+            match compact::compact(ctx, &folder_path) {
+                Ok(true) => compacted += 1,
+                Ok(false) => {}
+                Err(e) => report(e),
             }
         }
     }
+
+    status.last_removed += removed;
+    status.last_skipped += skipped;
+    status.last_pruned += pruned;
+    status.last_compacted += compacted;
+    store_bytes
 }
 
-fn is_file_expired(path: &Path, now: u64) -> std::io::Result<bool> {
-    let mut file = std::fs::File::open(path)?;
-    let mut header = [0u8; 10];
+enum Verdict {
+    /// With the expiration time of the entry, `None` for files that aren't
+    /// entries yet or couldn't be read.
+    Keep(Option<u64>),
+    Expired,
+    /// Written before a lazy clear.
+    Cleared,
+    Temp,
+    Foreign,
+}
+
+fn inspect(ctx: &Context, file: &std::fs::DirEntry, now: u64, temp_grace: Duration) -> Verdict {
+    let name = file.file_name();
+    let name = name.to_string_lossy();
 
-    if file.read_exact(&mut header).is_err() {
-        return Ok(true);
+    if name.ends_with(".tmp") {
+        let age = file
+            .metadata()
+            .and_then(|m| m.modified())
+            .ok()
+            .and_then(|modified| modified.elapsed().ok())
+            .unwrap_or(Duration::MAX);
+
+        return match age >= temp_grace {
+            true => Verdict::Temp,
+            false => Verdict::Keep(None),
+        };
+    }
+
+    if name.len() != 29 || !name.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return Verdict::Foreign;
+    }
+
+    if file.metadata().is_ok_and(|m| ctx.is_cleared(&m)) {
+        return Verdict::Cleared;
+    }
+
+    match read_expires_at(&file.path()) {
+        Ok(Some(expires_at)) if ctx.is_expired(expires_at, now) => Verdict::Expired,
+        Ok(Some(expires_at)) => Verdict::Keep(Some(expires_at)),
+        Ok(None) => Verdict::Foreign,
+        Err(_) => Verdict::Keep(None),
     }
+}
+
+/// Moves a file that doesn't look like an entry out of the shard folders, into
+/// `<root>/quarantine`.
+fn quarantine(
+    ctx: &Context,
+    root: &Path,
+    folder: &str,
+    file: &std::fs::DirEntry,
+) -> std::io::Result<PathBuf> {
+    let dir = root.join(QUARANTINE_DIR);
+    ctx.permissions.create_dir_all(&dir)?;
+
+    let target = dir.join(format!("{folder}-{}", file.file_name().to_string_lossy()));
+    utils::retry_io(|| std::fs::rename(file.path(), &target))?;
+    Ok(target)
+}
+
+/// `None` when the file doesn't start with a valid entry header.
+fn read_expires_at(path: &Path) -> std::io::Result<Option<u64>> {
+    let mut file = std::fs::File::open(path)?;
+    let mut prefix = [0u8; entry::PREFIX_LEN];
 
-    let expires_at = u64::from_be_bytes(header[2..10].try_into().unwrap());
-    if expires_at == 0 {
-        return Ok(false);
+    if file.read_exact(&mut prefix).is_err() || !entry::is_known_tag(&prefix) {
+        return Ok(None);
     }
 
-    Ok(expires_at < now)
+    Ok(Some(entry::expires_at(&prefix)))
 }
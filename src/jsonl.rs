@@ -0,0 +1,616 @@
+use std::{
+    collections::HashMap,
+    io::{BufRead, BufReader, BufWriter, ErrorKind, Read, Write},
+    path::Path,
+    time::UNIX_EPOCH,
+};
+
+use crate::{
+    context::Context,
+    entries::{self, EntryInfo},
+    entry,
+    error::Error,
+    manifest::FANOUT,
+    namespace,
+    store::BulkWriter,
+    tenant,
+    utils::parse_hash,
+};
+
+/// Writes every live entry of the store at `root` to `writer` as one JSON
+/// object per line:
+///
+/// ```text
+/// {"hash":"…","key":"…","value":"<base64>","expires_at":0,"version":2,"modified":1700000000}
+/// ```
+///
+/// `key` is left out for entries whose key is unknown and `modified` when the
+/// file system doesn't record it. Entries of a tenant carry its name in a
+/// `tenant` field. Expired entries are skipped, and so are entries that fail
+/// to decode. Returns the number of entries written.
+pub fn export(ctx: &Context, root: &Path, writer: impl Write) -> Result<u64, Error> {
+    let mut writer = BufWriter::new(writer);
+    let now = ctx.now();
+    let mut exported = 0;
+
+    for (i, root) in tenant::roots(root).iter().enumerate() {
+        let tenant = match i {
+            0 => None,
+            _ => root.file_name().and_then(|name| name.to_str()),
+        };
+        if i > 0 && tenant.is_none_or(|name| namespace::validate_name(name).is_err()) {
+            continue;
+        }
+
+        for folder in 0..FANOUT as u16 {
+            for (hash, info) in entries::read_shard(ctx, root, folder) {
+                if ctx.is_expired(info.expires_at, now) {
+                    continue;
+                }
+
+                let (p_folder, filename, shard_id) = parse_hash(hash.as_bytes());
+                let file_path = root.join(p_folder).join(filename);
+                let buffer = {
+                    let _lock = ctx.shards.read(shard_id);
+                    match ctx.read_file(&file_path) {
+                        Ok(buffer) => buffer,
+                        Err(e) if e.kind() == ErrorKind::NotFound => continue,
+                        Err(e) => return Err(e.into()),
+                    }
+                };
+                let key = info.key.as_deref().unwrap_or_default();
+                let Ok((_, payload)) = ctx.decode(&buffer, key) else {
+                    continue;
+                };
+
+                writer.write_all(record(tenant, &hash, &info, &payload).as_bytes())?;
+                exported += 1;
+            }
+        }
+    }
+
+    writer.flush()?;
+    Ok(exported)
+}
+
+/// Stores the entries read from `reader`, in the format written by
+/// [`export`], through the same path as [`crate::store::bulk_load`]. Records
+/// are placed by `key` when present and by `hash` otherwise; `expires_at` is
+/// kept as is and unknown fields are ignored. Records with a `tenant` go to
+/// that tenant's folder, created if needed. Records without a key can't be
+/// signed, so stores with a signing key skip them, and records that expired
+/// are skipped too.
+///
+/// A malformed line fails the import with [`Error::InvalidData`], possibly
+/// after some of the lines before it were written. Returns the number of
+/// entries written.
+pub fn import(ctx: &Context, root: &Path, reader: impl Read) -> Result<u64, Error> {
+    let now = ctx.now();
+    let mut writer = Writers::new(ctx, root);
+
+    for line in BufReader::new(reader).lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        import_line(ctx, &mut writer, &line, now)?;
+    }
+
+    writer.finish()
+}
+
+/// Queues the entry of one exported line on `writers`, unless it expired by
+/// `now` or can't be stored.
+pub(crate) fn import_line(
+    ctx: &Context,
+    writers: &mut Writers,
+    line: &str,
+    now: u64,
+) -> Result<(), Error> {
+    let record = Record::parse(line).ok_or(Error::InvalidData)?;
+    if ctx.is_expired(record.expires_at, now) {
+        return Ok(());
+    }
+
+    let writer = writers.get(record.tenant.as_deref())?;
+    match record.key {
+        Some(key) => {
+            let encoded = ctx.encode(record.expires_at, &key, &record.value)?;
+            writer.push(ctx.hash(&key), Some(key), encoded)
+        }
+        None if ctx.signing_key.is_none() => {
+            let hash = record.hash.ok_or(Error::InvalidData)?;
+            let encoded = entry::encode(entry::CURRENT, 0, record.expires_at, None, &record.value)?;
+            writer.push(hash.into_bytes(), None, encoded)
+        }
+        None => Ok(()),
+    }
+}
+
+pub(crate) fn record(tenant: Option<&str>, hash: &str, info: &EntryInfo, payload: &[u8]) -> String {
+    let mut line = format!("{{\"hash\":\"{hash}\"");
+    if let Some(tenant) = tenant {
+        line.push_str(",\"tenant\":");
+        push_string(&mut line, tenant);
+    }
+    if let Some(key) = &info.key {
+        line.push_str(",\"key\":");
+        push_string(&mut line, key);
+    }
+    line.push_str(",\"value\":\"");
+    line.push_str(&base64_encode(payload));
+    line.push_str(&format!(
+        "\",\"expires_at\":{},\"version\":{}",
+        info.expires_at, info.version
+    ));
+    if let Some(modified) = info
+        .modified
+        .and_then(|m| m.duration_since(UNIX_EPOCH).ok())
+    {
+        line.push_str(&format!(",\"modified\":{}", modified.as_secs()));
+    }
+    line.push_str("}\n");
+    line
+}
+
+/// Bulk writers for the store root and for each tenant records go to, opened
+/// as they're needed.
+pub(crate) struct Writers<'a> {
+    ctx: &'a Context,
+    root: &'a Path,
+    store: BulkWriter<'a>,
+    tenants: HashMap<String, BulkWriter<'a>>,
+}
+
+impl<'a> Writers<'a> {
+    pub fn new(ctx: &'a Context, root: &'a Path) -> Self {
+        Self {
+            ctx,
+            root,
+            store: BulkWriter::new(ctx, root),
+            tenants: HashMap::new(),
+        }
+    }
+
+    fn get(&mut self, tenant: Option<&str>) -> Result<&mut BulkWriter<'a>, Error> {
+        let Some(name) = tenant else {
+            return Ok(&mut self.store);
+        };
+        if !self.tenants.contains_key(name) {
+            let root = self.root.join(tenant::TENANTS_DIR).join(name);
+            self.ctx.permissions.create_dir_all(&root)?;
+            self.tenants
+                .insert(name.into(), BulkWriter::new(self.ctx, &root));
+        }
+        Ok(self.tenants.get_mut(name).expect("inserted above"))
+    }
+
+    /// Writes what's left and returns the number of entries written.
+    pub fn finish(self) -> Result<u64, Error> {
+        let mut loaded = self.store.finish()?;
+        for writer in self.tenants.into_values() {
+            loaded += writer.finish()?;
+        }
+        Ok(loaded)
+    }
+}
+
+fn push_string(out: &mut String, value: &str) {
+    out.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+/// The fields of an imported line that matter.
+#[derive(Debug, Default)]
+struct Record {
+    hash: Option<String>,
+    key: Option<String>,
+    tenant: Option<String>,
+    value: Vec<u8>,
+    expires_at: u64,
+}
+
+impl Record {
+    fn parse(line: &str) -> Option<Self> {
+        let mut record = Self::default();
+        let mut has_value = false;
+
+        for (name, value) in object(line)? {
+            match (name.as_str(), value) {
+                ("hash", Value::String(hash)) if is_hash(&hash) => record.hash = Some(hash),
+                ("key", Value::String(key)) => record.key = Some(key),
+                ("key", Value::Null) => {}
+                ("tenant", Value::String(tenant)) => {
+                    namespace::validate_name(&tenant).ok()?;
+                    record.tenant = Some(tenant);
+                }
+                ("tenant", Value::Null) => {}
+                ("value", Value::String(value)) => {
+                    record.value = base64_decode(&value)?;
+                    has_value = true;
+                }
+                ("expires_at", Value::Number(expires_at)) => record.expires_at = expires_at,
+                ("hash" | "key" | "tenant" | "value" | "expires_at", _) => return None,
+                _ => {}
+            }
+        }
+
+        (has_value && (record.key.is_some() || record.hash.is_some())).then_some(record)
+    }
+}
+
+/// The fields of a flat JSON object taking the whole line, in order.
+pub(crate) fn object(line: &str) -> Option<Vec<(String, Value)>> {
+    let mut parser = Parser {
+        input: line.as_bytes(),
+        pos: 0,
+    };
+    let mut fields = Vec::new();
+
+    parser.expect(b'{')?;
+    if !parser.eat(b'}') {
+        loop {
+            let name = parser.string()?;
+            parser.expect(b':')?;
+            fields.push((name, parser.value()?));
+
+            if parser.eat(b'}') {
+                break;
+            }
+            parser.expect(b',')?;
+        }
+    }
+
+    parser.skip_whitespace();
+    (parser.pos == line.len()).then_some(fields)
+}
+
+/// Hashes as [`crate::store::hash`] writes them.
+fn is_hash(hash: &str) -> bool {
+    hash.len() == 32
+        && hash
+            .bytes()
+            .all(|b| b.is_ascii_digit() || (b'a'..=b'f').contains(&b))
+}
+
+pub(crate) enum Value {
+    String(String),
+    Number(u64),
+    Null,
+    /// Booleans, negative and fractional numbers, none of which the known
+    /// fields take.
+    Other,
+}
+
+/// Just enough JSON for flat objects: nested objects and arrays are rejected.
+struct Parser<'a> {
+    input: &'a [u8],
+    pos: usize,
+}
+
+impl Parser<'_> {
+    fn skip_whitespace(&mut self) {
+        while self
+            .input
+            .get(self.pos)
+            .is_some_and(u8::is_ascii_whitespace)
+        {
+            self.pos += 1;
+        }
+    }
+
+    fn eat(&mut self, byte: u8) -> bool {
+        self.skip_whitespace();
+        let found = self.input.get(self.pos) == Some(&byte);
+        if found {
+            self.pos += 1;
+        }
+        found
+    }
+
+    fn expect(&mut self, byte: u8) -> Option<()> {
+        self.eat(byte).then_some(())
+    }
+
+    fn literal(&mut self, literal: &str) -> bool {
+        let found = self.input[self.pos..].starts_with(literal.as_bytes());
+        if found {
+            self.pos += literal.len();
+        }
+        found
+    }
+
+    fn value(&mut self) -> Option<Value> {
+        self.skip_whitespace();
+        match self.input.get(self.pos)? {
+            b'"' => self.string().map(Value::String),
+            b'n' => self.literal("null").then_some(Value::Null),
+            b't' => self.literal("true").then_some(Value::Other),
+            b'f' => self.literal("false").then_some(Value::Other),
+            b'-' | b'0'..=b'9' => {
+                let start = self.pos;
+                while self
+                    .input
+                    .get(self.pos)
+                    .is_some_and(|b| matches!(b, b'-' | b'+' | b'.' | b'e' | b'E' | b'0'..=b'9'))
+                {
+                    self.pos += 1;
+                }
+                let number = std::str::from_utf8(&self.input[start..self.pos]).ok()?;
+                match number.parse() {
+                    Ok(n) => Some(Value::Number(n)),
+                    Err(_) => number.parse::<f64>().ok().map(|_| Value::Other),
+                }
+            }
+            _ => None,
+        }
+    }
+
+    fn string(&mut self) -> Option<String> {
+        self.expect(b'"')?;
+        let mut out = Vec::new();
+        loop {
+            let byte = *self.input.get(self.pos)?;
+            self.pos += 1;
+            match byte {
+                b'"' => return String::from_utf8(out).ok(),
+                b'\\' => {
+                    let escape = *self.input.get(self.pos)?;
+                    self.pos += 1;
+                    let c = match escape {
+                        b'"' => '"',
+                        b'\\' => '\\',
+                        b'/' => '/',
+                        b'b' => '\u{8}',
+                        b'f' => '\u{c}',
+                        b'n' => '\n',
+                        b'r' => '\r',
+                        b't' => '\t',
+                        b'u' => self.unicode_escape()?,
+                        _ => return None,
+                    };
+                    out.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes());
+                }
+                byte if byte < 0x20 => return None,
+                byte => out.push(byte),
+            }
+        }
+    }
+
+    /// Decodes the digits after `\u`, combining surrogate pairs.
+    fn unicode_escape(&mut self) -> Option<char> {
+        let high = self.hex4()?;
+        if !(0xd800..0xdc00).contains(&high) {
+            return char::from_u32(high);
+        }
+        if !self.literal("\\u") {
+            return None;
+        }
+        let low = self.hex4()?;
+        if !(0xdc00..0xe000).contains(&low) {
+            return None;
+        }
+        char::from_u32(0x10000 + ((high - 0xd800) << 10) + (low - 0xdc00))
+    }
+
+    fn hex4(&mut self) -> Option<u32> {
+        let digits = self.input.get(self.pos..self.pos + 4)?;
+        if !digits.iter().all(u8::is_ascii_hexdigit) {
+            return None;
+        }
+        self.pos += 4;
+        u32::from_str_radix(std::str::from_utf8(digits).ok()?, 16).ok()
+    }
+}
+
+const BASE64: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+/// Standard base64 with padding.
+fn base64_encode(data: &[u8]) -> String {
+    let mut out = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let n = chunk
+            .iter()
+            .enumerate()
+            .fold(0u32, |n, (i, &b)| n | (b as u32) << (16 - 8 * i));
+        for i in 0..4 {
+            match i <= chunk.len() {
+                true => out.push(BASE64[(n >> (18 - 6 * i) & 0x3f) as usize] as char),
+                false => out.push('='),
+            }
+        }
+    }
+    out
+}
+
+/// Accepts padded and unpadded input.
+fn base64_decode(data: &str) -> Option<Vec<u8>> {
+    let data = data.trim_end_matches('=').as_bytes();
+    if data.len() % 4 == 1 {
+        return None;
+    }
+
+    let mut out = Vec::with_capacity(data.len() * 3 / 4);
+    for chunk in data.chunks(4) {
+        let mut n = 0u32;
+        for (i, &c) in chunk.iter().enumerate() {
+            let digit = BASE64.iter().position(|&b| b == c)? as u32;
+            n |= digit << (18 - 6 * i);
+        }
+        out.extend_from_slice(&n.to_be_bytes()[1..chunk.len()]);
+    }
+    Some(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// RFC 4648, section 10.
+    const BASE64_VECTORS: [(&str, &str); 7] = [
+        ("", ""),
+        ("f", "Zg=="),
+        ("fo", "Zm8="),
+        ("foo", "Zm9v"),
+        ("foob", "Zm9vYg=="),
+        ("fooba", "Zm9vYmE="),
+        ("foobar", "Zm9vYmFy"),
+    ];
+
+    #[test]
+    fn base64_rfc4648() {
+        for (data, encoded) in BASE64_VECTORS {
+            assert_eq!(base64_encode(data.as_bytes()), encoded);
+            assert_eq!(base64_decode(encoded).unwrap(), data.as_bytes());
+            assert_eq!(
+                base64_decode(encoded.trim_end_matches('=')).unwrap(),
+                data.as_bytes()
+            );
+        }
+    }
+
+    #[test]
+    fn base64_round_trip() {
+        let data: Vec<u8> = (0..=255).collect();
+        for len in 0..data.len() {
+            assert_eq!(
+                base64_decode(&base64_encode(&data[..len])).unwrap(),
+                &data[..len]
+            );
+        }
+    }
+
+    #[test]
+    fn base64_rejects_garbage() {
+        assert!(base64_decode("Zm9vY").is_none());
+        assert!(base64_decode("Zm9v!A==").is_none());
+        assert!(base64_decode("Zm9-").is_none());
+    }
+
+    fn string(json: &str) -> Option<String> {
+        match object(&format!("{{\"s\":{json}}}"))?.pop()? {
+            (_, Value::String(s)) => Some(s),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn object_fields() {
+        let fields = object(r#" { "a" : "x" , "b":42,"c":null,"d":true,"e":-1.5 } "#).unwrap();
+        let fields: Vec<_> = fields
+            .into_iter()
+            .map(|(name, value)| {
+                let value = match value {
+                    Value::String(s) => format!("s:{s}"),
+                    Value::Number(n) => format!("n:{n}"),
+                    Value::Null => "null".into(),
+                    Value::Other => "other".into(),
+                };
+                (name, value)
+            })
+            .collect();
+        assert_eq!(
+            fields,
+            [
+                ("a".into(), "s:x".into()),
+                ("b".into(), "n:42".into()),
+                ("c".into(), "null".into()),
+                ("d".into(), "other".into()),
+                ("e".into(), "other".into()),
+            ] as [(String, String); 5]
+        );
+        assert!(object("{}").unwrap().is_empty());
+    }
+
+    #[test]
+    fn object_rejects_malformed_lines() {
+        for line in [
+            "",
+            "{",
+            "{\"a\":1,}",
+            "{\"a\":1} x",
+            "{\"a\":{\"b\":1}}",
+            "{\"a\":[1]}",
+            "{a:1}",
+            "{\"a\":tru}",
+        ] {
+            assert!(object(line).is_none(), "{line}");
+        }
+    }
+
+    #[test]
+    fn string_escapes() {
+        assert_eq!(
+            string(r#""\"\\\/\b\f\n\r\t""#).unwrap(),
+            "\"\\/\u{8}\u{c}\n\r\t"
+        );
+        assert_eq!(string(r#""\u00e9\u20AC""#).unwrap(), "é€");
+        assert_eq!(string(r#""\ud83d\ude00""#).unwrap(), "😀");
+        assert_eq!(string(r#""café 😀""#).unwrap(), "café 😀");
+
+        for bad in [
+            r#""\x""#,
+            r#""\u12""#,
+            r#""\u+041""#,
+            r#""\ud83d""#,
+            r#""\ud83dA""#,
+            r#""\ude00""#,
+            "\"a\nb\"",
+            "\"open",
+        ] {
+            assert!(string(bad).is_none(), "{bad}");
+        }
+    }
+
+    #[test]
+    fn push_string_round_trips() {
+        let value = "quote\" backslash\\ nl\n cr\r tab\t bell\u{7} é 😀";
+        let mut json = String::new();
+        push_string(&mut json, value);
+        assert_eq!(string(&json).unwrap(), value);
+    }
+
+    #[test]
+    fn record_fields() {
+        let record = Record::parse(
+            r#"{"hash":"0123456789abcdef0123456789abcdef","tenant":"acme","key":"k","value":"dg==","expires_at":7,"version":2,"extra":[]}"#,
+        );
+        // Unknown fields are ignored, but still have to be flat JSON.
+        assert!(record.is_none());
+
+        let record = Record::parse(
+            r#"{"hash":"0123456789abcdef0123456789abcdef","tenant":"acme","key":"k","value":"dg==","expires_at":7,"extra":true}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            record.hash.as_deref(),
+            Some("0123456789abcdef0123456789abcdef")
+        );
+        assert_eq!(record.tenant.as_deref(), Some("acme"));
+        assert_eq!(record.key.as_deref(), Some("k"));
+        assert_eq!((record.value, record.expires_at), (b"v".to_vec(), 7));
+
+        for line in [
+            r#"{"key":"k"}"#,
+            r#"{"value":"dg=="}"#,
+            r#"{"key":"k","value":"d"}"#,
+            r#"{"key":1,"value":"dg=="}"#,
+            r#"{"key":"k","value":"dg==","expires_at":-1}"#,
+            r#"{"key":"k","tenant":"../x","value":"dg=="}"#,
+            r#"{"hash":"not-a-hash","value":"dg=="}"#,
+        ] {
+            assert!(Record::parse(line).is_none(), "{line}");
+        }
+    }
+}
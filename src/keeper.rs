@@ -1,9 +1,71 @@
-use std::{path::PathBuf, sync::Arc, thread::JoinHandle, time::Duration};
+use std::{
+    collections::{BTreeMap, HashMap},
+    path::{Path, PathBuf},
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicBool, AtomicU64, Ordering},
+    },
+    thread::JoinHandle,
+    time::{Duration, Instant},
+};
 
-use crossbeam::channel::{Sender, unbounded};
+use crossbeam::channel::{Receiver, RecvTimeoutError, Sender, unbounded};
 use pidlock::Pidlock;
 
-use crate::{error::Error, janitor, shards::Shards, store};
+use crate::{
+    backup::{self, Backup},
+    bypass::{Bypass, Degradation},
+    clock::{Clock, MonotonicClock, SystemClock},
+    codec::{Codec, Codecs},
+    context::Context,
+    durability::GroupCommit,
+    entries::{Entries, EntryInfo},
+    error::{Error, Worker},
+    estimate::{self, Estimate, ExpiryForecast},
+    eviction::Eviction,
+    executor::Executor,
+    handles::HandleCache,
+    headers::{Head, HeaderCache},
+    hooks::Hooks,
+    horizon::Horizons,
+    janitor::{self, JanitorStatus},
+    jsonl,
+    keylock::KeyLock,
+    keys::{self, KeyProvider, KeyPurpose, KeyRing},
+    keystats::{KeyStats, KeyTracker},
+    latency::{LatencySnapshot, SlowOp},
+    loader::{self, CircuitBreaker, Loader},
+    lock::LockStrategy,
+    mac::SigningKey,
+    manifest::{self, Manifest},
+    memory::MemoryCache,
+    migrate::{self, MigrationReport},
+    mounts,
+    namespace::{self, Namespace, Namespaces, Policy, Quota, Usage},
+    origin::{Attached, Origin},
+    permissions::Permissions,
+    pipeline::{Command, Pipeline, Reply},
+    pool::{Health, Pool},
+    pressure::Pressure,
+    purge::{self, PurgeReport},
+    queue::{self, Priority, Senders, Watermarks},
+    ratelimit::{RateLimiter, Window},
+    rdb::{self, RdbReport},
+    recovery::{self, RecoveryReport},
+    reindex::{self, IndexReport},
+    schedule::Schedule,
+    semaphore::Semaphore,
+    shards::{DEFAULT_SHARDS, Shards},
+    snapshot::Snapshot,
+    stats::{Counters, Stats},
+    store,
+    tenant::{self, Tenant},
+    utils, warmup,
+    weigher::Weigher,
+};
+
+#[cfg(unix)]
+use crate::control;
 
 #[cfg(feature = "async")]
 use tokio::sync::oneshot;
@@ -11,33 +73,182 @@ use tokio::sync::oneshot;
 #[derive(Debug)]
 struct Inner {
     path: Arc<PathBuf>,
-    _lock: Pidlock,
+    _lock: Option<Pidlock>,
+    ctx: Arc<Context>,
 
-    store_is: Sender<store::InputMessage>,
+    store_is: Senders,
     janitor_is: Sender<janitor::InputMessage>,
+    /// Kept to fail pending cleanups on shutdown; `None` without a janitor.
+    janitor_ir: Option<Receiver<janitor::InputMessage>>,
+    closing: AtomicBool,
+    max_queue_len: Option<usize>,
+    request_timeout: Option<Duration>,
+    recovery: Option<RecoveryReport>,
+    executor: Option<Arc<Executor>>,
 
-    store_handles: Vec<JoinHandle<()>>,
-    janitor_handle: Option<JoinHandle<()>>,
+    pool: Arc<Pool>,
+    janitor_handle: Mutex<Option<JoinHandle<()>>>,
+    /// Dropping the sender stops the supervisor thread.
+    supervisor: Mutex<Option<(Sender<()>, JoinHandle<()>)>>,
+    #[cfg(unix)]
+    control: Mutex<Option<control::Server>>,
 }
 
 #[derive(Debug)]
 pub struct KeeperBuilder {
     path: PathBuf,
-    cleanup_interval: Duration,
+    cleanup_schedule: Schedule,
+    cleanup_on_start: bool,
+    recovery_budget: Option<Duration>,
+    janitor: bool,
+    temp_grace: Duration,
     store_workers: usize,
+    store_idle_timeout: Option<Duration>,
+    store_scale_up_delay: Duration,
+    watermarks: Watermarks,
+    max_queue_len: Option<usize>,
+    request_timeout: Option<Duration>,
+    hooks: Hooks,
+    clock: Box<dyn Clock>,
+    skew_tolerance: Duration,
+    evict_on_full: bool,
+    max_store_bytes: Option<u64>,
+    weigher: Option<Weigher>,
+    min_free_bytes: Option<u64>,
+    quotas: BTreeMap<String, Quota>,
+    policies: HashMap<String, Policy>,
+    lock_strategy: LockStrategy,
+    slow_op_threshold: Option<Duration>,
+    loader: Option<Loader>,
+    refresh_ahead: Option<Duration>,
+    breaker: Option<CircuitBreaker>,
+    load_timeout: Duration,
+    degradation: Option<Degradation>,
+    executor: Option<Executor>,
+    #[cfg(feature = "testing")]
+    faults: Option<Arc<crate::faults::FaultInjector>>,
+    memory_capacity: Option<u64>,
+    max_concurrent_io: Option<usize>,
+    supervisor_timeout: Option<Duration>,
+    signing_key: Option<SigningKey>,
+    codecs: Codecs,
+    hash_key: Option<SigningKey>,
+    seeded_layout: bool,
+    key_ring: Option<KeyRing>,
+    secure_delete: bool,
+    compact_dirs: bool,
+    paths: Vec<PathBuf>,
+    #[cfg(unix)]
+    control_socket: Option<PathBuf>,
+    permissions: Permissions,
+    origin: Option<Attached>,
+    key_stats_capacity: Option<usize>,
+    durable: bool,
+    handle_cache: Option<usize>,
+    header_cache: Option<usize>,
+    direct_io: Option<u64>,
+    access_resolution: Option<Duration>,
+    shards: usize,
 }
 
 impl KeeperBuilder {
     pub fn new(path: PathBuf) -> Self {
         Self {
             path,
-            cleanup_interval: Duration::from_mins(60),
+            cleanup_schedule: Schedule::default(),
+            cleanup_on_start: false,
+            recovery_budget: None,
+            janitor: true,
+            temp_grace: Duration::from_mins(10),
             store_workers: 1,
+            store_idle_timeout: None,
+            store_scale_up_delay: Duration::ZERO,
+            watermarks: Watermarks::default(),
+            max_queue_len: None,
+            request_timeout: None,
+            hooks: Hooks::default(),
+            clock: Box::new(SystemClock),
+            skew_tolerance: Duration::ZERO,
+            evict_on_full: false,
+            max_store_bytes: None,
+            weigher: None,
+            min_free_bytes: None,
+            quotas: BTreeMap::new(),
+            policies: HashMap::new(),
+            lock_strategy: LockStrategy::default(),
+            slow_op_threshold: None,
+            loader: None,
+            refresh_ahead: None,
+            breaker: None,
+            load_timeout: loader::DEFAULT_LOAD_TIMEOUT,
+            degradation: None,
+            executor: None,
+            #[cfg(feature = "testing")]
+            faults: None,
+            memory_capacity: None,
+            max_concurrent_io: None,
+            supervisor_timeout: None,
+            signing_key: None,
+            codecs: Codecs::default(),
+            hash_key: None,
+            seeded_layout: false,
+            key_ring: None,
+            secure_delete: false,
+            compact_dirs: cfg!(unix),
+            paths: Vec::new(),
+            #[cfg(unix)]
+            control_socket: None,
+            permissions: Permissions::default(),
+            origin: None,
+            key_stats_capacity: None,
+            durable: false,
+            handle_cache: None,
+            header_cache: None,
+            direct_io: None,
+            access_resolution: None,
+            shards: DEFAULT_SHARDS,
         }
     }
 
     pub fn with_cleanup_interval(mut self, interval: Duration) -> Self {
-        self.cleanup_interval = interval;
+        self.cleanup_schedule = Schedule::Interval(interval);
+        self
+    }
+
+    pub fn with_cleanup_schedule(mut self, schedule: Schedule) -> Self {
+        self.cleanup_schedule = schedule;
+        self
+    }
+
+    /// Sweeps the store as soon as the janitor starts, also removing leftovers
+    /// of interrupted writes, instead of waiting for the first scheduled run.
+    pub fn with_cleanup_on_start(mut self, enabled: bool) -> Self {
+        self.cleanup_on_start = enabled;
+        self
+    }
+
+    /// Scans the store before `build` returns, spending at most `budget`,
+    /// and removes what a crash left behind: temporary files of unfinished
+    /// writes, entries too short to hold a header, and interrupted folder
+    /// repacks. Folders not reached in time are left to the janitor. See
+    /// [`Keeper::recovery_report`].
+    pub fn with_recovery_on_open(mut self, budget: Duration) -> Self {
+        self.recovery_budget = Some(budget);
+        self
+    }
+
+    /// Doesn't start the janitor thread: expired entries are only removed when
+    /// read or when `cleanup` is called, which then runs on a store worker.
+    /// Size and free space limits are also enforced by `cleanup` only.
+    pub fn without_janitor(mut self) -> Self {
+        self.janitor = false;
+        self
+    }
+
+    /// Age after which the janitor treats temporary files as leftovers of
+    /// interrupted writes and removes them. Defaults to 10 minutes.
+    pub fn with_temp_grace_period(mut self, grace: Duration) -> Self {
+        self.temp_grace = grace;
         self
     }
 
@@ -46,289 +257,2046 @@ impl KeeperBuilder {
         self
     }
 
-    pub fn build(self) -> Result<Keeper, Error> {
-        Keeper::new_with_builder(self)
+    pub fn with_store_workers_auto(mut self) -> Self {
+        self.store_workers = std::thread::available_parallelism()
+            .map(|n| n.get())
+            .unwrap_or(1);
+        self
+    }
+
+    /// Treats the worker count as an upper bound: the pool starts with a single
+    /// worker, spawns more while the queue is deeper than the number of live
+    /// workers and retires workers that stay idle for `idle_timeout`.
+    pub fn with_store_autoscale(mut self, idle_timeout: Duration) -> Self {
+        self.store_idle_timeout = Some(idle_timeout);
+        self
+    }
+
+    /// With autoscaling, only spawns a worker once the queue stayed deeper than
+    /// the number of live workers for `delay`, so short bursts are absorbed by
+    /// the workers already running.
+    pub fn with_store_scale_up_delay(mut self, delay: Duration) -> Self {
+        self.store_scale_up_delay = delay;
+        self
+    }
+
+    /// Starts a thread that replaces store workers stuck on a single operation
+    /// for longer than `timeout`, so queued requests aren't stranded behind a
+    /// hung disk. See [`Keeper::health`].
+    pub fn with_supervisor(mut self, timeout: Duration) -> Self {
+        self.supervisor_timeout = Some(timeout);
+        self
+    }
+
+    /// `on_queue_high` fires once the number of pending store operations reaches
+    /// `high`; `on_queue_low` fires after it drains back down to `low`.
+    pub fn with_queue_watermarks(mut self, high: usize, low: usize) -> Self {
+        self.watermarks.set_levels(high, low);
+        self
+    }
+
+    /// Rejects store operations with `Error::QueueFull` instead of queuing
+    /// them once `len` are pending, so callers can shed load or fall back
+    /// rather than wait. Unbounded by default.
+    pub fn with_max_queue_len(mut self, len: usize) -> Self {
+        self.max_queue_len = Some(len.max(1));
+        self
+    }
+
+    /// Gives every store operation `timeout` from the moment it's queued to
+    /// be picked up by a worker, failing it with `Error::Timeout` otherwise,
+    /// so a backlog doesn't do work nobody waits for anymore. Combines with
+    /// [`Keeper::with_deadline`], the earlier of the two applying.
+    pub fn with_request_timeout(mut self, timeout: Duration) -> Self {
+        self.request_timeout = Some(timeout);
+        self
+    }
+
+    pub fn on_queue_high<F>(mut self, hook: F) -> Self
+    where
+        F: Fn(usize) + Send + Sync + 'static,
+    {
+        self.watermarks.set_on_high(Box::new(hook));
+        self
+    }
+
+    pub fn on_queue_low<F>(mut self, hook: F) -> Self
+    where
+        F: Fn(usize) + Send + Sync + 'static,
+    {
+        self.watermarks.set_on_low(Box::new(hook));
+        self
+    }
+
+    /// Receives failures that have no caller to report to, such as janitor I/O
+    /// errors and worker panics.
+    pub fn with_error_hook<F>(mut self, hook: F) -> Self
+    where
+        F: Fn(&Error) + Send + Sync + 'static,
+    {
+        self.hooks.set_error(Box::new(hook));
+        self
+    }
+
+    pub fn with_clock<C: Clock>(mut self, clock: C) -> Self {
+        self.clock = Box::new(clock);
+        self
+    }
+
+    pub fn with_monotonic_clock(self) -> Self {
+        self.with_clock(MonotonicClock::default())
+    }
+
+    /// Entries are kept for this long past their deadline before being treated
+    /// as expired, absorbing small differences between clocks.
+    pub fn with_clock_skew_tolerance(mut self, tolerance: Duration) -> Self {
+        self.skew_tolerance = tolerance;
+        self
+    }
+
+    /// When a `set` fails because the disk is full, remove expired entries and
+    /// then the oldest ones, and retry the write once.
+    pub fn with_evict_on_full(mut self, enabled: bool) -> Self {
+        self.evict_on_full = enabled;
+        self
+    }
+
+    /// Wakes the janitor as soon as the store grows past `bytes`. Besides
+    /// removing expired entries, it then evicts the oldest ones until the store
+    /// fits again.
+    pub fn with_max_store_size(mut self, bytes: u64) -> Self {
+        self.max_store_bytes = Some(bytes);
+        self
+    }
+
+    /// Counts each entry against [`KeeperBuilder::with_max_store_size`] as
+    /// `weigh(key, value)` instead of the size of its file, e.g. to cap the
+    /// decompressed size or the cost of rebuilding values. The weight of an
+    /// entry isn't stored, so replacing, removing or sweeping one reads its
+    /// value back to weigh it. Running out of disk still evicts by size.
+    pub fn with_weigher<F>(mut self, weigh: F) -> Self
+    where
+        F: Fn(&str, &[u8]) -> u64 + Send + Sync + 'static,
+    {
+        self.weigher = Some(Weigher::new(Box::new(weigh)));
+        self
+    }
+
+    /// Like [`KeeperBuilder::with_max_store_size`], triggered when the free
+    /// space of the volume holding the store drops below `bytes`. Only
+    /// supported on Unix.
+    pub fn with_min_free_space(mut self, bytes: u64) -> Self {
+        self.min_free_bytes = Some(bytes);
+        self
+    }
+
+    /// Caps the bytes and entries the namespace may hold. Quotas are recorded
+    /// in the manifest and stay in effect for later opens of the store.
+    pub fn with_namespace_quota(mut self, name: &str, quota: Quota) -> Self {
+        self.quotas.insert(name.into(), quota);
+        self
+    }
+
+    /// Overrides the default TTL, value size limit and quota behaviour for
+    /// the entries of namespace `name`, see [`Policy`]. Unlike quotas,
+    /// policies aren't recorded in the manifest and must be passed on every
+    /// open.
+    pub fn with_namespace_policy(mut self, name: &str, policy: Policy) -> Self {
+        self.policies.insert(name.into(), policy);
+        self
+    }
+
+    /// Mode bits for every file keeper creates, like `0o600`, instead of what
+    /// the umask leaves. Only supported on Unix.
+    pub fn with_file_mode(mut self, mode: u32) -> Self {
+        self.permissions.file_mode = Some(mode);
+        self
+    }
+
+    /// Mode bits for the shard and quarantine directories, like `0o700`. Only
+    /// supported on Unix.
+    pub fn with_dir_mode(mut self, mode: u32) -> Self {
+        self.permissions.dir_mode = Some(mode);
+        self
+    }
+
+    pub fn with_lock_strategy(mut self, strategy: LockStrategy) -> Self {
+        self.lock_strategy = strategy;
+        self
+    }
+
+    /// Operations taking at least `threshold` inside a store worker are passed
+    /// to `hook` along with the key hash and value size.
+    pub fn with_slow_op_hook<F>(mut self, threshold: Duration, hook: F) -> Self
+    where
+        F: Fn(&SlowOp) + Send + Sync + 'static,
+    {
+        self.slow_op_threshold = Some(threshold);
+        self.hooks.set_slow_op(Box::new(hook));
+        self
+    }
+
+    /// Lets at most `n` store workers touch the disk at the same time, however
+    /// many workers there are.
+    pub fn with_max_concurrent_io(mut self, n: usize) -> Self {
+        self.max_concurrent_io = Some(n);
+        self
+    }
+
+    /// Signs every entry written with an HMAC-SHA256 keyed by `secret`. Reads
+    /// of entries that weren't signed with it, or were modified since, fail
+    /// with [`Error::TamperDetected`].
+    pub fn with_signing_key(mut self, secret: &[u8]) -> Self {
+        self.signing_key = Some(SigningKey::new(secret));
+        self
+    }
+
+    /// Names files after an HMAC of their key keyed by `secret` instead of a
+    /// plain hash, so someone who can list the store can't check whether a
+    /// given key is cached. A store must always be opened with the same
+    /// secret; the manifest only records that one is used.
+    pub fn with_hash_key(mut self, secret: &[u8]) -> Self {
+        self.hash_key = Some(SigningKey::new(secret));
+        self
+    }
+
+    /// Places entries by a hash seeded with a random value picked when the
+    /// store is created and recorded in its manifest, so a set of keys crafted
+    /// to land in the same folder and lock, or that does so by accident,
+    /// spreads out evenly like any other. The seed isn't secret, see
+    /// [`KeeperBuilder::with_hash_key`] for that, which takes precedence. A
+    /// store must always be opened with the same setting.
+    pub fn with_seeded_layout(mut self, enabled: bool) -> Self {
+        self.seeded_layout = enabled;
+        self
+    }
+
+    /// Takes the secrets for `purposes` from `provider` instead of raw bytes:
+    /// a random data key is generated per purpose on first open, wrapped by
+    /// the provider and stored next to the manifest. Overrides
+    /// [`KeeperBuilder::with_signing_key`] and
+    /// [`KeeperBuilder::with_hash_key`] for those purposes.
+    pub fn with_key_provider<P>(mut self, provider: P, purposes: &[KeyPurpose]) -> Self
+    where
+        P: KeyProvider + 'static,
+    {
+        self.key_ring = Some(KeyRing {
+            provider: Arc::new(provider),
+            purposes: purposes.to_vec(),
+        });
+        self
+    }
+
+    /// Appends `codec` to the transforms values go through before being
+    /// written, like compression then encryption; reads undo them in reverse.
+    /// Entries record the codecs they were written with, so changing the
+    /// chain leaves existing entries readable as long as every codec they
+    /// used is still registered. Signing, if enabled, covers the encoded
+    /// value.
+    pub fn with_codec<C>(mut self, codec: C) -> Self
+    where
+        C: Codec + 'static,
+    {
+        self.codecs.push(Arc::new(codec));
+        self
+    }
+
+    /// Registers `codec` for reading entries written with it, without using
+    /// it for new writes. For codecs retired from the chain.
+    pub fn with_read_codec<C>(mut self, codec: C) -> Self
+    where
+        C: Codec + 'static,
+    {
+        self.codecs.register(Arc::new(codec));
+        self
+    }
+
+    /// Syncs every write to disk before acknowledging it. Concurrent writes
+    /// share syncs, so throughput grows with the number of store workers.
+    pub fn with_durable_writes(mut self, enabled: bool) -> Self {
+        self.durable = enabled;
+        self
+    }
+
+    /// Overwrites entries with zeros before unlinking them when they are
+    /// removed, evicted, expire or cleared, and when [`KeeperBuilder::migrate`]
+    /// rewrites them. Best effort: journaling and copy-on-write
+    /// filesystems or SSD wear leveling may still keep the old blocks, and
+    /// values replaced by a `set` are not overwritten.
+    pub fn with_secure_delete(mut self, enabled: bool) -> Self {
+        self.secure_delete = enabled;
+        self
+    }
+
+    /// Lets the janitor repack shard folders whose directory grew much larger
+    /// than their entries need, as ext4 directories never shrink after heavy
+    /// churn. Enabled by default on Unix; it relies on renaming a directory
+    /// over an empty one, so it can't be enabled elsewhere.
+    pub fn with_directory_compaction(mut self, enabled: bool) -> Self {
+        self.compact_dirs = enabled && cfg!(unix);
+        self
+    }
+
+    /// Spreads the shard folders over `paths`, typically one per disk, folder
+    /// `f` living on `paths[f % paths.len()]` and linked from the store path.
+    /// Include the store path to keep its share of folders there. Only folders
+    /// created from now on are placed, so existing entries never move and
+    /// paths can be added later. The manifest, tenants and
+    /// [`KeeperBuilder::with_min_free_space`] stay on the store path. Unix
+    /// only.
+    pub fn with_paths(mut self, paths: Vec<PathBuf>) -> Self {
+        self.paths = paths;
+        self
+    }
+
+    /// Listens for admin commands on a Unix socket at `path`, which
+    /// `keeper-cli admin` sends: `cleanup-now`, `pause-janitor`,
+    /// `resume-janitor` and `set-interval <seconds>`. Only the owner can
+    /// connect, and the socket is removed on shutdown.
+    #[cfg(unix)]
+    pub fn with_control_socket(mut self, path: PathBuf) -> Self {
+        self.control_socket = Some(path);
+        self
+    }
+
+    /// Keeps up to `bytes` of recently read values in memory. Values are only
+    /// admitted when read more often than the ones they would evict.
+    pub fn with_memory_cache(mut self, bytes: u64) -> Self {
+        self.memory_capacity = Some(bytes);
+        self
+    }
+
+    /// Tracks hit counts and last access times of the `capacity` most read
+    /// keys, for [`Keeper::key_stats`] and [`Keeper::hottest_keys`].
+    pub fn with_key_stats(mut self, capacity: usize) -> Self {
+        self.key_stats_capacity = Some(capacity);
+        self
+    }
+
+    /// Keeps up to `capacity` entry files open so repeated reads of hot keys
+    /// skip opening and closing them. Keep it well below the process's file
+    /// descriptor limit. Only effective on Unix.
+    pub fn with_handle_cache(mut self, capacity: usize) -> Self {
+        self.handle_cache = Some(capacity);
+        self
+    }
+
+    /// Remembers the expiration time and size of up to `capacity` entries,
+    /// so [`Keeper::head`] and [`Keeper::contains_key`] on hot keys don't
+    /// touch the file system.
+    pub fn with_header_cache(mut self, capacity: usize) -> Self {
+        self.header_cache = Some(capacity);
+        self
+    }
+
+    /// Reads and writes entries of at least `threshold` bytes with direct I/O,
+    /// so large values don't push the application's data out of the page
+    /// cache. Falls back to buffered I/O where the platform or filesystem
+    /// doesn't support it; currently only Linux does.
+    pub fn with_direct_io(mut self, threshold: u64) -> Self {
+        self.direct_io = Some(threshold);
+        self
+    }
+
+    /// Records reads in the modification time of entries, so LRU eviction
+    /// (on a full disk, over the size limits or in namespaces that evict)
+    /// drops the least recently used entries rather than the least recently
+    /// written. Each read costs a `stat`, plus a metadata write when the
+    /// entry's time is older than `resolution`, so a larger resolution means
+    /// fewer writes and coarser ordering. Off by default, which leaves reads
+    /// free of any write.
+    pub fn with_access_tracking(mut self, resolution: Duration) -> Self {
+        self.access_resolution = Some(resolution);
+        self
+    }
+
+    /// Sets how many locks guard the entries, rounded up to a power of two
+    /// between 1 and 65536. The default of 4096 gives every shard folder its
+    /// own lock; more locks split each folder so writers of keys that share a
+    /// folder contend less, at the cost of whole-folder operations like the
+    /// janitor's scan taking several locks.
+    pub fn with_shards(mut self, count: usize) -> Self {
+        self.shards = count;
+        self
+    }
+
+    /// Registers the function `get_or_compute` calls on a miss. Its value is
+    /// stored with the returned time to live. A store worker waits for it,
+    /// up to [`KeeperBuilder::with_load_timeout`], so slow loaders hold up
+    /// other requests meanwhile.
+    pub fn with_loader<F>(mut self, load: F) -> Self
+    where
+        F: Fn(&str) -> Result<(Vec<u8>, Option<Duration>), Error> + Send + Sync + 'static,
+    {
+        self.loader = Some(Loader::new(Box::new(load)));
+        self
+    }
+
+    /// Puts the keeper in front of `origin`: `get` and `get_or_compute` load
+    /// missing keys from it, replacing any loader, and with `write_through`,
+    /// `set`, `try_set` and `merge` store the value in the origin before
+    /// caching it. A write the origin rejects isn't cached.
+    pub fn with_origin<O>(mut self, origin: O, write_through: bool) -> Self
+    where
+        O: Origin + 'static,
+    {
+        let origin: Arc<dyn Origin> = Arc::new(origin);
+        self.loader = Some(Loader::new(Box::new({
+            let origin = origin.clone();
+            move |key| origin.load(key)
+        })));
+        self.origin = Some(Attached {
+            origin,
+            write_through,
+        });
+        self
+    }
+
+    /// How long a store worker waits for the loader before failing the read
+    /// with [`Error::LoadTimeout`], 30 seconds by default. Loads run on a
+    /// thread of their own, so one that hangs is left behind instead of
+    /// blocking the worker; past a few of those, loads fail right away.
+    pub fn with_load_timeout(mut self, timeout: Duration) -> Self {
+        self.load_timeout = timeout;
+        self
+    }
+
+    /// Reloads entries read less than `window` before they expire, right
+    /// after answering the read. Needs a loader.
+    pub fn with_refresh_ahead(mut self, window: Duration) -> Self {
+        self.refresh_ahead = Some(window);
+        self
+    }
+
+    /// Fails fast, or serves stale entries, for keys whose loader keeps
+    /// failing. Needs a loader.
+    pub fn with_circuit_breaker(mut self, breaker: CircuitBreaker) -> Self {
+        self.breaker = Some(breaker);
+        self
+    }
+
+    /// Bypasses the disk after `failures` IO errors in a row, so a failing
+    /// disk degrades the cache instead of failing every request. Meanwhile
+    /// reads miss, `get_or_compute` calls the loader without storing, and
+    /// writes are dropped; requests that need what is stored fail with
+    /// [`Error::DiskBypassed`]. Every `probe_interval`, a request first writes and
+    /// reads back a probe file, and once that works the keys written
+    /// meanwhile are removed from the disk, or the whole store cleared when
+    /// too many were, before the disk is used again.
+    pub fn with_degradation(mut self, degradation: Degradation) -> Self {
+        self.degradation = Some(degradation);
+        self
+    }
+
+    /// Runs the callbacks of requests through `execute` instead of on the
+    /// store worker that answered them, so slow callbacks can't hold up the
+    /// queue. Requests rejected before reaching a worker have their callbacks
+    /// run through `execute` too. `execute` itself runs on the worker, or on
+    /// the calling thread for those, and should only hand the task over, to a
+    /// thread pool, a runtime or a channel.
+    #[cfg(all(not(feature = "async"), not(feature = "sync")))]
+    pub fn with_callback_executor<F>(mut self, execute: F) -> Self
+    where
+        F: Fn(crate::executor::Task) + Send + Sync + 'static,
+    {
+        self.executor = Some(Executor::new(Box::new(execute)));
+        self
+    }
+
+    /// Makes entry reads and writes fail, stall or tear as `faults` decides,
+    /// to test how the application copes. Keep the `Arc` to check how many
+    /// faults were injected.
+    #[cfg(feature = "testing")]
+    pub fn with_fault_injector(mut self, faults: Arc<crate::faults::FaultInjector>) -> Self {
+        self.faults = Some(faults);
+        self
+    }
+
+    /// Called for every entry removed without an explicit `remove`: expired
+    /// entries found by the janitor or by `get`, corrupted entries and entries
+    /// evicted to free space.
+    pub fn on_eviction<F>(mut self, hook: F) -> Self
+    where
+        F: Fn(&Eviction) + Send + Sync + 'static,
+    {
+        self.hooks.set_eviction(Box::new(hook));
+        self
+    }
+
+    /// Called with the outcome of every janitor sweep.
+    pub fn on_janitor_sweep<F>(mut self, hook: F) -> Self
+    where
+        F: Fn(&JanitorStatus) + Send + Sync + 'static,
+    {
+        self.hooks.set_sweep(Box::new(hook));
+        self
+    }
+
+    pub fn build(self) -> Result<Keeper, Error> {
+        Keeper::new_with_builder(self)
+    }
+
+    /// Migrates the store like [`Keeper::migrate`], locking it with the
+    /// builder's lock strategy and writing the entries with the file mode,
+    /// durability and secure delete set on the builder.
+    pub fn migrate(self, target_version: u16) -> Result<MigrationReport, Error> {
+        let durability = self
+            .durable
+            .then(|| GroupCommit::new(mounts::roots(&self.path, &self.paths)));
+        migrate::migrate_with(
+            &self.path,
+            target_version,
+            &self.lock_strategy,
+            &self.permissions,
+            durability.as_ref(),
+            self.secure_delete,
+        )
+    }
+}
+
+/// A handle to the store. Clones share it; see [`Keeper::with_deadline`] and
+/// [`Keeper::with_priority`] for what a handle attaches to its requests.
+#[derive(Debug, Clone)]
+pub struct Keeper(Arc<Inner>, Dispatch);
+
+/// How a handle queues its requests.
+#[derive(Debug, Clone, Copy, Default)]
+struct Dispatch {
+    deadline: Option<Instant>,
+    priority: Priority,
+}
+
+impl Keeper {
+    pub fn new(path: PathBuf) -> Result<Self, Error> {
+        KeeperBuilder::new(path).build()
+    }
+
+    pub fn new_with_builder(mut builder: KeeperBuilder) -> Result<Self, Error> {
+        builder.path = utils::extended_length(builder.path);
+        let lock = builder
+            .lock_strategy
+            .acquire(&builder.path, &builder.permissions)?;
+
+        if let Some(ring) = &builder.key_ring {
+            for &purpose in &ring.purposes {
+                let secret = keys::data_key(
+                    &*ring.provider,
+                    purpose,
+                    &builder.path,
+                    &builder.permissions,
+                )?;
+                let key = Some(SigningKey::new(&secret));
+                match purpose {
+                    KeyPurpose::Signing => builder.signing_key = key,
+                    KeyPurpose::Hashing => builder.hash_key = key,
+                }
+            }
+        }
+
+        let hasher = match (&builder.hash_key, builder.seeded_layout) {
+            (Some(_), _) => manifest::KEYED_HASHER,
+            (None, true) => manifest::SEEDED_HASHER,
+            (None, false) => manifest::HASHER,
+        };
+        let mut manifest = Manifest::open(&builder.path, builder.clock.now(), hasher)?;
+        for (name, quota) in builder.quotas {
+            namespace::validate_name(&name)?;
+            manifest.namespaces.entry(name).or_default().quota = quota;
+        }
+        for name in builder.policies.keys() {
+            namespace::validate_name(name)?;
+        }
+        manifest.save_with(&builder.path, &builder.permissions)?;
+        mounts::link(&builder.path, &builder.paths, &builder.permissions)?;
+        #[cfg(unix)]
+        let control = match builder.control_socket {
+            Some(socket) => Some((control::bind(&socket)?, socket)),
+            None => None,
+        };
+
+        let path = Arc::new(builder.path);
+        let (store_is, store_ir) = queue::channel();
+        let (janitor_is, janitor_ir) = unbounded::<janitor::InputMessage>();
+
+        let ctx = Arc::new(Context {
+            shards: Shards::new(builder.shards),
+            watermarks: builder.watermarks,
+            hooks: builder.hooks,
+            clock: builder.clock,
+            skew_tolerance: builder.skew_tolerance.as_secs(),
+            evict_on_full: builder.evict_on_full,
+            namespaces: Namespaces::new(manifest.namespaces.clone(), builder.policies),
+            counters: Counters::new(manifest.stats),
+            latencies: Default::default(),
+            slow_op_threshold: builder.slow_op_threshold,
+            janitor_status: Default::default(),
+            pressure: Pressure::new(
+                builder.max_store_bytes,
+                builder.min_free_bytes,
+                janitor_is.clone(),
+            ),
+            temp_grace: builder.temp_grace,
+            loader: builder.loader.map(|mut loader| {
+                loader.set_timeout(builder.load_timeout);
+                if let Some(window) = builder.refresh_ahead {
+                    loader.set_refresh_ahead(window);
+                }
+                if let Some(breaker) = builder.breaker {
+                    loader.set_breaker(breaker);
+                }
+                loader
+            }),
+            memory: builder.memory_capacity.map(MemoryCache::new),
+            io_limit: builder.max_concurrent_io.map(Semaphore::new),
+            signing_key: builder.signing_key,
+            codecs: builder.codecs,
+            hash_seed: (hasher == manifest::SEEDED_HASHER).then_some(manifest.seed),
+            hash_key: builder.hash_key,
+            origin: builder.origin,
+            key_stats: builder.key_stats_capacity.map(KeyTracker::new),
+            handles: builder.handle_cache.map(HandleCache::new),
+            headers: builder.header_cache.map(HeaderCache::new),
+            direct_io: builder.direct_io,
+            access_resolution: builder.access_resolution,
+            cleared_before: AtomicU64::new(manifest.cleared_before),
+            horizons: Horizons::open(&path)?,
+            generation: AtomicU64::new(0),
+            durability: builder
+                .durable
+                .then(|| GroupCommit::new(mounts::roots(&path, &builder.paths))),
+            mounts: mounts::roots(&path, &builder.paths),
+            secure_delete: builder.secure_delete,
+            compact_dirs: builder.compact_dirs,
+            weigher: builder.weigher,
+            bypass: builder
+                .degradation
+                .map(|policy| Bypass::new(policy, path.to_path_buf())),
+            #[cfg(feature = "testing")]
+            faults: builder.faults,
+            permissions: builder.permissions,
+            manifest: Mutex::new(manifest),
+        });
+
+        let recovery = builder
+            .recovery_budget
+            .map(|budget| recovery::recover(&ctx, &path, budget));
+
+        let pool = Arc::new(Pool::new(
+            ctx.clone(),
+            store_ir,
+            builder.store_workers,
+            builder.store_idle_timeout,
+            builder.store_scale_up_delay,
+        ));
+
+        let supervisor = builder.supervisor_timeout.map(|timeout| {
+            let (stop_is, stop_ir) = unbounded::<()>();
+            let handle = std::thread::spawn({
+                let pool = pool.clone();
+                let interval = (timeout / 4).max(Duration::from_millis(10));
+                move || {
+                    while let Err(RecvTimeoutError::Timeout) = stop_ir.recv_timeout(interval) {
+                        pool.supervise(timeout);
+                    }
+                }
+            });
+            (stop_is, handle)
+        });
+
+        let janitor_ir = builder.janitor.then_some(janitor_ir);
+        let janitor_handle = janitor_ir.clone().map(|janitor_ir| {
+            std::thread::spawn({
+                let path = path.clone();
+                let ctx = ctx.clone();
+                let mut on_start = builder.cleanup_on_start;
+                let mut control = janitor::Control {
+                    schedule: builder.cleanup_schedule.clone(),
+                    paused: false,
+                };
+                move || {
+                    ctx.respawn(Worker::Janitor, || {
+                        janitor::worker(
+                            &mut control,
+                            path.clone(),
+                            ctx.clone(),
+                            janitor_ir.clone(),
+                            std::mem::take(&mut on_start),
+                        )
+                    })
+                }
+            })
+        });
+
+        let inner = Inner {
+            path,
+            _lock: lock,
+            ctx,
+
+            store_is,
+            janitor_is,
+            janitor_ir,
+            closing: AtomicBool::new(false),
+            max_queue_len: builder.max_queue_len,
+            request_timeout: builder.request_timeout,
+            recovery,
+            executor: builder.executor.map(Arc::new),
+
+            pool,
+            janitor_handle: Mutex::new(janitor_handle),
+            supervisor: Mutex::new(supervisor),
+            #[cfg(unix)]
+            control: Mutex::new(None),
+        };
+        let keeper = Self(Arc::new(inner), Dispatch::default());
+
+        #[cfg(unix)]
+        if let Some((listener, socket)) = control {
+            let inner = Arc::downgrade(&keeper.0);
+            let server = control::Server::start(listener, socket, move || {
+                inner
+                    .upgrade()
+                    .map(|inner| Self(inner, Dispatch::default()))
+            });
+            *keeper.0.control.lock().unwrap_or_else(|e| e.into_inner()) = Some(server);
+        }
+
+        Ok(keeper)
+    }
+
+    /// Rewrites every entry of the store at `path` into `target_version`, see
+    /// [`migrate::migrate`]. [`KeeperBuilder::migrate`] locks the store and
+    /// writes them as the builder is set up instead of with the defaults.
+    pub fn migrate(path: &Path, target_version: u16) -> Result<MigrationReport, Error> {
+        migrate::migrate(path, target_version)
+    }
+
+    #[cfg(all(feature = "async", not(feature = "sync")))]
+    pub async fn get(&self, key: &str) -> Result<Vec<u8>, Error> {
+        let (tx, rx) = oneshot::channel();
+        self.dispatch_get(key, move |res| {
+            let _ = tx.send(res);
+        });
+        rx.await.map_err(|_| self.closed())?
+    }
+
+    /// Like `get`, queued with `priority`; see [`Keeper::with_priority`].
+    #[cfg(all(feature = "async", not(feature = "sync")))]
+    pub async fn get_with(&self, priority: Priority, key: &str) -> Result<Vec<u8>, Error> {
+        self.with_priority(priority).get(key).await
+    }
+
+    /// Like `get`, calling the registered loader on a miss and storing what it
+    /// returns.
+    #[cfg(all(feature = "async", not(feature = "sync")))]
+    pub async fn get_or_compute(&self, key: &str) -> Result<Vec<u8>, Error> {
+        let (tx, rx) = oneshot::channel();
+        self.dispatch_get_or_load(key, move |res| {
+            let _ = tx.send(res);
+        });
+        rx.await.map_err(|_| self.closed())?
+    }
+
+    #[cfg(all(feature = "async", not(feature = "sync")))]
+    pub async fn set(
+        &self,
+        key: &str,
+        value: &[u8],
+        duration: Option<Duration>,
+    ) -> Result<(), Error> {
+        let (tx, rx) = oneshot::channel();
+        self.dispatch_set(key, value, duration, move |res| {
+            let _ = tx.send(res);
+        });
+        rx.await.map_err(|_| self.closed())?
+    }
+
+    /// Like `set`, queueing `value` as is instead of copying it, for callers
+    /// that already own the value in a refcounted buffer.
+    #[cfg(all(feature = "async", not(feature = "sync")))]
+    pub async fn set_shared(
+        &self,
+        key: &str,
+        value: Arc<[u8]>,
+        duration: Option<Duration>,
+    ) -> Result<(), Error> {
+        let (tx, rx) = oneshot::channel();
+        self.dispatch_set(key, value, duration, move |res| {
+            let _ = tx.send(res);
+        });
+        rx.await.map_err(|_| self.closed())?
+    }
+
+    /// Blocks the current thread until the value was read, for code that runs
+    /// outside of a runtime. Must not be called from an async context.
+    #[cfg(all(feature = "async", not(feature = "sync")))]
+    pub fn get_blocking(&self, key: &str) -> Result<Vec<u8>, Error> {
+        let (tx, rx) = std::sync::mpsc::sync_channel(1);
+        self.dispatch_get(key, move |res| {
+            let _ = tx.send(res);
+        });
+        rx.recv().map_err(|_| self.closed())?
+    }
+
+    /// Blocks the current thread until the value was written, for code that
+    /// runs outside of a runtime. Must not be called from an async context.
+    #[cfg(all(feature = "async", not(feature = "sync")))]
+    pub fn set_blocking(
+        &self,
+        key: &str,
+        value: &[u8],
+        duration: Option<Duration>,
+    ) -> Result<(), Error> {
+        let (tx, rx) = std::sync::mpsc::sync_channel(1);
+        self.dispatch_set(key, value, duration, move |res| {
+            let _ = tx.send(res);
+        });
+        rx.recv().map_err(|_| self.closed())?
+    }
+
+    #[cfg(all(feature = "async", not(feature = "sync")))]
+    pub async fn remove(&self, key: &str) -> Result<(), Error> {
+        let (tx, rx) = oneshot::channel();
+        self.dispatch_remove(key, move |res| {
+            let _ = tx.send(res);
+        });
+        rx.await.map_err(|_| self.closed())?
+    }
+
+    /// Makes `key` expire after `duration` instead, or never with `None`,
+    /// keeping its value. Fails with `Error::NotFound` if it isn't stored.
+    #[cfg(all(feature = "async", not(feature = "sync")))]
+    pub async fn touch(&self, key: &str, duration: Option<Duration>) -> Result<(), Error> {
+        let (tx, rx) = oneshot::channel();
+        self.dispatch_touch(key, duration, move |res| {
+            let _ = tx.send(res);
+        });
+        rx.await.map_err(|_| self.closed())?
+    }
+
+    /// Like `get`, failing with `Error::WouldBlock` instead of waiting behind
+    /// queued requests, a locked shard or the I/O limit.
+    #[cfg(all(feature = "async", not(feature = "sync")))]
+    pub async fn try_get(&self, key: &str) -> Result<Vec<u8>, Error> {
+        let (tx, rx) = oneshot::channel();
+        self.dispatch_try_get(key, move |res| {
+            let _ = tx.send(res);
+        });
+        rx.await.map_err(|_| self.closed())?
+    }
+
+    /// Like `set`, failing with `Error::WouldBlock` instead of waiting behind
+    /// queued requests, a locked shard or the I/O limit.
+    #[cfg(all(feature = "async", not(feature = "sync")))]
+    pub async fn try_set(
+        &self,
+        key: &str,
+        value: &[u8],
+        duration: Option<Duration>,
+    ) -> Result<(), Error> {
+        let (tx, rx) = oneshot::channel();
+        self.dispatch_try_set(key, value, duration, move |res| {
+            let _ = tx.send(res);
+        });
+        rx.await.map_err(|_| self.closed())?
+    }
+
+    /// Combines the current value of `key` with `value` on a store worker,
+    /// under the shard write lock, and returns the stored result. `duration`
+    /// only applies when the entry is created.
+    #[cfg(all(feature = "async", not(feature = "sync")))]
+    pub async fn merge<F>(
+        &self,
+        key: &str,
+        value: &[u8],
+        duration: Option<Duration>,
+        merge_fn: F,
+    ) -> Result<Vec<u8>, Error>
+    where
+        F: FnOnce(Option<&[u8]>, &[u8]) -> Vec<u8> + Send + Sync + 'static,
+    {
+        let (tx, rx) = oneshot::channel();
+        self.dispatch_merge(key, value, duration, merge_fn, move |res| {
+            let _ = tx.send(res);
+        });
+        rx.await.map_err(|_| self.closed())?
+    }
+
+    /// Replaces the value of `key` like `set` and returns the live value it
+    /// replaced, if any, both under the shard write lock, so no other write
+    /// lands in between.
+    #[cfg(all(feature = "async", not(feature = "sync")))]
+    pub async fn get_and_set(
+        &self,
+        key: &str,
+        value: &[u8],
+        duration: Option<Duration>,
+    ) -> Result<Option<Vec<u8>>, Error> {
+        let (tx, rx) = oneshot::channel();
+        self.dispatch_get_and_set(key, value, duration, move |res| {
+            let _ = tx.send(res);
+        });
+        rx.await.map_err(|_| self.closed())?
+    }
+
+    /// Exchanges the values of two entries, atomically for other requests:
+    /// none sees one swapped without the other. A crash midway can leave
+    /// both entries with the same value, the swap not being journaled. Fails
+    /// with `Error::NotFound` unless both exist.
+    #[cfg(all(feature = "async", not(feature = "sync")))]
+    pub async fn swap(&self, a: &str, b: &str) -> Result<(), Error> {
+        let (tx, rx) = oneshot::channel();
+        self.dispatch_swap(a, b, move |res| {
+            let _ = tx.send(res);
+        });
+        rx.await.map_err(|_| self.closed())?
+    }
+
+    /// The metadata of the entry for each of `keys`, in order, read in one
+    /// pass on a store worker without the values. Missing and expired
+    /// entries fail with `Error::NotFound`.
+    #[cfg(all(feature = "async", not(feature = "sync")))]
+    pub async fn head_many<I>(&self, keys: I) -> Vec<Result<EntryInfo, Error>>
+    where
+        I: IntoIterator<Item = String>,
+    {
+        let keys: Vec<String> = keys.into_iter().collect();
+        let len = keys.len();
+        let (tx, rx) = oneshot::channel();
+        self.dispatch_head_many(keys, move |res| {
+            let _ = tx.send(res);
+        });
+        rx.await
+            .unwrap_or_else(|_| (0..len).map(|_| Err(self.closed())).collect())
+    }
+
+    #[cfg(all(feature = "async", not(feature = "sync")))]
+    pub async fn clear(&self) -> Result<(), Error> {
+        let (tx, rx) = oneshot::channel();
+        self.dispatch_clear(move |res| {
+            let _ = tx.send(res);
+        });
+        rx.await.map_err(|_| self.closed())?
+    }
+
+    #[cfg(all(feature = "async", not(feature = "sync")))]
+    pub async fn cleanup(&self) -> Result<(), Error> {
+        let (tx, rx) = oneshot::channel();
+        self.dispatch_cleanup(move |res| {
+            let _ = tx.send(res);
+        });
+        rx.await.map_err(|_| self.closed())?
+    }
+
+    #[cfg(all(feature = "sync", not(feature = "async")))]
+    pub fn get(&self, key: &str) -> Result<Vec<u8>, Error> {
+        let (tx, rx) = std::sync::mpsc::sync_channel(1);
+        self.dispatch_get(key, move |res| {
+            let _ = tx.send(res);
+        });
+        rx.recv().map_err(|_| self.closed())?
+    }
+
+    /// Like `get`, queued with `priority`; see [`Keeper::with_priority`].
+    #[cfg(all(feature = "sync", not(feature = "async")))]
+    pub fn get_with(&self, priority: Priority, key: &str) -> Result<Vec<u8>, Error> {
+        self.with_priority(priority).get(key)
+    }
+
+    /// Like `get`, calling the registered loader on a miss and storing what it
+    /// returns.
+    #[cfg(all(feature = "sync", not(feature = "async")))]
+    pub fn get_or_compute(&self, key: &str) -> Result<Vec<u8>, Error> {
+        let (tx, rx) = std::sync::mpsc::sync_channel(1);
+        self.dispatch_get_or_load(key, move |res| {
+            let _ = tx.send(res);
+        });
+        rx.recv().map_err(|_| self.closed())?
+    }
+
+    #[cfg(all(feature = "sync", not(feature = "async")))]
+    pub fn set(&self, key: &str, value: &[u8], duration: Option<Duration>) -> Result<(), Error> {
+        let (tx, rx) = std::sync::mpsc::sync_channel(1);
+        self.dispatch_set(key, value, duration, move |res| {
+            let _ = tx.send(res);
+        });
+        rx.recv().map_err(|_| self.closed())?
+    }
+
+    /// Like `set`, queueing `value` as is instead of copying it, for callers
+    /// that already own the value in a refcounted buffer.
+    #[cfg(all(feature = "sync", not(feature = "async")))]
+    pub fn set_shared(
+        &self,
+        key: &str,
+        value: Arc<[u8]>,
+        duration: Option<Duration>,
+    ) -> Result<(), Error> {
+        let (tx, rx) = std::sync::mpsc::sync_channel(1);
+        self.dispatch_set(key, value, duration, move |res| {
+            let _ = tx.send(res);
+        });
+        rx.recv().map_err(|_| self.closed())?
+    }
+
+    #[cfg(all(feature = "sync", not(feature = "async")))]
+    pub fn remove(&self, key: &str) -> Result<(), Error> {
+        let (tx, rx) = std::sync::mpsc::sync_channel(1);
+        self.dispatch_remove(key, move |res| {
+            let _ = tx.send(res);
+        });
+        rx.recv().map_err(|_| self.closed())?
+    }
+
+    /// Makes `key` expire after `duration` instead, or never with `None`,
+    /// keeping its value. Fails with `Error::NotFound` if it isn't stored.
+    #[cfg(all(feature = "sync", not(feature = "async")))]
+    pub fn touch(&self, key: &str, duration: Option<Duration>) -> Result<(), Error> {
+        let (tx, rx) = std::sync::mpsc::sync_channel(1);
+        self.dispatch_touch(key, duration, move |res| {
+            let _ = tx.send(res);
+        });
+        rx.recv().map_err(|_| self.closed())?
+    }
+
+    /// Like `get`, failing with `Error::WouldBlock` instead of waiting behind
+    /// queued requests, a locked shard or the I/O limit.
+    #[cfg(all(feature = "sync", not(feature = "async")))]
+    pub fn try_get(&self, key: &str) -> Result<Vec<u8>, Error> {
+        let (tx, rx) = std::sync::mpsc::sync_channel(1);
+        self.dispatch_try_get(key, move |res| {
+            let _ = tx.send(res);
+        });
+        rx.recv().map_err(|_| self.closed())?
+    }
+
+    /// Like `set`, failing with `Error::WouldBlock` instead of waiting behind
+    /// queued requests, a locked shard or the I/O limit.
+    #[cfg(all(feature = "sync", not(feature = "async")))]
+    pub fn try_set(
+        &self,
+        key: &str,
+        value: &[u8],
+        duration: Option<Duration>,
+    ) -> Result<(), Error> {
+        let (tx, rx) = std::sync::mpsc::sync_channel(1);
+        self.dispatch_try_set(key, value, duration, move |res| {
+            let _ = tx.send(res);
+        });
+        rx.recv().map_err(|_| self.closed())?
+    }
+
+    /// Combines the current value of `key` with `value` on a store worker,
+    /// under the shard write lock, and returns the stored result. `duration`
+    /// only applies when the entry is created.
+    #[cfg(all(feature = "sync", not(feature = "async")))]
+    pub fn merge<F>(
+        &self,
+        key: &str,
+        value: &[u8],
+        duration: Option<Duration>,
+        merge_fn: F,
+    ) -> Result<Vec<u8>, Error>
+    where
+        F: FnOnce(Option<&[u8]>, &[u8]) -> Vec<u8> + Send + Sync + 'static,
+    {
+        let (tx, rx) = std::sync::mpsc::sync_channel(1);
+        self.dispatch_merge(key, value, duration, merge_fn, move |res| {
+            let _ = tx.send(res);
+        });
+        rx.recv().map_err(|_| self.closed())?
+    }
+
+    /// Replaces the value of `key` like `set` and returns the live value it
+    /// replaced, if any, both under the shard write lock, so no other write
+    /// lands in between.
+    #[cfg(all(feature = "sync", not(feature = "async")))]
+    pub fn get_and_set(
+        &self,
+        key: &str,
+        value: &[u8],
+        duration: Option<Duration>,
+    ) -> Result<Option<Vec<u8>>, Error> {
+        let (tx, rx) = std::sync::mpsc::sync_channel(1);
+        self.dispatch_get_and_set(key, value, duration, move |res| {
+            let _ = tx.send(res);
+        });
+        rx.recv().map_err(|_| self.closed())?
+    }
+
+    /// Exchanges the values of two entries, atomically for other requests:
+    /// none sees one swapped without the other. A crash midway can leave
+    /// both entries with the same value, the swap not being journaled. Fails
+    /// with `Error::NotFound` unless both exist.
+    #[cfg(all(feature = "sync", not(feature = "async")))]
+    pub fn swap(&self, a: &str, b: &str) -> Result<(), Error> {
+        let (tx, rx) = std::sync::mpsc::sync_channel(1);
+        self.dispatch_swap(a, b, move |res| {
+            let _ = tx.send(res);
+        });
+        rx.recv().map_err(|_| self.closed())?
+    }
+
+    /// The metadata of the entry for each of `keys`, in order, read in one
+    /// pass on a store worker without the values. Missing and expired
+    /// entries fail with `Error::NotFound`.
+    #[cfg(all(feature = "sync", not(feature = "async")))]
+    pub fn head_many<I>(&self, keys: I) -> Vec<Result<EntryInfo, Error>>
+    where
+        I: IntoIterator<Item = String>,
+    {
+        let keys: Vec<String> = keys.into_iter().collect();
+        let len = keys.len();
+        let (tx, rx) = std::sync::mpsc::sync_channel(1);
+        self.dispatch_head_many(keys, move |res| {
+            let _ = tx.send(res);
+        });
+        rx.recv()
+            .unwrap_or_else(|_| (0..len).map(|_| Err(self.closed())).collect())
+    }
+
+    #[cfg(all(feature = "sync", not(feature = "async")))]
+    pub fn clear(&self) -> Result<(), Error> {
+        let (tx, rx) = std::sync::mpsc::sync_channel(1);
+        self.dispatch_clear(move |res| {
+            let _ = tx.send(res);
+        });
+        rx.recv().map_err(|_| self.closed())?
+    }
+
+    #[cfg(all(feature = "sync", not(feature = "async")))]
+    pub fn cleanup(&self) -> Result<(), Error> {
+        let (tx, rx) = std::sync::mpsc::sync_channel(1);
+        self.dispatch_cleanup(move |res| {
+            let _ = tx.send(res);
+        });
+        rx.recv().map_err(|_| self.closed())?
+    }
+
+    #[cfg(all(not(feature = "async"), not(feature = "sync")))]
+    pub fn get<F>(&self, key: &str, cb: F)
+    where
+        F: FnOnce(Result<Vec<u8>, Error>) + Send + Sync + 'static,
+    {
+        self.dispatch_get(key, cb);
+    }
+
+    /// Like `get`, queued with `priority`; see [`Keeper::with_priority`].
+    #[cfg(all(not(feature = "async"), not(feature = "sync")))]
+    pub fn get_with<F>(&self, priority: Priority, key: &str, cb: F)
+    where
+        F: FnOnce(Result<Vec<u8>, Error>) + Send + Sync + 'static,
+    {
+        self.with_priority(priority).dispatch_get(key, cb);
+    }
+
+    /// Like `get`, calling the registered loader on a miss and storing what it
+    /// returns.
+    #[cfg(all(not(feature = "async"), not(feature = "sync")))]
+    pub fn get_or_compute<F>(&self, key: &str, cb: F)
+    where
+        F: FnOnce(Result<Vec<u8>, Error>) + Send + Sync + 'static,
+    {
+        self.dispatch_get_or_load(key, cb);
+    }
+
+    #[cfg(all(not(feature = "async"), not(feature = "sync")))]
+    pub fn set<F>(&self, key: &str, value: &[u8], duration: Option<Duration>, cb: F)
+    where
+        F: FnOnce(Result<(), Error>) + Send + Sync + 'static,
+    {
+        self.dispatch_set(key, value, duration, cb);
+    }
+
+    /// Like `set`, queueing `value` as is instead of copying it, for callers
+    /// that already own the value in a refcounted buffer.
+    #[cfg(all(not(feature = "async"), not(feature = "sync")))]
+    pub fn set_shared<F>(&self, key: &str, value: Arc<[u8]>, duration: Option<Duration>, cb: F)
+    where
+        F: FnOnce(Result<(), Error>) + Send + Sync + 'static,
+    {
+        self.dispatch_set(key, value, duration, cb);
+    }
+
+    #[cfg(all(not(feature = "async"), not(feature = "sync")))]
+    pub fn remove<F>(&self, key: &str, cb: F)
+    where
+        F: FnOnce(Result<(), Error>) + Send + Sync + 'static,
+    {
+        self.dispatch_remove(key, cb);
+    }
+
+    /// Makes `key` expire after `duration` instead, or never with `None`,
+    /// keeping its value. Fails with `Error::NotFound` if it isn't stored.
+    #[cfg(all(not(feature = "async"), not(feature = "sync")))]
+    pub fn touch<F>(&self, key: &str, duration: Option<Duration>, cb: F)
+    where
+        F: FnOnce(Result<(), Error>) + Send + Sync + 'static,
+    {
+        self.dispatch_touch(key, duration, cb);
+    }
+
+    /// Like `get`, failing with `Error::WouldBlock` instead of waiting behind
+    /// queued requests, a locked shard or the I/O limit.
+    #[cfg(all(not(feature = "async"), not(feature = "sync")))]
+    pub fn try_get<F>(&self, key: &str, cb: F)
+    where
+        F: FnOnce(Result<Vec<u8>, Error>) + Send + Sync + 'static,
+    {
+        self.dispatch_try_get(key, cb);
+    }
+
+    /// Like `set`, failing with `Error::WouldBlock` instead of waiting behind
+    /// queued requests, a locked shard or the I/O limit.
+    #[cfg(all(not(feature = "async"), not(feature = "sync")))]
+    pub fn try_set<F>(&self, key: &str, value: &[u8], duration: Option<Duration>, cb: F)
+    where
+        F: FnOnce(Result<(), Error>) + Send + Sync + 'static,
+    {
+        self.dispatch_try_set(key, value, duration, cb);
+    }
+
+    /// Combines the current value of `key` with `value` on a store worker,
+    /// under the shard write lock, and returns the stored result. `duration`
+    /// only applies when the entry is created.
+    #[cfg(all(not(feature = "async"), not(feature = "sync")))]
+    pub fn merge<M, F>(
+        &self,
+        key: &str,
+        value: &[u8],
+        duration: Option<Duration>,
+        merge_fn: M,
+        cb: F,
+    ) where
+        M: FnOnce(Option<&[u8]>, &[u8]) -> Vec<u8> + Send + Sync + 'static,
+        F: FnOnce(Result<Vec<u8>, Error>) + Send + Sync + 'static,
+    {
+        self.dispatch_merge(key, value, duration, merge_fn, cb);
+    }
+
+    /// Replaces the value of `key` like `set` and returns the live value it
+    /// replaced, if any, both under the shard write lock, so no other write
+    /// lands in between.
+    #[cfg(all(not(feature = "async"), not(feature = "sync")))]
+    pub fn get_and_set<F>(&self, key: &str, value: &[u8], duration: Option<Duration>, cb: F)
+    where
+        F: FnOnce(Result<Option<Vec<u8>>, Error>) + Send + Sync + 'static,
+    {
+        self.dispatch_get_and_set(key, value, duration, cb);
+    }
+
+    /// Exchanges the values of two entries, atomically for other requests:
+    /// none sees one swapped without the other. A crash midway can leave
+    /// both entries with the same value, the swap not being journaled. Fails
+    /// with `Error::NotFound` unless both exist.
+    #[cfg(all(not(feature = "async"), not(feature = "sync")))]
+    pub fn swap<F>(&self, a: &str, b: &str, cb: F)
+    where
+        F: FnOnce(Result<(), Error>) + Send + Sync + 'static,
+    {
+        self.dispatch_swap(a, b, cb);
+    }
+
+    /// The metadata of the entry for each of `keys`, in order, read in one
+    /// pass on a store worker without the values. Missing and expired
+    /// entries fail with `Error::NotFound`.
+    #[cfg(all(not(feature = "async"), not(feature = "sync")))]
+    pub fn head_many<I, F>(&self, keys: I, cb: F)
+    where
+        I: IntoIterator<Item = String>,
+        F: FnOnce(Vec<Result<EntryInfo, Error>>) + Send + Sync + 'static,
+    {
+        self.dispatch_head_many(keys.into_iter().collect(), cb);
+    }
+
+    #[cfg(all(not(feature = "async"), not(feature = "sync")))]
+    pub fn clear<F>(&self, cb: F)
+    where
+        F: FnOnce(Result<(), Error>) + Send + Sync + 'static,
+    {
+        self.dispatch_clear(cb);
+    }
+
+    #[cfg(all(not(feature = "async"), not(feature = "sync")))]
+    pub fn cleanup<F>(&self, cb: F)
+    where
+        F: FnOnce(Result<(), Error>) + Send + Sync + 'static,
+    {
+        self.dispatch_cleanup(cb);
+    }
+
+    /// Imports many entries at once, blocking the calling thread until they
+    /// were all written. Much faster than individual `set`s, at the cost of
+    /// atomicity if the process crashes during the import. Returns how many
+    /// entries were written.
+    pub fn bulk_load<I>(&self, entries: I) -> Result<u64, Error>
+    where
+        I: IntoIterator<Item = (String, Vec<u8>, Option<Duration>)>,
+    {
+        store::bulk_load(&self.0.ctx, &self.0.path, entries)
+    }
+
+    /// Estimates the number of entries, their total size and the fraction
+    /// that expired from `sample_size` random shards out of 4096, on the
+    /// calling thread. Much faster than walking [`Keeper::entries`] on large
+    /// stores.
+    pub fn estimate(&self, sample_size: usize) -> Estimate {
+        estimate::estimate(&self.0.ctx, &self.0.path, sample_size)
+    }
+
+    /// Up to `n` entries picked at random, as `(hash, info)` like
+    /// [`Keeper::entries`], read on the calling thread from random shards.
+    /// Meant for a look at what a misbehaving cache holds without listing
+    /// or exporting it all.
+    pub fn sample(&self, n: usize) -> Vec<(String, EntryInfo)> {
+        estimate::sample(&self.0.ctx, &self.0.path, n)
+    }
+
+    /// Counts the entries and bytes expiring within each of `windows` from
+    /// now, say a minute, an hour and a day, on the calling thread, to predict
+    /// load spikes on the origin from mass expiry. Folders the janitor knows
+    /// expire later than every window are skipped.
+    pub fn expiry_forecast(&self, windows: &[Duration]) -> ExpiryForecast {
+        estimate::forecast(&self.0.ctx, &self.0.path, windows)
+    }
+
+    /// Recounts the usage of every namespace from the keys stored in the
+    /// entries, on the calling thread, and saves it. Writes wait until it's
+    /// done. See [`reindex::rebuild`].
+    pub fn rebuild_index(&self) -> Result<IndexReport, Error> {
+        reindex::rebuild(&self.0.ctx, &self.0.path)
+    }
+
+    /// Reads the entries for `keys` into the memory tier on the calling
+    /// thread, so a freshly started service doesn't serve every hot key from
+    /// disk first. Missing and expired keys are skipped. Returns how many
+    /// entries the tier admitted, `0` without a memory tier.
+    pub fn warm_up<I>(&self, keys: I) -> u64
+    where
+        I: IntoIterator<Item = String>,
+    {
+        warmup::warm_up(&self.0.ctx, &self.0.path, keys)
+    }
+
+    /// Like [`Keeper::warm_up`], with the `n` most recently written entries.
+    /// Lists every shard to find them.
+    pub fn warm_up_recent(&self, n: usize) -> u64 {
+        warmup::warm_up_recent(&self.0.ctx, &self.0.path, n)
+    }
+
+    /// Clears the store in constant time, on the calling thread: every entry
+    /// stored so far reads as missing from now on, and the janitor deletes
+    /// the files over its next sweeps. Unlike [`Keeper::clear`], shards are
+    /// only locked for a moment.
+    pub fn clear_lazy(&self) -> Result<(), Error> {
+        store::clear_lazy(&self.0.ctx, &self.0.path)
+    }
+
+    /// Takes an advisory lock on `key` for `ttl`, shared with every process
+    /// using the store, so only one of them recomputes an expired entry while
+    /// the others wait or serve the stale value. Returns `None` while another
+    /// holder has it. Meant for stores shared with `LockStrategy::Disabled`,
+    /// but works within a process too.
+    pub fn try_lock_key(&self, key: &str, ttl: Duration) -> Result<Option<KeyLock>, Error> {
+        KeyLock::try_acquire(self.0.ctx.clone(), &self.0.path, key, ttl)
+    }
+
+    /// Like [`Keeper::try_lock_key`], blocking the calling thread until the
+    /// lock is released or its holder's lease runs out. The returned lease
+    /// lasts `ttl` unless extended, so keep critical sections shorter than
+    /// that; the lock is released when the guard is dropped.
+    pub fn lock(&self, key: &str, ttl: Duration) -> Result<KeyLock, Error> {
+        KeyLock::acquire(self.0.ctx.clone(), &self.0.path, key, ttl)
+    }
+
+    /// Where the entry for `key` is stored, whether or not it exists, for
+    /// tools that inspect or back up the store's files. Accounts for the hash
+    /// key, if any.
+    pub fn path_for(&self, key: &str) -> PathBuf {
+        self.0.ctx.entry_path(&self.0.path, key)
+    }
+
+    /// The expiration time and size of the entry for `key`, read on the
+    /// calling thread without its value. Fails with `Error::NotFound` for
+    /// missing and expired entries; unlike `get`, expired entries are left
+    /// for the janitor.
+    pub fn head(&self, key: &str) -> Result<Head, Error> {
+        store::head(&self.0.ctx, &self.0.path, key)
+    }
+
+    /// Whether a live entry exists for `key`, checked like [`Keeper::head`].
+    pub fn contains_key(&self, key: &str) -> Result<bool, Error> {
+        match self.head(key) {
+            Ok(_) => Ok(true),
+            Err(Error::NotFound) => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Removes every listed key on the calling thread and reports, per key,
+    /// whether it was removed, wasn't there or is still present. Meant for
+    /// deletion requests that need proof; see [`PurgeReport::is_complete`].
+    pub fn purge<I>(&self, keys: I) -> PurgeReport
+    where
+        I: IntoIterator<Item = String>,
+    {
+        purge::purge(&self.0.ctx, &self.0.path, keys)
+    }
+
+    /// Stores the value read from `reader` without holding it in memory,
+    /// unless the store has codecs, blocking the calling thread until it was
+    /// written. Passing the value's
+    /// length as `len_hint` reserves the space up front, so a full disk fails
+    /// the call before anything is copied. Returns the value's length.
+    pub fn set_from_reader<R: std::io::Read>(
+        &self,
+        key: &str,
+        reader: R,
+        len_hint: Option<u64>,
+        duration: Option<Duration>,
+    ) -> Result<u64, Error> {
+        store::set_from_reader(&self.0.ctx, &self.0.path, key, reader, len_hint, duration)
+    }
+
+    /// Writes every live entry to `writer` as JSON Lines, one object per
+    /// entry with its key or hash, base64 value and expiration time, on the
+    /// calling thread. Meant for scripts and other caches; see
+    /// [`jsonl::export`] for the format. Returns the number of entries
+    /// written.
+    pub fn export_jsonl<W: std::io::Write>(&self, writer: W) -> Result<u64, Error> {
+        jsonl::export(&self.0.ctx, &self.0.path, writer)
+    }
+
+    /// Imports entries written by [`Keeper::export_jsonl`] like
+    /// [`Keeper::bulk_load`] does, keeping their expiration times. Returns
+    /// the number of entries written.
+    pub fn import_jsonl<R: std::io::Read>(&self, reader: R) -> Result<u64, Error> {
+        jsonl::import(&self.0.ctx, &self.0.path, reader)
+    }
+
+    /// Restores a backup written by [`Snapshot::backup`] like
+    /// [`Keeper::import_jsonl`] does, after checking its format version and
+    /// checksum. Incremental backups go after the backup they build on.
+    pub fn restore_backup<R: std::io::Read>(&self, reader: R) -> Result<Backup, Error> {
+        backup::restore(&self.0.ctx, &self.0.path, reader)
+    }
+
+    /// Loads the string keys of a Redis RDB snapshot like
+    /// [`Keeper::bulk_load`] does, keeping their TTLs. Keys of other types
+    /// are counted as skipped in the report.
+    pub fn import_rdb<R: std::io::Read>(&self, reader: R) -> Result<RdbReport, Error> {
+        rdb::import(&self.0.ctx, &self.0.path, reader)
+    }
+
+    /// Stores the output of Redis' `DUMP` for a string key under `key`, like
+    /// `RESTORE` would, on the calling thread.
+    pub fn restore_dump(
+        &self,
+        key: &str,
+        payload: &[u8],
+        duration: Option<Duration>,
+    ) -> Result<(), Error> {
+        let value = rdb::decode_dump(payload)?;
+        store::bulk_load(
+            &self.0.ctx,
+            &self.0.path,
+            [(key.to_string(), value, duration)],
+        )
+        .map(drop)
+    }
+
+    /// Pins the current state of the store for consistent reads on the
+    /// calling thread while writes continue. See [`Snapshot`] for what it
+    /// guarantees.
+    pub fn snapshot(&self) -> Result<Snapshot, Error> {
+        Snapshot::new(self.0.ctx.clone(), self.0.path.clone())
+    }
+
+    /// Lists the entries currently stored, shard by shard.
+    pub fn entries(&self) -> Entries {
+        Entries::new(self.0.ctx.clone(), self.0.path.clone())
+    }
+
+    /// Queues several operations to be handled by one worker with a single
+    /// message.
+    pub fn pipeline(&self) -> Pipeline {
+        Pipeline::new(self.clone())
+    }
+
+    pub fn manifest(&self) -> Manifest {
+        self.0.ctx.manifest()
+    }
+
+    /// Cumulative counters since the store was created. They are written to the
+    /// manifest after every janitor sweep and on shutdown.
+    pub fn stats(&self) -> Stats {
+        Stats {
+            memory: self.0.ctx.memory.as_ref().map(MemoryCache::stats),
+            ..self.0.ctx.counters.snapshot()
+        }
+    }
+
+    /// Hits and last access time of `key`, if it is among the tracked keys.
+    /// Needs [`KeeperBuilder::with_key_stats`].
+    pub fn key_stats(&self, key: &str) -> Option<KeyStats> {
+        self.0.ctx.key_stats.as_ref()?.get(key)
+    }
+
+    /// The `n` most read keys, most hit first. Needs
+    /// [`KeeperBuilder::with_key_stats`].
+    pub fn hottest_keys(&self, n: usize) -> Vec<(String, KeyStats)> {
+        self.0
+            .ctx
+            .key_stats
+            .as_ref()
+            .map(|tracker| tracker.hottest(n))
+            .unwrap_or_default()
+    }
+
+    /// Time spent by the store workers on each kind of operation since startup.
+    pub fn latencies(&self) -> LatencySnapshot {
+        self.0.ctx.latencies.snapshot()
     }
-}
 
-#[derive(Debug, Clone)]
-pub struct Keeper(Arc<Inner>);
+    /// Changes the capacity of the memory tier, evicting entries if it
+    /// shrinks. Does nothing if the keeper was built without one.
+    pub fn resize_memory_cache(&self, bytes: u64) {
+        if let Some(memory) = &self.0.ctx.memory {
+            memory.resize(bytes);
+        }
+    }
 
-impl Keeper {
-    pub fn new(path: PathBuf) -> Result<Self, Error> {
-        KeeperBuilder::new(path).build()
+    /// What the recovery pass cleaned up when the store was opened, `None`
+    /// unless enabled with [`KeeperBuilder::with_recovery_on_open`].
+    pub fn recovery_report(&self) -> Option<RecoveryReport> {
+        self.0.recovery
     }
 
-    pub fn new_with_builder(builder: KeeperBuilder) -> Result<Self, Error> {
-        let mut lock = Pidlock::new_validated(builder.path.join(".lock"))?;
-        lock.acquire()?;
+    /// Whether the store bypasses the disk after repeated IO failures, see
+    /// [`KeeperBuilder::with_degradation`].
+    pub fn is_bypassing_disk(&self) -> bool {
+        self.0.ctx.bypass.as_ref().is_some_and(Bypass::is_bypassing)
+    }
 
-        let path = Arc::new(builder.path);
-        let shards = Shards::new();
+    /// Stops scheduled sweeps until [`Keeper::resume_janitor`]. Sweeps
+    /// requested with `cleanup` still run, and so does eviction when the
+    /// store grows past its limits.
+    pub fn pause_janitor(&self) {
+        self.0.janitor_is.send(janitor::InputMessage::Pause).ok();
+    }
 
-        let (store_is, store_ir) = unbounded::<store::InputMessage>();
-        let (janitor_is, janitor_ir) = unbounded::<janitor::InputMessage>();
+    pub fn resume_janitor(&self) {
+        self.0.janitor_is.send(janitor::InputMessage::Resume).ok();
+    }
 
-        let mut store_handles = Vec::with_capacity(builder.store_workers);
-        for _ in 0..builder.store_workers {
-            let handle = std::thread::spawn({
-                let shards = shards.clone();
-                let ir = store_ir.clone();
-                move || store::worker(shards, ir)
-            });
-            store_handles.push(handle);
-        }
+    /// Replaces the schedule set with
+    /// [`KeeperBuilder::with_cleanup_schedule`]; the next sweep is planned
+    /// from now.
+    pub fn set_cleanup_schedule(&self, schedule: Schedule) {
+        self.0
+            .janitor_is
+            .send(janitor::InputMessage::Reschedule(schedule))
+            .ok();
+    }
 
-        let janitor_handle = std::thread::spawn({
-            let path = path.clone();
-            move || janitor::worker(builder.cleanup_interval, path, shards, janitor_ir)
-        });
+    pub fn janitor_status(&self) -> JanitorStatus {
+        *self
+            .0
+            .ctx
+            .janitor_status
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+    }
 
-        let inner = Inner {
-            path,
-            _lock: lock,
+    /// A handle to the same store whose requests fail with `Error::Timeout`
+    /// if no worker picks them up before `deadline`, for callers that stop
+    /// waiting by then, like a request handler whose client went away.
+    /// Namespaces, tenants and limiters created from it inherit the deadline.
+    pub fn with_deadline(&self, deadline: Instant) -> Keeper {
+        let dispatch = Dispatch {
+            deadline: Some(deadline),
+            ..self.1
+        };
+        Self(self.0.clone(), dispatch)
+    }
 
-            store_is,
-            janitor_is,
+    /// A handle to the same store whose requests workers take ahead of those
+    /// of normal priority, so latency-critical reads don't wait behind bulk
+    /// writes. A request already being served isn't preempted.
+    pub fn with_priority(&self, priority: Priority) -> Keeper {
+        let dispatch = Dispatch { priority, ..self.1 };
+        Self(self.0.clone(), dispatch)
+    }
 
-            store_handles,
-            janitor_handle: Some(janitor_handle),
-        };
+    pub fn namespace(&self, name: &str) -> Result<Namespace, Error> {
+        namespace::validate_name(name)?;
+        Ok(Namespace::new(self.clone(), name.into()))
+    }
 
-        Ok(Self(Arc::new(inner)))
+    /// A limiter allowing `limit` requests per key every `period`, counted in
+    /// this store; see [`RateLimiter`].
+    pub fn rate_limiter(&self, window: Window, limit: u64, period: Duration) -> RateLimiter {
+        RateLimiter::new(self.clone(), window, limit, period)
     }
 
-    #[cfg(all(feature = "async", not(feature = "sync")))]
-    pub async fn get(&self, key: &str) -> Result<Vec<u8>, Error> {
-        let (tx, rx) = oneshot::channel();
-        self.dispatch_get(key, move |res| {
-            let _ = tx.send(res);
-        });
-        rx.await.map_err(|_| Error::WorkerClosed)?
+    pub(crate) fn namespace_usage(&self, name: &str) -> Usage {
+        self.0.ctx.namespaces.usage(name)
     }
 
-    #[cfg(all(feature = "async", not(feature = "sync")))]
-    pub async fn set(
-        &self,
-        key: &str,
-        value: &[u8],
-        duration: Option<Duration>,
-    ) -> Result<(), Error> {
-        let (tx, rx) = oneshot::channel();
-        self.dispatch_set(key, value, duration, move |res| {
-            let _ = tx.send(res);
-        });
-        rx.await.map_err(|_| Error::WorkerClosed)?
+    /// The bytes and entries stored in namespace `name`, from counters kept
+    /// up to date by every write, removal and eviction under the shard lock,
+    /// so no directory walk is needed. Namespaces never written to report
+    /// zero.
+    pub fn du(&self, name: &str) -> Result<Usage, Error> {
+        namespace::validate_name(name)?;
+        Ok(self.namespace_usage(name))
     }
 
-    #[cfg(all(feature = "async", not(feature = "sync")))]
-    pub async fn remove(&self, key: &str) -> Result<(), Error> {
-        let (tx, rx) = oneshot::channel();
-        self.dispatch_remove(key, move |res| {
-            let _ = tx.send(res);
-        });
-        rx.await.map_err(|_| Error::WorkerClosed)?
+    /// A view of the store whose entries live in their own folder, with
+    /// usage and quota of the namespace `name`. See [`Tenant`].
+    pub fn tenant(&self, name: &str) -> Result<Tenant, Error> {
+        namespace::validate_name(name)?;
+        let root = self.0.path.join(tenant::TENANTS_DIR).join(name);
+        self.0.ctx.permissions.create_dir_all(&root)?;
+        Ok(Tenant::new(self.clone(), name.into(), root))
     }
 
-    #[cfg(all(feature = "async", not(feature = "sync")))]
-    pub async fn clear(&self) -> Result<(), Error> {
-        let (tx, rx) = oneshot::channel();
-        self.dispatch_clear(move |res| {
-            let _ = tx.send(res);
-        });
-        rx.await.map_err(|_| Error::WorkerClosed)?
+    pub(crate) fn clear_tenant(&self, root: &Path, name: &str) -> Result<(), Error> {
+        store::clear_tenant(&self.0.ctx, root, name)
     }
 
-    #[cfg(all(feature = "async", not(feature = "sync")))]
-    pub async fn cleanup(&self) -> Result<(), Error> {
-        let (tx, rx) = oneshot::channel();
-        self.dispatch_cleanup(move |res| {
-            let _ = tx.send(res);
-        });
-        rx.await.map_err(|_| Error::WorkerClosed)?
+    pub fn pending_ops(&self) -> usize {
+        self.0.store_is.len()
     }
 
-    #[cfg(all(feature = "sync", not(feature = "async")))]
-    pub fn get(&self, key: &str) -> Result<Vec<u8>, Error> {
-        let (tx, rx) = std::sync::mpsc::sync_channel(1);
-        self.dispatch_get(key, move |res| {
-            let _ = tx.send(res);
-        });
-        rx.recv().map_err(|_| Error::WorkerClosed)?
+    /// Shuts the keeper down without waiting longer than `timeout` for queued
+    /// operations, which are failed with `Error::ShuttingDown` past the
+    /// deadline. Any request made afterwards fails the same way. Returns
+    /// whether all workers stopped in time; those that didn't are left
+    /// running in the background.
+    pub fn shutdown_timeout(&self, timeout: Duration) -> bool {
+        if self.0.closing.load(Ordering::Acquire) {
+            return true;
+        }
+        self.0.shutdown(Some(Instant::now() + timeout))
     }
 
-    #[cfg(all(feature = "sync", not(feature = "async")))]
-    pub fn set(&self, key: &str, value: &[u8], duration: Option<Duration>) -> Result<(), Error> {
-        let (tx, rx) = std::sync::mpsc::sync_channel(1);
-        self.dispatch_set(key, value, duration, move |res| {
-            let _ = tx.send(res);
-        });
-        rx.recv().map_err(|_| Error::WorkerClosed)?
+    /// State of the store workers. Degraded while a worker is stuck on an
+    /// operation; only tracked with [`KeeperBuilder::with_supervisor`].
+    pub fn health(&self) -> Health {
+        self.0.pool.health()
     }
 
-    #[cfg(all(feature = "sync", not(feature = "async")))]
-    pub fn remove(&self, key: &str) -> Result<(), Error> {
-        let (tx, rx) = std::sync::mpsc::sync_channel(1);
-        self.dispatch_remove(key, move |res| {
-            let _ = tx.send(res);
-        });
-        rx.recv().map_err(|_| Error::WorkerClosed)?
+    pub fn is_saturated(&self) -> bool {
+        self.0.ctx.watermarks.is_saturated()
     }
 
-    #[cfg(all(feature = "sync", not(feature = "async")))]
-    pub fn clear(&self) -> Result<(), Error> {
-        let (tx, rx) = std::sync::mpsc::sync_channel(1);
-        self.dispatch_clear(move |res| {
-            let _ = tx.send(res);
-        });
-        rx.recv().map_err(|_| Error::WorkerClosed)?
+    fn after_dispatch(&self) {
+        let depth = self.0.store_is.len();
+        self.0.ctx.watermarks.observe(depth);
+        self.0.pool.grow(depth);
     }
 
-    #[cfg(all(feature = "sync", not(feature = "async")))]
-    pub fn cleanup(&self) -> Result<(), Error> {
-        let (tx, rx) = std::sync::mpsc::sync_channel(1);
-        self.dispatch_cleanup(move |res| {
-            let _ = tx.send(res);
-        });
-        rx.recv().map_err(|_| Error::WorkerClosed)?
+    /// Boxes `cb` for a request, to run through the callback executor if
+    /// there is one.
+    fn callback<T, F>(&self, cb: F) -> Box<dyn FnOnce(T) + Send + Sync + 'static>
+    where
+        T: Send + 'static,
+        F: FnOnce(T) + Send + Sync + 'static,
+    {
+        match &self.0.executor {
+            Some(executor) => executor.wrap(cb),
+            None => Box::new(cb),
+        }
     }
 
-    #[cfg(all(not(feature = "async"), not(feature = "sync")))]
-    pub fn get<F>(&self, key: &str, cb: F)
+    pub(crate) fn dispatch_get<F>(&self, key: &str, cb: F)
     where
         F: FnOnce(Result<Vec<u8>, Error>) + Send + Sync + 'static,
     {
-        self.dispatch_get(key, cb);
+        self.dispatch_get_in(self.0.path.clone(), key, cb);
     }
 
-    #[cfg(all(not(feature = "async"), not(feature = "sync")))]
-    pub fn set<F>(&self, key: &str, value: &[u8], duration: Option<Duration>, cb: F)
+    /// Like [`Keeper::dispatch_get`], for the entries stored under `path`.
+    pub(crate) fn dispatch_get_in<F>(&self, path: Arc<PathBuf>, key: &str, cb: F)
+    where
+        F: FnOnce(Result<Vec<u8>, Error>) + Send + Sync + 'static,
+    {
+        let msg = store::InputMessage::Get {
+            path,
+            key: key.into(),
+            callback: self.callback(cb),
+        };
+
+        self.send(msg);
+    }
+
+    pub(crate) fn dispatch_get_or_load<F>(&self, key: &str, cb: F)
     where
+        F: FnOnce(Result<Vec<u8>, Error>) + Send + Sync + 'static,
+    {
+        let msg = store::InputMessage::GetOrLoad {
+            path: self.0.path.clone(),
+            key: key.into(),
+            callback: self.callback(cb),
+        };
+
+        self.send(msg);
+    }
+
+    pub(crate) fn dispatch_set<F>(
+        &self,
+        key: &str,
+        value: impl Into<Arc<[u8]>>,
+        duration: Option<Duration>,
+        cb: F,
+    ) where
         F: FnOnce(Result<(), Error>) + Send + Sync + 'static,
     {
-        self.dispatch_set(key, value, duration, cb);
+        self.dispatch_set_in(self.0.path.clone(), key, value, duration, cb);
     }
 
-    #[cfg(all(not(feature = "async"), not(feature = "sync")))]
-    pub fn remove<F>(&self, key: &str, cb: F)
+    pub(crate) fn dispatch_set_in<F>(
+        &self,
+        path: Arc<PathBuf>,
+        key: &str,
+        value: impl Into<Arc<[u8]>>,
+        duration: Option<Duration>,
+        cb: F,
+    ) where
+        F: FnOnce(Result<(), Error>) + Send + Sync + 'static,
+    {
+        let msg = store::InputMessage::Set {
+            path,
+            key: key.into(),
+            value: value.into(),
+            duration,
+            callback: self.callback(cb),
+        };
+
+        self.send(msg);
+    }
+
+    pub(crate) fn dispatch_remove<F>(&self, key: &str, cb: F)
     where
         F: FnOnce(Result<(), Error>) + Send + Sync + 'static,
     {
-        self.dispatch_remove(key, cb);
+        self.dispatch_remove_in(self.0.path.clone(), key, cb);
     }
 
-    #[cfg(all(not(feature = "async"), not(feature = "sync")))]
-    pub fn clear<F>(&self, cb: F)
+    pub(crate) fn dispatch_remove_in<F>(&self, path: Arc<PathBuf>, key: &str, cb: F)
     where
         F: FnOnce(Result<(), Error>) + Send + Sync + 'static,
     {
-        self.dispatch_clear(cb);
+        let msg = store::InputMessage::Remove {
+            path,
+            key: key.into(),
+            callback: self.callback(cb),
+        };
+
+        self.send(msg);
     }
 
-    #[cfg(all(not(feature = "async"), not(feature = "sync")))]
-    pub fn cleanup<F>(&self, cb: F)
+    pub(crate) fn dispatch_touch<F>(&self, key: &str, duration: Option<Duration>, cb: F)
     where
         F: FnOnce(Result<(), Error>) + Send + Sync + 'static,
     {
-        self.dispatch_cleanup(cb);
+        let msg = store::InputMessage::Touch {
+            path: self.0.path.clone(),
+            key: key.into(),
+            duration,
+            callback: self.callback(cb),
+        };
+
+        self.send(msg);
+    }
+
+    pub(crate) fn dispatch_batch<F>(&self, commands: Vec<Command>, cb: F)
+    where
+        F: FnOnce(Vec<Reply>) + Send + Sync + 'static,
+    {
+        let msg = store::InputMessage::Batch {
+            path: self.0.path.clone(),
+            commands,
+            callback: self.callback(cb),
+        };
+
+        self.send(msg);
     }
 
-    fn dispatch_get<F>(&self, key: &str, cb: F)
+    pub(crate) fn dispatch_try_get<F>(&self, key: &str, cb: F)
     where
         F: FnOnce(Result<Vec<u8>, Error>) + Send + Sync + 'static,
     {
-        let msg = store::InputMessage::Get {
+        let callback = self.callback(cb);
+        if !self.0.store_is.is_empty() {
+            return callback(Err(Error::WouldBlock));
+        }
+
+        let msg = store::InputMessage::TryGet {
             path: self.0.path.clone(),
             key: key.into(),
-            callback: Box::new(cb),
+            callback,
         };
 
-        if let Err(e) = self.0.store_is.send(msg) {
-            if let store::InputMessage::Get { callback, .. } = e.0 {
-                callback(Err(Error::WorkerClosed));
-            }
-        }
+        self.send(msg);
     }
 
-    fn dispatch_set<F>(&self, key: &str, value: &[u8], duration: Option<Duration>, cb: F)
-    where
+    pub(crate) fn dispatch_try_set<F>(
+        &self,
+        key: &str,
+        value: &[u8],
+        duration: Option<Duration>,
+        cb: F,
+    ) where
         F: FnOnce(Result<(), Error>) + Send + Sync + 'static,
     {
-        let msg = store::InputMessage::Set {
+        let callback = self.callback(cb);
+        if !self.0.store_is.is_empty() {
+            return callback(Err(Error::WouldBlock));
+        }
+
+        let msg = store::InputMessage::TrySet {
             path: self.0.path.clone(),
             key: key.into(),
-            value: value.into(),
+            value: value.to_vec(),
             duration,
-            callback: Box::new(cb),
+            callback,
         };
 
-        if let Err(e) = self.0.store_is.send(msg) {
-            if let store::InputMessage::Set { callback, .. } = e.0 {
-                callback(Err(Error::WorkerClosed));
-            }
-        }
+        self.send(msg);
+    }
+
+    pub(crate) fn dispatch_merge<M, F>(
+        &self,
+        key: &str,
+        value: &[u8],
+        duration: Option<Duration>,
+        merge_fn: M,
+        cb: F,
+    ) where
+        M: FnOnce(Option<&[u8]>, &[u8]) -> Vec<u8> + Send + Sync + 'static,
+        F: FnOnce(Result<Vec<u8>, Error>) + Send + Sync + 'static,
+    {
+        let msg = store::InputMessage::Merge {
+            path: self.0.path.clone(),
+            key: key.into(),
+            value: value.to_vec(),
+            duration,
+            merge_fn: Box::new(merge_fn),
+            callback: self.callback(cb),
+        };
+
+        self.send(msg);
+    }
+
+    pub(crate) fn dispatch_get_and_set<F>(
+        &self,
+        key: &str,
+        value: &[u8],
+        duration: Option<Duration>,
+        cb: F,
+    ) where
+        F: FnOnce(Result<Option<Vec<u8>>, Error>) + Send + Sync + 'static,
+    {
+        let msg = store::InputMessage::GetAndSet {
+            path: self.0.path.clone(),
+            key: key.into(),
+            value: value.to_vec(),
+            duration,
+            callback: self.callback(cb),
+        };
+
+        self.send(msg);
     }
 
-    fn dispatch_remove<F>(&self, key: &str, cb: F)
+    pub(crate) fn dispatch_swap<F>(&self, a: &str, b: &str, cb: F)
     where
         F: FnOnce(Result<(), Error>) + Send + Sync + 'static,
     {
-        let msg = store::InputMessage::Remove {
+        let msg = store::InputMessage::Swap {
             path: self.0.path.clone(),
-            key: key.into(),
-            callback: Box::new(cb),
+            a: a.into(),
+            b: b.into(),
+            callback: self.callback(cb),
         };
 
-        if let Err(e) = self.0.store_is.send(msg) {
-            if let store::InputMessage::Remove { callback, .. } = e.0 {
-                callback(Err(Error::WorkerClosed));
-            }
-        }
+        self.send(msg);
+    }
+
+    pub(crate) fn dispatch_head_many<F>(&self, keys: Vec<String>, cb: F)
+    where
+        F: FnOnce(Vec<Result<EntryInfo, Error>>) + Send + Sync + 'static,
+    {
+        let msg = store::InputMessage::HeadMany {
+            path: self.0.path.clone(),
+            keys,
+            callback: self.callback(cb),
+        };
+
+        self.send(msg);
     }
 
-    fn dispatch_clear<F>(&self, cb: F)
+    pub(crate) fn dispatch_clear<F>(&self, cb: F)
     where
         F: FnOnce(Result<(), Error>) + Send + Sync + 'static,
     {
         let msg = store::InputMessage::Clear {
             path: self.0.path.clone(),
-            callback: Box::new(cb),
+            callback: self.callback(cb),
         };
 
-        if let Err(e) = self.0.store_is.send(msg) {
-            if let store::InputMessage::Clear { callback, .. } = e.0 {
-                callback(Err(Error::WorkerClosed));
-            }
-        }
+        self.send(msg);
     }
 
-    fn dispatch_cleanup<F>(&self, cb: F)
+    pub(crate) fn dispatch_cleanup<F>(&self, cb: F)
     where
         F: FnOnce(Result<(), Error>) + Send + Sync + 'static,
     {
-        let msg = janitor::InputMessage::Cleanup(Box::new(cb));
-        if let Err(e) = self.0.janitor_is.send(msg) {
-            if let janitor::InputMessage::Cleanup(callback) = e.0 {
-                callback(Err(Error::WorkerClosed));
+        if self.0.janitor_ir.is_none() {
+            let msg = store::InputMessage::Cleanup {
+                path: self.0.path.clone(),
+                callback: self.callback(cb),
+            };
+            return self.send(msg);
+        }
+
+        let callback = self.callback(cb);
+        if self.0.closing.load(Ordering::Acquire) {
+            return callback(Err(Error::ShuttingDown));
+        }
+
+        let msg = janitor::InputMessage::Cleanup(callback);
+        if let Err(e) = self.0.janitor_is.send(msg)
+            && let janitor::InputMessage::Cleanup(callback) = e.0
+        {
+            self.0
+                .ctx
+                .hooks
+                .report(&Error::WorkerPanicked(Worker::Janitor));
+            callback(Err(Error::WorkerPanicked(Worker::Janitor)));
+        }
+    }
+
+    /// Queues `msg` for the store workers, answering it with an error right
+    /// away when the keeper is shutting down, the queue is full or the workers
+    /// are gone.
+    fn send(&self, msg: store::InputMessage) {
+        if self.0.closing.load(Ordering::Acquire) {
+            return msg.reject(|| Error::ShuttingDown);
+        }
+
+        let timeout = self
+            .0
+            .request_timeout
+            .map(|timeout| Instant::now() + timeout);
+        let msg = match self.1.deadline.into_iter().chain(timeout).min() {
+            Some(deadline) => store::InputMessage::Deadline {
+                deadline,
+                msg: Box::new(msg),
+            },
+            None => msg,
+        };
+        if let Some(max) = self.0.max_queue_len {
+            let pending = self.0.store_is.len();
+            if pending >= max {
+                return msg.reject(|| Error::QueueFull(pending));
             }
         }
+
+        let sent = self.0.store_is.send(msg, self.1.priority);
+        self.after_dispatch();
+
+        if let Err(e) = sent {
+            let error = self.closed();
+            self.0.ctx.hooks.report(&error);
+            e.0.reject(|| self.closed());
+        }
+    }
+
+    /// The error for a request that was never answered: either the keeper
+    /// started shutting down and dropped it, or the store workers are gone.
+    pub(crate) fn closed(&self) -> Error {
+        match self.0.closing.load(Ordering::Acquire) {
+            true => Error::ShuttingDown,
+            false => Error::WorkerPanicked(Worker::Store),
+        }
     }
 }
 
-impl Drop for Inner {
-    fn drop(&mut self) {
-        let num_store_workers = self.store_handles.len();
-        for _ in 0..num_store_workers {
-            self.store_is.send(store::InputMessage::Quit).ok();
+impl Inner {
+    /// Stops accepting requests and lets the workers finish what was queued
+    /// until `deadline`. Whatever is still queued then is answered with
+    /// `Error::ShuttingDown`, and workers stuck on an operation are detached.
+    /// Returns whether every thread stopped in time.
+    fn shutdown(&self, deadline: Option<Instant>) -> bool {
+        self.closing.store(true, Ordering::Release);
+        let supervisor = self
+            .supervisor
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .take();
+        if let Some((stop, handle)) = supervisor {
+            drop(stop);
+            handle.join().ok();
+        }
+        #[cfg(unix)]
+        if let Some(control) = self
+            .control
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .take()
+        {
+            control.stop();
         }
+
         self.janitor_is.send(janitor::InputMessage::Quit).ok();
+        let mut stopped = self.pool.shutdown(&self.store_is, deadline);
 
-        for handle in self.store_handles.drain(..) {
-            handle.join().ok();
+        let handle = self
+            .janitor_handle
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .take();
+        if let Some(handle) = handle {
+            while let Some(deadline) = deadline
+                && !handle.is_finished()
+                && Instant::now() < deadline
+            {
+                std::thread::sleep(Duration::from_millis(5));
+            }
+
+            if deadline.is_some() && !handle.is_finished() {
+                stopped = false;
+            } else if handle.join().is_err() {
+                self.ctx
+                    .hooks
+                    .report(&Error::WorkerPanicked(Worker::Janitor));
+            }
         }
 
-        if let Some(handle) = self.janitor_handle.take() {
-            handle.join().ok();
+        if let Some(janitor_ir) = &self.janitor_ir {
+            for msg in janitor_ir.try_iter() {
+                if let janitor::InputMessage::Cleanup(callback) = msg {
+                    callback(Err(Error::ShuttingDown));
+                }
+            }
+        }
+
+        if let Err(e) = self.ctx.persist(&self.path) {
+            self.ctx.hooks.report(&e);
+        }
+        // A detached worker could still write after the horizons are saved.
+        if stopped && let Err(e) = self.ctx.horizons.save(&self.path, &self.ctx.permissions) {
+            self.ctx.hooks.report(&e.into());
+        }
+        stopped
+    }
+}
+
+impl Drop for Inner {
+    fn drop(&mut self) {
+        if !self.closing.load(Ordering::Acquire) {
+            self.shutdown(None);
         }
     }
 }
@@ -0,0 +1,178 @@
+use std::{
+    io::ErrorKind,
+    path::{Path, PathBuf},
+    sync::{
+        Arc,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::Duration,
+};
+
+use crate::{context::Context, error::Error};
+
+pub const LOCKS_DIR: &str = "locks";
+
+/// Distinguishes the locks taken by this process.
+static NEXT_TOKEN: AtomicU64 = AtomicU64::new(0);
+
+/// Longest pause between two attempts of [`KeyLock::acquire`].
+const MAX_BACKOFF: Duration = Duration::from_millis(50);
+
+/// An advisory lock on a key, held while one process recomputes its value so
+/// others sharing the store can wait or keep serving the stale one. Taken with
+/// [`crate::keeper::Keeper::try_lock_key`] and released when dropped.
+///
+/// The lock is a file under `<root>/locks` holding its deadline and owner,
+/// written under a name of its own then linked into place, so it never exists
+/// without them. A lock past its deadline counts as abandoned, so a
+/// process that dies while holding one only blocks the key until then.
+#[derive(Debug)]
+pub struct KeyLock {
+    ctx: Arc<Context>,
+    path: PathBuf,
+    token: String,
+}
+
+impl KeyLock {
+    /// Takes the lock on `key` for `ttl`, or returns `None` if another holder
+    /// has it and it isn't past its deadline.
+    pub(crate) fn try_acquire(
+        ctx: Arc<Context>,
+        root: &Path,
+        key: &str,
+        ttl: Duration,
+    ) -> Result<Option<Self>, Error> {
+        let dir = root.join(LOCKS_DIR);
+        ctx.permissions.create_dir_all(&dir)?;
+        let path = dir.join(String::from_utf8_lossy(&ctx.hash(key)).as_ref());
+
+        let deadline = ctx.expires_at(Some(ttl));
+        let token = format!(
+            "{} {} {}",
+            deadline,
+            std::process::id(),
+            NEXT_TOKEN.fetch_add(1, Ordering::Relaxed)
+        );
+
+        let (_, owner) = token.split_once(' ').unwrap_or_default();
+        let tmp = path.with_extension(format!("{}.tmp", owner.replace(' ', ".")));
+        ctx.permissions.write(&tmp, token.as_bytes())?;
+        let taken = link(&ctx, &tmp, &path);
+        let _ = std::fs::remove_file(&tmp);
+        match taken? {
+            true => Ok(Some(Self { ctx, path, token })),
+            false => Ok(None),
+        }
+    }
+
+    /// Takes the lock on `key` for `ttl`, waiting for the current holder to
+    /// release it or for its deadline to pass. Lock files can't be watched,
+    /// so waiters poll, backing off up to [`MAX_BACKOFF`].
+    pub(crate) fn acquire(
+        ctx: Arc<Context>,
+        root: &Path,
+        key: &str,
+        ttl: Duration,
+    ) -> Result<Self, Error> {
+        let mut backoff = Duration::from_millis(1);
+        loop {
+            if let Some(lock) = Self::try_acquire(ctx.clone(), root, key, ttl)? {
+                return Ok(lock);
+            }
+            std::thread::sleep(backoff);
+            backoff = (backoff * 2).min(MAX_BACKOFF);
+        }
+    }
+
+    /// Pushes the deadline back to `ttl` from now, for recomputations that
+    /// take longer than planned. Fails with [`Error::NotFound`] if the lock
+    /// was lost in the meantime, having been broken as abandoned.
+    pub fn extend(&mut self, ttl: Duration) -> Result<(), Error> {
+        if !self.is_held() {
+            return Err(Error::NotFound);
+        }
+
+        let deadline = self.ctx.expires_at(Some(ttl));
+        let (_, owner) = self.token.split_once(' ').unwrap_or_default();
+        let token = format!("{deadline} {owner}");
+
+        let tmp = self
+            .path
+            .with_extension(format!("{}.tmp", owner.replace(' ', ".")));
+        self.ctx.permissions.write(&tmp, token.as_bytes())?;
+        std::fs::rename(&tmp, &self.path)?;
+        self.token = token;
+        Ok(())
+    }
+
+    /// Whether the lock file still holds this lock.
+    pub fn is_held(&self) -> bool {
+        std::fs::read(&self.path).is_ok_and(|token| token == self.token.as_bytes())
+    }
+}
+
+impl Drop for KeyLock {
+    fn drop(&mut self) {
+        if self.is_held() {
+            let _ = std::fs::remove_file(&self.path);
+        }
+    }
+}
+
+/// Links the lock file written at `tmp` to `path` unless a lock is there,
+/// retrying once after moving an abandoned lock out of the way. Returns
+/// whether the lock was taken.
+fn link(ctx: &Context, tmp: &Path, path: &Path) -> Result<bool, Error> {
+    for _ in 0..2 {
+        match std::fs::hard_link(tmp, path) {
+            Ok(()) => return Ok(true),
+            Err(e) if e.kind() == ErrorKind::AlreadyExists => {
+                if !break_abandoned(ctx, path)? {
+                    return Ok(false);
+                }
+            }
+            Err(e) => return Err(e.into()),
+        }
+    }
+    Ok(false)
+}
+
+/// Removes the lock at `path` if it is past its deadline, returning whether
+/// the key can be locked again. The lock is moved to a name of its own first
+/// and checked there, so a lock taken meanwhile by another process isn't
+/// removed; such a lock is moved back when possible.
+fn break_abandoned(ctx: &Context, path: &Path) -> Result<bool, Error> {
+    let is_abandoned = |path: &Path| match std::fs::read_to_string(path) {
+        Ok(token) => Ok(token
+            .split(' ')
+            .next()
+            .and_then(|deadline| deadline.parse::<u64>().ok())
+            .is_none_or(|deadline| deadline < ctx.now())),
+        // Released meanwhile.
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(true),
+        Err(e) => Err(e),
+    };
+    if !is_abandoned(path)? {
+        return Ok(false);
+    }
+
+    let moved = path.with_extension(format!(
+        "{}.{}.broken",
+        std::process::id(),
+        NEXT_TOKEN.fetch_add(1, Ordering::Relaxed)
+    ));
+    match std::fs::rename(path, &moved) {
+        Ok(()) => {}
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(true),
+        Err(e) => return Err(e.into()),
+    }
+
+    if !is_abandoned(&moved)? {
+        // Doesn't replace a lock taken since.
+        let _ = std::fs::hard_link(&moved, path);
+        let _ = std::fs::remove_file(&moved);
+        return Ok(false);
+    }
+    std::fs::remove_file(&moved)?;
+    Ok(true)
+}
@@ -0,0 +1,103 @@
+use std::{fmt, io::ErrorKind, path::Path, sync::Arc};
+
+use crate::{error::Error, permissions::Permissions};
+
+/// What a secret managed by a [`KeyProvider`] is used for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum KeyPurpose {
+    /// Signing entries, like [`crate::keeper::KeeperBuilder::with_signing_key`].
+    Signing,
+    /// Hashing keys into file names, like
+    /// [`crate::keeper::KeeperBuilder::with_hash_key`].
+    Hashing,
+}
+
+impl KeyPurpose {
+    fn file_name(self) -> &'static str {
+        match self {
+            Self::Signing => "KEY.signing",
+            Self::Hashing => "KEY.hashing",
+        }
+    }
+}
+
+/// A key management service, HSM or OS keychain holding the master key that
+/// protects the store's secrets (envelope encryption). Keeper generates a
+/// random data key per purpose, has the provider wrap it and stores only the
+/// wrapped form next to the manifest; later opens ask the provider to unwrap
+/// it. The master key never reaches keeper.
+pub trait KeyProvider: Send + Sync {
+    /// Encrypts a freshly generated data key.
+    fn wrap(&self, purpose: KeyPurpose, data_key: &[u8]) -> Result<Vec<u8>, Error>;
+
+    /// Decrypts a data key returned by [`KeyProvider::wrap`].
+    fn unwrap(&self, purpose: KeyPurpose, wrapped: &[u8]) -> Result<Vec<u8>, Error>;
+}
+
+/// A provider along with the purposes it supplies keys for.
+pub struct KeyRing {
+    pub provider: Arc<dyn KeyProvider>,
+    pub purposes: Vec<KeyPurpose>,
+}
+
+impl fmt::Debug for KeyRing {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("KeyRing")
+            .field("purposes", &self.purposes)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Returns the data key for `purpose` of the store at `root`, creating and
+/// wrapping one on first use.
+pub fn data_key(
+    provider: &dyn KeyProvider,
+    purpose: KeyPurpose,
+    root: &Path,
+    permissions: &Permissions,
+) -> Result<Vec<u8>, Error> {
+    let path = root.join(purpose.file_name());
+    match std::fs::read(&path) {
+        Ok(wrapped) => return provider.unwrap(purpose, &wrapped),
+        Err(e) if e.kind() != ErrorKind::NotFound => return Err(e.into()),
+        Err(_) => {}
+    }
+
+    let data_key = random_key()?;
+    let wrapped = provider.wrap(purpose, &data_key)?;
+
+    let tmp = path.with_extension("tmp");
+    permissions.write(&tmp, &wrapped)?;
+    std::fs::rename(&tmp, &path)?;
+    Ok(data_key)
+}
+
+#[cfg(unix)]
+pub(crate) fn random_key() -> std::io::Result<Vec<u8>> {
+    use std::io::Read;
+
+    let mut key = vec![0u8; 32];
+    std::fs::File::open("/dev/urandom")?.read_exact(&mut key)?;
+    Ok(key)
+}
+
+/// Hashes the OS-seeded keys of several hashers together. The standard
+/// library has no other portable source of randomness.
+#[cfg(not(unix))]
+pub(crate) fn random_key() -> std::io::Result<Vec<u8>> {
+    use std::hash::{BuildHasher, RandomState};
+
+    let seeds: Vec<u8> = (0..16)
+        .flat_map(|i: u64| RandomState::new().hash_one(i).to_le_bytes())
+        .collect();
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_nanos())
+        .unwrap_or(0);
+    Ok(crate::mac::sha256(&[
+        &seeds,
+        &now.to_le_bytes(),
+        &std::process::id().to_le_bytes(),
+    ])
+    .to_vec())
+}
@@ -0,0 +1,88 @@
+use std::{
+    collections::{BTreeSet, HashMap},
+    sync::Mutex,
+};
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct KeyStats {
+    pub hits: u64,
+    /// Last hit, in seconds since the Unix epoch.
+    pub last_access: u64,
+}
+
+#[derive(Debug, Default)]
+struct Table {
+    keys: HashMap<String, KeyStats>,
+    /// `(hits, key)`, so the coldest key is the first.
+    by_hits: BTreeSet<(u64, String)>,
+}
+
+/// Hit counts of the most read keys, tracked with the Space-Saving algorithm:
+/// once `capacity` keys are tracked, a new key replaces the coldest one and
+/// inherits its count. Counts of keys that entered that way can be too high by
+/// at most the count they inherited; keys read often enough are never lost.
+#[derive(Debug)]
+pub struct KeyTracker {
+    capacity: usize,
+    table: Mutex<Table>,
+}
+
+impl KeyTracker {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity: capacity.max(1),
+            table: Mutex::new(Table::default()),
+        }
+    }
+
+    pub fn hit(&self, key: &str, now: u64) {
+        let mut table = self.table.lock().unwrap_or_else(|e| e.into_inner());
+        let table = &mut *table;
+
+        let previous = match table.keys.get(key) {
+            Some(stats) => {
+                let hits = stats.hits;
+                table.by_hits.remove(&(hits, key.to_string()));
+                hits
+            }
+            None if table.keys.len() < self.capacity => 0,
+            None => {
+                let (hits, coldest) = table.by_hits.pop_first().unwrap();
+                table.keys.remove(&coldest);
+                hits
+            }
+        };
+
+        let stats = KeyStats {
+            hits: previous + 1,
+            last_access: now,
+        };
+        table.keys.insert(key.into(), stats);
+        table.by_hits.insert((stats.hits, key.into()));
+    }
+
+    pub fn get(&self, key: &str) -> Option<KeyStats> {
+        let table = self.table.lock().unwrap_or_else(|e| e.into_inner());
+        table.keys.get(key).copied()
+    }
+
+    /// Up to `n` keys, most hit first.
+    pub fn hottest(&self, n: usize) -> Vec<(String, KeyStats)> {
+        let table = self.table.lock().unwrap_or_else(|e| e.into_inner());
+        table
+            .by_hits
+            .iter()
+            .rev()
+            .take(n)
+            .map(|(_, key)| (key.clone(), table.keys[key]))
+            .collect()
+    }
+
+    /// Stops tracking `key`, once it was removed.
+    pub fn forget(&self, key: &str) {
+        let mut table = self.table.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(stats) = table.keys.remove(key) {
+            table.by_hits.remove(&(stats.hits, key.to_string()));
+        }
+    }
+}
@@ -0,0 +1,162 @@
+use std::{
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Op {
+    Get,
+    Set,
+    Remove,
+    Clear,
+}
+
+/// Describes an operation that took longer than the configured threshold.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SlowOp {
+    pub op: Op,
+    /// Hex XXH3-128 hash of the key, empty for operations without a key.
+    pub hash: String,
+    pub size: usize,
+    pub elapsed: Duration,
+}
+
+const LINEAR: usize = 16;
+const SUB_BUCKETS: usize = 8;
+const BUCKETS: usize = LINEAR + (64 - 4) * SUB_BUCKETS;
+
+/// Log-linear histogram of microseconds: exact below 16µs, then eight buckets
+/// per power of two, so every recorded value is within 12.5% of its bucket.
+#[derive(Debug)]
+pub struct Histogram {
+    buckets: Box<[AtomicU64; BUCKETS]>,
+    count: AtomicU64,
+    sum: AtomicU64,
+    max: AtomicU64,
+}
+
+impl Default for Histogram {
+    fn default() -> Self {
+        Self {
+            buckets: Box::new(std::array::from_fn(|_| AtomicU64::new(0))),
+            count: AtomicU64::new(0),
+            sum: AtomicU64::new(0),
+            max: AtomicU64::new(0),
+        }
+    }
+}
+
+impl Histogram {
+    pub fn record(&self, elapsed: Duration) {
+        let micros = u64::try_from(elapsed.as_micros()).unwrap_or(u64::MAX);
+        self.buckets[bucket_of(micros)].fetch_add(1, Ordering::Relaxed);
+        self.count.fetch_add(1, Ordering::Relaxed);
+        self.sum.fetch_add(micros, Ordering::Relaxed);
+        self.max.fetch_max(micros, Ordering::Relaxed);
+    }
+
+    pub fn snapshot(&self) -> HistogramSnapshot {
+        HistogramSnapshot {
+            buckets: self
+                .buckets
+                .iter()
+                .map(|b| b.load(Ordering::Relaxed))
+                .collect(),
+            count: self.count.load(Ordering::Relaxed),
+            sum: self.sum.load(Ordering::Relaxed),
+            max: self.max.load(Ordering::Relaxed),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HistogramSnapshot {
+    buckets: Vec<u64>,
+    pub count: u64,
+    sum: u64,
+    max: u64,
+}
+
+impl HistogramSnapshot {
+    pub fn mean(&self) -> Duration {
+        Duration::from_micros(self.sum.checked_div(self.count).unwrap_or(0))
+    }
+
+    pub fn max(&self) -> Duration {
+        Duration::from_micros(self.max)
+    }
+
+    /// Upper bound of the bucket holding the `q`-th quantile (`0.0..=1.0`).
+    pub fn quantile(&self, q: f64) -> Duration {
+        if self.count == 0 {
+            return Duration::ZERO;
+        }
+
+        let rank = ((q.clamp(0.0, 1.0) * self.count as f64).ceil() as u64).max(1);
+        let mut seen = 0;
+        for (index, n) in self.buckets.iter().enumerate() {
+            seen += n;
+            if seen >= rank {
+                return Duration::from_micros(upper_bound(index).min(self.max));
+            }
+        }
+        self.max()
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Latencies {
+    pub get: Histogram,
+    pub set: Histogram,
+    pub remove: Histogram,
+    pub clear: Histogram,
+}
+
+impl Latencies {
+    pub fn histogram(&self, op: Op) -> &Histogram {
+        match op {
+            Op::Get => &self.get,
+            Op::Set => &self.set,
+            Op::Remove => &self.remove,
+            Op::Clear => &self.clear,
+        }
+    }
+
+    pub fn snapshot(&self) -> LatencySnapshot {
+        LatencySnapshot {
+            get: self.get.snapshot(),
+            set: self.set.snapshot(),
+            remove: self.remove.snapshot(),
+            clear: self.clear.snapshot(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LatencySnapshot {
+    pub get: HistogramSnapshot,
+    pub set: HistogramSnapshot,
+    pub remove: HistogramSnapshot,
+    pub clear: HistogramSnapshot,
+}
+
+fn bucket_of(micros: u64) -> usize {
+    if micros < LINEAR as u64 {
+        return micros as usize;
+    }
+
+    let exp = 63 - micros.leading_zeros() as usize;
+    let sub = ((micros >> (exp - 3)) & (SUB_BUCKETS as u64 - 1)) as usize;
+    LINEAR + (exp - 4) * SUB_BUCKETS + sub
+}
+
+fn upper_bound(index: usize) -> u64 {
+    if index < LINEAR {
+        return index as u64;
+    }
+
+    let exp = (index - LINEAR) / SUB_BUCKETS + 4;
+    let sub = ((index - LINEAR) % SUB_BUCKETS) as u64;
+    let width = 1u64 << (exp - 3);
+    ((SUB_BUCKETS as u64 + sub) << (exp - 3)).saturating_add(width - 1)
+}
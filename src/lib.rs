@@ -1,6 +1,63 @@
+pub mod backup;
+pub mod bypass;
+pub mod cache;
+pub mod clock;
+pub mod codec;
+pub mod compact;
+pub mod context;
+#[cfg(unix)]
+pub mod control;
+pub mod direct;
+pub mod durability;
+pub mod entries;
+pub mod entry;
 pub mod error;
+pub mod estimate;
+pub mod evict;
+pub mod eviction;
+pub mod executor;
+#[cfg(feature = "testing")]
+pub mod faults;
+pub mod handles;
+pub mod headers;
+pub mod hooks;
+pub mod horizon;
+pub mod http_cache;
 pub mod janitor;
+pub mod jsonl;
 pub mod keeper;
+pub mod keylock;
+pub mod keys;
+pub mod keystats;
+pub mod latency;
+pub mod loader;
+pub mod lock;
+pub mod mac;
+pub mod manifest;
+#[cfg(feature = "memcached")]
+pub mod memcached;
+pub mod memory;
+pub mod migrate;
+pub mod mounts;
+pub mod namespace;
+pub mod origin;
+pub mod permissions;
+pub mod pipeline;
+pub mod pool;
+pub mod pressure;
+pub mod purge;
+pub mod queue;
+pub mod ratelimit;
+pub mod rdb;
+pub mod recovery;
+pub mod reindex;
+pub mod schedule;
+pub mod semaphore;
 pub mod shards;
+pub mod snapshot;
+pub mod stats;
 pub mod store;
+pub mod tenant;
 mod utils;
+pub mod warmup;
+pub mod weigher;
@@ -0,0 +1,192 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicUsize, Ordering},
+        mpsc,
+    },
+    time::{Duration, Instant},
+};
+
+use crate::error::Error;
+
+/// Produces the value of a missing key along with its time to live.
+pub type LoadFn =
+    Box<dyn Fn(&str) -> Result<(Vec<u8>, Option<Duration>), Error> + Send + Sync + 'static>;
+
+/// How long a store worker waits for the loader by default.
+pub const DEFAULT_LOAD_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Loads still running past their timeout at most. Beyond that, loads fail
+/// right away instead of starting yet another thread.
+const MAX_OVERDUE: usize = 16;
+
+/// Stops calling the loader for a key after it failed `failures` times in a
+/// row, until `cooldown` has passed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CircuitBreaker {
+    pub failures: u32,
+    pub cooldown: Duration,
+    /// Makes `get_or_compute` return the expired entry, when there is one,
+    /// instead of the loader's error or [`Error::CircuitOpen`].
+    pub serve_stale: bool,
+}
+
+/// Keys whose failures are tracked at most. When full, keys whose circuit
+/// isn't open are forgotten to make room, and a new failing key isn't tracked
+/// if every tracked one still has its circuit open.
+const MAX_TRACKED: usize = 10_000;
+
+#[derive(Debug, Default)]
+struct Failures {
+    count: u32,
+    open_until: Option<Instant>,
+}
+
+pub struct Loader {
+    load: Arc<LoadFn>,
+    timeout: Duration,
+    /// Loads that timed out and haven't returned yet.
+    overdue: Arc<AtomicUsize>,
+    refresh_ahead: Option<u64>,
+    refreshing: Mutex<HashSet<String>>,
+    breaker: Option<CircuitBreaker>,
+    failures: Mutex<HashMap<String, Failures>>,
+}
+
+impl fmt::Debug for Loader {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Loader")
+            .field("timeout", &self.timeout)
+            .field("refresh_ahead", &self.refresh_ahead)
+            .field("breaker", &self.breaker)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Loader {
+    pub fn new(load: LoadFn) -> Self {
+        Self {
+            load: Arc::new(load),
+            timeout: DEFAULT_LOAD_TIMEOUT,
+            overdue: Arc::new(AtomicUsize::new(0)),
+            refresh_ahead: None,
+            refreshing: Mutex::new(HashSet::new()),
+            breaker: None,
+            failures: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = timeout;
+    }
+
+    pub fn set_breaker(&mut self, breaker: CircuitBreaker) {
+        self.breaker = Some(breaker);
+    }
+
+    pub fn serves_stale(&self) -> bool {
+        self.breaker.is_some_and(|b| b.serve_stale)
+    }
+
+    pub fn is_open(&self, key: &str) -> bool {
+        let failures = self.failures.lock().unwrap_or_else(|e| e.into_inner());
+        failures
+            .get(key)
+            .and_then(|f| f.open_until)
+            .is_some_and(|until| Instant::now() < until)
+    }
+
+    pub fn set_refresh_ahead(&mut self, window: Duration) {
+        self.refresh_ahead = Some(window.as_secs());
+    }
+
+    /// Calls the loader unless the circuit for `key` is open, tripping it
+    /// after too many consecutive failures.
+    pub fn load(&self, key: &str) -> Result<(Vec<u8>, Option<Duration>), Error> {
+        let Some(breaker) = self.breaker else {
+            return self.call(key);
+        };
+        if self.is_open(key) {
+            return Err(Error::CircuitOpen(key.into()));
+        }
+
+        let res = self.call(key);
+        let mut failures = self.failures.lock().unwrap_or_else(|e| e.into_inner());
+        match &res {
+            Ok(_) => {
+                failures.remove(key);
+            }
+            Err(_) => {
+                if failures.len() >= MAX_TRACKED && !failures.contains_key(key) {
+                    let now = Instant::now();
+                    failures.retain(|_, f| f.open_until.is_some_and(|until| now < until));
+                    if failures.len() >= MAX_TRACKED {
+                        return res;
+                    }
+                }
+                let f = failures.entry(key.into()).or_default();
+                f.count += 1;
+                if f.count >= breaker.failures {
+                    f.open_until = Some(Instant::now() + breaker.cooldown);
+                }
+            }
+        }
+        res
+    }
+
+    /// Runs the loader on a thread of its own and waits for it up to the
+    /// timeout. A load that takes longer is left to finish in the background,
+    /// its value discarded.
+    fn call(&self, key: &str) -> Result<(Vec<u8>, Option<Duration>), Error> {
+        if self.overdue.load(Ordering::Acquire) >= MAX_OVERDUE {
+            return Err(Error::LoadTimeout(key.into()));
+        }
+
+        // Without a buffer, sending fails once the worker stopped waiting.
+        let (tx, rx) = mpsc::sync_channel(0);
+        let load = self.load.clone();
+        let overdue = self.overdue.clone();
+        let owned = key.to_string();
+        std::thread::Builder::new()
+            .name("keeper-loader".into())
+            .spawn(move || {
+                if tx.send(load(&owned)).is_err() {
+                    overdue.fetch_sub(1, Ordering::AcqRel);
+                }
+            })?;
+
+        match rx.recv_timeout(self.timeout) {
+            Ok(res) => res,
+            Err(mpsc::RecvTimeoutError::Timeout) => {
+                self.overdue.fetch_add(1, Ordering::AcqRel);
+                drop(rx);
+                Err(Error::LoadTimeout(key.into()))
+            }
+            Err(mpsc::RecvTimeoutError::Disconnected) => {
+                Err(Error::WorkerPanicked(crate::error::Worker::Loader))
+            }
+        }
+    }
+
+    /// Whether `key`, read at `now` and expiring at `expires_at`, should be
+    /// reloaded. Only one refresh per key is handed out until
+    /// [`Loader::refreshed`] is called.
+    pub fn claim_refresh(&self, key: &str, expires_at: u64, now: u64) -> bool {
+        let Some(window) = self.refresh_ahead else {
+            return false;
+        };
+        if expires_at == 0 || expires_at.saturating_sub(now) > window {
+            return false;
+        }
+
+        let mut refreshing = self.refreshing.lock().unwrap_or_else(|e| e.into_inner());
+        refreshing.insert(key.into())
+    }
+
+    pub fn refreshed(&self, key: &str) {
+        let mut refreshing = self.refreshing.lock().unwrap_or_else(|e| e.into_inner());
+        refreshing.remove(key);
+    }
+}
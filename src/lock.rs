@@ -0,0 +1,75 @@
+use std::path::{Path, PathBuf};
+
+use pidlock::{Pidlock, PidlockError};
+
+use crate::{error::Error, permissions::Permissions};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LockStrategy {
+    /// Guards the store with a PID file, `<root>/.lock` unless `path` is given.
+    /// Locks left by dead processes are always replaced; with `takeover` an
+    /// existing lock is replaced even if its owner still appears alive, which
+    /// covers PIDs reused after a crash.
+    Pidfile {
+        path: Option<PathBuf>,
+        takeover: bool,
+    },
+    /// No inter-process locking, for deployments that guarantee a single
+    /// process per store.
+    Disabled,
+}
+
+impl Default for LockStrategy {
+    fn default() -> Self {
+        Self::Pidfile {
+            path: None,
+            takeover: false,
+        }
+    }
+}
+
+impl LockStrategy {
+    pub fn at(path: PathBuf) -> Self {
+        Self::Pidfile {
+            path: Some(path),
+            takeover: false,
+        }
+    }
+
+    pub fn with_takeover(self) -> Self {
+        match self {
+            Self::Pidfile { path, .. } => Self::Pidfile {
+                path,
+                takeover: true,
+            },
+            Self::Disabled => Self::Disabled,
+        }
+    }
+
+    pub fn acquire(
+        &self,
+        root: &Path,
+        permissions: &Permissions,
+    ) -> Result<Option<Pidlock>, Error> {
+        let Self::Pidfile { path, takeover } = self else {
+            return Ok(None);
+        };
+
+        let path = path.clone().unwrap_or_else(|| root.join(".lock"));
+        let mut lock = Pidlock::new_validated(&path)?;
+
+        let lock = match lock.acquire() {
+            Ok(()) => lock,
+            Err(PidlockError::LockExists) if *takeover => {
+                std::fs::remove_file(&path)?;
+                let mut lock = Pidlock::new_validated(&path)?;
+                lock.acquire()?;
+                lock
+            }
+            Err(e) => return Err(e.into()),
+        };
+
+        permissions.apply_file(&path)?;
+        Ok(Some(lock))
+    }
+}
@@ -0,0 +1,287 @@
+/// Length of a tag produced by [`SigningKey::sign`].
+pub const TAG_LEN: usize = 32;
+
+const BLOCK_LEN: usize = 64;
+
+/// Secret for HMAC-SHA256, used to sign entries and to key the hash of keys.
+#[derive(Clone)]
+pub struct SigningKey {
+    inner: [u8; BLOCK_LEN],
+    outer: [u8; BLOCK_LEN],
+}
+
+impl SigningKey {
+    pub fn new(secret: &[u8]) -> Self {
+        let mut key = [0u8; BLOCK_LEN];
+        match secret.len() > BLOCK_LEN {
+            true => key[..32].copy_from_slice(&sha256(&[secret])),
+            false => key[..secret.len()].copy_from_slice(secret),
+        }
+
+        Self {
+            inner: key.map(|b| b ^ 0x36),
+            outer: key.map(|b| b ^ 0x5c),
+        }
+    }
+
+    pub fn sign(&self, chunks: &[&[u8]]) -> [u8; TAG_LEN] {
+        let mut mac = self.begin();
+        for chunk in chunks {
+            mac.update(chunk);
+        }
+        mac.finish()
+    }
+
+    /// Starts an incremental [`SigningKey::sign`].
+    pub fn begin(&self) -> Mac {
+        let mut inner = Sha256::new();
+        inner.update(&self.inner);
+        Mac {
+            inner,
+            outer: self.outer,
+        }
+    }
+
+    /// Compares in constant time, so a forger learns nothing from how long a
+    /// rejection took.
+    pub fn verify(&self, chunks: &[&[u8]], tag: &[u8]) -> bool {
+        let expected = self.sign(chunks);
+        tag.len() == TAG_LEN
+            && expected
+                .iter()
+                .zip(tag)
+                .fold(0, |acc, (a, b)| acc | (a ^ b))
+                == 0
+    }
+}
+
+pub struct Mac {
+    inner: Sha256,
+    outer: [u8; BLOCK_LEN],
+}
+
+impl Mac {
+    pub fn update(&mut self, data: &[u8]) {
+        self.inner.update(data);
+    }
+
+    pub fn finish(self) -> [u8; TAG_LEN] {
+        let inner = self.inner.finish();
+
+        let mut hasher = Sha256::new();
+        hasher.update(&self.outer);
+        hasher.update(&inner);
+        hasher.finish()
+    }
+}
+
+impl std::fmt::Debug for SigningKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("SigningKey(..)")
+    }
+}
+
+pub fn sha256(chunks: &[&[u8]]) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    for chunk in chunks {
+        hasher.update(chunk);
+    }
+    hasher.finish()
+}
+
+const K: [u32; 64] = [
+    0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5, 0x3956c25b, 0x59f111f1, 0x923f82a4, 0xab1c5ed5,
+    0xd807aa98, 0x12835b01, 0x243185be, 0x550c7dc3, 0x72be5d74, 0x80deb1fe, 0x9bdc06a7, 0xc19bf174,
+    0xe49b69c1, 0xefbe4786, 0x0fc19dc6, 0x240ca1cc, 0x2de92c6f, 0x4a7484aa, 0x5cb0a9dc, 0x76f988da,
+    0x983e5152, 0xa831c66d, 0xb00327c8, 0xbf597fc7, 0xc6e00bf3, 0xd5a79147, 0x06ca6351, 0x14292967,
+    0x27b70a85, 0x2e1b2138, 0x4d2c6dfc, 0x53380d13, 0x650a7354, 0x766a0abb, 0x81c2c92e, 0x92722c85,
+    0xa2bfe8a1, 0xa81a664b, 0xc24b8b70, 0xc76c51a3, 0xd192e819, 0xd6990624, 0xf40e3585, 0x106aa070,
+    0x19a4c116, 0x1e376c08, 0x2748774c, 0x34b0bcb5, 0x391c0cb3, 0x4ed8aa4a, 0x5b9cca4f, 0x682e6ff3,
+    0x748f82ee, 0x78a5636f, 0x84c87814, 0x8cc70208, 0x90befffa, 0xa4506ceb, 0xbef9a3f7, 0xc67178f2,
+];
+
+struct Sha256 {
+    state: [u32; 8],
+    block: [u8; BLOCK_LEN],
+    filled: usize,
+    length: u64,
+}
+
+impl Sha256 {
+    fn new() -> Self {
+        Self {
+            state: [
+                0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab,
+                0x5be0cd19,
+            ],
+            block: [0; BLOCK_LEN],
+            filled: 0,
+            length: 0,
+        }
+    }
+
+    fn update(&mut self, mut data: &[u8]) {
+        self.length += data.len() as u64;
+
+        while !data.is_empty() {
+            let n = (BLOCK_LEN - self.filled).min(data.len());
+            self.block[self.filled..self.filled + n].copy_from_slice(&data[..n]);
+            self.filled += n;
+            data = &data[n..];
+
+            if self.filled == BLOCK_LEN {
+                compress(&mut self.state, &self.block);
+                self.filled = 0;
+            }
+        }
+    }
+
+    fn finish(mut self) -> [u8; 32] {
+        let bits = self.length.wrapping_mul(8);
+
+        self.update(&[0x80]);
+        while self.filled != BLOCK_LEN - 8 {
+            self.update(&[0]);
+        }
+        self.update(&bits.to_be_bytes());
+
+        let mut out = [0u8; 32];
+        for (chunk, word) in out.chunks_exact_mut(4).zip(self.state) {
+            chunk.copy_from_slice(&word.to_be_bytes());
+        }
+        out
+    }
+}
+
+fn compress(state: &mut [u32; 8], block: &[u8; BLOCK_LEN]) {
+    let mut w = [0u32; 64];
+    for (i, chunk) in block.chunks_exact(4).enumerate() {
+        w[i] = u32::from_be_bytes(chunk.try_into().unwrap());
+    }
+    for i in 16..64 {
+        let s0 = w[i - 15].rotate_right(7) ^ w[i - 15].rotate_right(18) ^ (w[i - 15] >> 3);
+        let s1 = w[i - 2].rotate_right(17) ^ w[i - 2].rotate_right(19) ^ (w[i - 2] >> 10);
+        w[i] = w[i - 16]
+            .wrapping_add(s0)
+            .wrapping_add(w[i - 7])
+            .wrapping_add(s1);
+    }
+
+    let [mut a, mut b, mut c, mut d, mut e, mut f, mut g, mut h] = *state;
+    for i in 0..64 {
+        let s1 = e.rotate_right(6) ^ e.rotate_right(11) ^ e.rotate_right(25);
+        let ch = (e & f) ^ (!e & g);
+        let t1 = h
+            .wrapping_add(s1)
+            .wrapping_add(ch)
+            .wrapping_add(K[i])
+            .wrapping_add(w[i]);
+        let s0 = a.rotate_right(2) ^ a.rotate_right(13) ^ a.rotate_right(22);
+        let maj = (a & b) ^ (a & c) ^ (b & c);
+        let t2 = s0.wrapping_add(maj);
+
+        h = g;
+        g = f;
+        f = e;
+        e = d.wrapping_add(t1);
+        d = c;
+        c = b;
+        b = a;
+        a = t1.wrapping_add(t2);
+    }
+
+    for (s, v) in state.iter_mut().zip([a, b, c, d, e, f, g, h]) {
+        *s = s.wrapping_add(v);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hex(bytes: &[u8]) -> String {
+        bytes.iter().map(|b| format!("{b:02x}")).collect()
+    }
+
+    #[test]
+    fn sha256_nist_vectors() {
+        assert_eq!(
+            hex(&sha256(&[b"abc"])),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        assert_eq!(
+            hex(&sha256(&[b""])),
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+        );
+        assert_eq!(
+            hex(&sha256(&[
+                b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq"
+            ])),
+            "248d6a61d20638b8e5c026930c3e6039a33ce45964ff2167f6ecedd419db06c1"
+        );
+    }
+
+    #[test]
+    fn sha256_across_chunks() {
+        // One million `a`, in chunks that don't line up with blocks.
+        let chunk = [b'a'; 1000];
+        let chunks: Vec<&[u8]> = (0..1000).map(|i| &chunk[..(i % 7 + 1) * 100]).collect();
+        let total: usize = chunks.iter().map(|c| c.len()).sum();
+        let rest = vec![b'a'; 1_000_000 - total];
+        let mut all = chunks;
+        all.push(&rest);
+        assert_eq!(
+            hex(&sha256(&all)),
+            "cdc76e5c9914fb9281a1c7e284d73e67f1809a48a497200e046d39ccc7112cd0"
+        );
+    }
+
+    /// RFC 4231, test cases 1, 2, 6 and 7.
+    #[test]
+    fn hmac_sha256_rfc4231() {
+        let cases: [(&[u8], &[u8], &str); 4] = [
+            (
+                &[0x0b; 20],
+                b"Hi There",
+                "b0344c61d8db38535ca8afceaf0bf12b881dc200c9833da726e9376c2e32cff7",
+            ),
+            (
+                b"Jefe",
+                b"what do ya want for nothing?",
+                "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843",
+            ),
+            (
+                &[0xaa; 131],
+                b"Test Using Larger Than Block-Size Key - Hash Key First",
+                "60e431591ee0b67f0d8a26aacbf5b77f8e0bc6213728c5140546040f0ee37f54",
+            ),
+            (
+                &[0xaa; 131],
+                b"This is a test using a larger than block-size key and a larger than \
+                  block-size data. The key needs to be hashed before being used by the \
+                  HMAC algorithm.",
+                "9b09ffa71b942fcb27635fbcd5b0e944bfdc63644f0713938a7f51535c3a35e2",
+            ),
+        ];
+
+        for (secret, data, tag) in cases {
+            let key = SigningKey::new(secret);
+            assert_eq!(hex(&key.sign(&[data])), tag);
+
+            let (head, tail) = data.split_at(data.len() / 3);
+            let mut mac = key.begin();
+            mac.update(head);
+            mac.update(tail);
+            assert_eq!(hex(&mac.finish()), tag);
+        }
+    }
+
+    #[test]
+    fn verify() {
+        let key = SigningKey::new(b"Jefe");
+        let tag = key.sign(&[b"what do ya want ", b"for nothing?"]);
+        assert!(key.verify(&[b"what do ya want for nothing?"], &tag));
+        assert!(!key.verify(&[b"what do ya want for nothing!"], &tag));
+        assert!(!key.verify(&[b"what do ya want for nothing?"], &tag[..31]));
+    }
+}
@@ -0,0 +1,289 @@
+use std::{collections::BTreeMap, io::ErrorKind, path::Path};
+
+use crate::{
+    entry,
+    error::Error,
+    keys,
+    namespace::{self, Record},
+    permissions::Permissions,
+    stats::Stats,
+};
+
+pub const FILE_NAME: &str = "MANIFEST";
+pub const FORMAT_VERSION: u16 = entry::CURRENT;
+pub const HASHER: &str = "xxh3-128";
+/// Recorded instead of [`HASHER`] for stores opened with a hash key.
+pub const KEYED_HASHER: &str = "hmac-sha256-128";
+/// Recorded instead of [`HASHER`] for stores whose layout is seeded, along
+/// with the seed.
+pub const SEEDED_HASHER: &str = "xxh3-128-seeded";
+pub const FANOUT: usize = 4096;
+pub const COMPRESSION: &str = "none";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Manifest {
+    pub format_version: u16,
+    pub hasher: String,
+    pub fanout: usize,
+    pub compression: String,
+    pub created_at: u64,
+    /// Number of lazy clears, see [`crate::store::clear_lazy`].
+    pub epoch: u64,
+    /// Entries last modified at or before this time, in nanoseconds since the
+    /// Unix epoch, belong to an earlier epoch and count as missing. `0` when
+    /// the store was never lazily cleared.
+    pub cleared_before: u64,
+    /// Seed of [`SEEDED_HASHER`], picked at random when the store is created.
+    /// `0` for other hashers.
+    pub seed: u64,
+    pub namespaces: BTreeMap<String, Record>,
+    pub stats: Stats,
+}
+
+impl Manifest {
+    pub fn new(created_at: u64) -> Self {
+        Self {
+            format_version: FORMAT_VERSION,
+            hasher: HASHER.into(),
+            fanout: FANOUT,
+            compression: COMPRESSION.into(),
+            created_at,
+            epoch: 0,
+            cleared_before: 0,
+            seed: 0,
+            namespaces: BTreeMap::new(),
+            stats: Stats::default(),
+        }
+    }
+
+    /// Loads the manifest of the store at `root`, writing a fresh one if the
+    /// store is empty, and checks it against this build's configuration. Stores
+    /// that hold entries but no manifest predate it and are reported as format
+    /// version 1.
+    pub fn open(root: &Path, now: u64, hasher: &str) -> Result<Self, Error> {
+        let seed = match hasher {
+            SEEDED_HASHER => random_seed()?,
+            _ => 0,
+        };
+        let expected = Self {
+            hasher: hasher.into(),
+            seed,
+            ..Self::new(now)
+        };
+        let manifest = match Self::load_or_legacy(root, now)? {
+            Some(manifest) => manifest,
+            None => {
+                expected.save(root)?;
+                expected.clone()
+            }
+        };
+
+        manifest.validate(&expected)?;
+        Ok(manifest)
+    }
+
+    pub fn load_or_legacy(root: &Path, now: u64) -> Result<Option<Self>, Error> {
+        if let Some(manifest) = Self::load(root)? {
+            return Ok(Some(manifest));
+        }
+
+        for entry in std::fs::read_dir(root)? {
+            if entry?.file_type()?.is_dir() {
+                return Ok(Some(Self {
+                    format_version: entry::V1,
+                    ..Self::new(now)
+                }));
+            }
+        }
+
+        Ok(None)
+    }
+
+    pub fn load(root: &Path) -> Result<Option<Self>, Error> {
+        match std::fs::read_to_string(root.join(FILE_NAME)) {
+            Ok(text) => Self::parse(&text).map(Some),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    pub fn save(&self, root: &Path) -> Result<(), Error> {
+        self.save_with(root, &Permissions::default())
+    }
+
+    pub fn save_with(&self, root: &Path, permissions: &Permissions) -> Result<(), Error> {
+        let tmp = root.join(format!("{FILE_NAME}.tmp"));
+        permissions.write(&tmp, self.to_string().as_bytes())?;
+        std::fs::rename(tmp, root.join(FILE_NAME))?;
+        Ok(())
+    }
+
+    pub fn parse(text: &str) -> Result<Self, Error> {
+        let mut format_version = None;
+        let mut hasher = None;
+        let mut fanout = None;
+        let mut compression = None;
+        let mut created_at = None;
+        let mut epoch = 0;
+        let mut cleared_before = 0;
+        let mut seed = 0;
+        let mut namespaces = BTreeMap::<String, Record>::new();
+        let mut stats = Stats::default();
+
+        for line in text.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let Some((key, value)) = line.split_once('=') else {
+                return Err(incompatible(format!("malformed manifest line `{line}`")));
+            };
+
+            let value = value.trim();
+            match key.trim() {
+                "format_version" => format_version = Some(parse_number(key, value)?),
+                "hasher" => hasher = Some(value.to_string()),
+                "fanout" => fanout = Some(parse_number(key, value)?),
+                "compression" => compression = Some(value.to_string()),
+                "created_at" => created_at = Some(parse_number(key, value)?),
+                "epoch" => epoch = parse_number(key, value)?,
+                "cleared_before" => cleared_before = parse_number(key, value)?,
+                "seed" => seed = parse_number(key, value)?,
+                key if key.starts_with("stats.") => {
+                    stats.set(&key["stats.".len()..], parse_number(key, value)?);
+                }
+                key => {
+                    if let Some((name, field)) = key
+                        .strip_prefix("namespace.")
+                        .and_then(|rest| rest.rsplit_once('.'))
+                        && namespace::validate_name(name).is_ok()
+                    {
+                        let record = namespaces.entry(name.into()).or_default();
+                        match field {
+                            "max_bytes" => record.quota.max_bytes = Some(parse_number(key, value)?),
+                            "max_entries" => {
+                                record.quota.max_entries = Some(parse_number(key, value)?)
+                            }
+                            "bytes" => record.usage.bytes = parse_number(key, value)?,
+                            "entries" => record.usage.entries = parse_number(key, value)?,
+                            _ => {}
+                        }
+                    }
+                }
+            }
+        }
+
+        Ok(Self {
+            format_version: required("format_version", format_version)?,
+            hasher: required("hasher", hasher)?,
+            fanout: required("fanout", fanout)?,
+            compression: required("compression", compression)?,
+            created_at: required("created_at", created_at)?,
+            epoch,
+            cleared_before,
+            seed,
+            namespaces,
+            stats,
+        })
+    }
+
+    pub fn validate(&self, expected: &Manifest) -> Result<(), Error> {
+        if self.format_version < expected.format_version {
+            return Err(incompatible(format!(
+                "format version {} (expected {}), run the migration first",
+                self.format_version, expected.format_version
+            )));
+        }
+
+        if self.format_version != expected.format_version {
+            return Err(incompatible(format!(
+                "format version {} (expected {})",
+                self.format_version, expected.format_version
+            )));
+        }
+
+        if self.hasher != expected.hasher {
+            return Err(incompatible(format!(
+                "hasher `{}` (expected `{}`)",
+                self.hasher, expected.hasher
+            )));
+        }
+
+        if self.hasher == SEEDED_HASHER && self.seed == 0 {
+            return Err(incompatible("missing `seed`".into()));
+        }
+
+        if self.fanout != expected.fanout {
+            return Err(incompatible(format!(
+                "fanout {} (expected {})",
+                self.fanout, expected.fanout
+            )));
+        }
+
+        if self.compression != expected.compression {
+            return Err(incompatible(format!(
+                "compression `{}` (expected `{}`)",
+                self.compression, expected.compression
+            )));
+        }
+
+        Ok(())
+    }
+}
+
+impl std::fmt::Display for Manifest {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "format_version={}", self.format_version)?;
+        writeln!(f, "hasher={}", self.hasher)?;
+        writeln!(f, "fanout={}", self.fanout)?;
+        writeln!(f, "compression={}", self.compression)?;
+        writeln!(f, "created_at={}", self.created_at)?;
+        if self.epoch > 0 {
+            writeln!(f, "epoch={}", self.epoch)?;
+            writeln!(f, "cleared_before={}", self.cleared_before)?;
+        }
+        if self.seed != 0 {
+            writeln!(f, "seed={}", self.seed)?;
+        }
+
+        for (name, record) in &self.namespaces {
+            if let Some(max) = record.quota.max_bytes {
+                writeln!(f, "namespace.{name}.max_bytes={max}")?;
+            }
+            if let Some(max) = record.quota.max_entries {
+                writeln!(f, "namespace.{name}.max_entries={max}")?;
+            }
+            writeln!(f, "namespace.{name}.bytes={}", record.usage.bytes)?;
+            writeln!(f, "namespace.{name}.entries={}", record.usage.entries)?;
+        }
+
+        for field in Stats::FIELDS {
+            writeln!(f, "stats.{field}={}", self.stats.get(field).unwrap_or(0))?;
+        }
+        Ok(())
+    }
+}
+
+/// A non-zero seed for [`SEEDED_HASHER`].
+fn random_seed() -> Result<u64, Error> {
+    let key = keys::random_key()?;
+    let seed = key
+        .first_chunk()
+        .map(|bytes| u64::from_le_bytes(*bytes))
+        .unwrap_or_default();
+    Ok(seed.max(1))
+}
+
+fn incompatible(reason: String) -> Error {
+    Error::IncompatibleStore(reason)
+}
+
+fn parse_number<T: std::str::FromStr>(key: &str, value: &str) -> Result<T, Error> {
+    value
+        .parse()
+        .map_err(|_| incompatible(format!("invalid value `{value}` for `{}`", key.trim())))
+}
+
+fn required<T>(key: &str, value: Option<T>) -> Result<T, Error> {
+    value.ok_or_else(|| incompatible(format!("missing `{key}`")))
+}
@@ -0,0 +1,571 @@
+use std::{
+    fmt,
+    io::{self, BufRead, BufReader, BufWriter, Read, Write},
+    net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs},
+    sync::{
+        Arc,
+        atomic::{AtomicUsize, Ordering},
+        mpsc,
+    },
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use crate::{error::Error, keeper::Keeper, mac};
+
+/// Relative expiration times are at most 30 days; larger ones are Unix
+/// timestamps, as in memcached.
+const MAX_RELATIVE_EXPTIME: i64 = 60 * 60 * 24 * 30;
+
+/// Longest key memcached accepts.
+const MAX_KEY_LEN: usize = 250;
+
+/// Longest command line accepted; connections sending longer ones are closed.
+const MAX_LINE_LEN: u64 = 8192;
+
+/// Largest value accepted by default, memcached's default item size.
+pub const DEFAULT_MAX_VALUE_SIZE: usize = 1024 * 1024;
+
+/// Connections served at once by default, memcached's default.
+pub const DEFAULT_MAX_CONNECTIONS: usize = 1024;
+
+/// How long a connection may stay silent by default before it's closed.
+pub const DEFAULT_IDLE_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// How long a connection has to authenticate, when credentials are required.
+const AUTH_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Bounds of the pause after failing to accept or serve a connection, which
+/// doubles while failures go on.
+const MIN_BACKOFF: Duration = Duration::from_millis(5);
+const MAX_BACKOFF: Duration = Duration::from_secs(1);
+
+pub type ErrorHook = Box<dyn Fn(&io::Error) + Send + Sync + 'static>;
+
+/// Serves a keeper over the memcached text protocol, so applications written
+/// against memcached can use it unchanged. `get`/`gets`, `set`, `delete`,
+/// `touch` and `flush_all` are supported with memcached's expiration times,
+/// along with `version` and `quit`; other commands answer `ERROR`.
+///
+/// Values are stored behind the 4 bytes of their client flags, big-endian,
+/// so keys are best left to the server: values written to them through the
+/// [`Keeper`] API read back with their first bytes taken as flags. `gets`
+/// reports a CAS value of `0`, there being no versions to compare.
+pub struct MemcachedServer {
+    keeper: Keeper,
+    listener: TcpListener,
+    config: Config,
+    max_connections: usize,
+    /// Connections being served.
+    connections: Arc<AtomicUsize>,
+    on_error: Option<ErrorHook>,
+}
+
+impl fmt::Debug for MemcachedServer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MemcachedServer")
+            .field("keeper", &self.keeper)
+            .field("listener", &self.listener)
+            .field("config", &self.config)
+            .field("max_connections", &self.max_connections)
+            .field("connections", &self.connections)
+            .field("on_error", &self.on_error.is_some())
+            .finish()
+    }
+}
+
+/// What every connection is served with.
+#[derive(Debug, Clone)]
+struct Config {
+    /// SHA-256 of `<username> <password>` for each accepted pair.
+    credentials: Arc<[[u8; 32]]>,
+    max_value_size: usize,
+    /// Requests allowed per connection in a period, and the period.
+    rate: Option<(u32, Duration)>,
+    idle_timeout: Duration,
+}
+
+impl MemcachedServer {
+    pub fn bind(keeper: Keeper, addr: impl ToSocketAddrs) -> io::Result<Self> {
+        Ok(Self {
+            keeper,
+            listener: TcpListener::bind(addr)?,
+            config: Config {
+                credentials: Arc::new([]),
+                max_value_size: DEFAULT_MAX_VALUE_SIZE,
+                rate: None,
+                idle_timeout: DEFAULT_IDLE_TIMEOUT,
+            },
+            max_connections: DEFAULT_MAX_CONNECTIONS,
+            connections: Arc::new(AtomicUsize::new(0)),
+            on_error: None,
+        })
+    }
+
+    /// Serves up to `connections` clients at once, each on its own thread.
+    /// Clients connecting beyond that are answered `SERVER_ERROR too many
+    /// open connections` and disconnected, as memcached does. Defaults to
+    /// [`DEFAULT_MAX_CONNECTIONS`].
+    pub fn with_max_connections(mut self, connections: usize) -> Self {
+        self.max_connections = connections.max(1);
+        self
+    }
+
+    /// Closes connections that send nothing for `timeout`, or that don't
+    /// read their replies for as long. Until they authenticate, connections
+    /// only have 10 seconds. Defaults to [`DEFAULT_IDLE_TIMEOUT`].
+    pub fn with_idle_timeout(mut self, timeout: Duration) -> Self {
+        self.config.idle_timeout = timeout.max(Duration::from_millis(1));
+        self
+    }
+
+    /// Called when accepting a connection or starting its thread fails. The
+    /// server goes on, pausing a little longer after each failure in a row.
+    pub fn on_error<F>(mut self, hook: F) -> Self
+    where
+        F: Fn(&io::Error) + Send + Sync + 'static,
+    {
+        self.on_error = Some(Box::new(hook));
+        self
+    }
+
+    /// Rejects values larger than `bytes` with `SERVER_ERROR object too
+    /// large for cache`, discarding their data as it arrives instead of
+    /// buffering it. Defaults to [`DEFAULT_MAX_VALUE_SIZE`]. Sizes too large
+    /// to buffer along with the flags and the trailing `\r\n` are clamped.
+    pub fn with_max_value_size(mut self, bytes: usize) -> Self {
+        self.config.max_value_size = bytes.min(usize::MAX - 6);
+        self
+    }
+
+    /// Lets each connection send up to `requests` commands per `period`,
+    /// in bursts of as many. Commands beyond that wait for their turn before
+    /// being read, so a client flooding the server slows down to the limit
+    /// instead of crowding out the others in the store queue.
+    pub fn with_connection_rate_limit(mut self, requests: u32, period: Duration) -> Self {
+        self.config.rate = Some((requests.max(1), period));
+        self
+    }
+
+    /// Requires connections to authenticate before anything else, the way
+    /// memcached does over the text protocol: with a `set` of any key whose
+    /// data is `<username> <password>`, answered `STORED` once accepted.
+    /// Until then, other commands fail with `CLIENT_ERROR unauthenticated`.
+    /// Can be called again to accept several pairs. Credentials travel in
+    /// clear text, so the port should only be reachable from a trusted
+    /// network or through a TLS tunnel.
+    pub fn with_credentials(mut self, username: &str, password: &str) -> Self {
+        let mut credentials = self.config.credentials.to_vec();
+        credentials.push(mac::sha256(&[
+            username.as_bytes(),
+            b" ",
+            password.as_bytes(),
+        ]));
+        self.config.credentials = credentials.into();
+        self
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    /// Accepts connections forever, serving each on its own thread. Failures
+    /// to accept, such as running out of file descriptors, are reported to
+    /// [`Self::on_error`] and retried after a pause.
+    pub fn serve(&self) -> ! {
+        let mut backoff = MIN_BACKOFF;
+        loop {
+            match self.accept() {
+                Ok(()) => backoff = MIN_BACKOFF,
+                Err(e) => {
+                    if let Some(hook) = &self.on_error {
+                        hook(&e);
+                    }
+                    std::thread::sleep(backoff);
+                    backoff = (backoff * 2).min(MAX_BACKOFF);
+                }
+            }
+        }
+    }
+
+    fn accept(&self) -> io::Result<()> {
+        let (mut stream, _) = self.listener.accept()?;
+        let slot = Slot::take(&self.connections, self.max_connections);
+        let Some(slot) = slot else {
+            stream.set_write_timeout(Some(AUTH_TIMEOUT))?;
+            return stream.write_all(b"SERVER_ERROR too many open connections\r\n");
+        };
+
+        let keeper = self.keeper.clone();
+        let config = self.config.clone();
+        std::thread::Builder::new()
+            .name("keeper-memcached".into())
+            .spawn(move || {
+                let _slot = slot;
+                let _ = Connection::new(keeper, config, stream).and_then(|mut c| c.run());
+            })?;
+        Ok(())
+    }
+}
+
+/// One of the connections a server may serve at once, given back on drop.
+struct Slot(Arc<AtomicUsize>);
+
+impl Slot {
+    fn take(connections: &Arc<AtomicUsize>, max: usize) -> Option<Self> {
+        connections
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |n| {
+                (n < max).then_some(n + 1)
+            })
+            .ok()
+            .map(|_| Self(connections.clone()))
+    }
+}
+
+impl Drop for Slot {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+struct Connection {
+    keeper: Keeper,
+    config: Config,
+    authenticated: bool,
+    throttle: Option<Throttle>,
+    reader: BufReader<TcpStream>,
+    writer: BufWriter<TcpStream>,
+}
+
+impl Connection {
+    fn new(keeper: Keeper, config: Config, stream: TcpStream) -> io::Result<Self> {
+        let authenticated = config.credentials.is_empty();
+        let timeout = match authenticated {
+            true => config.idle_timeout,
+            false => config.idle_timeout.min(AUTH_TIMEOUT),
+        };
+        stream.set_read_timeout(Some(timeout))?;
+        stream.set_write_timeout(Some(timeout))?;
+
+        Ok(Self {
+            keeper,
+            authenticated,
+            throttle: config
+                .rate
+                .map(|(requests, period)| Throttle::new(requests, period)),
+            config,
+            reader: BufReader::new(stream.try_clone()?),
+            writer: BufWriter::new(stream),
+        })
+    }
+
+    fn run(&mut self) -> io::Result<()> {
+        let mut line = String::new();
+        loop {
+            let delay = self
+                .throttle
+                .as_mut()
+                .map_or(Duration::ZERO, Throttle::take);
+            if !delay.is_zero() {
+                self.writer.flush()?;
+                std::thread::sleep(delay);
+            }
+
+            line.clear();
+            if (&mut self.reader).take(MAX_LINE_LEN).read_line(&mut line)? == 0 {
+                return Ok(());
+            }
+            if !line.ends_with('\n') && line.len() as u64 == MAX_LINE_LEN {
+                self.client_error("line too long")?;
+                return self.writer.flush();
+            }
+
+            let args: Vec<&str> = line.split_ascii_whitespace().collect();
+            let (command, args) = match args.split_first() {
+                Some((&command, args)) => (command, args),
+                None => ("", &[][..]),
+            };
+            match command {
+                "quit" => return self.writer.flush(),
+                "set" => self.set(args)?,
+                _ if !self.authenticated => self.client_error("unauthenticated")?,
+                "get" | "gets" => self.get(args, command == "gets")?,
+                "delete" => self.delete(args)?,
+                "touch" => self.touch(args)?,
+                "flush_all" => self.flush_all(args)?,
+                "version" => {
+                    let version = concat!("VERSION keeper-", env!("CARGO_PKG_VERSION"), "\r\n");
+                    self.writer.write_all(version.as_bytes())?
+                }
+                _ => self.writer.write_all(b"ERROR\r\n")?,
+            }
+
+            if self.reader.buffer().is_empty() {
+                self.writer.flush()?;
+            }
+        }
+    }
+
+    fn get(&mut self, keys: &[&str], cas: bool) -> io::Result<()> {
+        if keys.is_empty() {
+            return self.writer.write_all(b"ERROR\r\n");
+        }
+
+        for key in keys {
+            if !is_valid_key(key) {
+                return self.client_error("bad key");
+            }
+            let value = match wait(&self.keeper, |cb| self.keeper.dispatch_get(key, cb)) {
+                Ok(value) if value.len() >= 4 => value,
+                Ok(_) | Err(Error::NotFound) => continue,
+                Err(e) => return self.server_error(&e),
+            };
+
+            let (flags, data) = value.split_at(4);
+            let flags = u32::from_be_bytes(flags.try_into().unwrap());
+            let header = match cas {
+                true => format!("VALUE {key} {flags} {} 0\r\n", data.len()),
+                false => format!("VALUE {key} {flags} {}\r\n", data.len()),
+            };
+            self.writer.write_all(header.as_bytes())?;
+            self.writer.write_all(data)?;
+            self.writer.write_all(b"\r\n")?;
+        }
+        self.writer.write_all(b"END\r\n")
+    }
+
+    /// `set <key> <flags> <exptime> <bytes> [noreply]`, followed by the data.
+    fn set(&mut self, args: &[&str]) -> io::Result<()> {
+        let (noreply, args) = noreply(args);
+        let parsed = match args {
+            [key, flags, exptime, bytes] => flags
+                .parse::<u32>()
+                .ok()
+                .zip(exptime.parse::<i64>().ok())
+                .zip(bytes.parse::<usize>().ok())
+                .map(|((flags, exptime), bytes)| (key, flags, exptime, bytes)),
+            _ => None,
+        };
+        let Some((key, flags, exptime, bytes)) = parsed else {
+            return self.writer.write_all(b"ERROR\r\n");
+        };
+        if bytes > self.config.max_value_size {
+            io::copy(
+                &mut (&mut self.reader).take((bytes as u64).saturating_add(2)),
+                &mut io::sink(),
+            )?;
+            return self
+                .writer
+                .write_all(b"SERVER_ERROR object too large for cache\r\n");
+        }
+
+        let mut value = flags.to_be_bytes().to_vec();
+        value.resize(4 + bytes + 2, 0);
+        self.reader.read_exact(&mut value[4..])?;
+        if !value.ends_with(b"\r\n") {
+            return self.client_error("bad data chunk");
+        }
+        value.truncate(4 + bytes);
+        if !is_valid_key(key) {
+            return self.client_error("bad key");
+        }
+        if !self.authenticated {
+            return self.authenticate(&value[4..]);
+        }
+
+        let res = match expiration(exptime) {
+            Some(duration) => wait(&self.keeper, |cb| {
+                self.keeper.dispatch_set(key, value, duration, cb)
+            }),
+            // Already expired: memcached stores nothing, and drops what was there.
+            None => wait(&self.keeper, |cb| self.keeper.dispatch_remove(key, cb)),
+        };
+        self.reply(res, noreply, b"STORED\r\n", b"NOT_STORED\r\n")
+    }
+
+    /// Checks `<username> <password>` against every accepted pair, taking as
+    /// long whichever matches.
+    fn authenticate(&mut self, data: &[u8]) -> io::Result<()> {
+        let digest = mac::sha256(&[data]);
+        let matched = self
+            .config
+            .credentials
+            .iter()
+            .fold(false, |matched, credentials| {
+                let diff = credentials
+                    .iter()
+                    .zip(&digest)
+                    .fold(0, |acc, (a, b)| acc | (a ^ b));
+                matched | (diff == 0)
+            });
+
+        match matched {
+            true => {
+                self.authenticated = true;
+                let stream = self.reader.get_ref();
+                stream.set_read_timeout(Some(self.config.idle_timeout))?;
+                stream.set_write_timeout(Some(self.config.idle_timeout))?;
+                self.writer.write_all(b"STORED\r\n")
+            }
+            false => self.client_error("authentication failure"),
+        }
+    }
+
+    fn delete(&mut self, args: &[&str]) -> io::Result<()> {
+        let (noreply, args) = noreply(args);
+        let [key] = args else {
+            return self.writer.write_all(b"ERROR\r\n");
+        };
+        if !is_valid_key(key) {
+            return self.client_error("bad key");
+        }
+
+        let res = self.keeper.contains_key(key).and_then(|found| match found {
+            true => wait(&self.keeper, |cb| self.keeper.dispatch_remove(key, cb)),
+            false => Err(Error::NotFound),
+        });
+        self.reply(res, noreply, b"DELETED\r\n", b"NOT_FOUND\r\n")
+    }
+
+    fn touch(&mut self, args: &[&str]) -> io::Result<()> {
+        let (noreply, args) = noreply(args);
+        let [key, exptime] = args else {
+            return self.writer.write_all(b"ERROR\r\n");
+        };
+        let Ok(exptime) = exptime.parse::<i64>() else {
+            return self.client_error("invalid exptime argument");
+        };
+        if !is_valid_key(key) {
+            return self.client_error("bad key");
+        }
+
+        let res = match expiration(exptime) {
+            Some(duration) => wait(&self.keeper, |cb| {
+                self.keeper.dispatch_touch(key, duration, cb)
+            }),
+            None => self.keeper.contains_key(key).and_then(|found| match found {
+                true => wait(&self.keeper, |cb| self.keeper.dispatch_remove(key, cb)),
+                false => Err(Error::NotFound),
+            }),
+        };
+        self.reply(res, noreply, b"TOUCHED\r\n", b"NOT_FOUND\r\n")
+    }
+
+    /// Clears the store at once; delayed flushes aren't supported.
+    fn flush_all(&mut self, args: &[&str]) -> io::Result<()> {
+        let (noreply, args) = noreply(args);
+        match args {
+            [] => {}
+            ["0"] => {}
+            [delay] if delay.parse::<i64>().is_ok() => {
+                return self.client_error("delayed flush not supported");
+            }
+            _ => return self.writer.write_all(b"ERROR\r\n"),
+        }
+
+        let res = wait(&self.keeper, |cb| self.keeper.dispatch_clear(cb));
+        self.reply(res, noreply, b"OK\r\n", b"OK\r\n")
+    }
+
+    fn reply(
+        &mut self,
+        res: Result<(), Error>,
+        noreply: bool,
+        ok: &[u8],
+        not_found: &[u8],
+    ) -> io::Result<()> {
+        match res {
+            _ if noreply => Ok(()),
+            Ok(()) => self.writer.write_all(ok),
+            Err(Error::NotFound) => self.writer.write_all(not_found),
+            Err(e) => self.server_error(&e),
+        }
+    }
+
+    fn client_error(&mut self, message: &str) -> io::Result<()> {
+        self.writer
+            .write_all(format!("CLIENT_ERROR {message}\r\n").as_bytes())
+    }
+
+    fn server_error(&mut self, error: &Error) -> io::Result<()> {
+        let message = error.to_string().replace(['\r', '\n'], " ");
+        self.writer
+            .write_all(format!("SERVER_ERROR {message}\r\n").as_bytes())
+    }
+}
+
+/// Token bucket spacing out the commands of a connection.
+struct Throttle {
+    burst: f64,
+    /// Tokens gained per second.
+    rate: f64,
+    tokens: f64,
+    last: Instant,
+}
+
+impl Throttle {
+    fn new(requests: u32, period: Duration) -> Self {
+        let burst = requests as f64;
+        Self {
+            burst,
+            rate: burst / period.as_secs_f64().max(f64::MIN_POSITIVE),
+            tokens: burst,
+            last: Instant::now(),
+        }
+    }
+
+    /// Takes a token, returning how long to wait for it to be there.
+    fn take(&mut self) -> Duration {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.last).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.burst) - 1.0;
+        self.last = now;
+
+        match self.tokens < 0.0 {
+            true => Duration::from_secs_f64(-self.tokens / self.rate),
+            false => Duration::ZERO,
+        }
+    }
+}
+
+/// Sends a request through `dispatch` and waits for its answer.
+fn wait<T: Send + 'static>(
+    keeper: &Keeper,
+    dispatch: impl FnOnce(Box<dyn FnOnce(Result<T, Error>) + Send + Sync>),
+) -> Result<T, Error> {
+    let (tx, rx) = mpsc::sync_channel(1);
+    dispatch(Box::new(move |res| {
+        let _ = tx.send(res);
+    }));
+    rx.recv().map_err(|_| keeper.closed())?
+}
+
+fn noreply<'a>(args: &'a [&'a str]) -> (bool, &'a [&'a str]) {
+    match args.split_last() {
+        Some((&"noreply", rest)) => (true, rest),
+        _ => (false, args),
+    }
+}
+
+fn is_valid_key(key: &str) -> bool {
+    key.len() <= MAX_KEY_LEN && !key.bytes().any(|b| b.is_ascii_control())
+}
+
+/// The duration to store an entry for given a memcached expiration time:
+/// `0` never expires, up to 30 days is relative, larger values are Unix
+/// timestamps. `None` when the entry is expired already.
+fn expiration(exptime: i64) -> Option<Option<Duration>> {
+    match exptime {
+        0 => Some(None),
+        exptime if exptime < 0 => None,
+        exptime if exptime <= MAX_RELATIVE_EXPTIME => {
+            Some(Some(Duration::from_secs(exptime as u64)))
+        }
+        exptime => {
+            let now = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default();
+            let remaining = Duration::from_secs(exptime as u64).checked_sub(now)?;
+            (!remaining.is_zero()).then_some(Some(remaining))
+        }
+    }
+}
@@ -0,0 +1,239 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::Mutex,
+};
+
+/// Bytes charged per entry on top of its value.
+const ENTRY_OVERHEAD: u64 = 64;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct MemoryStats {
+    pub capacity: u64,
+    /// Bytes currently charged, values plus a fixed overhead per entry.
+    pub bytes: u64,
+    pub entries: u64,
+    pub hits: u64,
+    pub misses: u64,
+    /// Entries pushed out to make room for others or by a resize.
+    pub evictions: u64,
+    /// Values let into the tier after a disk read.
+    pub admitted: u64,
+    /// Values turned away because they were accessed less often than the
+    /// entries they would have evicted.
+    pub rejected: u64,
+}
+
+impl MemoryStats {
+    pub fn hit_ratio(&self) -> f64 {
+        match self.hits + self.misses {
+            0 => 0.0,
+            total => self.hits as f64 / total as f64,
+        }
+    }
+}
+
+/// In-memory copy of recently read entries, keyed by hash. Writes only
+/// invalidate it; values enter the tier when read from disk, subject to a
+/// TinyLFU admission policy so a scan of one-off keys can't flush hot ones.
+#[derive(Debug)]
+pub struct MemoryCache(Mutex<Tier>);
+
+#[derive(Debug)]
+struct Tier {
+    capacity: u64,
+    used: u64,
+    tick: u64,
+    slots: HashMap<Vec<u8>, Slot>,
+    /// Slots by last access, oldest first.
+    order: BTreeMap<u64, Vec<u8>>,
+    sketch: FrequencySketch,
+    stats: MemoryStats,
+}
+
+#[derive(Debug)]
+struct Slot {
+    value: Vec<u8>,
+    expires_at: u64,
+    tick: u64,
+}
+
+impl Slot {
+    fn charge(&self) -> u64 {
+        self.value.len() as u64 + ENTRY_OVERHEAD
+    }
+}
+
+impl MemoryCache {
+    pub fn new(capacity: u64) -> Self {
+        Self(Mutex::new(Tier {
+            capacity,
+            used: 0,
+            tick: 0,
+            slots: HashMap::new(),
+            order: BTreeMap::new(),
+            sketch: FrequencySketch::new(capacity),
+            stats: MemoryStats::default(),
+        }))
+    }
+
+    /// Returns the value stored for `hash` and its expiration time, counting
+    /// the access towards its frequency.
+    pub fn get(&self, hash: &[u8]) -> Option<(Vec<u8>, u64)> {
+        let mut tier = self.lock();
+        tier.sketch.increment(hash);
+
+        tier.tick += 1;
+        let tick = tier.tick;
+        let Some(slot) = tier.slots.get_mut(hash) else {
+            tier.stats.misses += 1;
+            return None;
+        };
+        let old_tick = std::mem::replace(&mut slot.tick, tick);
+        let found = (slot.value.clone(), slot.expires_at);
+
+        let key = tier
+            .order
+            .remove(&old_tick)
+            .unwrap_or_else(|| hash.to_vec());
+        tier.order.insert(tick, key);
+        tier.stats.hits += 1;
+        Some(found)
+    }
+
+    /// Offers a value read from disk to the tier. It's admitted if it fits, or
+    /// if it was accessed more often than each entry it has to evict. Returns
+    /// whether it was.
+    pub fn offer(&self, hash: &[u8], value: &[u8], expires_at: u64) -> bool {
+        let mut tier = self.lock();
+        tier.remove(hash);
+
+        let charge = value.len() as u64 + ENTRY_OVERHEAD;
+        if charge > tier.capacity {
+            tier.stats.rejected += 1;
+            return false;
+        }
+
+        let frequency = tier.sketch.estimate(hash);
+        while tier.used + charge > tier.capacity {
+            let Some((_, victim)) = tier.order.first_key_value() else {
+                break;
+            };
+            if tier.sketch.estimate(victim) >= frequency {
+                tier.stats.rejected += 1;
+                return false;
+            }
+
+            let victim = victim.clone();
+            tier.remove(&victim);
+            tier.stats.evictions += 1;
+        }
+
+        tier.tick += 1;
+        let slot = Slot {
+            value: value.to_vec(),
+            expires_at,
+            tick: tier.tick,
+        };
+        tier.used += slot.charge();
+        tier.order.insert(slot.tick, hash.to_vec());
+        tier.slots.insert(hash.to_vec(), slot);
+        tier.stats.admitted += 1;
+        true
+    }
+
+    pub fn invalidate(&self, hash: &[u8]) {
+        self.lock().remove(hash);
+    }
+
+    pub fn clear(&self) {
+        let mut tier = self.lock();
+        tier.slots.clear();
+        tier.order.clear();
+        tier.used = 0;
+    }
+
+    /// Changes the capacity, evicting the least recently read entries until
+    /// the tier fits.
+    pub fn resize(&self, capacity: u64) {
+        let mut tier = self.lock();
+        tier.capacity = capacity;
+        while tier.used > capacity
+            && let Some((_, victim)) = tier.order.pop_first()
+        {
+            tier.remove(&victim);
+            tier.stats.evictions += 1;
+        }
+    }
+
+    pub fn stats(&self) -> MemoryStats {
+        let tier = self.lock();
+        MemoryStats {
+            capacity: tier.capacity,
+            bytes: tier.used,
+            entries: tier.slots.len() as u64,
+            ..tier.stats
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Tier> {
+        self.0.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl Tier {
+    fn remove(&mut self, hash: &[u8]) {
+        if let Some(slot) = self.slots.remove(hash) {
+            self.used -= slot.charge();
+            self.order.remove(&slot.tick);
+        }
+    }
+}
+
+/// Count-min sketch of 4-bit counters estimating how often each hash was
+/// accessed. Counters are halved periodically so old popularity fades.
+#[derive(Debug)]
+struct FrequencySketch {
+    rows: [Vec<u8>; 4],
+    mask: usize,
+    additions: u64,
+    sample_size: u64,
+}
+
+impl FrequencySketch {
+    fn new(capacity: u64) -> Self {
+        let width = (capacity / 512).clamp(1024, 1 << 20).next_power_of_two() as usize;
+        Self {
+            rows: std::array::from_fn(|_| vec![0; width]),
+            mask: width - 1,
+            additions: 0,
+            sample_size: 10 * width as u64,
+        }
+    }
+
+    fn index(&self, hash: &[u8], row: usize) -> usize {
+        xxhash_rust::xxh3::xxh3_64_with_seed(hash, row as u64) as usize & self.mask
+    }
+
+    fn increment(&mut self, hash: &[u8]) {
+        for row in 0..self.rows.len() {
+            let i = self.index(hash, row);
+            let counter = &mut self.rows[row][i];
+            *counter = (*counter + 1).min(15);
+        }
+
+        self.additions += 1;
+        if self.additions >= self.sample_size {
+            for row in &mut self.rows {
+                row.iter_mut().for_each(|c| *c /= 2);
+            }
+            self.additions /= 2;
+        }
+    }
+
+    fn estimate(&self, hash: &[u8]) -> u8 {
+        (0..self.rows.len())
+            .map(|row| self.rows[row][self.index(hash, row)])
+            .min()
+            .unwrap_or(0)
+    }
+}
@@ -0,0 +1,180 @@
+use std::{fs::OpenOptions, io::Write, path::Path};
+
+use crate::{
+    clock::{Clock, SystemClock},
+    durability::GroupCommit,
+    entry,
+    error::Error,
+    lock::LockStrategy,
+    manifest::Manifest,
+    permissions::Permissions,
+    store, utils,
+};
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct MigrationReport {
+    pub from_version: u16,
+    pub to_version: u16,
+    pub rewritten: usize,
+    pub removed: usize,
+}
+
+/// Rewrites every entry of the store at `root` into `target_version` and
+/// updates its manifest. Entries that cannot be decoded are removed. The store
+/// must not be open by any process while this runs.
+pub fn migrate(root: &Path, target_version: u16) -> Result<MigrationReport, Error> {
+    migrate_with(
+        root,
+        target_version,
+        &LockStrategy::default(),
+        &Permissions::default(),
+        None,
+        false,
+    )
+}
+
+/// Like [`migrate`], locking the store with `lock` and writing entries the
+/// way the store does, with `permissions` and, with `durability`, synced
+/// before being renamed in. With `secure_delete`, the entries removed or
+/// replaced are overwritten.
+pub(crate) fn migrate_with(
+    root: &Path,
+    target_version: u16,
+    lock: &LockStrategy,
+    permissions: &Permissions,
+    durability: Option<&GroupCommit>,
+    secure_delete: bool,
+) -> Result<MigrationReport, Error> {
+    if !matches!(target_version, entry::V1 | entry::V2) {
+        return Err(Error::IncompatibleStore(format!(
+            "unknown target format version {target_version}"
+        )));
+    }
+
+    let _lock = lock.acquire(root, permissions)?;
+
+    let now = SystemClock.now();
+    let mut manifest = Manifest::load_or_legacy(root, now)?.unwrap_or_else(|| Manifest::new(now));
+    let mut report = MigrationReport {
+        from_version: manifest.format_version,
+        to_version: target_version,
+        ..Default::default()
+    };
+
+    for folder in std::fs::read_dir(root)? {
+        let folder = folder?;
+        // Shard folders only: quarantined files and compaction leftovers
+        // aren't entries to rewrite or remove.
+        if u16::from_str_radix(&folder.file_name().to_string_lossy(), 16).is_err()
+            || !folder.path().is_dir()
+        {
+            continue;
+        }
+
+        for file in std::fs::read_dir(folder.path())? {
+            let file = file?;
+            if !file.file_type()?.is_file() {
+                continue;
+            }
+
+            let path = file.path();
+            let buffer = std::fs::read(&path)?;
+            let Ok((header, payload)) = entry::decode(&buffer) else {
+                if secure_delete {
+                    utils::scrub(&path, buffer.len() as u64)?;
+                }
+                std::fs::remove_file(&path)?;
+                report.removed += 1;
+                continue;
+            };
+
+            // Flags can't be carried over to the original layout.
+            if header.version == target_version
+                || (target_version == entry::V1 && header.flags != 0)
+            {
+                continue;
+            }
+
+            let encoded = entry::encode(
+                target_version,
+                header.flags,
+                header.expires_at,
+                header.key.as_deref(),
+                payload,
+            )?;
+
+            // Still reaches the old contents once replaced.
+            let old = match secure_delete {
+                true => Some(OpenOptions::new().write(true).open(&path)?),
+                false => None,
+            };
+            store::replace_file(permissions, durability, &folder.path(), &path, |file| {
+                file.write_all(&encoded)
+            })?;
+            if let Some(old) = old {
+                utils::scrub_file(&old, buffer.len() as u64)?;
+            }
+            report.rewritten += 1;
+        }
+    }
+
+    manifest.format_version = target_version;
+    manifest.save_with(root, permissions)?;
+
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+    use crate::{janitor::QUARANTINE_DIR, keeper::KeeperBuilder};
+
+    fn store(name: &str) -> PathBuf {
+        let path =
+            std::env::temp_dir().join(format!("keeper-migrate-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&path);
+        drop(KeeperBuilder::new(path.clone()).build().unwrap());
+        path
+    }
+
+    #[test]
+    fn keeps_quarantined_files() {
+        let path = store("quarantine");
+        std::fs::create_dir_all(path.join(QUARANTINE_DIR)).unwrap();
+        let quarantined = path.join(QUARANTINE_DIR).join("unknown");
+        std::fs::write(&quarantined, b"?").unwrap();
+        std::fs::create_dir_all(path.join("abc")).unwrap();
+        let corrupt = path.join("abc").join("0".repeat(29));
+        std::fs::write(&corrupt, b"?").unwrap();
+
+        let report = migrate(&path, entry::V1).unwrap();
+        assert_eq!(report.removed, 1);
+        assert!(quarantined.exists());
+        assert!(!corrupt.exists());
+
+        std::fs::remove_dir_all(&path).unwrap();
+    }
+
+    #[test]
+    fn takes_the_configured_lock() {
+        let path = store("lock");
+        let strategy = LockStrategy::at(path.with_extension("lock"));
+        let held = strategy.acquire(&path, &Permissions::default()).unwrap();
+
+        let res = KeeperBuilder::new(path.clone())
+            .with_lock_strategy(strategy.clone())
+            .migrate(entry::V1);
+        assert!(matches!(res, Err(Error::PidLock(_))));
+
+        drop(held);
+        let res = KeeperBuilder::new(path.clone())
+            .with_lock_strategy(strategy)
+            .migrate(entry::V1);
+        assert_eq!(res.unwrap().to_version, entry::V1);
+
+        std::fs::remove_dir_all(&path).unwrap();
+        let _ = std::fs::remove_file(path.with_extension("lock"));
+    }
+}
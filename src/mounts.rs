@@ -0,0 +1,118 @@
+use std::{
+    io,
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+use crate::{manifest::FANOUT, permissions::Permissions, utils};
+
+/// How far behind the others the clock of a path may be when [`pin`] waits
+/// for it.
+const MAX_CLOCK_LAG: Duration = Duration::from_secs(5);
+
+/// Spreads the shard folders of the store at `root` over `paths`, folder `f`
+/// going to `paths[f % paths.len()]`. Folders placed on another path are
+/// created there and linked from `root`, so everything else keeps addressing
+/// entries through `root`. Folders that already exist, as directories or
+/// links, stay where they are: adding a path only places new folders, and
+/// nothing is moved.
+///
+/// Paths that are `root` itself keep their folders in place. Tenants, the
+/// manifest and the other store files stay in `root`.
+pub fn link(root: &Path, paths: &[PathBuf], permissions: &Permissions) -> io::Result<()> {
+    if paths.is_empty() {
+        return Ok(());
+    }
+
+    let root_id = std::fs::canonicalize(root)?;
+    let mut targets = Vec::with_capacity(paths.len());
+    for path in paths {
+        permissions.create_dir_all(path)?;
+        let path = std::fs::canonicalize(path)?;
+        targets.push((path != root_id).then_some(path));
+    }
+
+    for folder in 0..FANOUT {
+        let Some(target) = &targets[folder % targets.len()] else {
+            continue;
+        };
+
+        let name = format!("{folder:03x}");
+        let link = root.join(&name);
+        match std::fs::symlink_metadata(&link) {
+            Ok(_) => continue,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+
+        let target = target.join(&name);
+        permissions.create_dir_all(&target)?;
+        symlink(&target, &link)?;
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+fn symlink(target: &Path, link: &Path) -> io::Result<()> {
+    std::os::unix::fs::symlink(target, link)
+}
+
+#[cfg(not(unix))]
+fn symlink(_target: &Path, _link: &Path) -> io::Result<()> {
+    Err(io::ErrorKind::Unsupported.into())
+}
+
+/// The distinct paths the folders of the store at `root` live on, `root`
+/// first. Paths are compared once canonicalized, so a path spelled another
+/// way or reached through a link isn't counted twice.
+pub fn roots(root: &Path, paths: &[PathBuf]) -> Vec<PathBuf> {
+    let canonical =
+        |path: &Path| std::fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+    let mut roots = vec![root.to_path_buf()];
+    let mut seen = vec![canonical(root)];
+    for path in paths {
+        let id = canonical(path);
+        if !seen.contains(&id) {
+            seen.push(id);
+            roots.push(path.clone());
+        }
+    }
+    roots
+}
+
+/// Returns a file system time, in nanoseconds since the epoch, that splits
+/// the entries of every one of `roots` in two: those written before the call
+/// are no newer, those written after are newer. Each path may be another file
+/// system, with its own clock and timestamp granularity, so the marker file
+/// `name` is written on each, and rewritten until every one of them is past
+/// the latest time seen. Fails if a clock lags too far behind for that.
+pub fn pin(roots: &[PathBuf], name: &str, permissions: &Permissions) -> io::Result<u64> {
+    let touch = |root: &Path| {
+        let marker = root.join(name);
+        permissions.write(&marker, &[])?;
+        std::fs::metadata(&marker)?.modified().map(utils::nanos)
+    };
+    let res = (|| {
+        let mut pinned = 0;
+        for root in roots {
+            pinned = pinned.max(touch(root)?);
+        }
+
+        let deadline = Instant::now() + MAX_CLOCK_LAG;
+        for root in roots {
+            while touch(root)? <= pinned {
+                if Instant::now() > deadline {
+                    let message = format!("the clock of {} lags behind the store", root.display());
+                    return Err(io::Error::other(message));
+                }
+                std::thread::sleep(Duration::from_millis(1));
+            }
+        }
+        Ok(pinned)
+    })();
+
+    for root in roots {
+        let _ = std::fs::remove_file(root.join(name));
+    }
+    res
+}
@@ -0,0 +1,328 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::Mutex,
+    time::Duration,
+};
+
+use crate::{error::Error, keeper::Keeper};
+
+#[cfg(feature = "async")]
+use tokio::sync::oneshot;
+
+/// Separates the namespace from the user key in the stored key.
+pub const SEPARATOR: char = '\u{1f}';
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Quota {
+    pub max_bytes: Option<u64>,
+    pub max_entries: Option<u64>,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Usage {
+    pub bytes: u64,
+    pub entries: u64,
+}
+
+/// What a write does when it would take a namespace over its quota.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Overflow {
+    /// Fail with [`Error::QuotaExceeded`].
+    #[default]
+    Reject,
+    /// Evict the namespace's least recently written entries to make room.
+    EvictLru,
+}
+
+/// Settings applied to the entries of one namespace instead of the store's.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Policy {
+    /// Time to live of entries written without one.
+    pub default_ttl: Option<Duration>,
+    /// Whether reads push the expiration back to `default_ttl` from now, so
+    /// only entries left unread expire.
+    pub sliding: bool,
+    /// Largest value accepted, writes of bigger ones fail with
+    /// [`Error::ValueTooLarge`].
+    pub max_value_size: Option<u64>,
+    pub overflow: Overflow,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Record {
+    pub quota: Quota,
+    pub usage: Usage,
+}
+
+pub fn validate_name(name: &str) -> Result<(), Error> {
+    let valid = !name.is_empty()
+        && name
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b == b'_' || b == b'-');
+
+    match valid {
+        true => Ok(()),
+        false => Err(Error::InvalidNamespace(name.into())),
+    }
+}
+
+pub fn qualify(namespace: &str, key: &str) -> String {
+    format!("{namespace}{SEPARATOR}{key}")
+}
+
+pub fn namespace_of(key: &str) -> Option<&str> {
+    key.split_once(SEPARATOR).map(|(ns, _)| ns)
+}
+
+/// Byte and entry usage of every namespace, checked against its quota on
+/// `set`, along with their policies.
+#[derive(Debug, Default)]
+pub struct Namespaces {
+    records: Mutex<HashMap<String, Record>>,
+    policies: HashMap<String, Policy>,
+}
+
+impl Namespaces {
+    pub fn new(records: BTreeMap<String, Record>, policies: HashMap<String, Policy>) -> Self {
+        Self {
+            records: Mutex::new(records.into_iter().collect()),
+            policies,
+        }
+    }
+
+    pub fn policy(&self, namespace: &str) -> Policy {
+        self.policies.get(namespace).copied().unwrap_or_default()
+    }
+
+    pub fn records(&self) -> BTreeMap<String, Record> {
+        let records = self.records.lock().unwrap_or_else(|e| e.into_inner());
+        records.iter().map(|(k, v)| (k.clone(), *v)).collect()
+    }
+
+    pub fn usage(&self, namespace: &str) -> Usage {
+        let records = self.records.lock().unwrap_or_else(|e| e.into_inner());
+        records.get(namespace).map(|r| r.usage).unwrap_or_default()
+    }
+
+    /// Accounts for an entry of `new_size` bytes replacing one of `old_size`
+    /// bytes (if any), failing without changes when the quota would be exceeded.
+    pub fn charge(
+        &self,
+        namespace: &str,
+        old_size: Option<u64>,
+        new_size: u64,
+    ) -> Result<(), Error> {
+        let mut records = self.records.lock().unwrap_or_else(|e| e.into_inner());
+        let record = records.entry(namespace.into()).or_default();
+
+        let bytes = record
+            .usage
+            .bytes
+            .saturating_sub(old_size.unwrap_or(0))
+            .saturating_add(new_size);
+        let entries = record.usage.entries + u64::from(old_size.is_none());
+
+        let over_bytes = record.quota.max_bytes.is_some_and(|max| bytes > max);
+        let over_entries = record.quota.max_entries.is_some_and(|max| entries > max);
+        if over_bytes || over_entries {
+            return Err(Error::QuotaExceeded(namespace.into()));
+        }
+
+        record.usage = Usage { bytes, entries };
+        Ok(())
+    }
+
+    /// How many bytes and entries to evict before an entry of `new_size`
+    /// bytes replacing one of `old_size` bytes fits in the quota, leaving an
+    /// eighth of it free so the next writes don't evict again right away.
+    /// `None` if it fits already.
+    pub fn excess(&self, namespace: &str, old_size: Option<u64>, new_size: u64) -> Option<Usage> {
+        let records = self.records.lock().unwrap_or_else(|e| e.into_inner());
+        let record = records.get(namespace)?;
+
+        let bytes = record
+            .usage
+            .bytes
+            .saturating_sub(old_size.unwrap_or(0))
+            .saturating_add(new_size);
+        let entries = record.usage.entries + u64::from(old_size.is_none());
+
+        let over = |used: u64, max: Option<u64>| match max {
+            Some(max) if used > max => used - (max - max / 8),
+            _ => 0,
+        };
+        let excess = Usage {
+            bytes: over(bytes, record.quota.max_bytes),
+            entries: over(entries, record.quota.max_entries),
+        };
+        (excess != Usage::default()).then_some(excess)
+    }
+
+    /// Reverts a [`Namespaces::charge`] whose write failed.
+    pub fn refund(&self, namespace: &str, old_size: Option<u64>, new_size: u64) {
+        let mut records = self.records.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(record) = records.get_mut(namespace) {
+            record.usage.bytes = record
+                .usage
+                .bytes
+                .saturating_sub(new_size)
+                .saturating_add(old_size.unwrap_or(0));
+            if old_size.is_none() {
+                record.usage.entries = record.usage.entries.saturating_sub(1);
+            }
+        }
+    }
+
+    pub fn release(&self, namespace: &str, size: u64) {
+        let mut records = self.records.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(record) = records.get_mut(namespace) {
+            record.usage.bytes = record.usage.bytes.saturating_sub(size);
+            record.usage.entries = record.usage.entries.saturating_sub(1);
+        }
+    }
+
+    /// Forgets the usage of `namespace` once all its entries were removed.
+    pub fn reset_one(&self, namespace: &str) {
+        let mut records = self.records.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(record) = records.get_mut(namespace) {
+            record.usage = Usage::default();
+        }
+    }
+
+    /// Replaces the usage of every namespace with `usage`, recounted from
+    /// the entries. Namespaces missing from it have none.
+    pub fn rebuild(&self, mut usage: HashMap<String, Usage>) {
+        let mut records = self.records.lock().unwrap_or_else(|e| e.into_inner());
+        for (name, record) in records.iter_mut() {
+            record.usage = usage.remove(name).unwrap_or_default();
+        }
+        for (name, usage) in usage {
+            records.insert(
+                name,
+                Record {
+                    usage,
+                    ..Default::default()
+                },
+            );
+        }
+    }
+
+    pub fn reset(&self) {
+        let mut records = self.records.lock().unwrap_or_else(|e| e.into_inner());
+        for record in records.values_mut() {
+            record.usage = Usage::default();
+        }
+    }
+}
+
+/// A view of a [`Keeper`] whose keys live in their own namespace, with usage
+/// tracked separately and optionally capped by a [`Quota`].
+#[derive(Debug, Clone)]
+pub struct Namespace {
+    keeper: Keeper,
+    name: String,
+}
+
+impl Namespace {
+    pub(crate) fn new(keeper: Keeper, name: String) -> Self {
+        Self { keeper, name }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn usage(&self) -> Usage {
+        self.keeper.namespace_usage(&self.name)
+    }
+
+    #[cfg(all(feature = "async", not(feature = "sync")))]
+    pub async fn get(&self, key: &str) -> Result<Vec<u8>, Error> {
+        let (tx, rx) = oneshot::channel();
+        self.keeper
+            .dispatch_get(&qualify(&self.name, key), move |res| {
+                let _ = tx.send(res);
+            });
+        rx.await.map_err(|_| self.keeper.closed())?
+    }
+
+    #[cfg(all(feature = "async", not(feature = "sync")))]
+    pub async fn set(
+        &self,
+        key: &str,
+        value: &[u8],
+        duration: Option<Duration>,
+    ) -> Result<(), Error> {
+        let (tx, rx) = oneshot::channel();
+        self.keeper
+            .dispatch_set(&qualify(&self.name, key), value, duration, move |res| {
+                let _ = tx.send(res);
+            });
+        rx.await.map_err(|_| self.keeper.closed())?
+    }
+
+    #[cfg(all(feature = "async", not(feature = "sync")))]
+    pub async fn remove(&self, key: &str) -> Result<(), Error> {
+        let (tx, rx) = oneshot::channel();
+        self.keeper
+            .dispatch_remove(&qualify(&self.name, key), move |res| {
+                let _ = tx.send(res);
+            });
+        rx.await.map_err(|_| self.keeper.closed())?
+    }
+
+    #[cfg(all(feature = "sync", not(feature = "async")))]
+    pub fn get(&self, key: &str) -> Result<Vec<u8>, Error> {
+        let (tx, rx) = std::sync::mpsc::sync_channel(1);
+        self.keeper
+            .dispatch_get(&qualify(&self.name, key), move |res| {
+                let _ = tx.send(res);
+            });
+        rx.recv().map_err(|_| self.keeper.closed())?
+    }
+
+    #[cfg(all(feature = "sync", not(feature = "async")))]
+    pub fn set(&self, key: &str, value: &[u8], duration: Option<Duration>) -> Result<(), Error> {
+        let (tx, rx) = std::sync::mpsc::sync_channel(1);
+        self.keeper
+            .dispatch_set(&qualify(&self.name, key), value, duration, move |res| {
+                let _ = tx.send(res);
+            });
+        rx.recv().map_err(|_| self.keeper.closed())?
+    }
+
+    #[cfg(all(feature = "sync", not(feature = "async")))]
+    pub fn remove(&self, key: &str) -> Result<(), Error> {
+        let (tx, rx) = std::sync::mpsc::sync_channel(1);
+        self.keeper
+            .dispatch_remove(&qualify(&self.name, key), move |res| {
+                let _ = tx.send(res);
+            });
+        rx.recv().map_err(|_| self.keeper.closed())?
+    }
+
+    #[cfg(all(not(feature = "async"), not(feature = "sync")))]
+    pub fn get<F>(&self, key: &str, cb: F)
+    where
+        F: FnOnce(Result<Vec<u8>, Error>) + Send + Sync + 'static,
+    {
+        self.keeper.dispatch_get(&qualify(&self.name, key), cb);
+    }
+
+    #[cfg(all(not(feature = "async"), not(feature = "sync")))]
+    pub fn set<F>(&self, key: &str, value: &[u8], duration: Option<Duration>, cb: F)
+    where
+        F: FnOnce(Result<(), Error>) + Send + Sync + 'static,
+    {
+        self.keeper
+            .dispatch_set(&qualify(&self.name, key), value, duration, cb);
+    }
+
+    #[cfg(all(not(feature = "async"), not(feature = "sync")))]
+    pub fn remove<F>(&self, key: &str, cb: F)
+    where
+        F: FnOnce(Result<(), Error>) + Send + Sync + 'static,
+    {
+        self.keeper.dispatch_remove(&qualify(&self.name, key), cb);
+    }
+}
@@ -0,0 +1,40 @@
+use std::{fmt, sync::Arc, time::Duration};
+
+use crate::error::Error;
+
+/// The source of truth a keeper caches. Attached with
+/// [`crate::keeper::KeeperBuilder::with_origin`], it is read on every miss
+/// and, with write-through, written on every `set`.
+pub trait Origin: Send + Sync {
+    /// Produces the value of `key` along with its time to live in the cache.
+    fn load(&self, key: &str) -> Result<(Vec<u8>, Option<Duration>), Error>;
+
+    /// Persists a value written to the cache. Only called with write-through.
+    fn store(&self, key: &str, value: &[u8]) -> Result<(), Error> {
+        let _ = (key, value);
+        Ok(())
+    }
+}
+
+pub struct Attached {
+    pub origin: Arc<dyn Origin>,
+    pub write_through: bool,
+}
+
+impl Attached {
+    /// Stores `value` in the origin if writes go through to it.
+    pub fn write(&self, key: &str, value: &[u8]) -> Result<(), Error> {
+        match self.write_through {
+            true => self.origin.store(key, value),
+            false => Ok(()),
+        }
+    }
+}
+
+impl fmt::Debug for Attached {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Attached")
+            .field("write_through", &self.write_through)
+            .finish_non_exhaustive()
+    }
+}
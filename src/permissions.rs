@@ -0,0 +1,79 @@
+use std::{fs::File, io::Write, path::Path};
+
+/// Mode bits for the files and directories keeper creates. Modes are applied
+/// after creation, so the process umask doesn't widen or narrow them. Only
+/// supported on Unix; elsewhere they are ignored.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Permissions {
+    pub file_mode: Option<u32>,
+    pub dir_mode: Option<u32>,
+}
+
+impl Permissions {
+    pub fn create_file(&self, path: &Path) -> std::io::Result<File> {
+        self.open(
+            path,
+            std::fs::OpenOptions::new()
+                .write(true)
+                .create(true)
+                .truncate(true),
+        )
+    }
+
+    /// Like [`Permissions::create_file`], failing with `AlreadyExists` instead
+    /// of truncating an existing file. Atomic, even across processes.
+    pub fn create_new(&self, path: &Path) -> std::io::Result<File> {
+        self.open(
+            path,
+            std::fs::OpenOptions::new().write(true).create_new(true),
+        )
+    }
+
+    fn open(&self, path: &Path, options: &mut std::fs::OpenOptions) -> std::io::Result<File> {
+        #[cfg(unix)]
+        if let Some(mode) = self.file_mode {
+            use std::os::unix::fs::OpenOptionsExt;
+            options.mode(mode);
+        }
+
+        // The mode given to open is narrowed by the umask; set it again so it
+        // also holds when the umask is stricter.
+        let file = options.open(path)?;
+        if let Some(mode) = self.file_mode {
+            set_mode(path, mode)?;
+        }
+        Ok(file)
+    }
+
+    pub fn write(&self, path: &Path, contents: &[u8]) -> std::io::Result<()> {
+        self.create_file(path)?.write_all(contents)
+    }
+
+    pub fn create_dir_all(&self, path: &Path) -> std::io::Result<()> {
+        std::fs::create_dir_all(path)?;
+        if let Some(mode) = self.dir_mode {
+            set_mode(path, mode)?;
+        }
+        Ok(())
+    }
+
+    /// Applies the file mode to a file created by someone else, like the lock
+    /// file.
+    pub fn apply_file(&self, path: &Path) -> std::io::Result<()> {
+        match self.file_mode {
+            Some(mode) => set_mode(path, mode),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(unix)]
+fn set_mode(path: &Path, mode: u32) -> std::io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode))
+}
+
+#[cfg(not(unix))]
+fn set_mode(_path: &Path, _mode: u32) -> std::io::Result<()> {
+    Ok(())
+}
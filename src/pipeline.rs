@@ -0,0 +1,98 @@
+use std::time::Duration;
+
+#[cfg(feature = "async")]
+use tokio::sync::oneshot;
+
+use crate::{error::Error, keeper::Keeper};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Command {
+    Get {
+        key: String,
+    },
+    Set {
+        key: String,
+        value: Vec<u8>,
+        duration: Option<Duration>,
+    },
+    Remove {
+        key: String,
+    },
+}
+
+/// Outcome of a [`Command`]: the value for gets, `None` for sets and removes.
+pub type Reply = Result<Option<Vec<u8>>, Error>;
+
+/// Operations queued to be sent to a store worker as a single message, and
+/// answered all at once in the order they were queued.
+#[derive(Debug)]
+pub struct Pipeline {
+    keeper: Keeper,
+    commands: Vec<Command>,
+}
+
+impl Pipeline {
+    pub(crate) fn new(keeper: Keeper) -> Self {
+        Self {
+            keeper,
+            commands: Vec::new(),
+        }
+    }
+
+    pub fn get(&mut self, key: &str) -> &mut Self {
+        self.commands.push(Command::Get { key: key.into() });
+        self
+    }
+
+    pub fn set(&mut self, key: &str, value: &[u8], duration: Option<Duration>) -> &mut Self {
+        self.commands.push(Command::Set {
+            key: key.into(),
+            value: value.to_vec(),
+            duration,
+        });
+        self
+    }
+
+    pub fn remove(&mut self, key: &str) -> &mut Self {
+        self.commands.push(Command::Remove { key: key.into() });
+        self
+    }
+
+    pub fn len(&self) -> usize {
+        self.commands.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.commands.is_empty()
+    }
+
+    #[cfg(all(feature = "async", not(feature = "sync")))]
+    pub async fn execute(self) -> Vec<Reply> {
+        let len = self.commands.len();
+        let (tx, rx) = oneshot::channel();
+        self.keeper.dispatch_batch(self.commands, move |res| {
+            let _ = tx.send(res);
+        });
+        rx.await
+            .unwrap_or_else(|_| (0..len).map(|_| Err(self.keeper.closed())).collect())
+    }
+
+    #[cfg(all(feature = "sync", not(feature = "async")))]
+    pub fn execute(self) -> Vec<Reply> {
+        let len = self.commands.len();
+        let (tx, rx) = std::sync::mpsc::sync_channel(1);
+        self.keeper.dispatch_batch(self.commands, move |res| {
+            let _ = tx.send(res);
+        });
+        rx.recv()
+            .unwrap_or_else(|_| (0..len).map(|_| Err(self.keeper.closed())).collect())
+    }
+
+    #[cfg(all(not(feature = "async"), not(feature = "sync")))]
+    pub fn execute<F>(self, cb: F)
+    where
+        F: FnOnce(Vec<Reply>) + Send + Sync + 'static,
+    {
+        self.keeper.dispatch_batch(self.commands, cb);
+    }
+}
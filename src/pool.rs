@@ -0,0 +1,254 @@
+use std::{
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+    },
+    thread::JoinHandle,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    context::Context,
+    error::{Error, Worker},
+    queue::{Lanes, Priority, Senders},
+    store,
+};
+
+/// Lets the supervisor tell how long a worker has been busy with its current
+/// message.
+#[derive(Debug)]
+pub struct Heartbeat {
+    origin: Instant,
+    /// Milliseconds since `origin`, plus one, when the current message was
+    /// received; `0` while idle.
+    busy_since: AtomicU64,
+    /// Set once a replacement was spawned; the worker exits when it's done.
+    wedged: AtomicBool,
+}
+
+impl Heartbeat {
+    fn new() -> Self {
+        Self {
+            origin: Instant::now(),
+            busy_since: AtomicU64::new(0),
+            wedged: AtomicBool::new(false),
+        }
+    }
+
+    fn elapsed_ms(&self) -> u64 {
+        self.origin.elapsed().as_millis() as u64 + 1
+    }
+
+    pub fn begin(&self) {
+        self.busy_since.store(self.elapsed_ms(), Ordering::Release);
+    }
+
+    /// Returns whether the worker was replaced while busy and should exit.
+    pub fn end(&self) -> bool {
+        self.busy_since.store(0, Ordering::Release);
+        self.wedged.load(Ordering::Acquire)
+    }
+
+    fn busy_for(&self) -> Option<Duration> {
+        match self.busy_since.load(Ordering::Acquire) {
+            0 => None,
+            since => Some(Duration::from_millis(
+                self.elapsed_ms().saturating_sub(since),
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Health {
+    pub workers: usize,
+    /// Workers stuck on a single operation for longer than the supervisor's
+    /// timeout. Each was replaced by a fresh worker.
+    pub wedged: usize,
+    pub replaced: u64,
+}
+
+impl Health {
+    pub fn is_degraded(&self) -> bool {
+        self.wedged > 0
+    }
+}
+
+#[derive(Debug)]
+pub struct Pool {
+    ctx: Arc<Context>,
+    receiver: Lanes,
+    min: usize,
+    max: usize,
+    idle_timeout: Option<Duration>,
+    scale_up_delay: Duration,
+    /// When the queue last became deeper than the number of live workers.
+    backlog_since: Mutex<Option<Instant>>,
+    live: Arc<AtomicUsize>,
+    handles: Mutex<Vec<JoinHandle<()>>>,
+    heartbeats: Mutex<Vec<Arc<Heartbeat>>>,
+    replaced: AtomicU64,
+}
+
+impl Pool {
+    pub fn new(
+        ctx: Arc<Context>,
+        receiver: Lanes,
+        max: usize,
+        idle_timeout: Option<Duration>,
+        scale_up_delay: Duration,
+    ) -> Self {
+        let min = if idle_timeout.is_some() { 1 } else { max };
+        let pool = Self {
+            ctx,
+            receiver,
+            min,
+            max,
+            idle_timeout,
+            scale_up_delay,
+            backlog_since: Mutex::new(None),
+            live: Arc::new(AtomicUsize::new(0)),
+            handles: Mutex::new(Vec::with_capacity(max)),
+            heartbeats: Mutex::new(Vec::with_capacity(max)),
+            replaced: AtomicU64::new(0),
+        };
+
+        for _ in 0..min {
+            pool.live.fetch_add(1, Ordering::AcqRel);
+            pool.spawn();
+        }
+        pool
+    }
+
+    pub fn grow(&self, depth: usize) {
+        if self.idle_timeout.is_none() {
+            return;
+        }
+
+        if !self.scale_up_delay.is_zero() {
+            let mut since = self.backlog_since.lock().unwrap_or_else(|e| e.into_inner());
+            if depth <= self.live.load(Ordering::Acquire) {
+                *since = None;
+                return;
+            }
+            if since.get_or_insert_with(Instant::now).elapsed() < self.scale_up_delay {
+                return;
+            }
+            *since = None;
+        }
+
+        let grew = self
+            .live
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |live| {
+                (depth > live && live < self.max).then_some(live + 1)
+            })
+            .is_ok();
+
+        if grew {
+            self.spawn();
+        }
+    }
+
+    /// Replaces workers that have been busy with the same message for longer
+    /// than `timeout`, so the rest of the queue keeps moving.
+    pub fn supervise(&self, timeout: Duration) {
+        let wedged: Vec<_> = {
+            let mut heartbeats = self.heartbeats.lock().unwrap_or_else(|e| e.into_inner());
+            heartbeats.retain(|hb| Arc::strong_count(hb) > 1);
+            heartbeats
+                .iter()
+                .filter(|hb| !hb.wedged.load(Ordering::Acquire))
+                .filter(|hb| hb.busy_for().is_some_and(|busy| busy > timeout))
+                .cloned()
+                .collect()
+        };
+
+        for heartbeat in wedged {
+            heartbeat.wedged.store(true, Ordering::Release);
+            self.replaced.fetch_add(1, Ordering::Relaxed);
+            self.spawn();
+        }
+    }
+
+    pub fn health(&self) -> Health {
+        let mut heartbeats = self.heartbeats.lock().unwrap_or_else(|e| e.into_inner());
+        heartbeats.retain(|hb| Arc::strong_count(hb) > 1);
+
+        Health {
+            workers: self.live.load(Ordering::Acquire),
+            wedged: heartbeats
+                .iter()
+                .filter(|hb| hb.wedged.load(Ordering::Acquire))
+                .count(),
+            replaced: self.replaced.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Asks every worker to stop once the queue is drained and joins them. With
+    /// a deadline, requests still queued when it passes are rejected and
+    /// workers still busy are detached; returns `false` if any was.
+    pub fn shutdown(&self, sender: &Senders, deadline: Option<Instant>) -> bool {
+        let mut handles = self.handles.lock().unwrap_or_else(|e| e.into_inner());
+        for _ in 0..handles.len() {
+            sender
+                .send(store::InputMessage::Quit, Priority::Normal)
+                .ok();
+        }
+
+        let mut stopped = true;
+        if let Some(deadline) = deadline {
+            while !handles.iter().all(JoinHandle::is_finished) && Instant::now() < deadline {
+                std::thread::sleep(Duration::from_millis(5));
+            }
+
+            for msg in self.receiver.try_iter() {
+                msg.reject(|| Error::ShuttingDown);
+            }
+
+            let (finished, stuck): (Vec<_>, Vec<_>) =
+                handles.drain(..).partition(JoinHandle::is_finished);
+            for _ in &stuck {
+                sender
+                    .send(store::InputMessage::Quit, Priority::Normal)
+                    .ok();
+            }
+            stopped = stuck.is_empty();
+            handles.extend(finished);
+        }
+
+        for handle in handles.drain(..) {
+            if handle.join().is_err() {
+                self.ctx.hooks.report(&Error::WorkerPanicked(Worker::Store));
+            }
+        }
+        stopped
+    }
+
+    fn spawn(&self) {
+        let idle = self.idle_timeout.map(|timeout| store::Idle {
+            timeout,
+            min: self.min,
+            live: self.live.clone(),
+        });
+
+        let heartbeat = Arc::new(Heartbeat::new());
+        self.heartbeats
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .push(heartbeat.clone());
+
+        let handle = std::thread::spawn({
+            let ctx = self.ctx.clone();
+            let ir = self.receiver.clone();
+            move || {
+                ctx.respawn(Worker::Store, || {
+                    store::worker(ctx.clone(), ir.clone(), idle.clone(), heartbeat.clone())
+                })
+            }
+        });
+
+        let mut handles = self.handles.lock().unwrap_or_else(|e| e.into_inner());
+        handles.retain(|h| !h.is_finished());
+        handles.push(handle);
+    }
+}
@@ -0,0 +1,134 @@
+use std::{
+    path::Path,
+    sync::atomic::{AtomicBool, AtomicU64, Ordering},
+};
+
+use crossbeam::channel::Sender;
+
+use crate::janitor;
+
+/// How often, in writes, the free space of the volume is sampled.
+const FREE_SPACE_SAMPLING: u64 = 64;
+
+/// Wakes the janitor when the store grows past `max_store_bytes` or the volume
+/// drops below `min_free_bytes` of free space, instead of waiting for the next
+/// scheduled sweep.
+#[derive(Debug)]
+pub struct Pressure {
+    max_store_bytes: Option<u64>,
+    min_free_bytes: Option<u64>,
+    store_bytes: AtomicU64,
+    writes: AtomicU64,
+    pending: AtomicBool,
+    janitor: Sender<janitor::InputMessage>,
+}
+
+impl Pressure {
+    pub fn new(
+        max_store_bytes: Option<u64>,
+        min_free_bytes: Option<u64>,
+        janitor: Sender<janitor::InputMessage>,
+    ) -> Self {
+        Self {
+            max_store_bytes,
+            min_free_bytes,
+            store_bytes: AtomicU64::new(0),
+            writes: AtomicU64::new(0),
+            pending: AtomicBool::new(false),
+            janitor,
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.max_store_bytes.is_some() || self.min_free_bytes.is_some()
+    }
+
+    /// Approximate size of the store, adjusted on every write and removal and
+    /// recomputed by complete janitor sweeps.
+    pub fn store_bytes(&self) -> u64 {
+        self.store_bytes.load(Ordering::Relaxed)
+    }
+
+    pub fn resize(&self, old_size: u64, new_size: u64) {
+        let _ = self
+            .store_bytes
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |bytes| {
+                Some(bytes.saturating_sub(old_size).saturating_add(new_size))
+            });
+    }
+
+    pub fn reset(&self, store_bytes: u64) {
+        self.store_bytes.store(store_bytes, Ordering::Relaxed);
+    }
+
+    /// Bytes that have to be freed to get back under the limits, with a 10%
+    /// margin so the janitor isn't woken again by the very next write.
+    pub fn excess(&self, root: &Path) -> u64 {
+        let over_store = self
+            .max_store_bytes
+            .map(|max| self.store_bytes().saturating_sub(max - max / 10))
+            .unwrap_or(0);
+
+        let under_free = self
+            .min_free_bytes
+            .and_then(|min| Some((min + min / 10).saturating_sub(free_space(root)?)))
+            .unwrap_or(0);
+
+        over_store.max(under_free)
+    }
+
+    /// Called after each write; signals the janitor once until it reports back
+    /// through [`Pressure::relieved`].
+    pub fn check(&self, root: &Path) {
+        if !self.is_enabled() || self.pending.load(Ordering::Acquire) {
+            return;
+        }
+
+        let over_store = self
+            .max_store_bytes
+            .is_some_and(|max| self.store_bytes() > max);
+
+        let sample = self
+            .writes
+            .fetch_add(1, Ordering::Relaxed)
+            .is_multiple_of(FREE_SPACE_SAMPLING);
+        let under_free = sample
+            && self
+                .min_free_bytes
+                .zip(free_space(root))
+                .is_some_and(|(min, free)| free < min);
+
+        if (over_store || under_free) && !self.pending.swap(true, Ordering::AcqRel) {
+            let _ = self.janitor.send(janitor::InputMessage::Pressure);
+        }
+    }
+
+    pub fn relieved(&self) {
+        self.pending.store(false, Ordering::Release);
+    }
+}
+
+#[cfg(unix)]
+pub fn free_space(path: &Path) -> Option<u64> {
+    use std::os::unix::ffi::OsStrExt;
+
+    let path = std::ffi::CString::new(path.as_os_str().as_bytes()).ok()?;
+    let mut stat = std::mem::MaybeUninit::<libc::statvfs>::uninit();
+
+    // SAFETY: `path` is a valid C string and `stat` is only read after
+    // `statvfs` reports success.
+    let stat = unsafe {
+        if libc::statvfs(path.as_ptr(), stat.as_mut_ptr()) != 0 {
+            return None;
+        }
+        stat.assume_init()
+    };
+
+    #[allow(clippy::unnecessary_cast)]
+    Some(stat.f_bavail as u64 * stat.f_frsize as u64)
+}
+
+#[cfg(not(unix))]
+pub fn free_space(_path: &Path) -> Option<u64> {
+    None
+}
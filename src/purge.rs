@@ -0,0 +1,123 @@
+use std::path::{Path, PathBuf};
+
+use crate::{
+    context::Context,
+    error::Error,
+    stats::Counters,
+    tenant,
+    utils::{self, parse_hash},
+};
+
+#[derive(Debug)]
+pub enum PurgeStatus {
+    /// The entry existed and is gone.
+    Removed,
+    /// There was no entry for the key.
+    NotFound,
+    /// The entry could not be removed, or was still there afterwards.
+    Failed(Error),
+}
+
+#[derive(Debug, Default)]
+pub struct PurgeReport {
+    pub keys: Vec<(String, PurgeStatus)>,
+}
+
+impl PurgeReport {
+    pub fn removed(&self) -> usize {
+        self.count(|s| matches!(s, PurgeStatus::Removed))
+    }
+
+    pub fn not_found(&self) -> usize {
+        self.count(|s| matches!(s, PurgeStatus::NotFound))
+    }
+
+    pub fn failed(&self) -> usize {
+        self.count(|s| matches!(s, PurgeStatus::Failed(_)))
+    }
+
+    /// Whether no entry for any of the keys is left on disk or in memory.
+    pub fn is_complete(&self) -> bool {
+        self.failed() == 0
+    }
+
+    fn count(&self, f: impl Fn(&PurgeStatus) -> bool) -> usize {
+        self.keys.iter().filter(|(_, s)| f(s)).count()
+    }
+}
+
+/// Removes every key in `keys` on the calling thread, from the store root and
+/// every tenant, along with any temporary file left by an interrupted write
+/// of it, and checks under the shard lock that nothing is left behind.
+pub fn purge<I>(ctx: &Context, root: &Path, keys: I) -> PurgeReport
+where
+    I: IntoIterator<Item = String>,
+{
+    let roots = tenant::roots(root);
+    let mut report = PurgeReport::default();
+    for key in keys {
+        let res = roots.iter().try_fold(false, |existed, root| {
+            purge_key(ctx, root, &key).map(|found| existed | found)
+        });
+        let status = match res {
+            Ok(true) => PurgeStatus::Removed,
+            Ok(false) => PurgeStatus::NotFound,
+            Err(e) => PurgeStatus::Failed(e),
+        };
+        report.keys.push((key, status));
+    }
+    report
+}
+
+fn purge_key(ctx: &Context, root: &Path, key: &str) -> Result<bool, Error> {
+    let h = ctx.hash(key);
+    let (p_folder, filename, shard_id) = parse_hash(&h);
+    let folder = root.join(p_folder);
+    let file_path = folder.join(filename);
+
+    let _permit = ctx.io_permit();
+    let _lock = ctx.shards.write(shard_id);
+
+    ctx.forget(&file_path);
+    let existed = file_path.exists();
+    if existed {
+        ctx.remove_entry(&file_path)?;
+        Counters::add(&ctx.counters.removes, 1);
+    }
+    for tmp in temp_files(&folder, filename)? {
+        if ctx.secure_delete
+            && let Ok(meta) = std::fs::metadata(&tmp)
+        {
+            utils::scrub(&tmp, meta.len())?;
+        }
+        utils::retry_io(|| std::fs::remove_file(&tmp))?;
+    }
+
+    match file_path.exists() || !temp_files(&folder, filename)?.is_empty() {
+        true => Err(std::io::Error::other("entry still present after removal").into()),
+        false => Ok(existed),
+    }
+}
+
+/// The temporary files of writes of the entry `filename` in `folder`:
+/// `<filename>.tmp` for writes made under the shard lock and
+/// `<filename>.<id>.tmp` for streamed ones.
+fn temp_files(folder: &Path, filename: &str) -> std::io::Result<Vec<PathBuf>> {
+    let entries = match std::fs::read_dir(folder) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+
+    let prefix = format!("{filename}.");
+    let mut temp = Vec::new();
+    for entry in entries {
+        let entry = entry?;
+        let name = entry.file_name();
+        let name = name.to_string_lossy();
+        if name.starts_with(&prefix) && name.ends_with(".tmp") {
+            temp.push(entry.path());
+        }
+    }
+    Ok(temp)
+}
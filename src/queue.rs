@@ -0,0 +1,170 @@
+use std::{
+    fmt,
+    sync::atomic::{AtomicBool, Ordering},
+    time::Duration,
+};
+
+use crossbeam::channel::{
+    Receiver, RecvError, RecvTimeoutError, SendError, Sender, select_biased, unbounded,
+};
+
+use crate::store::InputMessage;
+
+pub type QueueHook = Box<dyn Fn(usize) + Send + Sync + 'static>;
+
+pub struct Watermarks {
+    high: usize,
+    low: usize,
+    on_high: Option<QueueHook>,
+    on_low: Option<QueueHook>,
+    saturated: AtomicBool,
+}
+
+impl Default for Watermarks {
+    fn default() -> Self {
+        Self {
+            high: usize::MAX,
+            low: 0,
+            on_high: None,
+            on_low: None,
+            saturated: AtomicBool::new(false),
+        }
+    }
+}
+
+impl fmt::Debug for Watermarks {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Watermarks")
+            .field("high", &self.high)
+            .field("low", &self.low)
+            .field("saturated", &self.saturated)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Watermarks {
+    pub fn set_levels(&mut self, high: usize, low: usize) {
+        self.high = high.max(1);
+        self.low = low.min(self.high - 1);
+    }
+
+    pub fn set_on_high(&mut self, hook: QueueHook) {
+        self.on_high = Some(hook);
+    }
+
+    pub fn set_on_low(&mut self, hook: QueueHook) {
+        self.on_low = Some(hook);
+    }
+
+    pub fn is_saturated(&self) -> bool {
+        self.saturated.load(Ordering::Acquire)
+    }
+
+    pub fn observe(&self, depth: usize) {
+        if depth >= self.high {
+            if !self.saturated.swap(true, Ordering::AcqRel)
+                && let Some(hook) = &self.on_high
+            {
+                hook(depth);
+            }
+        } else if depth <= self.low
+            && self.saturated.swap(false, Ordering::AcqRel)
+            && let Some(hook) = &self.on_low
+        {
+            hook(depth);
+        }
+    }
+}
+
+/// Which lane of the store queue a request waits in.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Priority {
+    /// Taken by workers before any normal request, for latency-critical
+    /// reads queued behind bulk work.
+    High,
+    #[default]
+    Normal,
+}
+
+/// The store queue, one channel per [`Priority`].
+pub fn channel() -> (Senders, Lanes) {
+    let (high_is, high_ir) = unbounded();
+    let (normal_is, normal_ir) = unbounded();
+    (
+        Senders {
+            high: high_is,
+            normal: normal_is,
+        },
+        Lanes {
+            high: high_ir,
+            normal: normal_ir,
+        },
+    )
+}
+
+#[derive(Debug, Clone)]
+pub struct Senders {
+    high: Sender<InputMessage>,
+    normal: Sender<InputMessage>,
+}
+
+impl Senders {
+    pub fn send(
+        &self,
+        msg: InputMessage,
+        priority: Priority,
+    ) -> Result<(), SendError<InputMessage>> {
+        match priority {
+            Priority::High => self.high.send(msg),
+            Priority::Normal => self.normal.send(msg),
+        }
+    }
+
+    /// Requests waiting in either lane.
+    pub fn len(&self) -> usize {
+        self.high.len() + self.normal.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.high.is_empty() && self.normal.is_empty()
+    }
+}
+
+/// The receiving end of the store queue. Workers take high priority requests
+/// first, and normal ones only while no high priority request waits.
+#[derive(Debug, Clone)]
+pub struct Lanes {
+    high: Receiver<InputMessage>,
+    normal: Receiver<InputMessage>,
+}
+
+impl Lanes {
+    pub fn recv(&self) -> Result<InputMessage, RecvError> {
+        select_biased! {
+            recv(self.high) -> msg => msg,
+            recv(self.normal) -> msg => msg,
+        }
+    }
+
+    pub fn recv_timeout(&self, timeout: Duration) -> Result<InputMessage, RecvTimeoutError> {
+        select_biased! {
+            recv(self.high) -> msg => msg.map_err(|_| RecvTimeoutError::Disconnected),
+            recv(self.normal) -> msg => msg.map_err(|_| RecvTimeoutError::Disconnected),
+            default(timeout) => Err(RecvTimeoutError::Timeout),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.high.len() + self.normal.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.high.is_empty() && self.normal.is_empty()
+    }
+
+    /// Takes every waiting request, high priority ones first, without
+    /// blocking.
+    pub fn try_iter(&self) -> impl Iterator<Item = InputMessage> + '_ {
+        self.high.try_iter().chain(self.normal.try_iter())
+    }
+}
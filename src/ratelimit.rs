@@ -0,0 +1,222 @@
+use std::{
+    sync::{
+        Arc,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::{Duration, SystemTime},
+};
+
+use crate::{error::Error, keeper::Keeper, utils};
+
+#[cfg(feature = "async")]
+use tokio::sync::oneshot;
+
+/// How a [`RateLimiter`] counts requests.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Window {
+    /// Up to `limit` requests in each period, counted from period boundaries.
+    /// Cheap, but allows bursts of twice the limit around a boundary.
+    Fixed,
+    /// Up to `limit` requests in any period, estimated from the counts of the
+    /// current and previous fixed periods, the previous one weighed by how
+    /// much of it the sliding period still covers. Costs an extra read.
+    Sliding,
+}
+
+/// Whether a request may go ahead.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Decision {
+    pub allowed: bool,
+    /// Requests still allowed in the current period.
+    pub remaining: u64,
+    /// When a denied request could be allowed, if no other request comes in
+    /// meanwhile.
+    pub retry_after: Option<Duration>,
+}
+
+/// Limits how often each key may be used, counting requests in entries of
+/// the store, so every process sharing it shares the limits. Each period
+/// is an entry, `ratelimit:<key>:<period>`, incremented through
+/// [`Keeper::merge`] under its shard lock and expiring once no decision
+/// needs it anymore. Periods are measured with the system clock.
+#[derive(Debug, Clone)]
+pub struct RateLimiter {
+    keeper: Keeper,
+    window: Window,
+    limit: u64,
+    period_ms: u64,
+}
+
+/// State of the current period when a request is checked.
+#[derive(Clone, Copy)]
+struct Check {
+    /// Request count of the previous period, for sliding windows.
+    previous: u64,
+    /// Milliseconds since the start of the current period.
+    elapsed: u64,
+}
+
+impl RateLimiter {
+    pub(crate) fn new(keeper: Keeper, window: Window, limit: u64, period: Duration) -> Self {
+        Self {
+            keeper,
+            window,
+            limit,
+            period_ms: (period.as_millis() as u64).max(1),
+        }
+    }
+
+    /// Counts a request for `key` if the limit allows it.
+    #[cfg(all(feature = "async", not(feature = "sync")))]
+    pub async fn check(&self, key: &str) -> Result<Decision, Error> {
+        let (tx, rx) = oneshot::channel();
+        self.dispatch_check(key, move |res| {
+            let _ = tx.send(res);
+        });
+        rx.await.map_err(|_| self.keeper.closed())?
+    }
+
+    /// Counts a request for `key` if the limit allows it.
+    #[cfg(all(feature = "sync", not(feature = "async")))]
+    pub fn check(&self, key: &str) -> Result<Decision, Error> {
+        let (tx, rx) = std::sync::mpsc::sync_channel(1);
+        self.dispatch_check(key, move |res| {
+            let _ = tx.send(res);
+        });
+        rx.recv().map_err(|_| self.keeper.closed())?
+    }
+
+    /// Counts a request for `key` if the limit allows it.
+    #[cfg(all(not(feature = "async"), not(feature = "sync")))]
+    pub fn check<F>(&self, key: &str, cb: F)
+    where
+        F: FnOnce(Result<Decision, Error>) + Send + Sync + 'static,
+    {
+        self.dispatch_check(key, cb);
+    }
+
+    pub(crate) fn dispatch_check<F>(&self, key: &str, cb: F)
+    where
+        F: FnOnce(Result<Decision, Error>) + Send + Sync + 'static,
+    {
+        let now = utils::nanos(SystemTime::now()) / 1_000_000;
+        let index = now / self.period_ms;
+        let elapsed = now % self.period_ms;
+
+        if self.window == Window::Fixed || index == 0 {
+            return self.count(
+                key,
+                index,
+                Check {
+                    previous: 0,
+                    elapsed,
+                },
+                cb,
+            );
+        }
+
+        let limiter = self.clone();
+        let key = key.to_string();
+        self.keeper
+            .dispatch_get(&entry_key(&key, index - 1), move |res| {
+                let previous = match res {
+                    Ok(count) => parse(Some(&count)),
+                    Err(Error::NotFound) => 0,
+                    Err(e) => return cb(Err(e)),
+                };
+                limiter.count(&key, index, Check { previous, elapsed }, cb);
+            });
+    }
+
+    /// Increments the count of the current period unless the request is
+    /// denied, deciding under the shard lock.
+    fn count<F>(&self, key: &str, index: u64, check: Check, cb: F)
+    where
+        F: FnOnce(Result<Decision, Error>) + Send + Sync + 'static,
+    {
+        // Kept through the next period, which sliding windows read it in.
+        let ttl =
+            Duration::from_millis(2 * self.period_ms - check.elapsed) + Duration::from_secs(1);
+        let decided = Arc::new(AtomicU64::new(u64::MAX));
+        let limiter = self.clone();
+
+        self.keeper.dispatch_merge(
+            &entry_key(key, index),
+            &[],
+            Some(ttl),
+            {
+                let decided = decided.clone();
+                move |current, _| {
+                    let count = parse(current);
+                    let allowed = limiter.estimate(count, &check) < limiter.limit as f64;
+                    let count = count + allowed as u64;
+                    decided.store(count << 1 | allowed as u64, Ordering::Release);
+                    count.to_string().into_bytes()
+                }
+            },
+            {
+                let limiter = self.clone();
+                move |res| {
+                    cb(res.map(|_| {
+                        let decided = decided.load(Ordering::Acquire);
+                        limiter.decide(decided >> 1, decided & 1 == 1, &check)
+                    }))
+                }
+            },
+        );
+    }
+
+    /// Requests counted in the sliding period ending now, given `count` in
+    /// the current fixed one.
+    fn estimate(&self, count: u64, check: &Check) -> f64 {
+        let uncovered = check.elapsed as f64 / self.period_ms as f64;
+        count as f64 + check.previous as f64 * (1.0 - uncovered)
+    }
+
+    fn decide(&self, count: u64, allowed: bool, check: &Check) -> Decision {
+        let remaining = match self.window {
+            Window::Fixed => self.limit.saturating_sub(count),
+            Window::Sliding => {
+                (self.limit as f64 - self.estimate(count, check).ceil()).max(0.0) as u64
+            }
+        };
+        let retry_after = (!allowed).then(|| Duration::from_millis(self.wait(count, check)));
+        Decision {
+            allowed,
+            remaining,
+            retry_after,
+        }
+    }
+
+    /// Milliseconds until a request would be allowed.
+    fn wait(&self, count: u64, check: &Check) -> u64 {
+        let period = self.period_ms as f64;
+        let to_next = self.period_ms - check.elapsed;
+        if self.window == Window::Fixed {
+            return to_next;
+        }
+
+        let limit = self.limit as f64;
+        let wait = match count < self.limit {
+            // The previous period's weight has to drop enough.
+            true => {
+                period * (1.0 - (limit - count as f64) / check.previous as f64)
+                    - check.elapsed as f64
+            }
+            // Only the next period helps, once this one weighs little enough.
+            false => to_next as f64 + period * (1.0 - limit / count as f64),
+        };
+        wait.max(0.0).ceil() as u64
+    }
+}
+
+fn entry_key(key: &str, index: u64) -> String {
+    format!("ratelimit:{key}:{index}")
+}
+
+fn parse(count: Option<&[u8]>) -> u64 {
+    count
+        .and_then(|count| std::str::from_utf8(count).ok())
+        .and_then(|count| count.parse().ok())
+        .unwrap_or(0)
+}
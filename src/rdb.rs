@@ -0,0 +1,747 @@
+use std::{
+    io::{BufReader, Read},
+    path::Path,
+};
+
+use crate::{context::Context, entry, error::Error, store::BulkWriter};
+
+/// What [`import`] did with the keys of a snapshot.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct RdbReport {
+    /// String keys written to the store.
+    pub imported: u64,
+    /// String keys whose TTL already ran out.
+    pub expired: u64,
+    /// Keys of other types (lists, hashes, streams...), whose name isn't
+    /// UTF-8 or is longer than [`crate::entry::MAX_KEY_LEN`] bytes, none of
+    /// which the store can hold.
+    pub skipped: u64,
+}
+
+/// Oldest and newest RDB versions understood, Redis 1.0 to 7.4.
+const VERSIONS: std::ops::RangeInclusive<u32> = 1..=12;
+
+const TYPE_STRING: u8 = 0;
+
+/// Loads the string keys of a Redis RDB snapshot (`dump.rdb`, or the output
+/// of `redis-cli --rdb`) from `reader` into the store at `root`, through the
+/// same path as [`crate::store::bulk_load`]. Keys of every database are
+/// loaded and their TTLs kept, rounded up to the second. The checksum at the
+/// end of the file isn't verified.
+///
+/// Fails with [`Error::InvalidData`] on a malformed snapshot, or one holding a
+/// value of a type that can't be skipped (version 1 modules, functions in
+/// their pre-release format), possibly after some of the keys before it were
+/// written.
+pub fn import(ctx: &Context, root: &Path, reader: impl Read) -> Result<RdbReport, Error> {
+    let mut rdb = Rdb::new(BufReader::new(reader));
+    let header = rdb.bytes(9)?;
+    let version = std::str::from_utf8(&header[5..])
+        .ok()
+        .and_then(|v| v.parse().ok());
+    if &header[..5] != b"REDIS" || !version.is_some_and(|v| VERSIONS.contains(&v)) {
+        return Err(Error::InvalidData);
+    }
+
+    let now = ctx.now();
+    let mut writer = BulkWriter::new(ctx, root);
+    let mut report = RdbReport::default();
+    let mut expires_ms = None;
+
+    loop {
+        match rdb.byte()? {
+            // End of file, followed by a checksum since version 5.
+            0xff => break,
+            // Select database.
+            0xfe => {
+                rdb.length()?;
+            }
+            0xfd => expires_ms = Some(u32::from_le_bytes(rdb.array()?) as u64 * 1000),
+            0xfc => expires_ms = Some(u64::from_le_bytes(rdb.array()?)),
+            // Resize database hints.
+            0xfb => {
+                rdb.length()?;
+                rdb.length()?;
+            }
+            // Auxiliary field.
+            0xfa => {
+                rdb.string()?;
+                rdb.string()?;
+            }
+            // LFU frequency.
+            0xf9 => {
+                rdb.byte()?;
+            }
+            // LRU idle time.
+            0xf8 => {
+                rdb.length()?;
+            }
+            // Module auxiliary data: module id, when opcode, when.
+            0xf7 => {
+                rdb.length()?;
+                rdb.length()?;
+                rdb.length()?;
+                rdb.skip_module_values()?;
+            }
+            // Function library in its pre-release format, which Redis itself
+            // refuses to load.
+            0xf6 => return Err(Error::InvalidData),
+            // Function library: its code.
+            0xf5 => {
+                rdb.string()?;
+            }
+            // Cluster slot info: slot, slot size, expires slot size.
+            0xf4 => {
+                rdb.length()?;
+                rdb.length()?;
+                rdb.length()?;
+            }
+            kind => {
+                let key = rdb.string()?;
+                let expires_ms = expires_ms.take();
+                if kind != TYPE_STRING {
+                    rdb.skip_value(kind)?;
+                    report.skipped += 1;
+                    continue;
+                }
+
+                let value = rdb.string()?;
+                let Ok(key) = String::from_utf8(key) else {
+                    report.skipped += 1;
+                    continue;
+                };
+                if key.len() > entry::MAX_KEY_LEN {
+                    report.skipped += 1;
+                    continue;
+                }
+
+                let expires_at = match expires_ms {
+                    Some(ms) => ms.div_ceil(1000).max(1),
+                    None => 0,
+                };
+                if ctx.is_expired(expires_at, now) {
+                    report.expired += 1;
+                    continue;
+                }
+
+                let encoded = ctx.encode(expires_at, &key, &value)?;
+                writer.push(ctx.hash(&key), Some(key), encoded)?;
+                report.imported += 1;
+            }
+        }
+    }
+
+    writer.finish()?;
+    Ok(report)
+}
+
+/// Decodes the payload of a Redis `DUMP` of a string key, as passed to
+/// `RESTORE`: the serialized value followed by its RDB version and checksum,
+/// which isn't verified. Fails with [`Error::InvalidData`] for other types.
+pub fn decode_dump(payload: &[u8]) -> Result<Vec<u8>, Error> {
+    if payload.len() < 11 {
+        return Err(Error::InvalidData);
+    }
+    let (value, footer) = payload.split_at(payload.len() - 10);
+    let version = u16::from_le_bytes([footer[0], footer[1]]) as u32;
+    if !VERSIONS.contains(&version) {
+        return Err(Error::InvalidData);
+    }
+
+    let mut rdb = Rdb::new(&value[1..]);
+    match value[0] {
+        TYPE_STRING => {
+            let string = rdb.string()?;
+            match rdb.reader.is_empty() {
+                true => Ok(string),
+                false => Err(Error::InvalidData),
+            }
+        }
+        _ => Err(Error::InvalidData),
+    }
+}
+
+struct Rdb<R> {
+    reader: R,
+}
+
+impl<R: Read> Rdb<R> {
+    fn new(reader: R) -> Self {
+        Self { reader }
+    }
+
+    fn byte(&mut self) -> Result<u8, Error> {
+        Ok(self.array::<1>()?[0])
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], Error> {
+        let mut buf = [0; N];
+        self.reader
+            .read_exact(&mut buf)
+            .map_err(|_| Error::InvalidData)?;
+        Ok(buf)
+    }
+
+    /// Reads `n` bytes without trusting `n` for the allocation, since it
+    /// comes from the file.
+    fn bytes(&mut self, n: u64) -> Result<Vec<u8>, Error> {
+        let mut buf = Vec::new();
+        (&mut self.reader).take(n).read_to_end(&mut buf)?;
+        match buf.len() as u64 == n {
+            true => Ok(buf),
+            false => Err(Error::InvalidData),
+        }
+    }
+
+    fn skip(&mut self, n: u64) -> Result<(), Error> {
+        let skipped = std::io::copy(&mut (&mut self.reader).take(n), &mut std::io::sink())?;
+        match skipped == n {
+            true => Ok(()),
+            false => Err(Error::InvalidData),
+        }
+    }
+
+    /// Reads a length, or the id of a special string encoding when the
+    /// second value is `true`.
+    fn length_or_encoding(&mut self) -> Result<(u64, bool), Error> {
+        let first = self.byte()?;
+        match first >> 6 {
+            0 => Ok(((first & 0x3f) as u64, false)),
+            1 => Ok(((((first & 0x3f) as u64) << 8) | self.byte()? as u64, false)),
+            3 => Ok(((first & 0x3f) as u64, true)),
+            _ => match first {
+                0x80 => Ok((u32::from_be_bytes(self.array()?) as u64, false)),
+                0x81 => Ok((u64::from_be_bytes(self.array()?), false)),
+                _ => Err(Error::InvalidData),
+            },
+        }
+    }
+
+    fn length(&mut self) -> Result<u64, Error> {
+        match self.length_or_encoding()? {
+            (len, false) => Ok(len),
+            (_, true) => Err(Error::InvalidData),
+        }
+    }
+
+    /// Reads a string, turning integer encoded ones back into decimal.
+    fn string(&mut self) -> Result<Vec<u8>, Error> {
+        match self.length_or_encoding()? {
+            (len, false) => self.bytes(len),
+            (0, true) => Ok((self.byte()? as i8).to_string().into_bytes()),
+            (1, true) => Ok(i16::from_le_bytes(self.array()?).to_string().into_bytes()),
+            (2, true) => Ok(i32::from_le_bytes(self.array()?).to_string().into_bytes()),
+            (3, true) => {
+                let compressed_len = self.length()?;
+                let len = self.length()?;
+                let compressed = self.bytes(compressed_len)?;
+                lzf_decompress(&compressed, len).ok_or(Error::InvalidData)
+            }
+            _ => Err(Error::InvalidData),
+        }
+    }
+
+    fn skip_strings(&mut self, n: u64) -> Result<(), Error> {
+        for _ in 0..n {
+            self.string()?;
+        }
+        Ok(())
+    }
+
+    /// Skips a value of a type other than string, following the layouts of
+    /// `rdb.c`.
+    fn skip_value(&mut self, kind: u8) -> Result<(), Error> {
+        match kind {
+            // Lists, sets and quicklists of strings.
+            1 | 2 | 14 => {
+                let n = self.length()?;
+                self.skip_strings(n)
+            }
+            // Sorted sets with scores as strings.
+            3 => {
+                for _ in 0..self.length()? {
+                    self.string()?;
+                    match self.byte()? {
+                        // NaN, +inf and -inf.
+                        253..=255 => {}
+                        len => self.skip(len as u64)?,
+                    }
+                }
+                Ok(())
+            }
+            // Hashes.
+            4 => {
+                let n = self.length()?;
+                self.skip_strings(n.saturating_mul(2))
+            }
+            // Sorted sets with binary scores.
+            5 => {
+                for _ in 0..self.length()? {
+                    self.string()?;
+                    self.skip(8)?;
+                }
+                Ok(())
+            }
+            // Version 2 modules: module id, then self-describing values.
+            7 => {
+                self.length()?;
+                self.skip_module_values()
+            }
+            // Encodings stored as a single blob: zipmaps, ziplists, intsets
+            // and listpacks.
+            9..=13 | 16 | 17 | 20 => self.string().map(drop),
+            // Streams.
+            15 | 19 | 21 => self.skip_stream(kind),
+            // Quicklists of listpacks: container kind, then the node.
+            18 => {
+                for _ in 0..self.length()? {
+                    self.length()?;
+                    self.string()?;
+                }
+                Ok(())
+            }
+            // Hashes with field expiration: the minimum expiration time, then
+            // either each field with its TTL or a listpack.
+            24 => {
+                self.skip(8)?;
+                for _ in 0..self.length()? {
+                    self.length()?;
+                    self.string()?;
+                    self.string()?;
+                }
+                Ok(())
+            }
+            25 => {
+                self.skip(8)?;
+                self.string().map(drop)
+            }
+            _ => Err(Error::InvalidData),
+        }
+    }
+
+    fn skip_stream(&mut self, kind: u8) -> Result<(), Error> {
+        // Listpacks, each keyed by its master id.
+        let n = self.length()?;
+        self.skip_strings(n.saturating_mul(2))?;
+
+        // Length and last id.
+        for _ in 0..3 {
+            self.length()?;
+        }
+        if kind >= 19 {
+            // First id, max deleted id and entries added.
+            for _ in 0..5 {
+                self.length()?;
+            }
+        }
+
+        for _ in 0..self.length()? {
+            // Consumer group name and last id.
+            self.string()?;
+            self.length()?;
+            self.length()?;
+            if kind >= 19 {
+                // Entries read.
+                self.length()?;
+            }
+
+            // Pending entries: id, delivery time and count.
+            for _ in 0..self.length()? {
+                self.skip(16 + 8)?;
+                self.length()?;
+            }
+
+            for _ in 0..self.length()? {
+                // Consumer name, seen time, active time since version 3.
+                self.string()?;
+                self.skip(if kind >= 21 { 16 } else { 8 })?;
+                let pending = self.length()?;
+                self.skip(pending.saturating_mul(16))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Skips values written by a module through the RDB API, each preceded by
+    /// its type, up to the end marker.
+    fn skip_module_values(&mut self) -> Result<(), Error> {
+        loop {
+            match self.length()? {
+                0 => return Ok(()),
+                // Signed and unsigned integers.
+                1 | 2 => {
+                    self.length()?;
+                }
+                3 => self.skip(4)?,
+                4 => self.skip(8)?,
+                5 => {
+                    self.string()?;
+                }
+                _ => return Err(Error::InvalidData),
+            }
+        }
+    }
+}
+
+/// Decompresses LZF data into exactly `len` bytes.
+fn lzf_decompress(input: &[u8], len: u64) -> Option<Vec<u8>> {
+    let mut out = Vec::new();
+    let mut i = 0;
+    while i < input.len() {
+        let ctrl = input[i] as usize;
+        i += 1;
+
+        if ctrl < 32 {
+            // Literal run.
+            let run = input.get(i..i + ctrl + 1)?;
+            out.extend_from_slice(run);
+            i += run.len();
+        } else {
+            // Back reference.
+            let mut run = ctrl >> 5;
+            if run == 7 {
+                run += *input.get(i)? as usize;
+                i += 1;
+            }
+            let back = ((ctrl & 0x1f) << 8) + *input.get(i)? as usize + 1;
+            i += 1;
+
+            let start = out.len().checked_sub(back)?;
+            for k in 0..run + 2 {
+                out.push(out[start + k]);
+            }
+        }
+
+        if out.len() as u64 > len {
+            return None;
+        }
+    }
+    (out.len() as u64 == len).then_some(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{path::PathBuf, time::Duration};
+
+    use super::*;
+    use crate::keeper::Keeper;
+
+    struct Store {
+        keeper: Option<Keeper>,
+        path: PathBuf,
+    }
+
+    impl Store {
+        fn new(name: &str) -> Self {
+            let path =
+                std::env::temp_dir().join(format!("keeper-rdb-{name}-{}", std::process::id()));
+            let _ = std::fs::remove_dir_all(&path);
+            Self {
+                keeper: Some(Keeper::new(path.clone()).unwrap()),
+                path,
+            }
+        }
+
+        fn keeper(&self) -> &Keeper {
+            self.keeper.as_ref().unwrap()
+        }
+
+        fn get(&self, key: &str) -> Result<Vec<u8>, Error> {
+            let (tx, rx) = std::sync::mpsc::sync_channel(1);
+            self.keeper().dispatch_get(key, move |res| {
+                let _ = tx.send(res);
+            });
+            rx.recv_timeout(Duration::from_secs(10)).unwrap()
+        }
+    }
+
+    impl Drop for Store {
+        fn drop(&mut self) {
+            drop(self.keeper.take());
+            let _ = std::fs::remove_dir_all(&self.path);
+        }
+    }
+
+    fn string(s: &[u8]) -> Vec<u8> {
+        let mut out = match s.len() {
+            len @ ..64 => vec![len as u8],
+            len => vec![0x40 | (len >> 8) as u8, len as u8],
+        };
+        out.extend_from_slice(s);
+        out
+    }
+
+    /// A version 12 snapshot with `opcodes` in database 0, before the string
+    /// key `k` holding `v`.
+    fn snapshot(opcodes: &[u8]) -> Vec<u8> {
+        let mut rdb = b"REDIS0012".to_vec();
+        rdb.extend_from_slice(&[0xfe, 0x00]);
+        rdb.extend_from_slice(opcodes);
+        rdb.push(TYPE_STRING);
+        rdb.extend(string(b"k"));
+        rdb.extend(string(b"v"));
+        rdb.push(0xff);
+        rdb.extend_from_slice(&[0; 8]);
+        rdb
+    }
+
+    fn import_with(name: &str, opcodes: &[u8]) -> Result<RdbReport, Error> {
+        let store = Store::new(name);
+        let report = store.keeper().import_rdb(&snapshot(opcodes)[..])?;
+        if report.imported == 1 {
+            assert_eq!(store.get("k").unwrap(), b"v");
+        }
+        Ok(report)
+    }
+
+    fn imported(report: Result<RdbReport, Error>) -> u64 {
+        report.unwrap().imported
+    }
+
+    #[test]
+    fn slot_info() {
+        assert_eq!(
+            imported(import_with("slot-info", &[0xf4, 0x01, 0x02, 0x03])),
+            1
+        );
+    }
+
+    #[test]
+    fn function2() {
+        let mut opcodes = vec![0xf5];
+        opcodes.extend(string(
+            b"#!lua name=lib\nredis.register_function('f', function() return 1 end)",
+        ));
+        assert_eq!(imported(import_with("function2", &opcodes)), 1);
+    }
+
+    #[test]
+    fn function_pre_ga() {
+        let mut opcodes = vec![0xf6];
+        opcodes.extend(string(b"lib"));
+        assert!(matches!(
+            import_with("function-pre-ga", &opcodes),
+            Err(Error::InvalidData)
+        ));
+    }
+
+    #[test]
+    fn module_aux() {
+        let mut opcodes = vec![0xf7, 0x81];
+        opcodes.extend_from_slice(&0x1234_5678_9abc_def0u64.to_be_bytes());
+        // When opcode and when.
+        opcodes.extend_from_slice(&[0x02, 0x02]);
+        // An unsigned integer, a string, then the end marker.
+        opcodes.extend_from_slice(&[0x02, 0x05, 0x05]);
+        opcodes.extend(string(b"abc"));
+        opcodes.push(0x00);
+        assert_eq!(imported(import_with("module-aux", &opcodes)), 1);
+    }
+
+    #[test]
+    fn idle() {
+        assert_eq!(imported(import_with("idle", &[0xf8, 0x40, 0xff])), 1);
+    }
+
+    #[test]
+    fn freq() {
+        // A length-like byte here would swallow the key type.
+        assert_eq!(imported(import_with("freq", &[0xf9, 0xc0])), 1);
+    }
+
+    #[test]
+    fn aux() {
+        let mut opcodes = vec![0xfa];
+        opcodes.extend(string(b"redis-ver"));
+        opcodes.extend(string(b"7.4.0"));
+        assert_eq!(imported(import_with("aux", &opcodes)), 1);
+    }
+
+    #[test]
+    fn resize_db() {
+        assert_eq!(imported(import_with("resize-db", &[0xfb, 0x01, 0x00])), 1);
+    }
+
+    #[test]
+    fn expire_time_ms() {
+        let mut opcodes = vec![0xfc];
+        opcodes.extend_from_slice(&u64::MAX.to_le_bytes());
+        assert_eq!(imported(import_with("expire-ms", &opcodes)), 1);
+
+        let mut opcodes = vec![0xfc];
+        opcodes.extend_from_slice(&1000u64.to_le_bytes());
+        let report = import_with("expired-ms", &opcodes).unwrap();
+        assert_eq!((report.imported, report.expired), (0, 1));
+    }
+
+    #[test]
+    fn expire_time() {
+        let mut opcodes = vec![0xfd];
+        opcodes.extend_from_slice(&u32::MAX.to_le_bytes());
+        assert_eq!(imported(import_with("expire", &opcodes)), 1);
+
+        let mut opcodes = vec![0xfd];
+        opcodes.extend_from_slice(&1u32.to_le_bytes());
+        let report = import_with("expired", &opcodes).unwrap();
+        assert_eq!((report.imported, report.expired), (0, 1));
+    }
+
+    #[test]
+    fn select_db() {
+        assert_eq!(imported(import_with("select-db", &[0xfe, 0x03])), 1);
+    }
+
+    #[test]
+    fn lzf_vectors() {
+        // A literal run, then a back reference with an extended length that
+        // overlaps what it copies.
+        let abc = [0x02, b'a', b'b', b'c', 0xe0, 0x0c, 0x02];
+        assert_eq!(lzf_decompress(&abc, 24).unwrap(), b"abc".repeat(8));
+        // A short back reference between two literal runs.
+        let xyz = [0x02, b'x', b'y', b'z', 0x20, 0x02, 0x00, b'Q'];
+        assert_eq!(lzf_decompress(&xyz, 7).unwrap(), b"xyzxyzQ");
+        // Back references reaching further than the first byte.
+        let far = [0x00, b'a', 0x3f, 0xff];
+        assert_eq!(lzf_decompress(&far[..2], 1).unwrap(), b"a");
+        assert!(lzf_decompress(&far, 4).is_none());
+    }
+
+    #[test]
+    fn lzf_rejects_bad_lengths() {
+        let abc = [0x02, b'a', b'b', b'c', 0xe0, 0x0c, 0x02];
+        assert!(lzf_decompress(&abc, 23).is_none());
+        assert!(lzf_decompress(&abc, 25).is_none());
+        // Truncated literal run and back reference.
+        assert!(lzf_decompress(&abc[..3], 3).is_none());
+        assert!(lzf_decompress(&abc[..6], 24).is_none());
+    }
+
+    fn aux_field(rdb: &mut Vec<u8>, name: &str, value: &[u8]) {
+        rdb.push(0xfa);
+        rdb.extend(string(name.as_bytes()));
+        rdb.extend_from_slice(value);
+    }
+
+    fn key(rdb: &mut Vec<u8>, kind: u8, name: &str) {
+        rdb.push(kind);
+        rdb.extend(string(name.as_bytes()));
+    }
+
+    /// A snapshot laid out as Redis 7.2 saves one: the auxiliary fields it
+    /// writes, with integers in their encoded forms, resize hints, strings
+    /// stored as integers and compressed with LZF, other types to skip,
+    /// TTLs, and a second database.
+    fn redis_snapshot() -> Vec<u8> {
+        let mut rdb = b"REDIS0011".to_vec();
+        aux_field(&mut rdb, "redis-ver", &string(b"7.2.4"));
+        aux_field(&mut rdb, "redis-bits", &[0xc0, 0x40]);
+        let mut ctime = vec![0xc2];
+        ctime.extend_from_slice(&1_700_000_000u32.to_le_bytes());
+        aux_field(&mut rdb, "ctime", &ctime);
+        let mut used_mem = vec![0xc2];
+        used_mem.extend_from_slice(&1_048_576u32.to_le_bytes());
+        aux_field(&mut rdb, "used-mem", &used_mem);
+        aux_field(&mut rdb, "aof-base", &[0xc0, 0x00]);
+
+        rdb.extend_from_slice(&[0xfe, 0x00, 0xfb, 0x07, 0x02]);
+
+        rdb.push(0xfc);
+        rdb.extend_from_slice(&4_102_444_800_000u64.to_le_bytes());
+        key(&mut rdb, TYPE_STRING, "session");
+        rdb.extend(string(b"token-1"));
+
+        key(&mut rdb, TYPE_STRING, "counter");
+        rdb.extend_from_slice(&[0xc1, 0x39, 0x30]);
+        key(&mut rdb, TYPE_STRING, "negative");
+        rdb.extend_from_slice(&[0xc0, 0xff]);
+        key(&mut rdb, TYPE_STRING, "large");
+        rdb.push(0xc2);
+        rdb.extend_from_slice(&(-2_000_000_000i32).to_le_bytes());
+
+        key(&mut rdb, TYPE_STRING, "compressed");
+        rdb.extend_from_slice(&[0xc3, 0x07, 0x18, 0x02, b'a', b'b', b'c', 0xe0, 0x0c, 0x02]);
+
+        // A quicklist of one packed listpack node holding "a" and "b".
+        key(&mut rdb, 18, "list");
+        rdb.extend_from_slice(&[0x01, 0x02]);
+        rdb.extend(string(&[
+            13, 0, 0, 0, 2, 0, 0x81, b'a', 2, 0x81, b'b', 2, 0xff,
+        ]));
+
+        key(&mut rdb, 2, "set");
+        rdb.push(0x02);
+        rdb.extend(string(b"m1"));
+        rdb.extend(string(b"m2"));
+
+        rdb.push(0xfc);
+        rdb.extend_from_slice(&1_000u64.to_le_bytes());
+        key(&mut rdb, TYPE_STRING, "old");
+        rdb.extend(string(b"gone"));
+
+        rdb.extend_from_slice(&[0xfe, 0x01, 0xfb, 0x01, 0x00]);
+        key(&mut rdb, TYPE_STRING, "other-db");
+        rdb.extend(string(b"value"));
+
+        // The checksum isn't verified.
+        rdb.push(0xff);
+        rdb.extend_from_slice(&[0; 8]);
+        rdb
+    }
+
+    #[test]
+    fn redis_layout() {
+        let store = Store::new("redis-layout");
+        let report = store.keeper().import_rdb(&redis_snapshot()[..]).unwrap();
+        assert_eq!(
+            report,
+            RdbReport {
+                imported: 6,
+                expired: 1,
+                skipped: 2,
+            }
+        );
+
+        for (key, value) in [
+            ("session", b"token-1".to_vec()),
+            ("counter", b"12345".to_vec()),
+            ("negative", b"-1".to_vec()),
+            ("large", b"-2000000000".to_vec()),
+            ("compressed", b"abc".repeat(8)),
+            ("other-db", b"value".to_vec()),
+        ] {
+            assert_eq!(store.get(key).unwrap(), value, "{key}");
+        }
+        for key in ["list", "set", "old"] {
+            assert!(store.get(key).is_err(), "{key}");
+        }
+    }
+
+    #[test]
+    fn truncated_snapshot() {
+        let rdb = redis_snapshot();
+        let store = Store::new("truncated");
+        for len in [5, 9, 40, rdb.len() - 9] {
+            assert!(
+                matches!(
+                    store.keeper().import_rdb(&rdb[..len]),
+                    Err(Error::InvalidData)
+                ),
+                "{len}"
+            );
+        }
+    }
+
+    #[test]
+    fn dump_payload() {
+        // `DUMP` of a key holding "hello": the value, RDB version 11, then
+        // the checksum.
+        let mut payload = vec![TYPE_STRING];
+        payload.extend(string(b"hello"));
+        payload.extend_from_slice(&11u16.to_le_bytes());
+        payload.extend_from_slice(&[0; 8]);
+        assert_eq!(decode_dump(&payload).unwrap(), b"hello");
+
+        payload[0] = 2;
+        assert!(matches!(decode_dump(&payload), Err(Error::InvalidData)));
+    }
+}
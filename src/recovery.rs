@@ -0,0 +1,99 @@
+use std::{
+    path::Path,
+    time::{Duration, Instant},
+};
+
+use crate::{compact, context::Context, entry, tenant, utils};
+
+/// What the recovery pass run by
+/// [`crate::keeper::KeeperBuilder::with_recovery_on_open`] cleaned up.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct RecoveryReport {
+    /// Temporary files left by writes the last run didn't finish.
+    pub removed_temp: u64,
+    /// Entries too short to hold a header.
+    pub removed_truncated: u64,
+    /// Folder repacks interrupted by the last run, moved back; see
+    /// [`crate::compact`].
+    pub restored_folders: u64,
+    pub errors: u64,
+    /// Whether every folder was scanned within the time budget.
+    pub complete: bool,
+    pub duration: Duration,
+}
+
+/// Removes the garbage a crash leaves in the store at `root`, tenants
+/// included, before the first request can trip over it. Runs on the calling
+/// thread before any worker starts, so every temporary file is a leftover.
+/// Stops once `budget` is spent, leaving the other folders to the janitor.
+pub fn recover(ctx: &Context, root: &Path, budget: Duration) -> RecoveryReport {
+    let start = Instant::now();
+    let mut report = RecoveryReport {
+        complete: true,
+        ..Default::default()
+    };
+
+    'roots: for root in tenant::roots(root) {
+        let Ok(folders) = std::fs::read_dir(&root) else {
+            continue;
+        };
+
+        for folder in folders.flatten() {
+            if start.elapsed() >= budget {
+                report.complete = false;
+                break 'roots;
+            }
+
+            let name = folder.file_name();
+            let name = name.to_string_lossy();
+            if let Some((_, folder)) = compact::leftover(&root, &name) {
+                match compact::restore(ctx, &folder) {
+                    Ok(()) => report.restored_folders += 1,
+                    Err(e) => {
+                        report.errors += 1;
+                        ctx.hooks.report(&e.into());
+                    }
+                }
+                scan(ctx, &folder, &mut report);
+            } else if name.len() == 3 && u16::from_str_radix(&name, 16).is_ok() {
+                scan(ctx, &folder.path(), &mut report);
+            }
+        }
+    }
+
+    report.duration = start.elapsed();
+    report
+}
+
+fn scan(ctx: &Context, folder: &Path, report: &mut RecoveryReport) {
+    let Ok(files) = std::fs::read_dir(folder) else {
+        return;
+    };
+
+    for file in files.flatten() {
+        let name = file.file_name();
+        let name = name.to_string_lossy();
+        let Ok(metadata) = file.metadata() else {
+            continue;
+        };
+        if !metadata.is_file() {
+            continue;
+        }
+
+        let counter = if name.ends_with(".tmp") {
+            &mut report.removed_temp
+        } else if name.len() == 29 && metadata.len() < entry::PREFIX_LEN as u64 {
+            &mut report.removed_truncated
+        } else {
+            continue;
+        };
+
+        match utils::retry_io(|| std::fs::remove_file(file.path())) {
+            Ok(()) => *counter += 1,
+            Err(e) => {
+                report.errors += 1;
+                ctx.hooks.report(&e.into());
+            }
+        }
+    }
+}
@@ -0,0 +1,58 @@
+use std::{collections::HashMap, path::Path};
+
+use crate::{
+    context::Context,
+    entries,
+    error::Error,
+    manifest::FANOUT,
+    namespace::{self, Usage},
+    tenant,
+};
+
+/// What [`rebuild`] found.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct IndexReport {
+    /// Entries walked, tenants included.
+    pub entries: u64,
+    /// Entries counted in the usage of a namespace.
+    pub namespaced: u64,
+    /// Entries written with a format that doesn't record keys, whose
+    /// namespace can't be told.
+    pub unknown_keys: u64,
+}
+
+/// Recounts the byte and entry usage of every namespace from the keys
+/// embedded in the entries of the store at `root`, tenants included, and
+/// saves it to the manifest. For usage that drifted, or quotas set on a store
+/// that already held entries.
+///
+/// Every shard lock is held while the entries are walked, so writes wait for
+/// the whole pass, as with [`crate::store::clear`].
+pub fn rebuild(ctx: &Context, root: &Path) -> Result<IndexReport, Error> {
+    let _permit = ctx.io_permit();
+    let _locks = ctx.shards.write_all();
+
+    let mut report = IndexReport::default();
+    let mut usage: HashMap<String, Usage> = HashMap::new();
+    for root in tenant::roots(root) {
+        for folder in 0..FANOUT as u16 {
+            for (_, info) in entries::read_folder(ctx, &root, folder) {
+                report.entries += 1;
+                let Some(key) = info.key else {
+                    report.unknown_keys += 1;
+                    continue;
+                };
+                if let Some(ns) = namespace::namespace_of(&key) {
+                    let counted = usage.entry(ns.into()).or_default();
+                    counted.bytes += info.size;
+                    counted.entries += 1;
+                    report.namespaced += 1;
+                }
+            }
+        }
+    }
+
+    ctx.namespaces.rebuild(usage);
+    ctx.persist(root)?;
+    Ok(report)
+}
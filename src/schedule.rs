@@ -0,0 +1,87 @@
+use std::{str::FromStr, time::Duration};
+
+use crate::error::Error;
+
+const DAY: u64 = 24 * 60 * 60;
+
+/// When the janitor sweeps the store.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Schedule {
+    Interval(Duration),
+    /// Sweeps at fixed times of day, given as seconds after midnight UTC
+    /// shifted by `utc_offset` seconds.
+    Daily {
+        times: Vec<u32>,
+        utc_offset: i32,
+    },
+}
+
+impl Schedule {
+    /// Daily sweeps at `hour:minute` UTC.
+    pub fn daily_at(hour: u8, minute: u8) -> Self {
+        Self::Daily {
+            times: vec![u32::from(hour % 24) * 3600 + u32::from(minute % 60) * 60],
+            utc_offset: 0,
+        }
+    }
+
+    /// Interprets the times of a daily schedule in a zone `offset` seconds
+    /// ahead of UTC.
+    pub fn with_utc_offset(self, offset: i32) -> Self {
+        match self {
+            Self::Daily { times, .. } => Self::Daily {
+                times,
+                utc_offset: offset,
+            },
+            interval => interval,
+        }
+    }
+
+    /// Time to wait from `now` (seconds since the Unix epoch) until the next
+    /// sweep.
+    pub fn next_delay(&self, now: u64) -> Duration {
+        match self {
+            Self::Interval(interval) => *interval,
+            Self::Daily { times, utc_offset } => {
+                let local = now.saturating_add_signed(i64::from(*utc_offset)) % DAY;
+                times
+                    .iter()
+                    .map(|&at| (u64::from(at) + DAY - local - 1) % DAY + 1)
+                    .min()
+                    .map(Duration::from_secs)
+                    .unwrap_or(Duration::from_secs(DAY))
+            }
+        }
+    }
+}
+
+impl Default for Schedule {
+    fn default() -> Self {
+        Self::Interval(Duration::from_mins(60))
+    }
+}
+
+/// Parses a comma separated list of `HH:MM` times, e.g. `"03:00,15:30"`.
+impl FromStr for Schedule {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || Error::InvalidSchedule(s.into());
+
+        let mut times = Vec::new();
+        for time in s.split(',').map(str::trim) {
+            let (hour, minute) = time.split_once(':').ok_or_else(invalid)?;
+            let hour: u32 = hour.parse().map_err(|_| invalid())?;
+            let minute: u32 = minute.parse().map_err(|_| invalid())?;
+            if hour > 23 || minute > 59 {
+                return Err(invalid());
+            }
+            times.push(hour * 3600 + minute * 60);
+        }
+
+        Ok(Self::Daily {
+            times,
+            utc_offset: 0,
+        })
+    }
+}
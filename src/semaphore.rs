@@ -0,0 +1,85 @@
+use std::{
+    cell::Cell,
+    sync::{Condvar, Mutex},
+};
+
+thread_local! {
+    static HELD: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Counting semaphore bounding how many threads do disk I/O at once. A thread
+/// that already holds a permit gets the next ones for free, so nested store
+/// operations can't deadlock.
+#[derive(Debug)]
+pub struct Semaphore {
+    available: Mutex<usize>,
+    released: Condvar,
+}
+
+#[derive(Debug)]
+pub struct Permit<'a> {
+    semaphore: Option<&'a Semaphore>,
+}
+
+impl Semaphore {
+    pub fn new(permits: usize) -> Self {
+        Self {
+            available: Mutex::new(permits.max(1)),
+            released: Condvar::new(),
+        }
+    }
+
+    pub fn acquire(&self) -> Permit<'_> {
+        if HELD.get() {
+            return Permit { semaphore: None };
+        }
+
+        let mut available = self.available.lock().unwrap_or_else(|e| e.into_inner());
+        while *available == 0 {
+            available = self
+                .released
+                .wait(available)
+                .unwrap_or_else(|e| e.into_inner());
+        }
+        *available -= 1;
+        HELD.set(true);
+
+        Permit {
+            semaphore: Some(self),
+        }
+    }
+
+    /// Like [`Semaphore::acquire`], returning `None` instead of waiting.
+    pub fn try_acquire(&self) -> Option<Permit<'_>> {
+        if HELD.get() {
+            return Some(Permit { semaphore: None });
+        }
+
+        let mut available = self.available.lock().unwrap_or_else(|e| e.into_inner());
+        if *available == 0 {
+            return None;
+        }
+        *available -= 1;
+        HELD.set(true);
+
+        Some(Permit {
+            semaphore: Some(self),
+        })
+    }
+}
+
+impl Drop for Permit<'_> {
+    fn drop(&mut self) {
+        let Some(semaphore) = self.semaphore else {
+            return;
+        };
+
+        HELD.set(false);
+        let mut available = semaphore
+            .available
+            .lock()
+            .unwrap_or_else(|e| e.into_inner());
+        *available += 1;
+        semaphore.released.notify_one();
+    }
+}
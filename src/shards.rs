@@ -1,32 +1,137 @@
-use std::sync::{Arc, RwLock, RwLockReadGuard, RwLockWriteGuard, TryLockError};
+use std::{
+    ops::RangeInclusive,
+    sync::{Arc, RwLock, RwLockReadGuard, RwLockWriteGuard, TryLockError},
+};
 
+/// Default lock count, one per shard folder.
+pub const DEFAULT_SHARDS: usize = 4096;
+
+/// The locks guarding the entries. An entry is identified by its shard, the
+/// first 16 bits of its hash (see [`crate::utils::parse_hash`]), and the lock
+/// count decides how many shards share a lock: with the default of 4096 every
+/// folder has its own lock, with more each folder is split between several.
+/// Operations on a whole folder take every lock covering it, in order.
 #[derive(Debug, Clone)]
-pub struct Shards(Arc<[RwLock<()>; 4096]>);
+pub struct Shards {
+    locks: Arc<[RwLock<()>]>,
+    /// log2 of the lock count.
+    bits: u32,
+}
+
+impl Default for Shards {
+    fn default() -> Self {
+        Self::new(DEFAULT_SHARDS)
+    }
+}
 
 impl Shards {
-    pub fn new() -> Self {
-        Self(Arc::new(std::array::from_fn(|_| RwLock::new(()))))
+    /// `count` is rounded up to a power of two, between 1 and 65536.
+    pub fn new(count: usize) -> Self {
+        let count = count.clamp(1, 1 << 16).next_power_of_two();
+        Self {
+            locks: (0..count).map(|_| RwLock::new(())).collect(),
+            bits: count.trailing_zeros(),
+        }
+    }
+
+    pub fn count(&self) -> usize {
+        self.locks.len()
+    }
+
+    fn index(&self, shard: u16) -> usize {
+        (shard as usize) >> (16 - self.bits)
+    }
+
+    /// The locks covering the folder with id `folder`, the first 12 bits of
+    /// the hashes it holds.
+    fn folder(&self, folder: u16) -> RangeInclusive<usize> {
+        self.index(folder << 4)..=self.index((folder << 4) | 0xf)
+    }
+
+    pub fn read(&self, shard: u16) -> RwLockReadGuard<'_, ()> {
+        self.locks[self.index(shard)]
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
     }
 
-    pub fn read(&self, id: u16) -> RwLockReadGuard<'_, ()> {
-        self.0[id as usize].read().expect("lock poisoned")
+    pub fn write(&self, shard: u16) -> RwLockWriteGuard<'_, ()> {
+        self.locks[self.index(shard)]
+            .write()
+            .unwrap_or_else(|e| e.into_inner())
     }
 
-    pub fn write(&self, id: u16) -> RwLockWriteGuard<'_, ()> {
-        self.0[id as usize].write().expect("lock poisoned")
+    /// Non-blocking variants, which only fail when the lock is held. A
+    /// poisoned lock is recovered like in [`Shards::read`].
+    pub fn read_now(&self, shard: u16) -> Option<RwLockReadGuard<'_, ()>> {
+        match self.try_read(shard) {
+            Ok(guard) => Some(guard),
+            Err(TryLockError::Poisoned(e)) => Some(e.into_inner()),
+            Err(TryLockError::WouldBlock) => None,
+        }
+    }
+
+    pub fn write_now(&self, shard: u16) -> Option<RwLockWriteGuard<'_, ()>> {
+        match self.try_write(shard) {
+            Ok(guard) => Some(guard),
+            Err(TryLockError::Poisoned(e)) => Some(e.into_inner()),
+            Err(TryLockError::WouldBlock) => None,
+        }
     }
 
     pub fn try_read(
         &self,
-        id: u16,
+        shard: u16,
     ) -> Result<RwLockReadGuard<'_, ()>, TryLockError<RwLockReadGuard<'_, ()>>> {
-        self.0[id as usize].try_read()
+        self.locks[self.index(shard)].try_read()
     }
 
     pub fn try_write(
         &self,
-        id: u16,
+        shard: u16,
     ) -> Result<RwLockWriteGuard<'_, ()>, TryLockError<RwLockWriteGuard<'_, ()>>> {
-        self.0[id as usize].try_write()
+        self.locks[self.index(shard)].try_write()
+    }
+
+    /// Write locks two shards without deadlocking against another pair,
+    /// taking their shared lock once when they have one.
+    pub fn write_pair(
+        &self,
+        a: u16,
+        b: u16,
+    ) -> (RwLockWriteGuard<'_, ()>, Option<RwLockWriteGuard<'_, ()>>) {
+        let (a, b) = (self.index(a), self.index(b));
+        let (a, b) = (a.min(b), a.max(b));
+        let lock = |i: usize| self.locks[i].write().unwrap_or_else(|e| e.into_inner());
+        (lock(a), (a != b).then(|| lock(b)))
+    }
+
+    pub fn read_folder(&self, folder: u16) -> Vec<RwLockReadGuard<'_, ()>> {
+        self.folder(folder)
+            .map(|i| self.locks[i].read().unwrap_or_else(|e| e.into_inner()))
+            .collect()
+    }
+
+    pub fn write_folder(&self, folder: u16) -> Vec<RwLockWriteGuard<'_, ()>> {
+        self.folder(folder)
+            .map(|i| self.locks[i].write().unwrap_or_else(|e| e.into_inner()))
+            .collect()
+    }
+
+    /// Like [`Shards::write_folder`], giving up if any of the locks is held.
+    pub fn try_write_folder(&self, folder: u16) -> Option<Vec<RwLockWriteGuard<'_, ()>>> {
+        self.folder(folder)
+            .map(|i| match self.locks[i].try_write() {
+                Ok(guard) => Some(guard),
+                Err(TryLockError::Poisoned(e)) => Some(e.into_inner()),
+                Err(TryLockError::WouldBlock) => None,
+            })
+            .collect()
+    }
+
+    pub fn write_all(&self) -> Vec<RwLockWriteGuard<'_, ()>> {
+        self.locks
+            .iter()
+            .map(|lock| lock.write().unwrap_or_else(|e| e.into_inner()))
+            .collect()
     }
 }
@@ -0,0 +1,233 @@
+use std::{
+    fmt,
+    io::{ErrorKind, Write},
+    path::PathBuf,
+    str::FromStr,
+    sync::{
+        Arc,
+        atomic::{AtomicU64, Ordering},
+    },
+};
+
+use crate::{
+    backup::{self, Backup},
+    context::Context,
+    entries::{Entries, EntryInfo},
+    error::Error,
+    mounts,
+    utils::{self, parse_hash},
+};
+
+/// Distinguishes the marker files of snapshots taken concurrently.
+static MARKER_ID: AtomicU64 = AtomicU64::new(0);
+
+/// A read-only view of the store as it was when [`crate::keeper::Keeper::snapshot`]
+/// was called, for backups and analytics passes that must not mix old and new
+/// values while writes continue.
+///
+/// Entries aren't copied: the snapshot pins the store's generation and the
+/// file system time it was taken at, and only serves entries last modified
+/// before it. Entries written or replaced since read as missing, and so do
+/// entries removed since, so a snapshot never returns a value newer than
+/// itself. Expiration is judged at the time of the snapshot. With access
+/// tracking, entries read since the snapshot count as modified.
+#[derive(Debug, Clone)]
+pub struct Snapshot {
+    ctx: Arc<Context>,
+    root: Arc<PathBuf>,
+    generation: u64,
+    /// File system time of the snapshot, in nanoseconds since the epoch.
+    pinned: u64,
+    /// Store clock at the time of the snapshot, in seconds.
+    now: u64,
+}
+
+/// Identifies a [`Snapshot`] across processes, so a later backup can hold
+/// only the entries that changed since. Written as `<generation>.<nanos>`,
+/// the store's generation and the file system time of the snapshot.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SnapshotId {
+    pub(crate) generation: u64,
+    pub(crate) pinned: u64,
+}
+
+impl fmt::Display for SnapshotId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}", self.generation, self.pinned)
+    }
+}
+
+impl FromStr for SnapshotId {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Error> {
+        let (generation, pinned) = s.split_once('.').ok_or(Error::InvalidData)?;
+        Ok(Self {
+            generation: generation.parse().map_err(|_| Error::InvalidData)?,
+            pinned: pinned.parse().map_err(|_| Error::InvalidData)?,
+        })
+    }
+}
+
+impl Snapshot {
+    /// Pins the current state of the store. Like a lazy clear, the cut-off is
+    /// pinned with marker files on every path of the store, see
+    /// [`mounts::pin`], so every later write is newer.
+    pub(crate) fn new(ctx: Arc<Context>, root: Arc<PathBuf>) -> Result<Self, Error> {
+        let generation = ctx.generation.load(Ordering::Acquire);
+        let now = ctx.now();
+
+        let id = MARKER_ID.fetch_add(1, Ordering::Relaxed);
+        let marker = format!("SNAPSHOT.{}.{id}", std::process::id());
+        let pinned = mounts::pin(&ctx.mounts, &marker, &ctx.permissions)?;
+
+        Ok(Self {
+            ctx,
+            root,
+            generation,
+            pinned,
+            now,
+        })
+    }
+
+    /// When the snapshot was taken, in seconds since the Unix epoch of the
+    /// store's clock.
+    pub fn taken_at(&self) -> u64 {
+        self.now
+    }
+
+    pub fn id(&self) -> SnapshotId {
+        SnapshotId {
+            generation: self.generation,
+            pinned: self.pinned,
+        }
+    }
+
+    /// Writes the entries of the snapshot to `writer` in the backup format
+    /// of [`crate::backup`]; with `since`, only those modified after that
+    /// earlier snapshot. Entries modified after this snapshot are written as
+    /// they are when read rather than left out, so the backup may be newer
+    /// than its snapshot but never misses an entry. Fails with
+    /// [`Error::Cleared`] if the store was cleared since `since`, as a full
+    /// backup is needed then.
+    pub fn backup(&self, writer: impl Write, since: Option<SnapshotId>) -> Result<Backup, Error> {
+        backup::write(self, since, writer)
+    }
+
+    /// Whether the store wasn't cleared since the snapshot was taken. Reads
+    /// fail with [`Error::Cleared`] otherwise.
+    pub fn is_valid(&self) -> bool {
+        self.ctx.generation.load(Ordering::Acquire) == self.generation
+    }
+
+    /// Reads the value of `key` as of the snapshot, on the calling thread.
+    /// Fails with [`Error::NotFound`] if it didn't exist then, expired by
+    /// then, or changed since.
+    pub fn get(&self, key: &str) -> Result<Vec<u8>, Error> {
+        if !self.is_valid() {
+            return Err(Error::Cleared);
+        }
+
+        let h = self.ctx.hash(key);
+        let (p_folder, filename, shard_id) = parse_hash(&h);
+        let file_path = self.root.join(p_folder).join(filename);
+
+        let value = {
+            let _permit = self.ctx.io_permit();
+            let _lock = self.ctx.shards.read(shard_id);
+            let file = match self.ctx.open_file(&file_path) {
+                Ok(file) => file,
+                Err(e) if e.kind() == ErrorKind::NotFound => return Err(Error::NotFound),
+                Err(e) => return Err(e.into()),
+            };
+            let metadata = file.metadata()?;
+            if !self.includes(&metadata) {
+                return Err(Error::NotFound);
+            }
+
+            let buffer = self.ctx.read_opened(&file_path, &file)?;
+            let (header, payload) = self.ctx.decode(&buffer, key)?;
+            if self.ctx.is_expired(header.expires_at, self.now) {
+                return Err(Error::NotFound);
+            }
+            payload.into_owned()
+        };
+
+        match self.is_valid() {
+            true => Ok(value),
+            false => Err(Error::Cleared),
+        }
+    }
+
+    /// Reads the payload of the entry listed as `hash` by [`Self::changes`],
+    /// as it is now.
+    pub(crate) fn read_changed(
+        &self,
+        hash: &str,
+        info: &EntryInfo,
+    ) -> Result<Option<Vec<u8>>, Error> {
+        let (p_folder, filename, shard_id) = parse_hash(hash.as_bytes());
+        let file_path = self.root.join(p_folder).join(filename);
+
+        let _permit = self.ctx.io_permit();
+        let _lock = self.ctx.shards.read(shard_id);
+        let file = match self.ctx.open_file(&file_path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        if self.ctx.is_cleared(&file.metadata()?) {
+            return Ok(None);
+        }
+
+        let buffer = self.ctx.read_opened(&file_path, &file)?;
+        match self
+            .ctx
+            .decode(&buffer, info.key.as_deref().unwrap_or_default())
+        {
+            Ok((_, payload)) => Ok(Some(payload.into_owned())),
+            Err(_) => Ok(None),
+        }
+    }
+
+    /// Lists the entries as of the snapshot, shard by shard, like
+    /// [`crate::keeper::Keeper::entries`]. Entries that changed since are
+    /// left out, and nothing is listed once the store was cleared.
+    pub fn entries(&self) -> impl Iterator<Item = (String, EntryInfo)> + use<> {
+        let snapshot = self.clone();
+        Entries::new(self.ctx.clone(), self.root.clone()).filter(move |(_, info)| {
+            snapshot.is_valid()
+                && info
+                    .modified
+                    .is_some_and(|modified| utils::nanos(modified) <= snapshot.pinned)
+                && !snapshot.ctx.is_expired(info.expires_at, snapshot.now)
+        })
+    }
+
+    /// Lists the entries modified after `since`, in nanoseconds, or every
+    /// entry without it, including the ones modified after the snapshot.
+    /// Expiration is still judged at the time of the snapshot.
+    pub(crate) fn changes(
+        &self,
+        since: Option<u64>,
+    ) -> impl Iterator<Item = (String, EntryInfo)> + use<> {
+        let snapshot = self.clone();
+        Entries::new(self.ctx.clone(), self.root.clone()).filter(move |(_, info)| {
+            snapshot.is_valid()
+                && since.is_none_or(|since| {
+                    info.modified
+                        .is_some_and(|modified| utils::nanos(modified) > since)
+                })
+                && !snapshot.ctx.is_expired(info.expires_at, snapshot.now)
+        })
+    }
+
+    /// Whether the entry with `metadata` was there when the snapshot was taken
+    /// and wasn't modified since.
+    fn includes(&self, metadata: &std::fs::Metadata) -> bool {
+        !self.ctx.is_cleared(metadata)
+            && metadata
+                .modified()
+                .is_ok_and(|modified| utils::nanos(modified) <= self.pinned)
+    }
+}
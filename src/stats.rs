@@ -0,0 +1,178 @@
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+
+use crate::memory::MemoryStats;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Stats {
+    pub hits: u64,
+    pub misses: u64,
+    pub sets: u64,
+    /// Entries deleted by `remove`.
+    pub removes: u64,
+    /// Entries removed without a `remove`, split by cause below.
+    pub evictions: u64,
+    /// Expired entries removed by the read that found them.
+    pub expired_on_read: u64,
+    /// Expired entries removed by the janitor's sweeps.
+    pub expired_by_janitor: u64,
+    /// Entries evicted to free space: disk full, size limits or namespace
+    /// quotas.
+    pub evicted_for_space: u64,
+    /// Entries removed because they could not be decoded.
+    pub corrupted: u64,
+    pub bytes_written: u64,
+    pub janitor_runs: u64,
+    /// State of the memory tier since startup. Not persisted.
+    pub memory: Option<MemoryStats>,
+}
+
+impl Stats {
+    pub const FIELDS: [&str; 11] = [
+        "hits",
+        "misses",
+        "sets",
+        "removes",
+        "evictions",
+        "expired_on_read",
+        "expired_by_janitor",
+        "evicted_for_space",
+        "corrupted",
+        "bytes_written",
+        "janitor_runs",
+    ];
+
+    pub fn get(&self, field: &str) -> Option<u64> {
+        Some(match field {
+            "hits" => self.hits,
+            "misses" => self.misses,
+            "sets" => self.sets,
+            "removes" => self.removes,
+            "evictions" => self.evictions,
+            "expired_on_read" => self.expired_on_read,
+            "expired_by_janitor" => self.expired_by_janitor,
+            "evicted_for_space" => self.evicted_for_space,
+            "corrupted" => self.corrupted,
+            "bytes_written" => self.bytes_written,
+            "janitor_runs" => self.janitor_runs,
+            _ => return None,
+        })
+    }
+
+    pub fn set(&mut self, field: &str, value: u64) {
+        match field {
+            "hits" => self.hits = value,
+            "misses" => self.misses = value,
+            "sets" => self.sets = value,
+            "removes" => self.removes = value,
+            "evictions" => self.evictions = value,
+            "expired_on_read" => self.expired_on_read = value,
+            "expired_by_janitor" => self.expired_by_janitor = value,
+            "evicted_for_space" => self.evicted_for_space = value,
+            "corrupted" => self.corrupted = value,
+            "bytes_written" => self.bytes_written = value,
+            "janitor_runs" => self.janitor_runs = value,
+            _ => {}
+        }
+    }
+}
+
+/// Cumulative counters, seeded from the manifest so they survive restarts.
+#[derive(Debug, Default)]
+pub struct Counters {
+    pub hits: Counter,
+    pub misses: Counter,
+    pub sets: Counter,
+    pub removes: Counter,
+    pub evictions: Counter,
+    pub expired_on_read: Counter,
+    pub expired_by_janitor: Counter,
+    pub evicted_for_space: Counter,
+    pub corrupted: Counter,
+    pub bytes_written: Counter,
+    pub janitor_runs: Counter,
+}
+
+impl Counters {
+    pub fn new(stats: Stats) -> Self {
+        Self {
+            hits: Counter::new(stats.hits),
+            misses: Counter::new(stats.misses),
+            sets: Counter::new(stats.sets),
+            removes: Counter::new(stats.removes),
+            evictions: Counter::new(stats.evictions),
+            expired_on_read: Counter::new(stats.expired_on_read),
+            expired_by_janitor: Counter::new(stats.expired_by_janitor),
+            evicted_for_space: Counter::new(stats.evicted_for_space),
+            corrupted: Counter::new(stats.corrupted),
+            bytes_written: Counter::new(stats.bytes_written),
+            janitor_runs: Counter::new(stats.janitor_runs),
+        }
+    }
+
+    pub fn add(counter: &Counter, n: u64) {
+        counter.add(n);
+    }
+
+    /// Counts an eviction along with its cause's counter.
+    pub fn evicted(counter: &Counter, total: &Counter) {
+        Self::add(counter, 1);
+        Self::add(total, 1);
+    }
+
+    pub fn snapshot(&self) -> Stats {
+        Stats {
+            hits: self.hits.load(),
+            misses: self.misses.load(),
+            sets: self.sets.load(),
+            removes: self.removes.load(),
+            evictions: self.evictions.load(),
+            expired_on_read: self.expired_on_read.load(),
+            expired_by_janitor: self.expired_by_janitor.load(),
+            evicted_for_space: self.evicted_for_space.load(),
+            corrupted: self.corrupted.load(),
+            bytes_written: self.bytes_written.load(),
+            janitor_runs: self.janitor_runs.load(),
+            memory: None,
+        }
+    }
+}
+
+const CELLS: usize = 16;
+
+/// Gives each thread one of the cells of every counter, round-robin.
+static NEXT_CELL: AtomicUsize = AtomicUsize::new(0);
+
+thread_local! {
+    static CELL: usize = NEXT_CELL.fetch_add(1, Ordering::Relaxed) % CELLS;
+}
+
+/// A counter split over cells on separate cache lines, so threads adding to
+/// it concurrently don't contend. Reads sum the cells.
+#[derive(Debug, Default)]
+pub struct Counter {
+    cells: [Cell; CELLS],
+}
+
+/// Padded to the size of two cache lines, which some CPUs prefetch together.
+#[derive(Debug, Default)]
+#[repr(align(128))]
+struct Cell(AtomicU64);
+
+impl Counter {
+    pub fn new(value: u64) -> Self {
+        let counter = Self::default();
+        counter.cells[0].0.store(value, Ordering::Relaxed);
+        counter
+    }
+
+    pub fn add(&self, n: u64) {
+        let cell = CELL.with(|cell| *cell);
+        self.cells[cell].0.fetch_add(n, Ordering::Relaxed);
+    }
+
+    pub fn load(&self) -> u64 {
+        self.cells.iter().fold(0, |sum, cell| {
+            sum.wrapping_add(cell.0.load(Ordering::Relaxed))
+        })
+    }
+}
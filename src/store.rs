@@ -1,20 +1,50 @@
 use std::{
-    io::{Read, Write},
-    path::PathBuf,
-    sync::Arc,
-    time::Duration,
+    collections::BTreeMap,
+    fs::File,
+    io::{Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+    sync::{
+        Arc,
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+    },
+    time::{Duration, Instant, SystemTime},
 };
 
-use crossbeam::channel::Receiver;
+use crossbeam::channel::RecvTimeoutError;
 
 use crate::{
-    error::Error,
-    shards::Shards,
-    utils::{now, parse_hash},
+    bypass::{Bypass, Dropped},
+    context::Context,
+    direct,
+    durability::GroupCommit,
+    entries::EntryInfo,
+    entry,
+    error::{Error, Worker},
+    evict::{self, is_storage_full},
+    eviction::EvictionCause,
+    headers::Head,
+    janitor,
+    latency::Op,
+    mac::SigningKey,
+    manifest::FANOUT,
+    mounts,
+    namespace::{self, Overflow},
+    permissions::Permissions,
+    pipeline::{Command, Reply},
+    pool::Heartbeat,
+    queue::Lanes,
+    stats::Counters,
+    tenant,
+    utils::{self, ReadAt, parse_hash},
 };
 
 type GetCallback = Box<dyn FnOnce(Result<Vec<u8>, Error>) + Send + Sync + 'static>;
 type Callback = Box<dyn FnOnce(Result<(), Error>) + Send + Sync + 'static>;
+/// Receives the current value, if any, and the value passed to `merge`.
+pub type MergeFn = Box<dyn FnOnce(Option<&[u8]>, &[u8]) -> Vec<u8> + Send + Sync + 'static>;
+type BatchCallback = Box<dyn FnOnce(Vec<Reply>) + Send + Sync + 'static>;
+type PreviousCallback = Box<dyn FnOnce(Result<Option<Vec<u8>>, Error>) + Send + Sync + 'static>;
+type HeadManyCallback = Box<dyn FnOnce(Vec<Result<EntryInfo, Error>>) + Send + Sync + 'static>;
 
 pub enum InputMessage {
     Get {
@@ -22,51 +52,483 @@ pub enum InputMessage {
         key: String,
         callback: GetCallback,
     },
+    /// The value is shared so callers owning a refcounted buffer don't copy
+    /// it, see `Keeper::set_shared`.
     Set {
         path: Arc<PathBuf>,
         key: String,
-        value: Vec<u8>,
+        value: Arc<[u8]>,
         duration: Option<Duration>,
         callback: Callback,
     },
+    /// Like `Get`, calling the registered loader on a miss.
+    GetOrLoad {
+        path: Arc<PathBuf>,
+        key: String,
+        callback: GetCallback,
+    },
     Remove {
         path: Arc<PathBuf>,
         key: String,
         callback: Callback,
     },
+    /// Changes when a live entry expires, failing with [`Error::NotFound`]
+    /// otherwise.
+    Touch {
+        path: Arc<PathBuf>,
+        key: String,
+        duration: Option<Duration>,
+        callback: Callback,
+    },
+    Merge {
+        path: Arc<PathBuf>,
+        key: String,
+        value: Vec<u8>,
+        duration: Option<Duration>,
+        merge_fn: MergeFn,
+        callback: GetCallback,
+    },
+    /// `Set` answering with the value it replaced.
+    GetAndSet {
+        path: Arc<PathBuf>,
+        key: String,
+        value: Vec<u8>,
+        duration: Option<Duration>,
+        callback: PreviousCallback,
+    },
+    /// `Get` and `Set` failing with [`Error::WouldBlock`] instead of waiting
+    /// for a lock.
+    TryGet {
+        path: Arc<PathBuf>,
+        key: String,
+        callback: GetCallback,
+    },
+    TrySet {
+        path: Arc<PathBuf>,
+        key: String,
+        value: Vec<u8>,
+        duration: Option<Duration>,
+        callback: Callback,
+    },
+    Swap {
+        path: Arc<PathBuf>,
+        a: String,
+        b: String,
+        callback: Callback,
+    },
     Clear {
         path: Arc<PathBuf>,
         callback: Callback,
     },
+    Batch {
+        path: Arc<PathBuf>,
+        commands: Vec<Command>,
+        callback: BatchCallback,
+    },
+    /// [`head_info`] for every key, in one message.
+    HeadMany {
+        path: Arc<PathBuf>,
+        keys: Vec<String>,
+        callback: HeadManyCallback,
+    },
+    /// Janitor sweep requested through `Keeper::cleanup` when the keeper was
+    /// built without a janitor thread.
+    Cleanup {
+        path: Arc<PathBuf>,
+        callback: Callback,
+    },
+    /// `msg`, to be answered with [`Error::Timeout`] instead of handled if no
+    /// worker picks it up before `deadline`.
+    Deadline {
+        deadline: Instant,
+        msg: Box<InputMessage>,
+    },
     Quit,
 }
 
-pub fn worker(shards: Shards, input_receiver: Receiver<InputMessage>) {
-    while let Ok(msg) = input_receiver.recv() {
+impl InputMessage {
+    /// Answers the message with an error without handling it.
+    pub fn reject(self, error: impl Fn() -> Error) {
+        match self {
+            Self::Get { callback, .. }
+            | Self::GetOrLoad { callback, .. }
+            | Self::TryGet { callback, .. }
+            | Self::Merge { callback, .. } => callback(Err(error())),
+            Self::GetAndSet { callback, .. } => callback(Err(error())),
+            Self::Set { callback, .. }
+            | Self::TrySet { callback, .. }
+            | Self::Remove { callback, .. }
+            | Self::Touch { callback, .. }
+            | Self::Swap { callback, .. }
+            | Self::Clear { callback, .. }
+            | Self::Cleanup { callback, .. } => callback(Err(error())),
+            Self::Batch {
+                commands, callback, ..
+            } => callback(commands.iter().map(|_| Err(error())).collect()),
+            Self::HeadMany { keys, callback, .. } => {
+                callback(keys.iter().map(|_| Err(error())).collect())
+            }
+            Self::Deadline { msg, .. } => msg.reject(error),
+            Self::Quit => {}
+        }
+    }
+
+    /// Unwraps a message with a deadline, answering it with
+    /// [`Error::Timeout`] instead when the deadline passed.
+    pub fn open(self, now: Instant) -> Option<Self> {
+        match self {
+            Self::Deadline { deadline, msg } if deadline <= now => {
+                msg.reject(|| Error::Timeout);
+                None
+            }
+            Self::Deadline { msg, .. } => msg.open(now),
+            msg => Some(msg),
+        }
+    }
+
+    /// Answers the message without touching the disk, which `bypass` gave up
+    /// on: reads miss, or call the loader without storing what it returns,
+    /// and writes are dropped, their keys being invalidated on recovery.
+    /// Requests that must return what is stored fail with
+    /// [`Error::DiskBypassed`]. Hands the message back when the store recovered
+    /// meanwhile.
+    pub fn bypass(self, ctx: &Context, bypass: &Bypass) -> Option<Self> {
+        let dropped = match &self {
+            Self::Set { path, key, .. }
+            | Self::TrySet { path, key, .. }
+            | Self::Remove { path, key, .. } => bypass.drop_writes(path, &[key], false),
+            Self::Swap { path, a, b, .. } => bypass.drop_writes(path, &[a, b], false),
+            Self::Clear { path, .. } => bypass.drop_writes(path, &[], true),
+            Self::Batch { path, commands, .. } => {
+                let keys: Vec<&str> = commands
+                    .iter()
+                    .filter_map(|command| match command {
+                        Command::Get { .. } => None,
+                        Command::Set { key, .. } | Command::Remove { key } => Some(key.as_str()),
+                    })
+                    .collect();
+                bypass.drop_writes(path, &keys, false)
+            }
+            Self::Deadline { .. } | Self::Quit => false,
+            _ => true,
+        };
+        if !dropped {
+            return Some(self);
+        }
+
+        match self {
+            Self::Get { callback, .. } | Self::TryGet { callback, .. } => {
+                callback(Err(Error::NotFound))
+            }
+            Self::Touch { callback, .. } => callback(Err(Error::NotFound)),
+            Self::GetOrLoad { key, callback, .. } => callback(match &ctx.loader {
+                Some(loader) => loader.load(&key).map(|(value, _)| value),
+                None => Err(Error::NotFound),
+            }),
+            Self::Merge { callback, .. } => callback(Err(Error::DiskBypassed)),
+            Self::GetAndSet { callback, .. } => callback(Err(Error::DiskBypassed)),
+            Self::Set { callback, .. }
+            | Self::TrySet { callback, .. }
+            | Self::Remove { callback, .. }
+            | Self::Swap { callback, .. }
+            | Self::Clear { callback, .. }
+            | Self::Cleanup { callback, .. } => callback(Ok(())),
+            Self::Batch {
+                commands, callback, ..
+            } => callback(
+                commands
+                    .iter()
+                    .map(|command| match command {
+                        Command::Get { .. } => Err(Error::NotFound),
+                        Command::Set { .. } | Command::Remove { .. } => Ok(None),
+                    })
+                    .collect(),
+            ),
+            Self::HeadMany { keys, callback, .. } => {
+                callback(keys.iter().map(|_| Err(Error::DiskBypassed)).collect())
+            }
+            Self::Deadline { .. } | Self::Quit => {}
+        }
+        None
+    }
+}
+
+#[derive(Clone)]
+pub struct Idle {
+    pub timeout: Duration,
+    pub min: usize,
+    pub live: Arc<AtomicUsize>,
+}
+
+impl Idle {
+    fn retire(&self) -> bool {
+        self.live
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |live| {
+                (live > self.min).then_some(live - 1)
+            })
+            .is_ok()
+    }
+}
+
+pub fn worker(
+    ctx: Arc<Context>,
+    input_receiver: Lanes,
+    idle: Option<Idle>,
+    heartbeat: Arc<Heartbeat>,
+) {
+    loop {
+        let msg = match &idle {
+            Some(idle) => match input_receiver.recv_timeout(idle.timeout) {
+                Ok(msg) => msg,
+                Err(RecvTimeoutError::Timeout) if idle.retire() => break,
+                Err(RecvTimeoutError::Timeout) => continue,
+                Err(RecvTimeoutError::Disconnected) => break,
+            },
+            None => match input_receiver.recv() {
+                Ok(msg) => msg,
+                Err(_) => break,
+            },
+        };
+        ctx.watermarks.observe(input_receiver.len());
+        let Some(msg) = msg.open(Instant::now()) else {
+            continue;
+        };
+        let msg = match &ctx.bypass {
+            Some(bypass) if bypass.is_bypassing() && !recover(&ctx, bypass) => {
+                match msg.bypass(&ctx, bypass) {
+                    Some(msg) => msg,
+                    None => continue,
+                }
+            }
+            _ => msg,
+        };
+        heartbeat.begin();
+
         match msg {
             InputMessage::Get {
                 path,
                 key,
                 callback,
-            } => callback(get(&shards, path, key)),
+            } => {
+                let start = Instant::now();
+                let res = ctx.guard(Worker::Store, || read_through(&ctx, path.clone(), &key));
+                let size = res.as_ref().map(|(v, _)| v.len()).unwrap_or(0);
+                ctx.record(Op::Get, Some(&key), size, start.elapsed());
+                finish_get(&ctx, path, &key, res, callback);
+            }
+            InputMessage::GetOrLoad {
+                path,
+                key,
+                callback,
+            } => {
+                let start = Instant::now();
+                let res = ctx.guard(Worker::Store, || get_or_load(&ctx, path.clone(), &key));
+                let size = res.as_ref().map(|(v, _)| v.len()).unwrap_or(0);
+                ctx.record(Op::Get, Some(&key), size, start.elapsed());
+                finish_get(&ctx, path, &key, res, callback);
+            }
             InputMessage::Set {
                 path,
                 key,
                 value,
                 duration,
                 callback,
-            } => callback(set(&shards, path, key, value, duration)),
+            } => {
+                let start = Instant::now();
+                let res = ctx.guard(Worker::Store, || set(&ctx, path, &key, &value, duration));
+                ctx.record(Op::Set, Some(&key), value.len(), start.elapsed());
+                callback(res)
+            }
             InputMessage::Remove {
                 path,
                 key,
                 callback,
-            } => callback(remove(&shards, path, key)),
-            InputMessage::Clear { path, callback } => callback(clear(&shards, path)),
+            } => callback(execute(&ctx, path, Command::Remove { key }).map(|_| ())),
+            InputMessage::Touch {
+                path,
+                key,
+                duration,
+                callback,
+            } => {
+                let start = Instant::now();
+                let res = ctx.guard(Worker::Store, || touch(&ctx, path, &key, duration));
+                ctx.record(Op::Set, Some(&key), 0, start.elapsed());
+                callback(res)
+            }
+            InputMessage::Merge {
+                path,
+                key,
+                value,
+                duration,
+                merge_fn,
+                callback,
+            } => {
+                let start = Instant::now();
+                let res = ctx.guard(Worker::Store, || {
+                    merge(&ctx, path, &key, &value, duration, merge_fn)
+                });
+                let size = res.as_ref().map(Vec::len).unwrap_or(0);
+                ctx.record(Op::Set, Some(&key), size, start.elapsed());
+                callback(res)
+            }
+            InputMessage::GetAndSet {
+                path,
+                key,
+                value,
+                duration,
+                callback,
+            } => {
+                let start = Instant::now();
+                let res = ctx.guard(Worker::Store, || {
+                    get_and_set(&ctx, path, &key, &value, duration)
+                });
+                ctx.record(Op::Set, Some(&key), value.len(), start.elapsed());
+                callback(res)
+            }
+            InputMessage::TryGet {
+                path,
+                key,
+                callback,
+            } => {
+                let start = Instant::now();
+                let res = ctx.guard(Worker::Store, || get_with(&ctx, path.clone(), &key, false));
+                let size = res.as_ref().map(|(v, _)| v.len()).unwrap_or(0);
+                ctx.record(Op::Get, Some(&key), size, start.elapsed());
+                finish_get(&ctx, path, &key, res, callback);
+            }
+            InputMessage::TrySet {
+                path,
+                key,
+                value,
+                duration,
+                callback,
+            } => {
+                let start = Instant::now();
+                let res = ctx.guard(Worker::Store, || {
+                    set_with(&ctx, path, &key, &value, duration, false, true)
+                });
+                ctx.record(Op::Set, Some(&key), value.len(), start.elapsed());
+                callback(res)
+            }
+            InputMessage::Swap {
+                path,
+                a,
+                b,
+                callback,
+            } => {
+                let start = Instant::now();
+                let res = ctx.guard(Worker::Store, || swap(&ctx, path, &a, &b));
+                ctx.record(Op::Set, Some(&a), 0, start.elapsed());
+                callback(res)
+            }
+            InputMessage::Clear { path, callback } => {
+                let start = Instant::now();
+                let res = ctx.guard(Worker::Store, || clear(&ctx, path));
+                ctx.record(Op::Clear, None, 0, start.elapsed());
+                callback(res)
+            }
+            InputMessage::Batch {
+                path,
+                commands,
+                callback,
+            } => callback(
+                commands
+                    .into_iter()
+                    .map(|command| execute(&ctx, path.clone(), command))
+                    .collect(),
+            ),
+            InputMessage::HeadMany {
+                path,
+                keys,
+                callback,
+            } => callback(
+                keys.iter()
+                    .map(|key| ctx.guard(Worker::Store, || head_info(&ctx, &path, key)))
+                    .collect(),
+            ),
+            InputMessage::Cleanup { path, callback } => callback(ctx.guard(Worker::Store, || {
+                let _permit = ctx.io_permit();
+                match ctx.pressure.is_enabled() {
+                    true => evict::relieve(&path, &ctx),
+                    false => janitor::cleanup(&path, &ctx),
+                }
+                ctx.persist(&path)
+            })),
+            // Opened as soon as it was received.
+            InputMessage::Deadline { msg, .. } => msg.reject(|| Error::Timeout),
             InputMessage::Quit => break,
         }
+
+        if heartbeat.end() {
+            break;
+        }
     }
 }
 
+/// Runs a single command, timing it like any other operation.
+/// Probes the disk of a store that bypasses it, and stops bypassing once the
+/// writes dropped meanwhile were invalidated. Returns whether it recovered.
+fn recover(ctx: &Context, bypass: &Bypass) -> bool {
+    let Some(mut dropped) = bypass.probe(&ctx.permissions) else {
+        return false;
+    };
+
+    loop {
+        if let Err(e) = invalidate(ctx, bypass, &dropped) {
+            bypass.restore(dropped);
+            ctx.hooks.report(&e);
+            return false;
+        }
+        match bypass.recover() {
+            Some(more) => dropped = more,
+            None => return true,
+        }
+    }
+}
+
+fn invalidate(ctx: &Context, bypass: &Bypass, dropped: &Dropped) -> Result<(), Error> {
+    if dropped.overflowed {
+        return clear_lazy(ctx, bypass.root());
+    }
+    for (root, keys) in &dropped.keys {
+        for key in keys {
+            remove(ctx, root.clone(), key)?;
+        }
+    }
+    Ok(())
+}
+
+fn execute(ctx: &Context, path: Arc<PathBuf>, command: Command) -> Reply {
+    let start = Instant::now();
+    match command {
+        Command::Get { key } => {
+            let res = ctx
+                .guard(Worker::Store, || read_through(ctx, path, &key))
+                .map(|(value, _)| value);
+            let size = res.as_ref().map(Vec::len).unwrap_or(0);
+            ctx.record(Op::Get, Some(&key), size, start.elapsed());
+            res.map(Some)
+        }
+        Command::Set {
+            key,
+            value,
+            duration,
+        } => {
+            let res = ctx.guard(Worker::Store, || set(ctx, path, &key, &value, duration));
+            ctx.record(Op::Set, Some(&key), value.len(), start.elapsed());
+            res.map(|_| None)
+        }
+        Command::Remove { key } => {
+            let res = ctx.guard(Worker::Store, || remove(ctx, path, &key));
+            ctx.record(Op::Remove, Some(&key), 0, start.elapsed());
+            res.map(|_| None)
+        }
+    }
+}
+
+/// The 32 hex digit name of `input`'s entry: a 128-bit XXH3 of the key. The
+/// first 3 digits name the shard folder (4096 of them) and the rest the file,
+/// as in `<root>/a3f/0c9...`. Stores with a hash key use [`keyed_hash`].
 pub fn hash(input: &str) -> Vec<u8> {
     let n = xxhash_rust::xxh3::xxh3_128(input.as_bytes());
     let mut buf = vec![0u8; 32];
@@ -74,89 +536,1303 @@ pub fn hash(input: &str) -> Vec<u8> {
     buf
 }
 
-fn get(shards: &Shards, path: Arc<PathBuf>, key: String) -> Result<Vec<u8>, Error> {
-    let h = hash(&key);
+/// Like [`hash`], seeded with the store's random `seed` so the layout of a
+/// given set of keys differs between stores.
+pub fn seeded_hash(seed: u64, input: &str) -> Vec<u8> {
+    let n = xxhash_rust::xxh3::xxh3_128_with_seed(input.as_bytes(), seed);
+    let mut buf = vec![0u8; 32];
+    faster_hex::hex_encode(&n.to_be_bytes(), &mut buf).unwrap();
+    buf
+}
+
+/// Like [`hash`], with the first 128 bits of an HMAC-SHA256 of the key
+/// instead, so file names can't be matched against guessed keys without
+/// `hash_key`.
+pub fn keyed_hash(hash_key: &SigningKey, input: &str) -> Vec<u8> {
+    let tag = hash_key.sign(&[input.as_bytes()]);
+    let mut buf = vec![0u8; 32];
+    faster_hex::hex_encode(&tag[..16], &mut buf).unwrap();
+    buf
+}
+
+/// Answers the caller and then, if the entry is about to expire, reloads it on
+/// this worker.
+fn finish_get(
+    ctx: &Context,
+    path: Arc<PathBuf>,
+    key: &str,
+    res: Result<(Vec<u8>, bool), Error>,
+    callback: GetCallback,
+) {
+    let (res, refresh) = match res {
+        Ok((value, refresh)) => (Ok(value), refresh),
+        Err(e) => (Err(e), false),
+    };
+    callback(res);
+
+    if refresh && let Some(loader) = &ctx.loader {
+        if let Err(e) = ctx.guard(Worker::Store, || load(ctx, path, key)) {
+            ctx.hooks.report(&e);
+        }
+        loader.refreshed(key);
+    }
+}
+
+fn get_or_load(ctx: &Context, path: Arc<PathBuf>, key: &str) -> Result<(Vec<u8>, bool), Error> {
+    let Some(loader) = ctx.loader.as_ref().filter(|l| l.serves_stale()) else {
+        return match get(ctx, path.clone(), key) {
+            Err(Error::NotFound) => load(ctx, path, key).map(|v| (v, false)),
+            res => res,
+        };
+    };
+
+    // Expired entries are kept around so they can be served when the loader
+    // fails; the next successful load replaces them.
+    let now = ctx.now();
+    let stale = match read_entry(ctx, &path, key) {
+        Ok((value, expires_at)) if !ctx.is_expired(expires_at, now) => {
+            ctx.hit(key, &ctx.entry_path(&path, key));
+            let refresh = loader.claim_refresh(key, expires_at, now);
+            return Ok((value, refresh));
+        }
+        Ok((value, _)) => Some(value),
+        Err(_) => None,
+    };
+
+    Counters::add(&ctx.counters.misses, 1);
+    match (load(ctx, path, key), stale) {
+        (Ok(value), _) => Ok((value, false)),
+        (Err(_), Some(value)) => Ok((value, false)),
+        (Err(e), None) => Err(e),
+    }
+}
+
+/// Reads an entry and its expiration time, without checking whether it
+/// expired.
+fn read_entry(ctx: &Context, path: &Path, key: &str) -> Result<(Vec<u8>, u64), Error> {
+    let h = ctx.hash(key);
+    let (p_folder, filename, shard_id) = parse_hash(&h);
+
+    let file_path = path.join(p_folder).join(filename);
+    let _permit = ctx.io_permit();
+    let _lock = ctx.shards.read(shard_id);
+
+    let buffer = match ctx.read_file(&file_path) {
+        Ok(buffer) => buffer,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Err(Error::NotFound),
+        Err(e) => return Err(e.into()),
+    };
+
+    let (header, payload) = ctx.decode(&buffer, key)?;
+    Ok((payload.into_owned(), header.expires_at))
+}
+
+fn load(ctx: &Context, path: Arc<PathBuf>, key: &str) -> Result<Vec<u8>, Error> {
+    let Some(loader) = &ctx.loader else {
+        return Err(Error::NotFound);
+    };
+
+    let (value, duration) = loader.load(key)?;
+    set_with(ctx, path, key, &value, duration, true, false)?;
+    Ok(value)
+}
+
+/// Like [`get`], loading missing keys from the origin when one is attached.
+fn read_through(ctx: &Context, path: Arc<PathBuf>, key: &str) -> Result<(Vec<u8>, bool), Error> {
+    match ctx.origin {
+        Some(_) => get_or_load(ctx, path, key),
+        None => get(ctx, path, key),
+    }
+}
+
+/// Returns the value and whether it should be refreshed ahead of expiry.
+fn get(ctx: &Context, path: Arc<PathBuf>, key: &str) -> Result<(Vec<u8>, bool), Error> {
+    let res = get_with(ctx, path.clone(), key, true)?;
+    slide(ctx, &path, key);
+    Ok(res)
+}
+
+/// Pushes the expiration of an entry that was just read back to the default
+/// TTL of its namespace, when that namespace has sliding expiration. The
+/// entry is rewritten at most once a second, and left alone while its shard
+/// is busy.
+fn slide(ctx: &Context, path: &Path, key: &str) {
+    let policy = match namespace::namespace_of(key) {
+        Some(ns) => ctx.namespaces.policy(ns),
+        None => return,
+    };
+    let Some(ttl) = policy.default_ttl.filter(|_| policy.sliding) else {
+        return;
+    };
+
+    let h = ctx.hash(key);
     let (p_folder, filename, shard_id) = parse_hash(&h);
+    let folder = path.join(p_folder);
+    let file_path = folder.join(filename);
+
+    let _permit = ctx.io_permit();
+    let Some(_lock) = ctx.shards.write_now(shard_id) else {
+        return;
+    };
+
+    let res = (|| {
+        let buffer = ctx.read_file(&file_path)?;
+        let (header, value) = ctx.decode(&buffer, key)?;
+        let expires_at = ctx.expires_at(Some(ttl));
+        if header.expires_at == 0
+            || header.expires_at >= expires_at
+            || ctx.is_expired(header.expires_at, ctx.now())
+        {
+            return Ok(());
+        }
+
+        let encoded = ctx.encode(expires_at, key, &value)?;
+        commit(ctx, path, key, shard_id, &file_path, &value, &encoded)
+    })();
+    match res {
+        Err(Error::Io(e)) if e.kind() == std::io::ErrorKind::NotFound => {}
+        Err(e) => ctx.hooks.report(&e),
+        Ok(()) => {}
+    }
+}
+
+/// Without `blocking`, fails with [`Error::WouldBlock`] rather than waiting for
+/// the shard lock or an I/O permit, and leaves expired or corrupted entries to
+/// the janitor. Reads racing a clear fail with [`Error::Cleared`].
+fn get_with(
+    ctx: &Context,
+    path: Arc<PathBuf>,
+    key: &str,
+    blocking: bool,
+) -> Result<(Vec<u8>, bool), Error> {
+    ctx.snapshot(|| read_current(ctx, path, key, blocking))
+}
 
+fn read_current(
+    ctx: &Context,
+    path: Arc<PathBuf>,
+    key: &str,
+    blocking: bool,
+) -> Result<(Vec<u8>, bool), Error> {
+    let h = ctx.hash(key);
+    let (p_folder, filename, shard_id) = parse_hash(&h);
     let file_path = path.join(p_folder).join(filename);
-    let _lock = shards.read(shard_id);
 
-    let mut file = std::fs::File::open(&file_path).map_err(|_| Error::NotFound)?;
-    let mut buffer = Vec::new();
-    file.read_to_end(&mut buffer)?;
+    if let Some(memory) = &ctx.memory
+        && let Some((value, expires_at)) = memory.get(&h)
+    {
+        let now = ctx.now();
+        if !ctx.is_expired(expires_at, now) {
+            ctx.hit(key, &file_path);
+            let refresh = ctx
+                .loader
+                .as_ref()
+                .is_some_and(|l| l.claim_refresh(key, expires_at, now));
+            return Ok((value, refresh));
+        }
+        memory.invalidate(&h);
+    }
+
+    let _permit = match blocking {
+        true => ctx.io_permit(),
+        false => ctx.try_io_permit()?,
+    };
+
+    let now = ctx.now();
+    if let Some((payload, expires_at)) = read_unlocked(ctx, &file_path, key, now) {
+        ctx.hit(key, &file_path);
+        let refresh = ctx
+            .loader
+            .as_ref()
+            .is_some_and(|l| l.claim_refresh(key, expires_at, now));
+        return Ok((payload, refresh));
+    }
+
+    let _lock = match blocking {
+        true => ctx.shards.read(shard_id),
+        false => ctx.shards.read_now(shard_id).ok_or(Error::WouldBlock)?,
+    };
+
+    let file = match ctx.open_file(&file_path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            Counters::add(&ctx.counters.misses, 1);
+            return Err(Error::NotFound);
+        }
+        Err(e) => return Err(e.into()),
+    };
+    if ctx.is_cleared(&file.metadata()?) {
+        Counters::add(&ctx.counters.misses, 1);
+        return Err(Error::NotFound);
+    }
 
-    if buffer.len() < 10 {
+    // Check the expiration from the header alone, so an expired entry's
+    // payload is never read. Signed headers can't be trusted before the whole
+    // entry is verified, and a damaged header is left to the full decode.
+    if ctx.signing_key.is_none()
+        && let Ok(header) = entry::read_header_from(ReadAt::new(&file))
+        && ctx.is_expired(header.expires_at, now)
+    {
         drop(_lock);
-        remove_with_hash(&h, shards, path)?;
-        return Err(Error::InvalidData);
+        Counters::add(&ctx.counters.misses, 1);
+        if blocking && remove_with_hash(&h, ctx, path, Some(EvictionCause::Expired), Some(&file))? {
+            Counters::evicted(&ctx.counters.expired_on_read, &ctx.counters.evictions);
+        }
+        return Err(Error::NotFound);
     }
 
-    let expires_at = u64::from_be_bytes(buffer[2..10].try_into().unwrap());
+    let buffer = ctx.read_opened(&file_path, &file)?;
+    let (header, payload) = match ctx.decode(&buffer, key) {
+        Ok(decoded) => decoded,
+        // Left in place: neither is fixed by removing the entry.
+        Err(e @ (Error::TamperDetected | Error::UnknownCodec(_))) => {
+            Counters::add(&ctx.counters.misses, 1);
+            return Err(e);
+        }
+        Err(e) => {
+            drop(_lock);
+            Counters::add(&ctx.counters.misses, 1);
+            if blocking
+                && remove_with_hash(&h, ctx, path, Some(EvictionCause::Corrupted), Some(&file))?
+            {
+                Counters::evicted(&ctx.counters.corrupted, &ctx.counters.evictions);
+            }
+            return Err(e);
+        }
+    };
 
-    if expires_at != 0 && expires_at < now() {
+    if ctx.is_expired(header.expires_at, now) {
         drop(_lock);
-        remove_with_hash(&h, shards, path)?;
+        Counters::add(&ctx.counters.misses, 1);
+        if blocking && remove_with_hash(&h, ctx, path, Some(EvictionCause::Expired), Some(&file))? {
+            Counters::evicted(&ctx.counters.expired_on_read, &ctx.counters.evictions);
+        }
         return Err(Error::NotFound);
     }
 
-    Ok(buffer[10..].to_vec())
+    if let Some(memory) = &ctx.memory {
+        memory.offer(&h, &payload, header.expires_at);
+    }
+
+    ctx.hit(key, &file_path);
+    let refresh = ctx
+        .loader
+        .as_ref()
+        .is_some_and(|l| l.claim_refresh(key, header.expires_at, now));
+    Ok((payload.into_owned(), refresh))
+}
+
+/// Reads a live entry without the shard lock. Writers replace entries by
+/// renaming a complete file over them, so an open file never changes, but an
+/// in-place bulk load or a secure delete rewrites it. Only entries that carry
+/// their key and a checksum or tag, and whose key is `key`, are trusted: a
+/// zeroed file would pass for a [`entry::V1`] entry that never expires.
+/// Returns `None` whenever the locked path must decide, for misses, expired,
+/// damaged or older entries. The memory tier and the handle cache are filled under the
+/// lock only, so stores using them skip this path.
+fn read_unlocked(ctx: &Context, file_path: &Path, key: &str, now: u64) -> Option<(Vec<u8>, u64)> {
+    if ctx.memory.is_some() || ctx.handles.is_some() {
+        return None;
+    }
+
+    ctx.inject_read().ok()?;
+    let file = File::open(file_path).ok()?;
+    if ctx.is_cleared(&file.metadata().ok()?) {
+        return None;
+    }
+    let header = entry::read_header_from(ReadAt::new(&file)).ok()?;
+    if header.version < entry::V2 || ctx.is_expired(header.expires_at, now) {
+        return None;
+    }
+
+    let buffer = ctx.read_opened(file_path, &file).ok()?;
+    let (header, payload) = ctx.decode(&buffer, key).ok()?;
+    if header.version < entry::V2 || header.key.as_deref() != Some(key) {
+        return None;
+    }
+    Some((payload.into_owned(), header.expires_at))
 }
 
 fn set(
-    shards: &Shards,
+    ctx: &Context,
+    path: Arc<PathBuf>,
+    key: &str,
+    value: &[u8],
+    duration: Option<Duration>,
+) -> Result<(), Error> {
+    set_with(ctx, path, key, value, duration, true, true)
+}
+
+/// Without `blocking`, fails with [`Error::WouldBlock`] rather than waiting for
+/// the shard lock or an I/O permit. With `write_through`, the value is also
+/// stored in the origin, if writes go through to it, while the lock is held.
+fn set_with(
+    ctx: &Context,
+    path: Arc<PathBuf>,
+    key: &str,
+    value: &[u8],
+    duration: Option<Duration>,
+    blocking: bool,
+    write_through: bool,
+) -> Result<(), Error> {
+    let h = ctx.hash(key);
+    let (p_folder, filename, shard_id) = parse_hash(&h);
+
+    let folder = path.join(p_folder);
+    let file_path = folder.join(filename);
+
+    ctx.check_key(key)?;
+    ctx.check_size(key, value.len() as u64)?;
+    let expires_at = ctx.expires_for(key, duration);
+
+    let encoded = ctx.encode(expires_at, key, value)?;
+
+    let (_permit, _lock) = match blocking {
+        true => (ctx.io_permit(), ctx.shards.write(shard_id)),
+        false => {
+            let permit = ctx.try_io_permit()?;
+            let lock = ctx.shards.write_now(shard_id).ok_or(Error::WouldBlock)?;
+            (permit, lock)
+        }
+    };
+    if write_through && let Some(origin) = &ctx.origin {
+        origin.write(key, value)?;
+    }
+    commit(ctx, &path, key, shard_id, &file_path, value, &encoded)
+}
+
+/// Replaces the entry at `file_path` with `encoded`, the encoding of `value`.
+/// The caller must hold the shard write lock.
+fn commit(
+    ctx: &Context,
+    path: &Path,
+    key: &str,
+    shard_id: u16,
+    file_path: &Path,
+    value: &[u8],
+    encoded: &[u8],
+) -> Result<(), Error> {
+    let folder = file_path.parent().unwrap_or(path);
+    let old_size = ctx.live_size(file_path);
+    let old_weight = old_size.map_or(0, |size| ctx.weigh_file(file_path, size));
+    charge(ctx, path, key, old_size, encoded.len() as u64)?;
+
+    ctx.forget(file_path);
+    if let Some(prefix) = encoded.first_chunk() {
+        ctx.horizons.lower(shard_id, entry::expires_at(prefix));
+    }
+    let res = match write_entry(ctx, folder, file_path, encoded) {
+        Err(e) if ctx.evict_on_full && is_storage_full(&e) => {
+            evict::emergency(path, ctx, encoded.len() as u64);
+            write_entry(ctx, folder, file_path, encoded)
+        }
+        res => res,
+    };
+
+    if res.is_err()
+        && let Some(ns) = namespace::namespace_of(key)
+    {
+        ctx.namespaces.refund(ns, old_size, encoded.len() as u64);
+    }
+    res?;
+
+    ctx.pressure
+        .resize(old_weight, ctx.weigh(key, value, encoded.len() as u64));
+    ctx.pressure.check(path);
+
+    Counters::add(&ctx.counters.sets, 1);
+    Counters::add(&ctx.counters.bytes_written, encoded.len() as u64);
+    Ok(())
+}
+
+/// Charges an entry of `new_size` bytes replacing one of `old_size` bytes to
+/// the namespace of `key`, if any. Namespaces whose policy is to evict make
+/// room first. The caller must hold the shard write lock.
+fn charge(
+    ctx: &Context,
+    path: &Path,
+    key: &str,
+    old_size: Option<u64>,
+    new_size: u64,
+) -> Result<(), Error> {
+    let Some(ns) = namespace::namespace_of(key) else {
+        return Ok(());
+    };
+
+    match ctx.namespaces.charge(ns, old_size, new_size) {
+        Err(Error::QuotaExceeded(_))
+            if ctx.namespaces.policy(ns).overflow == Overflow::EvictLru =>
+        {
+            if let Some(excess) = ctx.namespaces.excess(ns, old_size, new_size) {
+                evict::namespace(path, ctx, ns, excess);
+            }
+            ctx.namespaces.charge(ns, old_size, new_size)
+        }
+        res => res,
+    }
+}
+
+/// Combines the current value of `key`, if any, with `value` and stores the
+/// result, all under the shard write lock. New entries expire after
+/// `duration`; merging into an existing entry keeps its expiration time.
+fn merge(
+    ctx: &Context,
     path: Arc<PathBuf>,
-    key: String,
-    value: Vec<u8>,
+    key: &str,
+    value: &[u8],
+    duration: Option<Duration>,
+    merge_fn: MergeFn,
+) -> Result<Vec<u8>, Error> {
+    let h = ctx.hash(key);
+    let (p_folder, filename, shard_id) = parse_hash(&h);
+
+    let folder = path.join(p_folder);
+    let file_path = folder.join(filename);
+
+    let _permit = ctx.io_permit();
+    let _lock = ctx.shards.write(shard_id);
+
+    let buffer = match ctx.read_file(&file_path) {
+        Ok(buffer) => Some(buffer),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+        Err(e) => return Err(e.into()),
+    };
+    let now = ctx.now();
+    let current = match buffer.as_deref().map(|buffer| ctx.decode(buffer, key)) {
+        Some(Err(e @ (Error::TamperDetected | Error::UnknownCodec(_)))) => return Err(e),
+        decoded => decoded.and_then(Result::ok),
+    }
+    .filter(|(header, _)| !ctx.is_expired(header.expires_at, now));
+
+    let expires_at = match &current {
+        Some((header, _)) => header.expires_at,
+        None => ctx.expires_for(key, duration),
+    };
+    let merged = merge_fn(current.as_ref().map(|(_, payload)| payload.as_ref()), value);
+    ctx.check_size(key, merged.len() as u64)?;
+    if let Some(origin) = &ctx.origin {
+        origin.write(key, &merged)?;
+    }
+
+    let encoded = ctx.encode(expires_at, key, &merged)?;
+    commit(ctx, &path, key, shard_id, &file_path, &merged, &encoded)?;
+    Ok(merged)
+}
+
+/// Rewrites the live entry of `key` with the expiration `duration` gives it,
+/// keeping its value, under the shard write lock.
+fn touch(
+    ctx: &Context,
+    path: Arc<PathBuf>,
+    key: &str,
     duration: Option<Duration>,
 ) -> Result<(), Error> {
-    let h = hash(&key);
+    let h = ctx.hash(key);
+    let (p_folder, filename, shard_id) = parse_hash(&h);
+    let file_path = path.join(p_folder).join(filename);
+
+    let _permit = ctx.io_permit();
+    let _lock = ctx.shards.write(shard_id);
+
+    let buffer = match ctx.read_file(&file_path) {
+        Ok(buffer) => buffer,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Err(Error::NotFound),
+        Err(e) => return Err(e.into()),
+    };
+    let (header, payload) = ctx.decode(&buffer, key)?;
+    if ctx.is_expired(header.expires_at, ctx.now()) {
+        return Err(Error::NotFound);
+    }
+
+    let encoded = ctx.encode(ctx.expires_for(key, duration), key, &payload)?;
+    commit(ctx, &path, key, shard_id, &file_path, &payload, &encoded)
+}
+
+/// Replaces the value of `key` like `set` and returns the live value it
+/// replaced, both under the shard write lock.
+fn get_and_set(
+    ctx: &Context,
+    path: Arc<PathBuf>,
+    key: &str,
+    value: &[u8],
+    duration: Option<Duration>,
+) -> Result<Option<Vec<u8>>, Error> {
+    let h = ctx.hash(key);
     let (p_folder, filename, shard_id) = parse_hash(&h);
+    let file_path = path.join(p_folder).join(filename);
+
+    ctx.check_size(key, value.len() as u64)?;
+    let expires_at = ctx.expires_for(key, duration);
+    let encoded = ctx.encode(expires_at, key, value)?;
 
+    let _permit = ctx.io_permit();
+    let _lock = ctx.shards.write(shard_id);
+
+    let buffer = match ctx.read_file(&file_path) {
+        Ok(buffer) => Some(buffer),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+        Err(e) => return Err(e.into()),
+    };
+    let now = ctx.now();
+    let previous = match buffer.as_deref().map(|buffer| ctx.decode(buffer, key)) {
+        Some(Err(e @ (Error::TamperDetected | Error::UnknownCodec(_)))) => return Err(e),
+        decoded => decoded.and_then(Result::ok),
+    }
+    .filter(|(header, _)| !ctx.is_expired(header.expires_at, now))
+    .map(|(_, payload)| payload.into_owned());
+
+    if let Some(origin) = &ctx.origin {
+        origin.write(key, value)?;
+    }
+    commit(ctx, &path, key, shard_id, &file_path, value, &encoded)?;
+    Ok(previous)
+}
+
+/// Entries are grouped by shard so each lock is taken and each folder created
+/// once per batch.
+const BULK_BATCH: usize = 65536;
+
+/// Encoded entries waiting to be written, as `(hash, key, encoded)` by shard
+/// folder. The key is unknown for entries imported by hash.
+type BulkBatch = BTreeMap<u16, Vec<(Vec<u8>, Option<String>, Vec<u8>)>>;
+
+/// Writes already encoded entries in batches, for [`bulk_load`] and imports.
+pub(crate) struct BulkWriter<'a> {
+    ctx: &'a Context,
+    path: PathBuf,
+    batch: BulkBatch,
+    pending: usize,
+    loaded: u64,
+}
+
+impl<'a> BulkWriter<'a> {
+    pub fn new(ctx: &'a Context, path: &Path) -> Self {
+        Self {
+            ctx,
+            path: path.to_path_buf(),
+            batch: BulkBatch::new(),
+            pending: 0,
+            loaded: 0,
+        }
+    }
+
+    pub fn push(&mut self, h: Vec<u8>, key: Option<String>, encoded: Vec<u8>) -> Result<(), Error> {
+        let (_, _, shard_id) = parse_hash(&h);
+        self.batch
+            .entry(shard_id >> 4)
+            .or_default()
+            .push((h, key, encoded));
+
+        self.pending += 1;
+        if self.pending == BULK_BATCH {
+            self.loaded += flush_bulk(self.ctx, &self.path, &mut self.batch)?;
+            self.pending = 0;
+        }
+        Ok(())
+    }
+
+    /// Writes what's left and returns the number of entries written.
+    pub fn finish(mut self) -> Result<u64, Error> {
+        self.loaded += flush_bulk(self.ctx, &self.path, &mut self.batch)?;
+        self.ctx.pressure.check(&self.path);
+        Ok(self.loaded)
+    }
+}
+
+/// Imports `entries` on the calling thread. Files are written in place rather
+/// than through a temporary file: an import interrupted by a crash can leave
+/// truncated entries behind, which fail their checksum and are dropped when
+/// read. Returns the number of entries written.
+pub fn bulk_load<I>(ctx: &Context, path: &Path, entries: I) -> Result<u64, Error>
+where
+    I: IntoIterator<Item = (String, Vec<u8>, Option<Duration>)>,
+{
+    let mut writer = BulkWriter::new(ctx, path);
+    for (key, value, duration) in entries {
+        ctx.check_size(&key, value.len() as u64)?;
+        let encoded = ctx.encode(ctx.expires_for(&key, duration), &key, &value)?;
+        writer.push(ctx.hash(&key), Some(key), encoded)?;
+    }
+    writer.finish()
+}
+
+fn flush_bulk(ctx: &Context, path: &Path, batch: &mut BulkBatch) -> Result<u64, Error> {
+    let mut loaded = 0;
+    let mut written = Vec::new();
+    for (folder_id, items) in std::mem::take(batch) {
+        let _locks = ctx.shards.write_folder(folder_id);
+        let folder = path.join(format!("{folder_id:03x}"));
+        ctx.permissions.create_dir_all(&folder)?;
+        if ctx.durability.is_some() {
+            written.push(folder.clone());
+        }
+
+        for (h, key, encoded) in items {
+            let (_, filename, shard_id) = parse_hash(&h);
+            let file_path = folder.join(filename);
+            let size = encoded.len() as u64;
+
+            let old_size = ctx.live_size(&file_path);
+            let old_weight = old_size.map_or(0, |size| ctx.weigh_file(&file_path, size));
+            let ns = key.as_deref().and_then(namespace::namespace_of);
+            if let Some(key) = &key {
+                charge(ctx, path, key, old_size, size)?;
+            }
+
+            ctx.forget(&file_path);
+            if let Some(prefix) = encoded.first_chunk() {
+                ctx.horizons.lower(shard_id, entry::expires_at(prefix));
+            }
+            if let Err(e) = ctx.permissions.write(&file_path, &encoded) {
+                if let Some(ns) = ns {
+                    ctx.namespaces.refund(ns, old_size, size);
+                }
+                return Err(e.into());
+            }
+
+            if ctx.durability.is_some() {
+                written.push(file_path);
+            }
+            ctx.pressure.resize(old_weight, ctx.weigh_encoded(&encoded));
+            Counters::add(&ctx.counters.sets, 1);
+            Counters::add(&ctx.counters.bytes_written, size);
+            loaded += 1;
+        }
+    }
+
+    let written: Vec<&Path> = written.iter().map(PathBuf::as_path).collect();
+    ctx.durable(&written)?;
+    Ok(loaded)
+}
+
+/// Reads the header of the entry for `key` on the calling thread, or takes it
+/// from the header cache. Expired entries fail with [`Error::NotFound`] but
+/// aren't removed.
+pub fn head(ctx: &Context, path: &Path, key: &str) -> Result<Head, Error> {
+    let h = ctx.hash(key);
+    let now = ctx.now();
+    let live = |head: Head| match ctx.is_expired(head.expires_at, now) {
+        true => Err(Error::NotFound),
+        false => Ok(head),
+    };
+    if let Some(head) = ctx.headers.as_ref().and_then(|headers| headers.get(&h)) {
+        return live(head);
+    }
+
+    let (header, metadata) = read_head(ctx, path, key, &h)?;
+    let head = Head {
+        expires_at: header.expires_at,
+        size: metadata.len(),
+    };
+    if let Some(headers) = &ctx.headers {
+        headers.insert(&h, head);
+    }
+    live(head)
+}
+
+/// Like [`head`], with the format version and modification time of the
+/// entry. The header cache doesn't keep those, so the file is always read,
+/// and the cache is refreshed.
+pub fn head_info(ctx: &Context, path: &Path, key: &str) -> Result<EntryInfo, Error> {
+    let h = ctx.hash(key);
+    let (header, metadata) = read_head(ctx, path, key, &h)?;
+    let size = metadata.len();
+    if let Some(headers) = &ctx.headers {
+        let head = Head {
+            expires_at: header.expires_at,
+            size,
+        };
+        headers.insert(&h, head);
+    }
+    if ctx.is_expired(header.expires_at, ctx.now()) {
+        return Err(Error::NotFound);
+    }
+
+    Ok(EntryInfo {
+        key: Some(key.into()),
+        version: header.version,
+        expires_at: header.expires_at,
+        size,
+        modified: metadata.modified().ok(),
+    })
+}
+
+/// Reads the header of the entry for `key`, whose hash is `h`, under the
+/// shard read lock, failing with [`Error::NotFound`] when it's missing,
+/// cleared or holds another key.
+fn read_head(
+    ctx: &Context,
+    path: &Path,
+    key: &str,
+    h: &[u8],
+) -> Result<(entry::Header, std::fs::Metadata), Error> {
+    let (p_folder, filename, shard_id) = parse_hash(h);
+    let file_path = path.join(p_folder).join(filename);
+
+    let _permit = ctx.io_permit();
+    let _lock = ctx.shards.read(shard_id);
+    let file = match ctx.open_file(&file_path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Err(Error::NotFound),
+        Err(e) => return Err(e.into()),
+    };
+    let metadata = file.metadata()?;
+    if ctx.is_cleared(&metadata) {
+        return Err(Error::NotFound);
+    }
+    let header = entry::read_header_from(ReadAt::new(&file))?;
+    if header.key.as_deref().is_some_and(|k| k != key) {
+        return Err(Error::NotFound);
+    }
+    Ok((header, metadata))
+}
+
+/// Distinguishes the temporary files of streamed writes, which aren't made
+/// under the shard lock.
+static STREAM_ID: AtomicU64 = AtomicU64::new(0);
+
+/// Streams the value of `key` from `reader` into a temporary file on the
+/// calling thread, then renames it into place under the shard lock. With
+/// `len_hint`, the file is preallocated so a full disk fails the write before
+/// any data is copied. Codecs work on whole values, so with codecs the value
+/// is read into memory and stored like [`set`] does instead. Returns the size
+/// of the value.
+pub fn set_from_reader<R: Read>(
+    ctx: &Context,
+    path: &Path,
+    key: &str,
+    mut reader: R,
+    len_hint: Option<u64>,
+    duration: Option<Duration>,
+) -> Result<u64, Error> {
+    ctx.check_key(key)?;
+    if !ctx.codecs.is_empty() {
+        if let Some(len) = len_hint {
+            ctx.check_size(key, len)?;
+        }
+        let mut value = Vec::new();
+        reader.read_to_end(&mut value)?;
+        set(ctx, Arc::new(path.to_path_buf()), key, &value, duration)?;
+        return Ok(value.len() as u64);
+    }
+
+    let h = ctx.hash(key);
+    let (p_folder, filename, shard_id) = parse_hash(&h);
     let folder = path.join(p_folder);
     let file_path = folder.join(filename);
 
-    let expires_at = duration.map(|d| now() + d.as_secs()).unwrap_or(0);
+    if !folder.exists() {
+        ctx.permissions.create_dir_all(&folder)?;
+    }
+    let id = STREAM_ID.fetch_add(1, Ordering::Relaxed);
+    let tmp = folder.join(format!("{filename}.{id}.tmp"));
+
+    let expires_at = ctx.expires_for(key, duration);
+    let res = stream_to(ctx, &tmp, key, &mut reader, len_hint, expires_at);
+    let (size, len) = match res {
+        Ok(written) => written,
+        Err(e) => {
+            let _ = std::fs::remove_file(&tmp);
+            return Err(e);
+        }
+    };
 
-    let _lock = shards.write(shard_id);
+    let _permit = ctx.io_permit();
+    let _lock = ctx.shards.write(shard_id);
 
+    let old_size = ctx.live_size(&file_path);
+    if let Err(e) = charge(ctx, path, key, old_size, size) {
+        let _ = std::fs::remove_file(&tmp);
+        return Err(e);
+    }
+    let old_weight = old_size.map_or(0, |size| ctx.weigh_file(&file_path, size));
+    let weight = ctx.weigh_file(&tmp, size);
+
+    // The value was written outside the lock, possibly before a lazy clear
+    // that finished since, which would hide it.
+    if let Ok(metadata) = std::fs::metadata(&tmp)
+        && ctx.is_cleared(&metadata)
+        && let Err(e) = File::options()
+            .write(true)
+            .open(&tmp)
+            .and_then(|file| file.set_modified(SystemTime::now()))
+    {
+        let _ = std::fs::remove_file(&tmp);
+        if let Some(ns) = namespace::namespace_of(key) {
+            ctx.namespaces.refund(ns, old_size, size);
+        }
+        return Err(e.into());
+    }
+
+    ctx.forget(&file_path);
+    ctx.horizons.lower(shard_id, expires_at);
+    let renamed = utils::retry_io(|| std::fs::rename(&tmp, &file_path));
+    if let Err(e) = renamed.and_then(|_| ctx.durable(&[&folder])) {
+        let _ = std::fs::remove_file(&tmp);
+        if let Some(ns) = namespace::namespace_of(key) {
+            ctx.namespaces.refund(ns, old_size, size);
+        }
+        return Err(e.into());
+    }
+
+    ctx.pressure.resize(old_weight, weight);
+    ctx.pressure.check(path);
+    Counters::add(&ctx.counters.sets, 1);
+    Counters::add(&ctx.counters.bytes_written, size);
+    Ok(len)
+}
+
+/// Writes the entry to `tmp`, returning the size of the file and of the
+/// value.
+fn stream_to(
+    ctx: &Context,
+    tmp: &Path,
+    key: &str,
+    reader: &mut impl Read,
+    len_hint: Option<u64>,
+    expires_at: u64,
+) -> Result<(u64, u64), Error> {
+    if let Some(len) = len_hint {
+        ctx.check_size(key, len)?;
+    }
+    let mut encoder = ctx.stream_encoder(expires_at, key)?;
+    let header_len = encoder.header().len() as u64;
+
+    ctx.inject_write()?;
+    let mut file = ctx.permissions.create_file(tmp)?;
+    if let Some(len) = len_hint {
+        utils::preallocate(&file, header_len + len)?;
+    }
+    file.write_all(encoder.header())?;
+
+    let mut buffer = vec![0u8; 64 * 1024];
+    let mut len = 0u64;
+    loop {
+        let n = match reader.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(n) => n,
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e.into()),
+        };
+        len += n as u64;
+        ctx.check_size(key, len)?;
+        encoder.update(&buffer[..n]);
+        file.write_all(&buffer[..n])?;
+    }
+
+    let size = header_len + len;
+    file.set_len(size)?;
+    file.seek(SeekFrom::Start(entry::PREFIX_LEN as u64))?;
+    file.write_all(&encoder.finish())?;
+    ctx.durable(&[tmp])?;
+    Ok((size, len))
+}
+
+fn write_entry(
+    ctx: &Context,
+    folder: &Path,
+    file_path: &Path,
+    encoded: &[u8],
+) -> std::io::Result<()> {
     if !folder.exists() {
-        std::fs::create_dir_all(&folder)?;
+        ctx.permissions.create_dir_all(folder)?;
+    }
+
+    ctx.inject_write()?;
+    let encoded = &encoded[..ctx.written_len(encoded.len())];
+    let direct = ctx.direct_io.is_some_and(|t| encoded.len() as u64 >= t);
+    replace_file(
+        &ctx.permissions,
+        ctx.durability.as_ref(),
+        folder,
+        file_path,
+        |file| match direct {
+            true => direct::write(file, encoded),
+            false => file.write_all(encoded),
+        },
+    )
+}
+
+/// Has `write` fill a temporary file next to `file_path`, then renames it
+/// over `file_path` in `folder`. The file gets the store's mode and, with
+/// `durability`, reaches the disk before the rename, which does too.
+pub(crate) fn replace_file(
+    permissions: &Permissions,
+    durability: Option<&GroupCommit>,
+    folder: &Path,
+    file_path: &Path,
+    write: impl FnOnce(&mut File) -> std::io::Result<()>,
+) -> std::io::Result<()> {
+    let durable = |paths: &[&Path]| match durability {
+        Some(group) => group.sync(paths),
+        None => Ok(()),
+    };
+
+    let tmp = file_path.with_extension("tmp");
+    let mut file = permissions.create_file(&tmp)?;
+    if let Err(e) = write(&mut file).and_then(|_| durable(&[&tmp])) {
+        drop(file);
+        let _ = std::fs::remove_file(&tmp);
+        return Err(e);
+    }
+
+    utils::retry_io(|| std::fs::rename(&tmp, file_path))?;
+    durable(&[folder])
+}
+
+fn remove(ctx: &Context, path: Arc<PathBuf>, key: &str) -> Result<(), Error> {
+    let h = ctx.hash(key);
+    if remove_with_hash(&h, ctx, path, None, None)? {
+        Counters::add(&ctx.counters.removes, 1);
+    }
+    Ok(())
+}
+
+/// Exchanges the values, and expiration times, of two entries. Both files are
+/// rewritten under their keys and renamed into place while both shard locks
+/// are held, so neither key is ever missing. The two renames aren't atomic
+/// together on disk: a crash, or a failed rename, between them leaves both
+/// entries holding the value `b` had.
+fn swap(ctx: &Context, path: Arc<PathBuf>, a: &str, b: &str) -> Result<(), Error> {
+    if a == b {
+        return Ok(());
+    }
+
+    let (ha, hb) = (ctx.hash(a), ctx.hash(b));
+    let (folder_a, file_a, shard_a) = parse_hash(&ha);
+    let (folder_b, file_b, shard_b) = parse_hash(&hb);
+    let path_a = path.join(folder_a).join(file_a);
+    let path_b = path.join(folder_b).join(file_b);
+
+    let _permit = ctx.io_permit();
+    let _locks = ctx.shards.write_pair(shard_a, shard_b);
+
+    let read = |file_path: &Path| match ctx.read_file(file_path) {
+        Ok(buffer) => Ok(buffer),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Err(Error::NotFound),
+        Err(e) => Err(e.into()),
+    };
+    let (buf_a, buf_b) = (read(&path_a)?, read(&path_b)?);
+    let (header_a, payload_a) = ctx.decode(&buf_a, a)?;
+    let (header_b, payload_b) = ctx.decode(&buf_b, b)?;
+
+    let new_a = ctx.encode(header_b.expires_at, a, &payload_b)?;
+    let new_b = ctx.encode(header_a.expires_at, b, &payload_a)?;
+    let (old_a, old_b) = (buf_a.len() as u64, buf_b.len() as u64);
+    let (size_a, size_b) = (new_a.len() as u64, new_b.len() as u64);
+
+    let ns_a = namespace::namespace_of(a);
+    let ns_b = namespace::namespace_of(b);
+    if let Some(ns) = ns_a {
+        ctx.namespaces.charge(ns, Some(old_a), size_a)?;
+    }
+    if let Some(ns) = ns_b
+        && let Err(e) = ctx.namespaces.charge(ns, Some(old_b), size_b)
+    {
+        if let Some(ns) = ns_a {
+            ctx.namespaces.refund(ns, Some(old_a), size_a);
+        }
+        return Err(e);
     }
 
-    let mut file = std::fs::File::create(file_path)?;
-    file.write_all(&0u16.to_be_bytes())?;
-    file.write_all(&expires_at.to_be_bytes())?;
-    file.write_all(&value)?;
+    ctx.forget(&path_a);
+    ctx.forget(&path_b);
+    ctx.horizons.lower(shard_a, header_b.expires_at);
+    ctx.horizons.lower(shard_b, header_a.expires_at);
+    let tmp_a = path_a.with_extension("tmp");
+    let tmp_b = path_b.with_extension("tmp");
+    let res = ctx
+        .permissions
+        .write(&tmp_a, &new_a)
+        .and_then(|_| ctx.permissions.write(&tmp_b, &new_b))
+        .and_then(|_| ctx.durable(&[&tmp_a, &tmp_b]))
+        .and_then(|_| utils::retry_io(|| std::fs::rename(&tmp_a, &path_a)))
+        .and_then(|_| utils::retry_io(|| std::fs::rename(&tmp_b, &path_b)))
+        .and_then(|_| ctx.durable(&[&path.join(folder_a), &path.join(folder_b)]));
+
+    if let Err(e) = res {
+        let _ = std::fs::remove_file(&tmp_a);
+        let _ = std::fs::remove_file(&tmp_b);
+        if let Some(ns) = ns_a {
+            ctx.namespaces.refund(ns, Some(old_a), size_a);
+        }
+        if let Some(ns) = ns_b {
+            ctx.namespaces.refund(ns, Some(old_b), size_b);
+        }
+        return Err(e.into());
+    }
+
+    let old = ctx.weigh(a, &payload_a, old_a) + ctx.weigh(b, &payload_b, old_b);
+    let new = ctx.weigh(a, &payload_b, size_a) + ctx.weigh(b, &payload_a, size_b);
+    ctx.pressure.resize(old, new);
+    Counters::add(&ctx.counters.bytes_written, size_a + size_b);
+    Ok(())
+}
+
+/// Clears the store like [`clear_lazy`], which only holds every shard lock for
+/// a moment, then deletes the cleared entries, tenants included, one folder
+/// at a time under that folder's locks. Operations on the other folders go on
+/// meanwhile, and entries written since the clear are kept.
+fn clear(ctx: &Context, path: Arc<PathBuf>) -> Result<(), Error> {
+    clear_lazy(ctx, &path)?;
+
+    let _permit = ctx.io_permit();
+    for root in tenant::roots(&path) {
+        for folder_id in 0..FANOUT as u16 {
+            let folder = root.join(format!("{folder_id:03x}"));
+            if !folder.exists() {
+                continue;
+            }
+
+            let _locks = ctx.shards.write_folder(folder_id);
+            for file in std::fs::read_dir(&folder)? {
+                let file = file?;
+                // Streamed writes rename their temporary file in later.
+                let is_temp = file.file_name().to_string_lossy().ends_with(".tmp");
+                if !is_temp && file.metadata().is_ok_and(|m| ctx.is_cleared(&m)) {
+                    ctx.remove_entry(&file.path())?;
+                }
+            }
+            // Fails when entries were written since.
+            let _ = std::fs::remove_dir(&folder);
+        }
+
+        let quarantine = root.join(janitor::QUARANTINE_DIR);
+        if quarantine.exists() {
+            remove_tree(ctx, &quarantine)?;
+        }
+    }
 
     Ok(())
 }
 
-fn remove(shards: &Shards, path: Arc<PathBuf>, key: String) -> Result<(), Error> {
-    let h = hash(&key);
-    remove_with_hash(&h, shards, path)
+/// Removes the entries of the tenant `name`, stored under `root`. Every shard
+/// lock is held meanwhile.
+pub fn clear_tenant(ctx: &Context, root: &Path, name: &str) -> Result<(), Error> {
+    let _permit = ctx.io_permit();
+    let _locks = ctx.shards.write_all();
+    ctx.generation.fetch_add(1, Ordering::AcqRel);
+
+    for entry in std::fs::read_dir(root)? {
+        let entry = entry?;
+        if entry.file_type()?.is_dir() {
+            remove_tree(ctx, &entry.path())?;
+        }
+    }
+    if let Some(memory) = &ctx.memory {
+        memory.clear();
+    }
+    if let Some(handles) = &ctx.handles {
+        handles.clear();
+    }
+    if let Some(headers) = &ctx.headers {
+        headers.clear();
+    }
+    ctx.pressure.resize(ctx.namespaces.usage(name).bytes, 0);
+    ctx.namespaces.reset_one(name);
+
+    Ok(())
 }
 
-fn clear(shards: &Shards, path: Arc<PathBuf>) -> Result<(), Error> {
-    let mut locks = Vec::with_capacity(4096);
-    for i in 0..4096 {
-        locks.push(shards.write(i as u16));
+/// Removes `dir` and everything in it, overwriting the files first with
+/// secure delete.
+fn remove_tree(ctx: &Context, dir: &Path) -> std::io::Result<()> {
+    if ctx.secure_delete {
+        for entry in std::fs::read_dir(dir)? {
+            let entry = entry?;
+            let file_type = entry.file_type()?;
+            if file_type.is_dir() {
+                remove_tree(ctx, &entry.path())?;
+            } else if file_type.is_file() {
+                utils::scrub(&entry.path(), entry.metadata()?.len())?;
+            }
+        }
     }
+    std::fs::remove_dir_all(dir)
+}
+
+/// Clears the store without deleting anything: entries last modified up to
+/// now are from then on treated as missing, and the janitor deletes them
+/// over its next sweeps. The cut-off is pinned with marker files on every
+/// path of the store under every shard lock, see [`mounts::pin`], so every
+/// later write is newer wherever it lands.
+pub fn clear_lazy(ctx: &Context, path: &Path) -> Result<(), Error> {
+    let _permit = ctx.io_permit();
+    let _locks = ctx.shards.write_all();
+
+    let cleared_before = mounts::pin(&ctx.mounts, "EPOCH", &ctx.permissions)?;
 
-    if path.exists() {
-        std::fs::remove_dir_all(&*path)?;
+    {
+        let mut manifest = ctx.manifest.lock().unwrap_or_else(|e| e.into_inner());
+        manifest.epoch += 1;
+        manifest.cleared_before = cleared_before;
     }
-    std::fs::create_dir_all(&*path)?;
+    if let Err(e) = ctx.persist(path) {
+        let mut manifest = ctx.manifest.lock().unwrap_or_else(|e| e.into_inner());
+        manifest.epoch -= 1;
+        manifest.cleared_before = ctx.cleared_before.load(Ordering::Acquire);
+        return Err(e);
+    }
+    ctx.cleared_before.store(cleared_before, Ordering::Release);
+    ctx.generation.fetch_add(1, Ordering::AcqRel);
 
+    if let Some(memory) = &ctx.memory {
+        memory.clear();
+    }
+    if let Some(handles) = &ctx.handles {
+        handles.clear();
+    }
+    if let Some(headers) = &ctx.headers {
+        headers.clear();
+    }
+    ctx.namespaces.reset();
+    ctx.horizons.reset();
+    ctx.pressure.reset(0);
     Ok(())
 }
 
-fn remove_with_hash(h: &[u8], shards: &Shards, path: Arc<PathBuf>) -> Result<(), Error> {
+/// Removes the entry for hash `h`. With `open`, a handle on the entry taken
+/// before the shard lock was released, the entry is only removed if it wasn't
+/// replaced in the meantime, and its header is read through that handle.
+fn remove_with_hash(
+    h: &[u8],
+    ctx: &Context,
+    path: Arc<PathBuf>,
+    cause: Option<EvictionCause>,
+    open: Option<&File>,
+) -> Result<bool, Error> {
     let (p_folder, filename, shard_id) = parse_hash(h);
     let file_path = path.join(p_folder).join(filename);
 
-    let _lock = shards.write(shard_id);
-    if file_path.exists() {
-        std::fs::remove_file(file_path)?;
+    let _permit = ctx.io_permit();
+    let _lock = ctx.shards.write(shard_id);
+    let Ok(metadata) = std::fs::metadata(&file_path) else {
+        return Ok(false);
+    };
+    if let Some(file) = open
+        && !utils::same_file(&file.metadata()?, &metadata)
+    {
+        return Ok(false);
+    }
+
+    match cause {
+        Some(cause) => ctx.evict_entry_with(&file_path, open, cause)?,
+        None => {
+            ctx.remove_entry_with(&file_path, open)?;
+        }
+    }
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc::sync_channel;
+
+    use super::*;
+    use crate::keeper::{Keeper, KeeperBuilder};
+
+    struct Store {
+        keeper: Option<Keeper>,
+        path: PathBuf,
+    }
+
+    impl Store {
+        fn new(name: &str) -> Self {
+            Self::build(name, KeeperBuilder::new)
+        }
+
+        fn build(name: &str, builder: impl FnOnce(PathBuf) -> KeeperBuilder) -> Self {
+            let path =
+                std::env::temp_dir().join(format!("keeper-store-{name}-{}", std::process::id()));
+            let _ = std::fs::remove_dir_all(&path);
+            Self {
+                keeper: Some(builder(path.clone()).build().unwrap()),
+                path,
+            }
+        }
+
+        fn keeper(&self) -> &Keeper {
+            self.keeper.as_ref().unwrap()
+        }
+
+        fn set(&self, key: &str, value: &[u8]) -> Result<(), Error> {
+            let (tx, rx) = sync_channel(1);
+            self.keeper().dispatch_set(key, value, None, move |res| {
+                let _ = tx.send(res);
+            });
+            rx.recv_timeout(Duration::from_secs(10)).unwrap()
+        }
+
+        fn get(&self, key: &str) -> Result<Vec<u8>, Error> {
+            let (tx, rx) = sync_channel(1);
+            self.keeper().dispatch_get(key, move |res| {
+                let _ = tx.send(res);
+            });
+            rx.recv_timeout(Duration::from_secs(10)).unwrap()
+        }
+    }
+
+    impl Drop for Store {
+        fn drop(&mut self) {
+            drop(self.keeper.take());
+            let _ = std::fs::remove_dir_all(&self.path);
+        }
+    }
+
+    #[test]
+    fn rejects_keys_too_long() {
+        let store = Store::new("long-key");
+        let key = "k".repeat(70 * 1024);
+        assert!(matches!(
+            store.set(&key, b"v"),
+            Err(Error::KeyTooLong(len)) if len == key.len()
+        ));
+        assert!(matches!(store.get(&key), Err(Error::NotFound)));
+
+        let key = "k".repeat(entry::MAX_KEY_LEN);
+        store.set(&key, b"v").unwrap();
+        assert_eq!(store.get(&key).unwrap(), b"v");
+    }
+
+    #[test]
+    fn rejects_streamed_keys_too_long() {
+        let plain = Store::new("long-streamed-key");
+        let signed = Store::build("long-streamed-key-signed", |path| {
+            KeeperBuilder::new(path).with_signing_key(b"secret")
+        });
+
+        for store in [&plain, &signed] {
+            // A multi-byte character straddling the limit.
+            let key = format!("{}é", "k".repeat(entry::MAX_KEY_LEN - 1));
+            let res = store.keeper().set_from_reader(&key, &b"v"[..], None, None);
+            assert!(matches!(res, Err(Error::KeyTooLong(len)) if len == key.len()));
+            assert!(matches!(store.get(&key), Err(Error::NotFound)));
+
+            let key = "k".repeat(entry::MAX_KEY_LEN);
+            let res = store
+                .keeper()
+                .set_from_reader(&key, &b"v"[..], Some(1), None);
+            assert_eq!(res.unwrap(), 1);
+            assert_eq!(store.get(&key).unwrap(), b"v");
+        }
+    }
+
+    #[test]
+    fn encoders_reject_keys_too_long() {
+        let key = "k".repeat(entry::MAX_KEY_LEN + 1);
+        let signing_key = SigningKey::new(b"secret");
+        assert!(matches!(
+            entry::encode(entry::V2, 0, 0, Some(&key), b"v"),
+            Err(Error::KeyTooLong(_))
+        ));
+        assert!(matches!(
+            entry::encode_signed(0, 0, &key, b"v", &signing_key),
+            Err(Error::KeyTooLong(_))
+        ));
+        assert!(matches!(
+            entry::StreamEncoder::new(0, &key, None),
+            Err(Error::KeyTooLong(_))
+        ));
+        assert!(entry::encode(entry::V1, 0, 0, Some(&key), b"v").is_ok());
     }
-    Ok(())
 }
@@ -0,0 +1,178 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+
+use crate::{
+    error::Error,
+    keeper::Keeper,
+    namespace::{Usage, qualify},
+};
+
+#[cfg(feature = "async")]
+use tokio::sync::oneshot;
+
+/// Folder of the store root holding one subfolder per tenant.
+pub const TENANTS_DIR: &str = "tenants";
+
+/// The store root followed by the root of every tenant, for the passes that
+/// walk all entries.
+pub fn roots(root: &Path) -> Vec<PathBuf> {
+    let mut roots = vec![root.to_path_buf()];
+    if let Ok(tenants) = std::fs::read_dir(root.join(TENANTS_DIR)) {
+        roots.extend(
+            tenants
+                .flatten()
+                .map(|tenant| tenant.path())
+                .filter(|path| path.is_dir()),
+        );
+    }
+    roots
+}
+
+/// A view of a [`Keeper`] whose entries live in their own folder,
+/// `<root>/tenants/<name>`, with the same layout as the store root. Tenants
+/// share the store's workers, janitor and shard locks. Their usage and quota
+/// are those of the namespace with the same name.
+#[derive(Debug, Clone)]
+pub struct Tenant {
+    keeper: Keeper,
+    name: String,
+    root: Arc<PathBuf>,
+}
+
+impl Tenant {
+    pub(crate) fn new(keeper: Keeper, name: String, root: PathBuf) -> Self {
+        Self {
+            keeper,
+            name,
+            root: Arc::new(root),
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    pub fn usage(&self) -> Usage {
+        self.keeper.namespace_usage(&self.name)
+    }
+
+    /// Removes every entry of the tenant on the calling thread, leaving the
+    /// rest of the store untouched.
+    pub fn clear(&self) -> Result<(), Error> {
+        self.keeper.clear_tenant(&self.root, &self.name)
+    }
+
+    #[cfg(all(feature = "async", not(feature = "sync")))]
+    pub async fn get(&self, key: &str) -> Result<Vec<u8>, Error> {
+        let (tx, rx) = oneshot::channel();
+        self.keeper
+            .dispatch_get_in(self.root.clone(), &qualify(&self.name, key), move |res| {
+                let _ = tx.send(res);
+            });
+        rx.await.map_err(|_| self.keeper.closed())?
+    }
+
+    #[cfg(all(feature = "async", not(feature = "sync")))]
+    pub async fn set(
+        &self,
+        key: &str,
+        value: &[u8],
+        duration: Option<Duration>,
+    ) -> Result<(), Error> {
+        let (tx, rx) = oneshot::channel();
+        self.keeper.dispatch_set_in(
+            self.root.clone(),
+            &qualify(&self.name, key),
+            value,
+            duration,
+            move |res| {
+                let _ = tx.send(res);
+            },
+        );
+        rx.await.map_err(|_| self.keeper.closed())?
+    }
+
+    #[cfg(all(feature = "async", not(feature = "sync")))]
+    pub async fn remove(&self, key: &str) -> Result<(), Error> {
+        let (tx, rx) = oneshot::channel();
+        self.keeper
+            .dispatch_remove_in(self.root.clone(), &qualify(&self.name, key), move |res| {
+                let _ = tx.send(res);
+            });
+        rx.await.map_err(|_| self.keeper.closed())?
+    }
+
+    #[cfg(all(feature = "sync", not(feature = "async")))]
+    pub fn get(&self, key: &str) -> Result<Vec<u8>, Error> {
+        let (tx, rx) = std::sync::mpsc::sync_channel(1);
+        self.keeper
+            .dispatch_get_in(self.root.clone(), &qualify(&self.name, key), move |res| {
+                let _ = tx.send(res);
+            });
+        rx.recv().map_err(|_| self.keeper.closed())?
+    }
+
+    #[cfg(all(feature = "sync", not(feature = "async")))]
+    pub fn set(&self, key: &str, value: &[u8], duration: Option<Duration>) -> Result<(), Error> {
+        let (tx, rx) = std::sync::mpsc::sync_channel(1);
+        self.keeper.dispatch_set_in(
+            self.root.clone(),
+            &qualify(&self.name, key),
+            value,
+            duration,
+            move |res| {
+                let _ = tx.send(res);
+            },
+        );
+        rx.recv().map_err(|_| self.keeper.closed())?
+    }
+
+    #[cfg(all(feature = "sync", not(feature = "async")))]
+    pub fn remove(&self, key: &str) -> Result<(), Error> {
+        let (tx, rx) = std::sync::mpsc::sync_channel(1);
+        self.keeper
+            .dispatch_remove_in(self.root.clone(), &qualify(&self.name, key), move |res| {
+                let _ = tx.send(res);
+            });
+        rx.recv().map_err(|_| self.keeper.closed())?
+    }
+
+    #[cfg(all(not(feature = "async"), not(feature = "sync")))]
+    pub fn get<F>(&self, key: &str, cb: F)
+    where
+        F: FnOnce(Result<Vec<u8>, Error>) + Send + Sync + 'static,
+    {
+        self.keeper
+            .dispatch_get_in(self.root.clone(), &qualify(&self.name, key), cb);
+    }
+
+    #[cfg(all(not(feature = "async"), not(feature = "sync")))]
+    pub fn set<F>(&self, key: &str, value: &[u8], duration: Option<Duration>, cb: F)
+    where
+        F: FnOnce(Result<(), Error>) + Send + Sync + 'static,
+    {
+        self.keeper.dispatch_set_in(
+            self.root.clone(),
+            &qualify(&self.name, key),
+            value,
+            duration,
+            cb,
+        );
+    }
+
+    #[cfg(all(not(feature = "async"), not(feature = "sync")))]
+    pub fn remove<F>(&self, key: &str, cb: F)
+    where
+        F: FnOnce(Result<(), Error>) + Send + Sync + 'static,
+    {
+        self.keeper
+            .dispatch_remove_in(self.root.clone(), &qualify(&self.name, key), cb);
+    }
+}
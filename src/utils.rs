@@ -1,16 +1,261 @@
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::{
+    fs::{File, Metadata},
+    io::{self, Read, Seek, Write},
+    panic::{AssertUnwindSafe, catch_unwind},
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
 
-pub fn now() -> u64 {
-    SystemTime::now()
-        .duration_since(UNIX_EPOCH)
-        .unwrap()
-        .as_secs()
-}
+use crate::error::{Error, Worker};
 
+/// Splits a hash into its folder (the first 12 bits), its file name and its
+/// shard id (the first 16 bits), which picks the lock guarding the entry.
 pub fn parse_hash(h: &[u8]) -> (&str, &str, u16) {
     let p_folder = unsafe { std::str::from_utf8_unchecked(&h[0..3]) };
     let filename = unsafe { std::str::from_utf8_unchecked(&h[3..]) };
-    let shard_id = u16::from_str_radix(p_folder, 16).unwrap_or(0);
+    let shard_id = unsafe { std::str::from_utf8_unchecked(&h[0..4]) };
+    let shard_id = u16::from_str_radix(shard_id, 16).unwrap_or(0);
 
     (p_folder, filename, shard_id)
 }
+
+pub fn guard<T>(worker: Worker, f: impl FnOnce() -> Result<T, Error>) -> Result<T, Error> {
+    catch_unwind(AssertUnwindSafe(f)).unwrap_or(Err(Error::WorkerPanicked(worker)))
+}
+
+pub fn respawn(mut f: impl FnMut(), on_panic: impl Fn()) {
+    while catch_unwind(AssertUnwindSafe(&mut f)).is_err() {
+        on_panic();
+    }
+}
+
+/// On Windows, turns `path` into its `\\?\` extended-length form, so deep
+/// store roots don't hit the 260 character `MAX_PATH` limit once the shard
+/// folder and file name are appended. Roots that don't exist yet are made
+/// absolute and prefixed by hand. Paths on other platforms are returned
+/// unchanged.
+pub fn extended_length(path: PathBuf) -> PathBuf {
+    #[cfg(windows)]
+    {
+        if let Ok(canonical) = std::fs::canonicalize(&path) {
+            return canonical;
+        }
+
+        let Ok(absolute) = std::path::absolute(&path) else {
+            return path;
+        };
+        let absolute = absolute.to_string_lossy();
+        PathBuf::from(match absolute.strip_prefix(r"\\") {
+            Some(_) if absolute.starts_with(r"\\?\") => absolute.into_owned(),
+            Some(unc) => format!(r"\\?\UNC\{unc}"),
+            None => format!(r"\\?\{absolute}"),
+        })
+    }
+
+    #[cfg(not(windows))]
+    path
+}
+
+/// Runs a file system operation, retrying it with a growing delay while it
+/// fails because another handle has the file open. That happens on Windows
+/// when a reader, the janitor or an antivirus holds a file being replaced or
+/// deleted. Elsewhere the operation runs once.
+pub fn retry_io<T>(mut op: impl FnMut() -> std::io::Result<T>) -> std::io::Result<T> {
+    #[cfg(windows)]
+    {
+        const ERROR_ACCESS_DENIED: i32 = 5;
+        const ERROR_SHARING_VIOLATION: i32 = 32;
+        const ERROR_LOCK_VIOLATION: i32 = 33;
+
+        let mut delay = std::time::Duration::from_millis(1);
+        for _ in 0..8 {
+            match op() {
+                Err(e)
+                    if matches!(
+                        e.raw_os_error(),
+                        Some(ERROR_ACCESS_DENIED | ERROR_SHARING_VIOLATION | ERROR_LOCK_VIOLATION)
+                    ) =>
+                {
+                    std::thread::sleep(delay);
+                    delay *= 2;
+                }
+                res => return res,
+            }
+        }
+    }
+
+    op()
+}
+
+/// `time` in nanoseconds since the Unix epoch, `0` before it.
+pub fn nanos(time: SystemTime) -> u64 {
+    time.duration_since(SystemTime::UNIX_EPOCH)
+        .map(|d| d.as_nanos() as u64)
+        .unwrap_or(0)
+}
+
+/// Reads `file` from `offset` without moving a cursor other threads may share.
+#[cfg(unix)]
+pub fn read_at(file: &File, buffer: &mut [u8], offset: u64) -> io::Result<usize> {
+    use std::os::unix::fs::FileExt;
+    file.read_at(buffer, offset)
+}
+
+#[cfg(not(unix))]
+pub fn read_at(mut file: &File, buffer: &mut [u8], offset: u64) -> io::Result<usize> {
+    use std::io::{Seek, SeekFrom};
+    file.seek(SeekFrom::Start(offset))?;
+    file.read(buffer)
+}
+
+/// A [`Read`] over a shared file, starting at its beginning.
+pub struct ReadAt<'a> {
+    file: &'a File,
+    offset: u64,
+}
+
+impl<'a> ReadAt<'a> {
+    pub fn new(file: &'a File) -> Self {
+        Self { file, offset: 0 }
+    }
+}
+
+impl Read for ReadAt<'_> {
+    fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+        let n = read_at(self.file, buffer, self.offset)?;
+        self.offset += n as u64;
+        Ok(n)
+    }
+}
+
+/// Whether both metadata describe the same file, so a handle opened earlier
+/// still refers to what is at its path.
+pub fn same_file(a: &Metadata, b: &Metadata) -> bool {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        a.dev() == b.dev() && a.ino() == b.ino()
+    }
+
+    #[cfg(not(unix))]
+    {
+        a.len() == b.len() && a.modified().ok() == b.modified().ok()
+    }
+}
+
+/// Overwrites the first `len` bytes of the file at `path` with zeros and
+/// flushes them to the device.
+pub fn scrub(path: &Path, len: u64) -> std::io::Result<()> {
+    scrub_file(&std::fs::OpenOptions::new().write(true).open(path)?, len)
+}
+
+/// Like [`scrub`], through a handle opened for writing, which works even
+/// once the file was unlinked or replaced.
+pub fn scrub_file(mut file: &File, len: u64) -> io::Result<()> {
+    static ZEROS: [u8; 64 * 1024] = [0; 64 * 1024];
+
+    file.seek(io::SeekFrom::Start(0))?;
+    let mut left = len;
+    while left > 0 {
+        let n = left.min(ZEROS.len() as u64) as usize;
+        file.write_all(&ZEROS[..n])?;
+        left -= n as u64;
+    }
+    file.sync_all()
+}
+
+const CRC32_TABLE: [u32; 256] = {
+    let mut table = [0u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xEDB8_8320
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+};
+
+pub fn crc32(chunks: &[&[u8]]) -> u32 {
+    let mut crc = Crc32::default();
+    for chunk in chunks {
+        crc.update(chunk);
+    }
+    crc.finish()
+}
+
+/// Incremental [`crc32`], for data that isn't in memory all at once.
+pub struct Crc32(u32);
+
+impl Default for Crc32 {
+    fn default() -> Self {
+        Self(!0)
+    }
+}
+
+impl Crc32 {
+    pub fn update(&mut self, data: &[u8]) {
+        for &byte in data {
+            self.0 = CRC32_TABLE[((self.0 ^ byte as u32) & 0xFF) as usize] ^ (self.0 >> 8);
+        }
+    }
+
+    pub fn finish(&self) -> u32 {
+        !self.0
+    }
+}
+
+/// Reserves `len` bytes for `file` so a large write fails early when the disk
+/// is full and ends up less fragmented. A no-op where the platform or
+/// filesystem can't preallocate.
+#[cfg(target_os = "linux")]
+pub fn preallocate(file: &std::fs::File, len: u64) -> std::io::Result<()> {
+    use std::os::fd::AsRawFd;
+
+    match unsafe { libc::fallocate(file.as_raw_fd(), 0, 0, len as libc::off_t) } {
+        0 => Ok(()),
+        _ => match std::io::Error::last_os_error() {
+            e if e.raw_os_error() == Some(libc::EOPNOTSUPP) => Ok(()),
+            e => Err(e),
+        },
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn preallocate(_file: &std::fs::File, _len: u64) -> std::io::Result<()> {
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn crc32_check_values() {
+        assert_eq!(crc32(&[b"123456789"]), 0xcbf4_3926);
+        assert_eq!(crc32(&[b""]), 0);
+        assert_eq!(
+            crc32(&[b"The quick brown fox jumps over the lazy dog"]),
+            0x414f_a339
+        );
+    }
+
+    #[test]
+    fn crc32_across_chunks() {
+        assert_eq!(crc32(&[b"1234", b"", b"56789"]), 0xcbf4_3926);
+
+        let mut crc = Crc32::default();
+        for byte in b"123456789" {
+            crc.update(&[*byte]);
+        }
+        assert_eq!(crc.finish(), 0xcbf4_3926);
+    }
+}
@@ -0,0 +1,71 @@
+use std::{cmp::Reverse, collections::BinaryHeap, path::Path, time::SystemTime};
+
+use crate::{context::Context, entries, manifest::FANOUT, utils::parse_hash};
+
+/// Reads the entries for `keys` into the memory tier on the calling thread,
+/// in order, so the last keys end up the most recently used. Missing, expired
+/// and unreadable entries are skipped. Returns how many entries the tier
+/// admitted, `0` without a memory tier.
+pub fn warm_up<I>(ctx: &Context, root: &Path, keys: I) -> u64
+where
+    I: IntoIterator<Item = String>,
+{
+    let Some(memory) = &ctx.memory else {
+        return 0;
+    };
+
+    let now = ctx.now();
+    let mut admitted = 0;
+    for key in keys {
+        let h = ctx.hash(&key);
+        let (p_folder, filename, shard_id) = parse_hash(&h);
+        let file_path = root.join(p_folder).join(filename);
+
+        let _permit = ctx.io_permit();
+        let _lock = ctx.shards.read(shard_id);
+        let Ok(buffer) = ctx.read_file(&file_path) else {
+            continue;
+        };
+        let Ok((header, payload)) = ctx.decode(&buffer, &key) else {
+            continue;
+        };
+        if !ctx.is_expired(header.expires_at, now) && memory.offer(&h, &payload, header.expires_at)
+        {
+            admitted += 1;
+        }
+    }
+    admitted
+}
+
+/// Like [`warm_up`], with the `n` most recently written entries that record
+/// their key, found by listing every shard.
+pub fn warm_up_recent(ctx: &Context, root: &Path, n: usize) -> u64 {
+    if ctx.memory.is_none() || n == 0 {
+        return 0;
+    }
+
+    let now = ctx.now();
+    let mut newest: BinaryHeap<Reverse<(SystemTime, String)>> = BinaryHeap::with_capacity(n + 1);
+    for folder in 0..FANOUT as u16 {
+        for (_, info) in entries::read_shard(ctx, root, folder) {
+            let (Some(key), Some(modified)) = (info.key, info.modified) else {
+                continue;
+            };
+            if ctx.is_expired(info.expires_at, now) {
+                continue;
+            }
+
+            newest.push(Reverse((modified, key)));
+            if newest.len() > n {
+                newest.pop();
+            }
+        }
+    }
+
+    let keys = newest
+        .into_sorted_vec()
+        .into_iter()
+        .rev()
+        .map(|Reverse((_, key))| key);
+    warm_up(ctx, root, keys)
+}
@@ -0,0 +1,24 @@
+use std::fmt;
+
+/// Gives the cost of an entry from its key and value, counted against
+/// [`crate::keeper::KeeperBuilder::with_max_store_size`] instead of the size
+/// of its file.
+pub type WeighFn = Box<dyn Fn(&str, &[u8]) -> u64 + Send + Sync + 'static>;
+
+pub struct Weigher(WeighFn);
+
+impl fmt::Debug for Weigher {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Weigher").finish_non_exhaustive()
+    }
+}
+
+impl Weigher {
+    pub fn new(weigh: WeighFn) -> Self {
+        Self(weigh)
+    }
+
+    pub fn weigh(&self, key: &str, value: &[u8]) -> u64 {
+        (self.0)(key, value)
+    }
+}